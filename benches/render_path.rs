@@ -0,0 +1,160 @@
+//! Benchmarks for the hot paths in rendering a frame.
+//!
+//! Run with `cargo bench`. The end-to-end benchmark needs `Xvfb` and a GPU, and is skipped
+//! without them.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use recomp::texture::{upload_region, upload_regions, DamagePolicy, WindowUniforms};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConnectionExt as _, CreateWindowAux, Rectangle, WindowClass},
+};
+
+/// How long to run each benchmark for, after warming up.
+const MEASURE_TIME: Duration = Duration::from_secs(2);
+
+/// Run `f` repeatedly for about `MEASURE_TIME` and print the mean time per iteration.
+fn bench(name: &str, mut f: impl FnMut()) {
+    for _ in 0..100 {
+        f();
+    }
+
+    let start = Instant::now();
+    let mut iterations = 0u64;
+    while start.elapsed() < MEASURE_TIME {
+        for _ in 0..100 {
+            f();
+        }
+        iterations += 100;
+    }
+
+    let per_iter = start.elapsed() / iterations as u32;
+    println!("{name:<40} {per_iter:>12.2?}/iter ({iterations} iterations)");
+}
+
+fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+    Rectangle {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+fn uniform_packing() {
+    let uniforms = WindowUniforms {
+        geometry: rect(100, 200, 1280, 720),
+        screen: (1920, 1080),
+        dim: 0.25,
+        opacity: 1.0,
+        has_alpha: false,
+        straight_alpha: false,
+        corner_radius: 0.0,
+        corner_smoothing: 0.0,
+        corner_aa_width: 1.0,
+    };
+    bench("uniform packing", || {
+        black_box(black_box(&uniforms).pack());
+    });
+}
+
+fn damage_clipping() {
+    bench("damage clipping (partial)", || {
+        black_box(upload_region(black_box(rect(10, 10, 64, 64)), 1280, 720));
+    });
+    bench("damage clipping (full fallback)", || {
+        black_box(upload_region(
+            black_box(rect(-50, -50, 2000, 2000)),
+            1280,
+            720,
+        ));
+    });
+    bench("damage clipping (outside)", || {
+        black_box(upload_region(
+            black_box(rect(2000, 2000, 10, 10)),
+            1280,
+            720,
+        ));
+    });
+}
+
+fn damage_coalescing() {
+    let policy = DamagePolicy::default();
+    // A row of changed characters, close enough together to merge into one upload.
+    let typing: Vec<_> = (0..24).map(|i| rect(40 + i * 9, 300, 8, 16)).collect();
+    bench("damage coalescing (clustered)", || {
+        black_box(upload_regions(black_box(&typing), 1280, 720, &policy));
+    });
+    // Small changes spread over the window, which stay separate.
+    let scattered: Vec<_> = (0..16)
+        .map(|i| rect((i % 4) * 300 + 10, (i / 4) * 170 + 10, 12, 12))
+        .collect();
+    bench("damage coalescing (scattered)", || {
+        black_box(upload_regions(black_box(&scattered), 1280, 720, &policy));
+    });
+    // More rectangles than get merged one by one.
+    let noisy: Vec<_> = (0..256)
+        .map(|i| rect((i % 32) * 40, (i / 32) * 90, 4, 4))
+        .collect();
+    bench("damage coalescing (many rects)", || {
+        black_box(upload_regions(black_box(&noisy), 1280, 720, &policy));
+    });
+}
+
+async fn end_to_end(windows: u16) {
+    let Some(server) = common::Xvfb::try_spawn() else {
+        return common::skip("Xvfb is not available");
+    };
+    let Some(mut compositor) = common::try_compositor(&server, Default::default()).await else {
+        return common::skip("no GPU adapter available");
+    };
+
+    let client = server.connect();
+    let root = compositor.root_window();
+    for i in 0..windows {
+        let win = client.generate_id().unwrap();
+        let offset = (i % 32) as i16 * 20;
+        client
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                win,
+                root,
+                offset,
+                offset,
+                400,
+                300,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+        client.map_window(win).unwrap();
+    }
+    client.flush().unwrap();
+
+    // Give the server a moment to deliver everything before we start tracking.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    compositor.process_events().await.unwrap();
+
+    bench(&format!("render ({windows} windows)"), || {
+        compositor.render().unwrap();
+    });
+}
+
+#[tokio::main]
+async fn main() {
+    uniform_packing();
+    damage_clipping();
+    damage_coalescing();
+    for windows in [1, 16, 64] {
+        end_to_end(windows).await;
+    }
+}
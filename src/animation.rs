@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use x11rb::protocol::xproto;
+
+/// How often animations are stepped, independent of the frame rate.
+pub const TIMESTEP: Duration = Duration::from_micros(1_000_000 / 120);
+
+/// The longest frame time we'll simulate in one go. After a stall (or waking up from
+/// idle) animations just jump ahead instead of running hundreds of steps at once.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// The animatable properties of a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Appearance {
+    /// From 0 (invisible) to 1 (opaque).
+    pub opacity: f32,
+    /// Offset from the window's real position, in pixels.
+    pub offset: (f32, f32),
+    /// Added to the window's real size, in pixels.
+    pub grow: (f32, f32),
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            opacity: 1.0,
+            offset: (0.0, 0.0),
+            grow: (0.0, 0.0),
+        }
+    }
+}
+
+impl Appearance {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            opacity: mix(self.opacity, to.opacity),
+            offset: (
+                mix(self.offset.0, to.offset.0),
+                mix(self.offset.1, to.offset.1),
+            ),
+            grow: (mix(self.grow.0, to.grow.0), mix(self.grow.1, to.grow.1)),
+        }
+    }
+
+    /// Apply the offset and growth to a window's geometry.
+    pub fn apply(&self, rect: xproto::Rectangle) -> xproto::Rectangle {
+        xproto::Rectangle {
+            x: (rect.x as f32 + self.offset.0).round() as i16,
+            y: (rect.y as f32 + self.offset.1).round() as i16,
+            width: (rect.width as f32 + self.grow.0).round().max(0.0) as u16,
+            height: (rect.height as f32 + self.grow.1).round().max(0.0) as u16,
+        }
+    }
+}
+
+/// How a tween progresses over its duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    Linear,
+    /// Starts slow, speeds up, and slows down again towards the end.
+    EaseInOut,
+    /// Starts fast and slows down towards the end.
+    #[default]
+    EaseOutCubic,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut if t < 0.5 => 4.0 * t.powi(3),
+            Easing::EaseInOut => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+            Easing::EaseOutCubic => 1.0 - (1.0 - t).powi(3),
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "linear" => Easing::Linear,
+            "ease-in-out" => Easing::EaseInOut,
+            "cubic" => Easing::EaseOutCubic,
+            _ => anyhow::bail!("unknown easing {s}, expected linear, ease-in-out, or cubic"),
+        })
+    }
+}
+
+/// The easing each kind of animation uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Easings {
+    /// The tint fading in or out.
+    pub tint: Easing,
+    /// Windows moving into or out of the expose grid.
+    pub expose: Easing,
+    /// Windows moving or resizing, with a geometry animation.
+    pub geometry: Easing,
+}
+
+impl Default for Easings {
+    fn default() -> Self {
+        Self {
+            tint: Easing::Linear,
+            expose: Easing::EaseOutCubic,
+            geometry: Easing::EaseOutCubic,
+        }
+    }
+}
+
+impl Easings {
+    /// Use `easing` for the kind of animation called `name`: `tint`, `expose` or
+    /// `geometry`.
+    pub fn set(&mut self, name: &str, easing: Easing) -> anyhow::Result<()> {
+        let slot = match name {
+            "tint" => &mut self.tint,
+            "expose" => &mut self.expose,
+            "geometry" => &mut self.geometry,
+            _ => anyhow::bail!("unknown animation {name}, expected tint, expose, or geometry"),
+        };
+        *slot = easing;
+        Ok(())
+    }
+}
+
+/// An animation from one appearance to another.
+#[derive(Debug, Clone, Copy)]
+struct Tween {
+    from: Appearance,
+    to: Appearance,
+    /// When it started, on the manager's clock.
+    start: Duration,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Tween {
+    fn sample(&self, clock: Duration) -> Appearance {
+        if self.finished(clock) {
+            return self.to;
+        }
+        let t = (clock - self.start).as_secs_f32() / self.duration.as_secs_f32();
+        self.from.lerp(self.to, self.easing.apply(t))
+    }
+
+    fn finished(&self, clock: Duration) -> bool {
+        clock.saturating_sub(self.start) >= self.duration
+    }
+}
+
+/// A window's tween, along with its appearance at the last two simulation steps.
+#[derive(Debug, Clone, Copy)]
+struct Animation {
+    tween: Tween,
+    previous: Appearance,
+    current: Appearance,
+}
+
+/// Runs per-window animations on a fixed timestep.
+///
+/// Animations are stepped by [`TIMESTEP`] regardless of the frame rate, so they run at
+/// the same speed everywhere, and the appearance used for rendering is interpolated
+/// between the last two steps so motion stays smooth when frames don't line up with
+/// steps.
+///
+/// Every animation runs on the manager's one clock, which only moves when it's given the
+/// time of a frame with [`Self::advance_to`]. Animations started together stay in step,
+/// and the same frame times always animate the same way.
+#[derive(Debug, Default)]
+pub struct AnimationManager {
+    animations: HashMap<xproto::Window, Animation>,
+    /// How much time has been simulated, in whole steps.
+    clock: Duration,
+    /// The time of the last frame animations were advanced to.
+    last_frame: Option<Instant>,
+    /// Time that has passed but hasn't been simulated yet, always less than a step.
+    accumulator: Duration,
+}
+
+impl AnimationManager {
+    /// Animate `win` to `to` over `duration`, starting from wherever it currently is.
+    ///
+    /// Once finished, the window stays at `to` until it's animated again or removed.
+    pub fn animate(
+        &mut self,
+        win: xproto::Window,
+        to: Appearance,
+        duration: Duration,
+        easing: Easing,
+    ) {
+        let from = self.appearance(win);
+        self.animate_from(win, from, to, duration, easing);
+    }
+
+    /// Animate `win` from `from` to `to` over `duration`.
+    pub fn animate_from(
+        &mut self,
+        win: xproto::Window,
+        from: Appearance,
+        to: Appearance,
+        duration: Duration,
+        easing: Easing,
+    ) {
+        self.animations.insert(
+            win,
+            Animation {
+                tween: Tween {
+                    from,
+                    to,
+                    start: self.clock,
+                    duration,
+                    easing,
+                },
+                previous: from,
+                current: from,
+            },
+        );
+    }
+
+    /// Stop animating `win` and reset it to its default appearance.
+    pub fn remove(&mut self, win: xproto::Window) {
+        self.animations.remove(&win);
+    }
+
+    /// Whether any animation is still in progress, meaning frames need to keep being
+    /// rendered.
+    pub fn is_animating(&self) -> bool {
+        self.animations.values().any(|animation| {
+            !animation.tween.finished(self.clock) || animation.previous != animation.current
+        })
+    }
+
+    /// Whether `win` has an animation that is still in progress.
+    pub fn is_animating_window(&self, win: xproto::Window) -> bool {
+        self.animations
+            .get(&win)
+            .is_some_and(|animation| !animation.tween.finished(self.clock))
+    }
+
+    /// Advance all animations to a frame shown at `frame_time`. The first frame only
+    /// starts the clock.
+    pub fn advance_to(&mut self, frame_time: Instant) {
+        let dt = self.last_frame.map_or(Duration::ZERO, |last| {
+            frame_time.saturating_duration_since(last)
+        });
+        self.last_frame = Some(frame_time);
+        self.accumulator += dt.min(MAX_FRAME_TIME);
+
+        while self.accumulator >= TIMESTEP {
+            self.accumulator -= TIMESTEP;
+            self.clock += TIMESTEP;
+            for animation in self.animations.values_mut() {
+                animation.previous = animation.current;
+                animation.current = animation.tween.sample(self.clock);
+            }
+        }
+
+        // Finished animations that ended up back at the default look don't need
+        // tracking any more.
+        let clock = self.clock;
+        self.animations.retain(|_, animation| {
+            !(animation.tween.finished(clock)
+                && animation.previous == animation.current
+                && animation.current == Appearance::default())
+        });
+    }
+
+    /// The appearance to render `win` with, interpolated between simulation steps.
+    pub fn appearance(&self, win: xproto::Window) -> Appearance {
+        let Some(animation) = self.animations.get(&win) else {
+            return Appearance::default();
+        };
+        let alpha = self.accumulator.as_secs_f32() / TIMESTEP.as_secs_f32();
+        animation.previous.lerp(animation.current, alpha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Show the next frame `dt` after the last one.
+    fn advance(animations: &mut AnimationManager, dt: Duration) {
+        let last = *animations.last_frame.get_or_insert_with(Instant::now);
+        animations.advance_to(last + dt);
+    }
+
+    fn faded(opacity: f32) -> Appearance {
+        Appearance {
+            opacity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn untracked_windows_have_default_appearance() {
+        let animations = AnimationManager::default();
+        assert_eq!(animations.appearance(1), Appearance::default());
+        assert!(!animations.is_animating());
+    }
+
+    #[test]
+    fn reaches_target_after_duration() {
+        let mut animations = AnimationManager::default();
+        animations.animate(1, faded(0.0), Duration::from_millis(100), Easing::Linear);
+        assert!(animations.is_animating());
+
+        // Half way through, in frames that don't line up with the timestep.
+        for _ in 0..5 {
+            advance(&mut animations, Duration::from_millis(10));
+        }
+        let opacity = animations.appearance(1).opacity;
+        assert!((opacity - 0.5).abs() < 0.1, "opacity was {opacity}");
+
+        for _ in 0..10 {
+            advance(&mut animations, Duration::from_millis(10));
+        }
+        assert_eq!(animations.appearance(1), faded(0.0));
+        assert!(!animations.is_animating());
+    }
+
+    #[test]
+    fn interpolates_between_steps() {
+        let mut animations = AnimationManager::default();
+        animations.animate(1, faded(0.0), TIMESTEP * 10, Easing::Linear);
+
+        advance(&mut animations, TIMESTEP);
+        let stepped = animations.appearance(1).opacity;
+        advance(&mut animations, TIMESTEP / 2);
+        let between = animations.appearance(1).opacity;
+        advance(&mut animations, TIMESTEP / 2);
+        let next = animations.appearance(1).opacity;
+
+        assert!(stepped > between && between > next);
+    }
+
+    #[test]
+    fn drops_animations_that_return_to_default() {
+        let mut animations = AnimationManager::default();
+        animations.animate_from(
+            1,
+            faded(0.0),
+            Appearance::default(),
+            Duration::from_millis(50),
+            Easing::EaseOutCubic,
+        );
+        advance(&mut animations, Duration::from_millis(100));
+        assert!(animations.animations.is_empty());
+    }
+
+    #[test]
+    fn clamps_long_frames() {
+        let mut animations = AnimationManager::default();
+        animations.animate(1, faded(0.0), Duration::from_secs(1), Easing::Linear);
+        advance(&mut animations, Duration::from_secs(10));
+        assert!(animations.is_animating_window(1));
+    }
+
+    #[test]
+    fn animations_started_in_one_frame_stay_in_step() {
+        let mut animations = AnimationManager::default();
+        animations.animate(1, faded(0.0), Duration::from_millis(100), Easing::Linear);
+        // Part way to the next step, so the second starts later in real time.
+        advance(&mut animations, TIMESTEP / 3);
+        animations.animate(2, faded(0.0), Duration::from_millis(100), Easing::Linear);
+
+        for _ in 0..7 {
+            advance(&mut animations, Duration::from_millis(7));
+            assert_eq!(animations.appearance(1), animations.appearance(2));
+        }
+    }
+
+    #[test]
+    fn animates_the_same_for_the_same_frame_times() {
+        let start = Instant::now();
+        let run = || {
+            let mut animations = AnimationManager::default();
+            animations.advance_to(start);
+            animations.animate(1, faded(0.0), Duration::from_millis(100), Easing::EaseInOut);
+            [3, 11, 19, 40, 41]
+                .map(|ms| {
+                    animations.advance_to(start + Duration::from_millis(ms));
+                    animations.appearance(1)
+                })
+                .to_vec()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn eases_in_and_out() {
+        assert_eq!(Easing::EaseInOut.apply(0.0), 0.0);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert_eq!(Easing::EaseInOut.apply(1.0), 1.0);
+        assert!(Easing::EaseInOut.apply(0.1) < Easing::Linear.apply(0.1));
+        assert!(Easing::EaseInOut.apply(0.9) > Easing::Linear.apply(0.9));
+    }
+
+    #[test]
+    fn sets_easings_by_name() {
+        let mut easings = Easings::default();
+        easings
+            .set("geometry", "ease-in-out".parse().unwrap())
+            .unwrap();
+        assert_eq!(easings.geometry, Easing::EaseInOut);
+        assert_eq!(easings.tint, Easing::Linear);
+        assert!(easings.set("fade", Easing::Linear).is_err());
+        assert!("bounce".parse::<Easing>().is_err());
+    }
+}
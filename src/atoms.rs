@@ -0,0 +1,109 @@
+use anyhow::Result;
+use x11rb::protocol::xproto::Atom;
+use x11rb_async::protocol::xproto::ConnectionExt as _;
+
+use crate::connection::XConn;
+
+/// Declare [`Atoms`] with a field for each atom, and intern them all in one batch.
+macro_rules! atoms {
+    ($($(#[$doc:meta])* $field:ident: $name:literal,)*) => {
+        /// The atoms we need that aren't predefined by the core protocol, interned once at
+        /// startup.
+        #[derive(Debug, Clone)]
+        pub struct Atoms {
+            $($(#[$doc])* pub $field: Atom,)*
+            /// `_NET_WM_CM_Sn` for our screen, the selection owned by its compositing
+            /// manager.
+            pub net_wm_cm_s: Atom,
+            /// The extra atoms asked for with [`Config::prefetch_atoms`], by name.
+            ///
+            /// [`Config::prefetch_atoms`]: crate::config::Config::prefetch_atoms
+            extra: Vec<(String, Atom)>,
+        }
+
+        impl Atoms {
+            /// Intern every atom we use, along with `extra`, in a single round trip.
+            pub async fn intern(conn: &XConn, extra: &[String]) -> Result<Self> {
+                // Send every request before waiting for any of the replies.
+                $(let $field = conn.intern_atom(false, $name).await?;)*
+                let cm_name = format!("_NET_WM_CM_S{}", conn.screen());
+                let net_wm_cm_s = conn.intern_atom(false, cm_name.as_bytes()).await?;
+                let mut extra_cookies = Vec::with_capacity(extra.len());
+                for name in extra {
+                    extra_cookies.push(conn.intern_atom(false, name.as_bytes()).await?);
+                }
+
+                $(let $field = $field.reply().await?.atom;)*
+                let net_wm_cm_s = net_wm_cm_s.reply().await?.atom;
+                let mut interned = Vec::with_capacity(extra.len());
+                for (name, cookie) in extra.iter().zip(extra_cookies) {
+                    interned.push((name.clone(), cookie.reply().await?.atom));
+                }
+
+                Ok(Self {
+                    $($field,)*
+                    net_wm_cm_s,
+                    extra: interned,
+                })
+            }
+
+            /// Look up an interned atom by name, whether it's one we use or was prefetched.
+            pub fn get(&self, name: &str) -> Option<Atom> {
+                match name.as_bytes() {
+                    $($name => Some(self.$field),)*
+                    _ => self
+                        .extra
+                        .iter()
+                        .find(|(extra, _)| extra == name)
+                        .map(|(_, atom)| *atom),
+                }
+            }
+        }
+    };
+}
+
+atoms! {
+    net_active_window: b"_NET_ACTIVE_WINDOW",
+    net_client_list_stacking: b"_NET_CLIENT_LIST_STACKING",
+    net_supporting_wm_check: b"_NET_SUPPORTING_WM_CHECK",
+    /// Icons in several sizes, drawn as a placeholder until a new window draws itself.
+    net_wm_icon: b"_NET_WM_ICON",
+    net_wm_name: b"_NET_WM_NAME",
+    net_wm_pid: b"_NET_WM_PID",
+    net_wm_state: b"_NET_WM_STATE",
+    net_wm_state_hidden: b"_NET_WM_STATE_HIDDEN",
+    net_wm_state_fullscreen: b"_NET_WM_STATE_FULLSCREEN",
+    /// Space reserved along the screen's edges by panels and docks.
+    net_wm_strut: b"_NET_WM_STRUT",
+    net_wm_strut_partial: b"_NET_WM_STRUT_PARTIAL",
+    /// Set by clients or tools like `transset` to make a window translucent, from 0 to
+    /// `u32::MAX`.
+    net_wm_window_opacity: b"_NET_WM_WINDOW_OPACITY",
+    net_wm_window_type: b"_NET_WM_WINDOW_TYPE",
+    net_wm_window_type_desktop: b"_NET_WM_WINDOW_TYPE_DESKTOP",
+    net_wm_window_type_dock: b"_NET_WM_WINDOW_TYPE_DOCK",
+    net_wm_window_type_dialog: b"_NET_WM_WINDOW_TYPE_DIALOG",
+    net_wm_window_type_menu: b"_NET_WM_WINDOW_TYPE_MENU",
+    net_wm_window_type_dropdown_menu: b"_NET_WM_WINDOW_TYPE_DROPDOWN_MENU",
+    net_wm_window_type_popup_menu: b"_NET_WM_WINDOW_TYPE_POPUP_MENU",
+    net_wm_window_type_tooltip: b"_NET_WM_WINDOW_TYPE_TOOLTIP",
+    net_wm_window_type_notification: b"_NET_WM_WINDOW_TYPE_NOTIFICATION",
+    net_wm_window_type_normal: b"_NET_WM_WINDOW_TYPE_NORMAL",
+    /// Motif hints, which clients still use to ask for no decorations.
+    motif_wm_hints: b"_MOTIF_WM_HINTS",
+    /// Our own property for clients to pick their corner radius with: a single CARDINAL,
+    /// in pixels, overriding [`Config::corner_radius`]. Zero asks for square corners.
+    ///
+    /// [`Config::corner_radius`]: crate::config::Config::corner_radius
+    recomp_corner_radius: b"_RECOMP_CORNER_RADIUS",
+    /// Our own property for clients to pick their blend mode with: the name of a
+    /// [`BlendMode`], as a STRING, overriding [`Config::blend_modes`].
+    ///
+    /// [`BlendMode`]: crate::window::BlendMode
+    /// [`Config::blend_modes`]: crate::config::Config::blend_modes
+    recomp_blend_mode: b"_RECOMP_BLEND_MODE",
+    utf8_string: b"UTF8_STRING",
+    /// The ICCCM `WM_STATE` property, which window managers set on client windows.
+    wm_state: b"WM_STATE",
+    wm_protocols: b"WM_PROTOCOLS",
+}
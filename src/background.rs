@@ -0,0 +1,388 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    config::{srgb_to_linear, Config, Gradient, GradientDirection},
+    monitors::Monitor,
+    pipeline::{GradientPipeline, WindowPipeline},
+    texture::{WindowTexture, WindowUniforms},
+};
+
+/// A decoded image in the same BGRA layout as window contents.
+pub struct Image {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+/// Load a binary PPM (`P6`) image.
+pub fn load_ppm(path: &Path) -> Result<Image> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_ppm(&bytes).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+fn parse_ppm(bytes: &[u8]) -> Result<Image> {
+    let mut pos = 0;
+
+    // The header is four whitespace-separated tokens, with `#` comments allowed between them.
+    let mut next_token = || -> Result<&[u8]> {
+        loop {
+            match bytes.get(pos) {
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(_) => break,
+                None => bail!("Unexpected end of header"),
+            }
+        }
+        let start = pos;
+        while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        Ok(&bytes[start..pos])
+    };
+
+    if next_token()? != b"P6" {
+        bail!("Not a binary PPM image");
+    }
+    let mut number = || -> Result<u32> {
+        let token = next_token()?;
+        Ok(std::str::from_utf8(token)?.parse()?)
+    };
+    let width = number()?;
+    let height = number()?;
+    let max = number()?;
+    if max == 0 || max > 255 {
+        bail!("Unsupported maximum value {max}");
+    }
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        bail!("Image is too large ({width}x{height})");
+    };
+
+    // Exactly one whitespace byte separates the header from the pixel data.
+    let pixels = bytes.get(pos + 1..).unwrap_or_default();
+    let len = width as usize * height as usize * 3;
+    if pixels.len() < len {
+        bail!("Image data is truncated");
+    }
+
+    let data = pixels[..len]
+        .chunks_exact(3)
+        .flat_map(|rgb| {
+            let scale = |c: u8| (c as u32 * 255 / max) as u8;
+            [scale(rgb[2]), scale(rgb[1]), scale(rgb[0]), 255]
+        })
+        .collect();
+
+    Ok(Image {
+        width,
+        height,
+        data,
+    })
+}
+
+/// Work out which image to draw on each monitor.
+///
+/// Outputs without an entry of their own in `per_monitor` use `global`, and outputs with
+/// neither are left out. Mirrored outputs (with identical rects) are only drawn once,
+/// preferring one with a specific entry. The primary monitor is placed last so that it
+/// wins where outputs overlap.
+fn assign<'a>(
+    monitors: &[Monitor],
+    per_monitor: &'a HashMap<String, PathBuf>,
+    global: Option<&'a PathBuf>,
+) -> Vec<(Rectangle, &'a PathBuf)> {
+    let mut assigned: Vec<(Rectangle, &PathBuf, bool, bool)> = Vec::new();
+
+    for monitor in monitors {
+        let specific = per_monitor.get(&monitor.name);
+        let Some(path) = specific.or(global) else {
+            continue;
+        };
+
+        match assigned.iter_mut().find(|(rect, ..)| *rect == monitor.rect) {
+            Some(mirror) => {
+                if specific.is_some() && !mirror.2 {
+                    *mirror = (monitor.rect, path, true, monitor.primary || mirror.3);
+                }
+            }
+            None => assigned.push((monitor.rect, path, specific.is_some(), monitor.primary)),
+        }
+    }
+
+    assigned.sort_by_key(|&(.., primary)| primary);
+    assigned
+        .into_iter()
+        .map(|(rect, path, ..)| (rect, path))
+        .collect()
+}
+
+/// A single monitor's wallpaper.
+struct Layer {
+    rect: Rectangle,
+    texture: WindowTexture,
+}
+
+/// A gradient covering the whole screen, along with what's needed to draw it.
+struct GradientLayer {
+    pipeline: GradientPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GradientLayer {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        gradient: &Gradient,
+    ) -> Self {
+        let pipeline = GradientPipeline::new(device, format, sample_count);
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gradient Uniforms"),
+            size: 48,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&uniforms, 0, &pack_gradient(gradient));
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gradient Bind Group"),
+            layout: pipeline.bind_group_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+        }
+    }
+}
+
+/// Pack a gradient into the layout expected by `gradient.wgsl`.
+fn pack_gradient(gradient: &Gradient) -> [u8; 48] {
+    let [r0, g0, b0] = gradient.from.map(srgb_to_linear);
+    let [r1, g1, b1] = gradient.to.map(srgb_to_linear);
+    let direction: u32 = match gradient.direction {
+        GradientDirection::Vertical => 0,
+        GradientDirection::Horizontal => 1,
+        GradientDirection::Radial => 2,
+    };
+
+    let mut bytes = [0; 48];
+    for (chunk, value) in bytes
+        .chunks_exact_mut(4)
+        .zip([r0, g0, b0, 1.0, r1, g1, b1, 1.0])
+    {
+        chunk.copy_from_slice(&value.to_ne_bytes());
+    }
+    bytes[32..36].copy_from_slice(&direction.to_ne_bytes());
+    bytes
+}
+
+/// Everything drawn underneath all windows: an optional gradient, and wallpapers on
+/// top of it, one per monitor.
+#[derive(Default)]
+pub struct Background {
+    gradient: Option<GradientLayer>,
+    layers: Vec<Layer>,
+}
+
+impl Background {
+    /// Load the configured wallpapers and upload them. Images shared between monitors are
+    /// only decoded once.
+    ///
+    /// `format` is the format of the surface being drawn to.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+        format: wgpu::TextureFormat,
+        config: &Config,
+        monitors: &[Monitor],
+    ) -> Result<Self> {
+        let gradient = config.gradient.as_ref().map(|gradient| {
+            GradientLayer::new(device, queue, format, pipeline.sample_count(), gradient)
+        });
+
+        let mut images: HashMap<&PathBuf, Image> = HashMap::new();
+        let mut layers = Vec::new();
+
+        let assignments = assign(
+            monitors,
+            &config.monitor_backgrounds,
+            config.background.as_ref(),
+        );
+        for (rect, path) in assignments {
+            let image = match images.entry(path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(load_ppm(path)?),
+            };
+
+            let texture = WindowTexture::new(
+                device,
+                pipeline.bind_group_layout(),
+                pipeline.sampler(),
+                image.width,
+                image.height,
+            );
+            texture.write(
+                queue,
+                Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: image.width,
+                    height: image.height,
+                },
+                &image.data,
+            );
+            layers.push(Layer { rect, texture });
+        }
+
+        Ok(Self { gradient, layers })
+    }
+
+    /// Update each layer's uniforms for the current output size.
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, screen: (u32, u32)) {
+        for layer in &self.layers {
+            layer.texture.write_uniforms(
+                queue,
+                &WindowUniforms {
+                    geometry: layer.rect,
+                    screen,
+                    dim: 0.0,
+                    opacity: 1.0,
+                    has_alpha: false,
+                    straight_alpha: false,
+                    corner_radius: 0.0,
+                    corner_smoothing: 0.0,
+                    corner_aa_width: 1.0,
+                },
+            );
+        }
+    }
+
+    /// Draw the gradient, then each monitor's wallpaper, stretched to fill and clipped to
+    /// that monitor.
+    /// Estimated GPU memory used by the wallpaper textures.
+    pub fn texture_bytes(&self) -> u64 {
+        self.layers
+            .iter()
+            .map(|layer| layer.texture.size_bytes())
+            .sum()
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a WindowPipeline,
+        screen: (u32, u32),
+    ) {
+        if let Some(gradient) = &self.gradient {
+            pass.set_pipeline(gradient.pipeline.pipeline());
+            pass.set_bind_group(0, &gradient.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        pass.set_pipeline(pipeline.pipeline());
+        for layer in &self.layers {
+            let Some((x, y, width, height)) = clip(layer.rect, screen) else {
+                continue;
+            };
+            pass.set_scissor_rect(x, y, width, height);
+            pass.set_bind_group(0, layer.texture.bind_group(), &[]);
+            pass.draw(0..6, 0..1);
+        }
+        pass.set_scissor_rect(0, 0, screen.0, screen.1);
+    }
+}
+
+/// Clip `rect` to the screen, returning it as a scissor rect.
+fn clip(rect: Rectangle, screen: (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    let x0 = (rect.x as i64).clamp(0, screen.0 as i64);
+    let y0 = (rect.y as i64).clamp(0, screen.1 as i64);
+    let x1 = (rect.x as i64 + rect.width as i64).clamp(0, screen.0 as i64);
+    let y1 = (rect.y as i64 + rect.height as i64).clamp(0, screen.1 as i64);
+    (x1 > x0 && y1 > y0).then(|| (x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i16, primary: bool) -> Monitor {
+        Monitor {
+            name: name.to_owned(),
+            rect: Rectangle {
+                x,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            },
+            primary,
+            size_mm: (0, 0),
+            refresh_mhz: None,
+        }
+    }
+
+    #[test]
+    fn parses_ppm_with_comments() {
+        let mut bytes = b"P6\n# a comment\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[255, 0, 0, 0, 128, 255]);
+
+        let image = parse_ppm(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.data, [0, 0, 255, 255, 255, 128, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_truncated_ppm() {
+        assert!(parse_ppm(b"P6 2 2 255\n\0\0\0").is_err());
+        assert!(parse_ppm(b"P3 1 1 255\n0 0 0").is_err());
+    }
+
+    #[test]
+    fn assigns_specific_and_global_backgrounds() {
+        let global = PathBuf::from("global.ppm");
+        let left = PathBuf::from("left.ppm");
+        let per_monitor = HashMap::from([("DP-1".to_owned(), left.clone())]);
+        let monitors = [monitor("HDMI-1", 1920, true), monitor("DP-1", 0, false)];
+
+        let assigned = assign(&monitors, &per_monitor, Some(&global));
+        assert_eq!(
+            assigned,
+            [(monitors[1].rect, &left), (monitors[0].rect, &global)]
+        );
+
+        assert_eq!(
+            assign(&monitors, &per_monitor, None),
+            [(monitors[1].rect, &left)]
+        );
+    }
+
+    #[test]
+    fn mirrored_monitors_prefer_specific_background() {
+        let global = PathBuf::from("global.ppm");
+        let mirror = PathBuf::from("mirror.ppm");
+        let per_monitor = HashMap::from([("DP-2".to_owned(), mirror.clone())]);
+        let monitors = [monitor("DP-1", 0, true), monitor("DP-2", 0, false)];
+
+        assert_eq!(
+            assign(&monitors, &per_monitor, Some(&global)),
+            [(monitors[0].rect, &mirror)]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! The border, and optional glow, drawn around the active window.
+
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{config::ActiveBorder, pipeline::BorderPipeline};
+
+/// Size of the uniform buffer for `border.wgsl`.
+const BORDER_UNIFORMS_SIZE: u64 = 64;
+
+/// Everything `border.wgsl` needs to draw a border around one window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BorderUniforms {
+    /// Where the window is drawn, in screen pixels. The border goes around the outside.
+    pub geometry: Rectangle,
+    /// Size of the output in pixels.
+    pub screen: (u32, u32),
+    pub border: ActiveBorder,
+    /// The window's rounded corners, which the border follows.
+    pub corner_radius: f32,
+    pub corner_smoothing: f32,
+    /// How opaque to draw the border, from 0 to 1, e.g. to fade it with its window.
+    pub opacity: f32,
+    /// How wide the border's edges fade out over, in pixels, matching the window's.
+    pub corner_aa_width: f32,
+}
+
+impl BorderUniforms {
+    /// Everything the border can draw over, which is as far out as `border.wgsl` grows
+    /// the window's rectangle.
+    pub fn extent(&self) -> Rectangle {
+        let reach = (self.border.width + self.border.glow_radius + 1.0).ceil() as i16;
+        Rectangle {
+            x: self.geometry.x - reach,
+            y: self.geometry.y - reach,
+            width: self.geometry.width + 2 * reach as u16,
+            height: self.geometry.height + 2 * reach as u16,
+        }
+    }
+
+    /// Pack the uniforms into the layout expected by `border.wgsl`.
+    pub fn pack(&self) -> [u8; BORDER_UNIFORMS_SIZE as usize] {
+        let color = self.border.color;
+        let values = [
+            self.geometry.x as f32,
+            self.geometry.y as f32,
+            self.geometry.width as f32,
+            self.geometry.height as f32,
+            color.r as f32,
+            color.g as f32,
+            color.b as f32,
+            color.a as f32,
+            self.screen.0 as f32,
+            self.screen.1 as f32,
+            self.border.width,
+            self.border.glow_radius,
+            self.corner_radius,
+            self.corner_smoothing,
+            self.opacity,
+            self.corner_aa_width,
+        ];
+
+        let mut bytes = [0; BORDER_UNIFORMS_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+/// The pipeline and uniforms for drawing the active window's border.
+pub struct BorderLayer {
+    pipeline: BorderPipeline,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl BorderLayer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self::with_samples(device, format, 1)
+    }
+
+    /// A layer for drawing into targets with `sample_count` samples per pixel.
+    pub fn with_samples(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline = BorderPipeline::new(device, format, sample_count);
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Border Uniforms"),
+            size: BORDER_UNIFORMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Border Bind Group"),
+            layout: pipeline.bind_group_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            uniforms,
+            bind_group,
+        }
+    }
+
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, uniforms: &BorderUniforms) {
+        queue.write_buffer(&self.uniforms, 0, &uniforms.pack());
+    }
+
+    /// Draw the border with the last uniforms written. This switches the pass to the
+    /// border pipeline, so whatever draws next has to set its own again.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(self.pipeline.pipeline());
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_uniforms_in_shader_order() {
+        let uniforms = BorderUniforms {
+            geometry: Rectangle {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200,
+            },
+            screen: (1920, 1080),
+            border: ActiveBorder {
+                width: 2.0,
+                color: wgpu::Color {
+                    r: 0.25,
+                    g: 0.5,
+                    b: 0.75,
+                    a: 1.0,
+                },
+                glow_radius: 8.0,
+            },
+            corner_radius: 6.0,
+            corner_smoothing: 0.5,
+            opacity: 0.9,
+            corner_aa_width: 1.5,
+        };
+        let floats: Vec<f32> = uniforms
+            .pack()
+            .chunks_exact(4)
+            .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        assert_eq!(
+            floats,
+            [
+                10.0, 20.0, 300.0, 200.0, 0.25, 0.5, 0.75, 1.0, 1920.0, 1080.0, 2.0, 8.0, 6.0, 0.5,
+                0.9, 1.5
+            ]
+        );
+    }
+
+    #[test]
+    fn reaches_past_the_glow() {
+        let uniforms = BorderUniforms {
+            geometry: Rectangle {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200,
+            },
+            screen: (1920, 1080),
+            border: ActiveBorder {
+                width: 2.0,
+                glow_radius: 4.5,
+                ..Default::default()
+            },
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            opacity: 1.0,
+            corner_aa_width: 1.0,
+        };
+        assert_eq!(
+            uniforms.extent(),
+            Rectangle {
+                x: 2,
+                y: 12,
+                width: 316,
+                height: 216,
+            }
+        );
+    }
+}
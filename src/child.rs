@@ -0,0 +1,56 @@
+//! Running a program under the compositor, for kiosk-like sessions that show a single
+//! application.
+
+use std::process::{ExitStatus, Stdio};
+
+use anyhow::{Context, Result};
+use tokio::process::{Child, Command};
+use tracing::info;
+
+/// Start `command` with the shell, as a client of `display` if one was given rather
+/// than whatever `$DISPLAY` says.
+pub fn spawn(command: &str, display: Option<&str>) -> Result<Child> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command).stdin(Stdio::null());
+    if let Some(display) = display {
+        cmd.env("DISPLAY", display);
+    }
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run {command:?}"))?;
+    info!("Started {:?} as process {:?}", command, child.id());
+    Ok(child)
+}
+
+/// The exit code to pass on for a child that exited with `status`. Like shells, a child
+/// killed by a signal gives 128 plus the signal's number.
+pub fn exit_code(status: ExitStatus) -> u8 {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code as u8,
+        (None, Some(signal)) => 128u8.wrapping_add(signal as u8),
+        (None, None) => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    use super::*;
+
+    #[test]
+    fn passes_on_exit_codes() {
+        // Wait statuses keep the exit code in the second byte and the signal in the first.
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), 137);
+    }
+
+    #[tokio::test]
+    async fn runs_commands_with_the_shell() {
+        let mut child = spawn("exit 7", None).unwrap();
+        assert_eq!(exit_code(child.wait().await.unwrap()), 7);
+    }
+}
@@ -0,0 +1,752 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::{
+        self, srgb_to_linear, ActiveBorder, AdapterMatch, Config, Gradient, GradientDirection,
+        Mirror, WindowMatch,
+    },
+    keys::KeyBinding,
+    logging::LogOptions,
+    watchdog::{StallAction, WatchdogConfig},
+};
+
+/// Stall threshold for `--watchdog-abort` without `--watchdog`.
+const DEFAULT_WATCHDOG_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Frame rate for `--stream` without `--stream-fps`.
+#[cfg(feature = "capture-stream")]
+const DEFAULT_STREAM_FPS: u32 = 30;
+
+/// Command-line arguments.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// The X display to connect to. Defaults to `$DISPLAY`.
+    pub display: Option<String>,
+    pub config: Config,
+    pub log: LogOptions,
+    /// Write every X event to this file, for replaying later.
+    pub record: Option<PathBuf>,
+    /// Take X events from this file instead of the X server.
+    pub replay: Option<PathBuf>,
+    /// Composite inside a new window of this size instead of the whole screen.
+    pub nested: Option<(u16, u16)>,
+    /// Composite a single frame of the windows as they are and exit.
+    pub once: bool,
+    /// Describe the available GPU adapters and what they can present with, and exit.
+    pub list_adapters: bool,
+    /// Check the configuration, print it with defaults filled in, and exit.
+    pub check_config: bool,
+    /// A shell command to run once the compositor is up.
+    pub exec: Option<String>,
+    /// Exit along with the `exec` command, with its exit code.
+    pub exit_with_child: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        #[cfg(feature = "capture-stream")]
+        let mut stream_fps = None;
+        #[cfg(feature = "hdr")]
+        let mut sdr_white = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--display" | "-d" => {
+                    let Some(display) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.display = Some(display);
+                }
+                "--log-format" => {
+                    let Some(format) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.log.format = Some(
+                        format
+                            .parse()
+                            .with_context(|| format!("Invalid value for {arg}: {format}"))?,
+                    );
+                }
+                "--log-file" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.log.file = Some(path.into());
+                }
+                "--perf" => parsed.log.perf = Some(true),
+                "--no-perf" => parsed.log.perf = Some(false),
+                "--record" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.record = Some(path.into());
+                }
+                "--replay" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.replay = Some(path.into());
+                }
+                "--once" => parsed.once = true,
+                "--list-adapters" => parsed.list_adapters = true,
+                "--check-config" => parsed.check_config = true,
+                "--icon-placeholders" => parsed.config.icon_placeholders = true,
+                "--exec" => {
+                    let Some(command) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.exec = Some(command);
+                }
+                "--exit-with-child" => parsed.exit_with_child = true,
+                "--nested" => {
+                    let Some(size) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.nested = Some(
+                        parse_size(&size)
+                            .with_context(|| format!("Invalid value for {arg}: {size}"))?,
+                    );
+                }
+                "--bypass" => parsed.config.bypass = true,
+                "--debug-damage" => parsed.config.debug_damage = true,
+                "--grab-server" => parsed.config.grab_server = true,
+                "--pause-on-vt-switch" => parsed.config.pause_on_vt_switch = true,
+                "--force-effects" => parsed.config.force_effects = true,
+                "--manual-redirect" => parsed.config.manual_redirect = true,
+                "--prefetch-atom" => {
+                    let Some(name) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.prefetch_atoms.push(name);
+                }
+                "--idle-timeout" => {
+                    let Some(secs) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let secs: f64 = secs
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {secs}"))?;
+                    // Zero disables idling entirely.
+                    parsed.config.idle_timeout =
+                        (secs > 0.0).then(|| Duration::from_secs_f64(secs));
+                }
+                "--startup-delay" => {
+                    let Some(secs) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let secs: f64 = secs
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {secs}"))?;
+                    parsed.config.startup_delay =
+                        (secs > 0.0).then(|| Duration::from_secs_f64(secs));
+                }
+                "--wait-for-wm" => {
+                    let Some(secs) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let secs: f64 = secs
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {secs}"))?;
+                    parsed.config.wait_for_wm = (secs > 0.0).then(|| Duration::from_secs_f64(secs));
+                }
+                "--damage-driven" => parsed.config.damage_driven = true,
+                "--cull-occluded" => parsed.config.cull_occluded = true,
+                "--idle-refresh" => {
+                    let Some(ms) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let ms: u64 = ms
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {ms}"))?;
+                    parsed.config.idle_refresh = (ms > 0).then(|| Duration::from_millis(ms));
+                }
+                "--animate-geometry" => {
+                    let Some(ms) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let ms: u64 = ms
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {ms}"))?;
+                    parsed.config.geometry_animation = (ms > 0).then(|| Duration::from_millis(ms));
+                }
+                "--easing" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((animation, easing)) = value.split_once('=') else {
+                        bail!("{arg} expects ANIMATION=EASING, got {value}");
+                    };
+                    let easing = easing
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                    parsed
+                        .config
+                        .easings
+                        .set(animation, easing)
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                }
+                "--inactive-dim" => {
+                    let Some(dim) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let dim: f32 = dim
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {dim}"))?;
+                    parsed.config.inactive_dim = dim.clamp(0.0, 1.0);
+                }
+                "--scale" => {
+                    let Some(scale) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let scale: f32 = scale
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {scale}"))?;
+                    if !(scale > 0.0 && scale.is_finite()) {
+                        bail!("{arg} must be positive, got {scale}");
+                    }
+                    parsed.config.global_scale = scale;
+                }
+                "--render-scale" => {
+                    let Some(scale) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let scale: f32 = scale
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {scale}"))?;
+                    if !(scale > 0.0 && scale <= 1.0) {
+                        bail!("{arg} must be greater than 0 and at most 1, got {scale}");
+                    }
+                    parsed.config.render_scale = scale;
+                }
+                "--msaa" => {
+                    let Some(samples) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.msaa_samples = samples
+                        .parse()
+                        .ok()
+                        .filter(|samples: &u32| [1, 2, 4, 8].contains(samples))
+                        .with_context(|| format!("Invalid value for {arg}: {samples}"))?;
+                }
+                "--active-border" => {
+                    let Some(width) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let width: f32 = width
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {width}"))?;
+                    parsed.config.active_border = (width > 0.0).then(|| ActiveBorder {
+                        width,
+                        ..parsed.config.active_border.unwrap_or_default()
+                    });
+                }
+                "--active-border-color" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let border = parsed
+                        .config
+                        .active_border
+                        .get_or_insert_with(Default::default);
+                    border.color = parse_clear_color(&value)
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                }
+                "--active-border-glow" => {
+                    let Some(radius) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let radius: f32 = radius
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {radius}"))?;
+                    let border = parsed
+                        .config
+                        .active_border
+                        .get_or_insert_with(Default::default);
+                    border.glow_radius = radius.max(0.0);
+                }
+                "--tint" => parsed.config.tint_enabled = true,
+                "--tint-color" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.tint.color = parse_color(&value)
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                }
+                "--tint-opacity" => {
+                    let Some(opacity) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let opacity: f32 = opacity
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {opacity}"))?;
+                    parsed.config.tint.opacity = opacity.clamp(0.0, 1.0);
+                }
+                "--corner-radius" => {
+                    let Some(radius) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let radius: f32 = radius
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {radius}"))?;
+                    parsed.config.corner_radius = radius.max(0.0);
+                }
+                "--corner-smoothing" => {
+                    let Some(smoothing) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let smoothing: f32 = smoothing
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {smoothing}"))?;
+                    parsed.config.corner_smoothing = smoothing.clamp(0.0, 1.0);
+                }
+                "--corner-aa-width" => {
+                    let Some(width) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let width: f32 = width
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {width}"))?;
+                    parsed.config.corner_aa_width = width.max(0.0);
+                }
+                "--exclude-id" => {
+                    let Some(id) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let parsed_id = match id.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => id.parse(),
+                    }
+                    .with_context(|| format!("Invalid value for {arg}: {id}"))?;
+                    parsed.config.exclude.push(WindowMatch::Id(parsed_id));
+                }
+                "--exclude-name" => {
+                    let Some(pattern) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.exclude.push(WindowMatch::Name(pattern));
+                }
+                "--exclude-class" => {
+                    let Some(pattern) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.exclude.push(WindowMatch::Class(pattern));
+                }
+                "--straight-alpha" => {
+                    let Some(pattern) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed
+                        .config
+                        .straight_alpha
+                        .push(WindowMatch::Class(pattern));
+                }
+                "--blend-mode" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((class, mode)) = value.split_once('=') else {
+                        bail!("{arg} expects CLASS=MODE, got {value}");
+                    };
+                    let mode = mode
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                    parsed
+                        .config
+                        .blend_modes
+                        .push((WindowMatch::Class(class.to_owned()), mode));
+                }
+                "--output" => {
+                    let Some(output) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.output = Some(output);
+                }
+                "--mirror" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((source, destination)) = value.split_once('=') else {
+                        bail!("{arg} expects SOURCE=DESTINATION, got {value}");
+                    };
+                    parsed.config.mirror = Some(Mirror {
+                        source: source.to_owned(),
+                        destination: destination.to_owned(),
+                    });
+                }
+                "--watchdog" => {
+                    let Some(ms) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let ms: u64 = ms
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {ms}"))?;
+                    let action = parsed
+                        .config
+                        .watchdog
+                        .map(|watchdog| watchdog.action)
+                        .unwrap_or_default();
+                    parsed.config.watchdog = (ms > 0).then(|| WatchdogConfig {
+                        threshold: Duration::from_millis(ms),
+                        action,
+                    });
+                }
+                "--watchdog-abort" => {
+                    let watchdog = parsed.config.watchdog.get_or_insert(WatchdogConfig {
+                        threshold: DEFAULT_WATCHDOG_THRESHOLD,
+                        action: StallAction::Abort,
+                    });
+                    watchdog.action = StallAction::Abort;
+                }
+                #[cfg(feature = "capture-stream")]
+                "--stream" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.stream = Some(crate::stream::StreamConfig {
+                        path: path.into(),
+                        fps: DEFAULT_STREAM_FPS,
+                    });
+                }
+                #[cfg(feature = "capture-stream")]
+                "--stream-fps" => {
+                    let Some(fps) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    stream_fps = Some(
+                        fps.parse()
+                            .ok()
+                            .filter(|&fps| fps > 0)
+                            .with_context(|| format!("Invalid value for {arg}: {fps}"))?,
+                    );
+                }
+                #[cfg(feature = "hdr")]
+                "--hdr" => parsed.config.hdr = Some(crate::hdr::HdrConfig::default()),
+                #[cfg(feature = "hdr")]
+                "--sdr-white" => {
+                    let Some(nits) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    sdr_white = Some(
+                        nits.parse()
+                            .ok()
+                            .filter(|&nits: &f32| nits > 0.0)
+                            .with_context(|| format!("Invalid value for {arg}: {nits}"))?,
+                    );
+                }
+                "--damage-merge-distance" => {
+                    let Some(distance) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.damage.merge_distance = distance
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {distance}"))?;
+                }
+                "--window-full-upload-ratio" => {
+                    let Some(ratio) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let ratio: f32 = ratio
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {ratio}"))?;
+                    parsed.config.damage.window_full_upload_ratio = ratio.clamp(0.0, 1.0);
+                }
+                "--adapter" => {
+                    let Some(name) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.adapter = Some(AdapterMatch::Name(name));
+                }
+                "--present" => {
+                    let Some(backend) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.present = backend
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {backend}"))?;
+                }
+                "--present-mode" => {
+                    let Some(mode) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.present_mode = mode
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {mode}"))?;
+                }
+                "--frame-pacing" => {
+                    let Some(pacing) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.frame_pacing = pacing
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {pacing}"))?;
+                }
+                "--max-frames-in-flight" => {
+                    let Some(frames) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.max_frames_in_flight = frames
+                        .parse()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .with_context(|| format!("Invalid value for {arg}: {frames}"))?;
+                }
+                "--max-events-per-frame" => {
+                    let Some(events) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let events: usize = events
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {events}"))?;
+                    parsed.config.max_events_per_frame = (events > 0).then_some(events);
+                }
+                "--max-window-textures" => {
+                    let Some(textures) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let textures: usize = textures
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {textures}"))?;
+                    parsed.config.max_window_textures = (textures > 0).then_some(textures);
+                }
+                "--frame-budget" => {
+                    let Some(ms) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let ms: f64 = ms
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {ms}"))?;
+                    // Zero never turns effects off.
+                    parsed.config.frame_budget =
+                        (ms > 0.0).then(|| Duration::from_secs_f64(ms / 1000.0));
+                }
+                "--surface-format" => {
+                    let Some(format) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.surface_format = Some(
+                        config::parse_texture_format(&format)
+                            .with_context(|| format!("Invalid value for {arg}: {format}"))?,
+                    );
+                }
+                "--adapter-id" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.adapter = Some(
+                        parse_pci_id(&value)
+                            .with_context(|| format!("Invalid value for {arg}: {value}"))?,
+                    );
+                }
+                "--extension-version" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((name, version)) = value.split_once('=') else {
+                        bail!("{arg} expects NAME=MAJOR.MINOR, got {value}");
+                    };
+                    let version = version
+                        .parse()
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                    parsed
+                        .config
+                        .extension_versions
+                        .set(name, version)
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                }
+                "--bind" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((combo, command)) = value.split_once('=') else {
+                        bail!("{arg} expects KEYS=COMMAND, got {value}");
+                    };
+                    parsed.config.key_bindings.push(KeyBinding {
+                        combo: combo
+                            .parse()
+                            .with_context(|| format!("Invalid value for {arg}: {value}"))?,
+                        command: command
+                            .parse()
+                            .with_context(|| format!("Invalid value for {arg}: {value}"))?,
+                    });
+                }
+                "--clear-color" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.clear_color = parse_clear_color(&value)
+                        .with_context(|| format!("Invalid value for {arg}: {value}"))?;
+                }
+                "--gradient" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.gradient = Some(
+                        parse_gradient(&value)
+                            .with_context(|| format!("Invalid value for {arg}: {value}"))?,
+                    );
+                }
+                "--background" => {
+                    let Some(path) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    parsed.config.background = Some(path.into());
+                }
+                "--monitor-background" => {
+                    let Some(value) = args.next() else {
+                        bail!("{arg} requires a value");
+                    };
+                    let Some((output, path)) = value.split_once('=') else {
+                        bail!("{arg} expects OUTPUT=PATH, got {value}");
+                    };
+                    parsed
+                        .config
+                        .monitor_backgrounds
+                        .insert(output.to_owned(), path.into());
+                }
+                _ => bail!("Unknown argument: {arg}"),
+            }
+        }
+
+        if parsed.record.is_some() && parsed.replay.is_some() {
+            bail!("--record and --replay can't be used together");
+        }
+        #[cfg(feature = "capture-stream")]
+        if let Some(fps) = stream_fps {
+            match &mut parsed.config.stream {
+                Some(stream) => stream.fps = fps,
+                None => bail!("--stream-fps needs --stream"),
+            }
+        }
+        #[cfg(feature = "hdr")]
+        if let Some(nits) = sdr_white {
+            match &mut parsed.config.hdr {
+                Some(hdr) => hdr.sdr_white_nits = nits,
+                None => bail!("--sdr-white needs --hdr"),
+            }
+        }
+        #[cfg(feature = "hdr")]
+        if parsed.config.hdr.is_some() && parsed.config.surface_format.is_some() {
+            bail!("--hdr can't be used with --surface-format");
+        }
+        // Streamed frames are 8-bit sRGB, which HDR frames can't be copied out as.
+        #[cfg(all(feature = "hdr", feature = "capture-stream"))]
+        if parsed.config.hdr.is_some() && parsed.config.stream.is_some() {
+            bail!("--hdr can't be used with --stream");
+        }
+        if parsed.once && (parsed.record.is_some() || parsed.replay.is_some()) {
+            bail!("--once can't be used with --record or --replay");
+        }
+        if parsed.exec.is_some() && (parsed.once || parsed.replay.is_some()) {
+            bail!("--exec can't be used with --once or --replay");
+        }
+        if parsed.config.mirror.is_some() && parsed.config.output.is_some() {
+            bail!("--mirror can't be used with --output");
+        }
+        if parsed.exit_with_child && parsed.exec.is_none() {
+            bail!("--exit-with-child needs --exec");
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Parse a size given as `WIDTHxHEIGHT`, like `1280x720`.
+fn parse_size(value: &str) -> Result<(u16, u16)> {
+    let Some((width, height)) = value.split_once('x') else {
+        bail!("expected WIDTHxHEIGHT");
+    };
+    let (width, height): (u16, u16) = (width.parse()?, height.parse()?);
+    if width == 0 || height == 0 {
+        bail!("the size can't be empty");
+    }
+    Ok((width, height))
+}
+
+/// Parse a PCI id given as `VENDOR[:DEVICE]` in hex, like `1002:744c`.
+fn parse_pci_id(value: &str) -> Result<AdapterMatch> {
+    let hex = |id: &str| {
+        u32::from_str_radix(id.strip_prefix("0x").unwrap_or(id), 16)
+            .with_context(|| format!("expected a hex id, got {id}"))
+    };
+    Ok(match value.split_once(':') {
+        Some((vendor, device)) => AdapterMatch::PciId {
+            vendor: hex(vendor)?,
+            device: Some(hex(device)?),
+        },
+        None => AdapterMatch::PciId {
+            vendor: hex(value)?,
+            device: None,
+        },
+    })
+}
+
+/// Parse a gradient given as `FROM,TO[,DIRECTION]`, with hex colors like `#1e1e2e`.
+fn parse_gradient(value: &str) -> Result<Gradient> {
+    let mut parts = value.split(',');
+    let (Some(from), Some(to)) = (parts.next(), parts.next()) else {
+        bail!("expected FROM,TO[,DIRECTION]");
+    };
+    let direction = match parts.next() {
+        None | Some("vertical") => GradientDirection::Vertical,
+        Some("horizontal") => GradientDirection::Horizontal,
+        Some("radial") => GradientDirection::Radial,
+        Some(other) => bail!("unknown direction {other}"),
+    };
+    if parts.next().is_some() {
+        bail!("expected FROM,TO[,DIRECTION]");
+    }
+
+    Ok(Gradient {
+        from: parse_color(from)?,
+        to: parse_color(to)?,
+        direction,
+    })
+}
+
+/// Parse a `#rrggbb` color into sRGB components from 0 to 1.
+fn parse_color(value: &str) -> Result<[f32; 3]> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.is_ascii() {
+        bail!("expected a color like #rrggbb, got {value}");
+    }
+    let channel = |i: usize| -> Result<f32> {
+        Ok(u8::from_str_radix(&hex[i..i + 2], 16)
+            .with_context(|| format!("expected a color like #rrggbb, got {value}"))?
+            as f32
+            / 255.0)
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Parse a color given as `#rrggbb`, `#rrggbbaa`, or `transparent`.
+fn parse_clear_color(value: &str) -> Result<wgpu::Color> {
+    if value == "transparent" {
+        return Ok(wgpu::Color::TRANSPARENT);
+    }
+
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let (rgb, alpha) = match hex.len() {
+        8 if hex.is_ascii() => (&hex[..6], &hex[6..]),
+        _ => (hex, "ff"),
+    };
+    let [r, g, b] = parse_color(rgb)?.map(|c| srgb_to_linear(c) as f64);
+    let a = u8::from_str_radix(alpha, 16)
+        .with_context(|| format!("expected a color like #rrggbbaa, got {value}"))?
+        as f64
+        / 255.0;
+
+    Ok(wgpu::Color {
+        r: r * a,
+        g: g * a,
+        b: b * a,
+        a,
+    })
+}
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+/// An action that can be triggered at runtime, e.g. from a key binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Show or hide all windows in a grid.
+    ToggleExpose,
+    /// Show all windows in a grid for as long as the key is held.
+    HoldExpose,
+    /// Switch between compositing normally and presenting an empty frame.
+    ToggleBypass,
+    /// Start or stop tinting damaged regions, in debug builds.
+    ToggleDamageOverlay,
+    /// Keep showing the last frame until [`Command::Unfreeze`], e.g. for screenshots of
+    /// menus that close as soon as they lose focus.
+    Freeze,
+    /// Go back to rendering after [`Command::Freeze`].
+    Unfreeze,
+    /// Freeze or unfreeze, whichever isn't the case.
+    ToggleFreeze,
+    /// Fade the tint over the whole screen in or out.
+    ToggleTint,
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "toggle-expose" => Command::ToggleExpose,
+            "hold-expose" => Command::HoldExpose,
+            "toggle-bypass" => Command::ToggleBypass,
+            "toggle-damage-overlay" => Command::ToggleDamageOverlay,
+            "freeze" => Command::Freeze,
+            "unfreeze" => Command::Unfreeze,
+            "toggle-freeze" => Command::ToggleFreeze,
+            "toggle-tint" => Command::ToggleTint,
+            _ => bail!("unknown command {s}"),
+        })
+    }
+}
@@ -1,359 +1,4482 @@
-use std::{ffi::CString, sync::Arc};
-
-use anyhow::Result;
-use tracing::{info, warn};
-use wgpu::{
-    rwh::{RawDisplayHandle, RawWindowHandle, XcbDisplayHandle, XcbWindowHandle},
-    SurfaceTargetUnsafe,
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write as _,
+    ops::ControlFlow,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use x11rb::protocol::{
-    composite::Redirect,
-    shape::SK,
-    xproto::{self, CreateWindowAux, WindowClass},
+
+use anyhow::{Context as _, Result};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, trace, trace_span, warn};
+use x11rb::{
+    errors::{ConnectionError, ReplyError},
+    protocol::{
+        composite::Redirect,
+        damage::ReportLevel,
+        present::CompleteNotifyEvent,
+        randr::NotifyMask,
+        shape::SK,
+        xfixes::{CursorNotifyMask, SelectionEventMask},
+        xproto::{
+            self, AtomEnum, ChangeWindowAttributesAux, ConfigureWindowAux, CreateWindowAux,
+            EventMask, ImageFormat, WindowClass,
+        },
+    },
+    xcb_ffi::XCBConnection,
 };
 use x11rb_async::{
     connection::Connection,
     protocol::{
-        composite::ConnectionExt as _, damage::ConnectionExt, xfixes::ConnectionExt as _,
-        xproto::ConnectionExt as _,
+        composite::ConnectionExt as _, damage::ConnectionExt, randr::ConnectionExt as _,
+        xfixes::ConnectionExt as _, xproto::ConnectionExt as _,
+    },
+};
+
+use crate::{
+    animation::{AnimationManager, Appearance},
+    atoms::Atoms,
+    background::Background,
+    border::{BorderLayer, BorderUniforms},
+    command::Command,
+    config::{
+        ActiveBorder, AdapterMatch, Config, FramePacing, Mirror, PresentBackend, Tint, WindowMatch,
+        WindowProps,
     },
+    connection::{self, XConn},
+    cursor::CursorImage,
+    display_timing::{select_complete_notify, DisplayTimer},
+    error::{is_window_gone, Error},
+    events::EventSource,
+    eviction,
+    expose::{self, EXPOSE_DURATION},
+    extensions::{negotiate_extensions, RequestedVersions},
+    frames::FramesInFlight,
+    hooks::{
+        DisplayHook, DisplayInfo, FrameInfo, PresentHook, PresentInfo, RenderContext, RenderHook,
+    },
+    icon,
+    image::ImageLayout,
+    input::{HeldInputs, Input, InputEvent},
+    keys::{self, KeyGrabs},
+    monitors::{self, query_monitors, Monitor},
+    notify::{self, CompositorEvent, EventStream},
+    occlusion::{self, Layer},
+    overlay::{OverlayRectId, OverlayRects},
+    pipeline::{self, WindowPipeline},
+    power::{IdleTracker, PowerState},
+    present::{self, create_surface, DbePresenter, OverlayPresenter, PresentTarget},
+    quality::{QualityController, Transition},
+    scaled::ScaledTarget,
+    snapshot::Snapshot,
+    stats::{self, FrameStats, ResourceStats},
+    texture::{self, WindowTexture, WindowUniforms},
+    thumbnail::{self, Thumbnail},
+    tint::TintLayer,
+    trace::{Recorder, Replay},
+    vt::{self, VtWatcher},
+    watchdog::Watchdog,
+    window::{self, AlphaMode, BlendMode, Strut, WindowInfo, WindowState, WmState},
 };
 
-use crate::connection::XConn;
+/// Configures closer together than this are assumed to come from the user interactively
+/// moving or resizing a window.
+const INTERACTIVE_CONFIGURE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to look for a window manager while waiting for one at startup.
+const WM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait before trying again after skipping a frame because the GPU was busy.
+const BACKPRESSURE_RETRY: Duration = Duration::from_millis(1);
+
+/// How long the tint takes to fade in or out.
+const TINT_FADE: Duration = Duration::from_millis(200);
+
+/// What the tint's fade is animated as. The animation manager animates windows, and this
+/// is never one, since X doesn't give any window the ID 0.
+const TINT_ANIMATION: xproto::Window = x11rb::NONE;
 
 pub struct Compositor<'a> {
     conn: XConn,
+    /// The window whose children we composite: the screen's root, or our own container
+    /// when nested.
     root_win: xproto::Window,
+    /// The window we render into: the composite overlay, or our own output window when
+    /// nested.
     overlay_win: xproto::Window,
-    #[allow(unused)]
+    /// Where the overlay receives input, set again whenever the server may have dropped it.
+    overlay_input: Vec<xproto::Rectangle>,
     root_size: (u16, u16),
-    surface: wgpu::Surface<'a>,
+    /// The windows we created to composite inside of, if nested.
+    nested: Option<NestedWindows>,
+    presenter: Box<dyn PresentTarget + 'a>,
+    /// Kept to present to a new overlay window after [`Self::resume`].
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// Set by the device-lost callback when the driver resets or the GPU goes away, so
+    /// that the event loop can recreate the device.
+    device_lost: Arc<AtomicBool>,
+    /// The frames the GPU is still working on. Once there are as many as configured,
+    /// frames are skipped until one is done, so they can't pile up faster than the GPU
+    /// can draw them.
+    frames_in_flight: FramesInFlight,
     config: wgpu::SurfaceConfiguration,
+    options: Config,
+    pipeline: WindowPipeline,
+    /// All windows we're currently tracking, keyed by their X id.
+    windows: HashMap<xproto::Window, WindowState>,
+    /// Tracked windows in stacking order, bottom to top.
+    stack: Vec<xproto::Window>,
+    stats: FrameStats,
+    /// Events taken from the event source that haven't been handled yet, because the
+    /// per-frame budget ran out, along with when they were taken.
+    pending_events: VecDeque<(x11rb::protocol::Event, Instant)>,
+    /// Total number of frames rendered.
+    frame_count: u64,
+    /// Whether anything has changed since the last frame was rendered.
+    needs_frame: bool,
+    /// When the last frame was presented.
+    last_frame: Instant,
+    /// Watches for the event loop getting stuck, from the first step until it stops.
+    watchdog: Option<Watchdog>,
+    /// How long to leave between frames when pacing them with a timer.
+    frame_interval: Option<Duration>,
+    /// When the next frame is due, when pacing frames with a timer.
+    next_frame: Instant,
+    pre_render: Option<RenderHook>,
+    post_render: Option<RenderHook>,
+    on_present: Option<PresentHook>,
+    on_display: Option<DisplayHook>,
+    /// Where [`Compositor::events`] subscribers get events from.
+    events: broadcast::Sender<CompositorEvent>,
+    /// Tracks when frames reached the screen, if the server has the Present extension.
+    display_timer: Option<DisplayTimer>,
+    idle: IdleTracker,
+    atoms: Atoms,
+    /// The selection we hold as the screen's compositing manager, unless nested.
+    cm_selection: Option<CmSelection>,
+    /// Set once another compositing manager has taken over, to stop the event loop.
+    replaced: bool,
+    cursor: Option<CursorImage>,
+    /// The name of the EWMH window manager found at startup, if there was one.
+    window_manager: Option<String>,
+    /// The tracked top-level window containing the window manager's active window.
+    active_window: Option<xproto::Window>,
+    /// The area of the root we composite, when limited to a single output.
+    output: Option<xproto::Rectangle>,
+    /// Where the output being mirrored is, and where it's mirrored onto.
+    mirror: Option<(xproto::Rectangle, xproto::Rectangle)>,
+    /// The screen's depth-32 visuals. Windows using one of these have an alpha channel.
+    argb_visuals: HashSet<xproto::Visualid>,
+    monitors: Vec<Monitor>,
+    /// The space reserved by every mapped panel and dock.
+    reserved: Vec<Strut>,
+    background: Background,
+    /// Draws the border around the active window, if there is one.
+    active_border: Option<BorderLayer>,
+    /// What the scene is drawn into before being scaled up to the output, when rendering
+    /// below native resolution.
+    scaled: Option<ScaledTarget>,
+    /// What the scene is drawn into before being resolved, when multisampling.
+    msaa: Option<wgpu::TextureView>,
+    overlay_rects: OverlayRects,
+    tint: TintLayer,
+    /// Turns effects off while frames are over [`Config::frame_budget`].
+    quality: Option<QualityController>,
+    /// The options as they were before effects were turned off to keep to the frame
+    /// budget, for turning them back on.
+    full_quality: Option<Box<Config>>,
+    /// Whether the tint is on, or fading in. It keeps being drawn while it fades out.
+    tinted: bool,
+    /// What was recently damaged, when visualizing damage.
+    #[cfg(debug_assertions)]
+    damage_overlay: crate::damage_overlay::DamageOverlay,
+    animations: AnimationManager,
+    /// Whether all windows are currently shown scaled down in a grid.
+    expose: bool,
+    #[cfg(all(feature = "shader-reload", debug_assertions))]
+    shader_watcher: crate::reload::ShaderWatcher,
+    /// Global key grabs for the configured key bindings.
+    keys: KeyGrabs,
+    /// The keys and buttons held down, to end what they started once they're released.
+    held: HeldInputs,
+    /// The service manager's notification socket, when run as a `Type=notify` service.
+    #[cfg(feature = "systemd")]
+    systemd: Option<crate::systemd::Notifier>,
+    /// Where frames are streamed to, when recording.
+    #[cfg(feature = "capture-stream")]
+    stream: Option<crate::stream::FrameStream>,
+    /// Set once [`Self::into_connection`] has cleaned up, so that dropping doesn't do it
+    /// again.
+    released: bool,
+    /// Set while [`Self::suspend`] has stopped compositing, until [`Self::resume`].
+    suspended: bool,
+    /// Set while [`Self::freeze`] holds the last frame on screen.
+    frozen: bool,
+    /// Uploads of window contents to textures since startup.
+    upload_count: u64,
+    /// Watches for VT switches, with [`Config::pause_on_vt_switch`].
+    vt: Option<VtWatcher>,
+    /// Set while suspended because of a VT switch, so that only those suspensions are
+    /// resumed by switching back.
+    vt_suspended: bool,
 }
 
-/// The main compositor state struct, which manages the [`XConn`],
-/// the [`wgpu`] surface, and the [`wgpu`] render pipeline.
-impl<'a> Compositor<'a> {
-    /// Create a new compositor instance. This will create a new overlay window and
-    /// initialize a wgpu surface and render pipeline for it.
-    ///
-    /// Note that this will show the window immediately, so it should not be called
-    /// until you are ready to start rendering.
-    pub async fn new(display: Option<&str>) -> Result<Self> {
-        let display = display.map(CString::new).transpose()?;
-        let conn = x11rb::xcb_ffi::XCBConnection::connect(display.as_deref())
-            .map(|(conn, screen)| XConn::new(Arc::new(conn), screen))?;
-        let setup = conn.setup();
-        let screen: &xproto::Screen = &setup.roots[conn.screen()];
-
-        let root: xproto::Window = screen.root;
-        let root_size = (screen.width_in_pixels, screen.height_in_pixels);
-
-        // It is required to query the composite extension before making any other
-        // composite extension requests, or those requests will fail with BadRequest.
-        let composite_version = conn
-            .composite_query_version(
-                999, // idk just pick a big number
+/// The X connection and the screen we're compositing.
+struct XSetup {
+    conn: XConn,
+    root: xproto::Window,
+    root_size: (u16, u16),
+    /// The screen's depth-32 visuals, which carry an alpha channel.
+    argb_visuals: HashSet<xproto::Visualid>,
+}
+
+/// The `_NET_WM_CM_Sn` selection that announces us as the screen's compositing manager,
+/// and the window we own it with.
+#[derive(Debug, Clone, Copy)]
+struct CmSelection {
+    atom: xproto::Atom,
+    window: xproto::Window,
+}
+
+/// The windows created by [`Compositor::new_nested`].
+///
+/// The frame is an ordinary top-level window for the window manager to manage. Inside it
+/// are the container, which stands in for the root window, and the output window stacked
+/// above the container, which stands in for the composite overlay.
+#[derive(Debug, Clone, Copy)]
+struct NestedWindows {
+    frame: xproto::Window,
+    container: xproto::Window,
+    output: xproto::Window,
+}
+
+/// The composite overlay window that we render into.
+struct Overlay {
+    window: xproto::Window,
+    size: (u16, u16),
+    /// The overlay's depth, which is the root window's. Only depth 32 has an alpha
+    /// channel for the server to keep what we present translucent.
+    depth: u8,
+}
+
+/// Everything needed to render into the overlay with wgpu.
+struct Gpu {
+    presenter: OverlayPresenter,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    device_lost: Arc<AtomicBool>,
+    config: wgpu::SurfaceConfiguration,
+    pipeline: WindowPipeline,
+}
+
+/// Open a connection to the X server.
+fn connect_x(display: Option<&str>) -> Result<XConn> {
+    let display = connection::display_name(display)?;
+    let conn = x11rb::xcb_ffi::XCBConnection::connect(display.as_deref())
+        .map(|(conn, screen)| XConn::new(Arc::new(conn), screen))?;
+    Ok(conn)
+}
+
+/// Look up the screen we'll be compositing on an existing connection.
+fn screen_setup(conn: XConn) -> Result<XSetup> {
+    let setup = conn.setup();
+    let screen: &xproto::Screen = setup
+        .roots
+        .get(conn.screen())
+        .ok_or_else(|| anyhow::anyhow!("Screen {} does not exist", conn.screen()))?;
+
+    let root: xproto::Window = screen.root;
+    let root_size = (screen.width_in_pixels, screen.height_in_pixels);
+    let root_visual = screen
+        .allowed_depths
+        .iter()
+        .flat_map(|depth| &depth.visuals)
+        .find(|visual| visual.visual_id == screen.root_visual);
+    info!(
+        "Root window depth {}, visual {:#x} ({:?}, {} bits per channel)",
+        screen.root_depth,
+        screen.root_visual,
+        root_visual.map(|visual| visual.class),
+        root_visual.map_or(0, |visual| visual.bits_per_rgb_value),
+    );
+    let argb_visuals = screen
+        .allowed_depths
+        .iter()
+        .filter(|depth| depth.depth == 32)
+        .flat_map(|depth| depth.visuals.iter().map(|visual| visual.visual_id))
+        .collect();
+
+    Ok(XSetup {
+        conn,
+        root,
+        root_size,
+        argb_visuals,
+    })
+}
+
+/// Make sure the extensions we need are available, redirect the root's children,
+/// and start listening for events about them.
+///
+/// Returns the windows that already existed, bottom to top. If `grab` is set, the server
+/// is grabbed while we do this.
+async fn setup_composite(
+    x: &XSetup,
+    versions: &RequestedVersions,
+    mode: Redirect,
+    grab: bool,
+) -> Result<Vec<ExistingWindow>> {
+    let conn = &x.conn;
+
+    negotiate_extensions(conn, versions).await?;
+
+    // Grabbing the server stops other clients from creating or destroying windows while
+    // we take stock, so the snapshot is consistent with the events that follow it.
+    if grab {
+        conn.grab_server().await?.check().await?;
+    }
+    let result = redirect_and_snapshot(x, mode).await;
+    if grab {
+        conn.ungrab_server().await?.check().await?;
+    }
+
+    result
+}
+
+/// Add up the resources held by the compositor. This is a free function so it can be
+/// called while `FrameStats` is borrowed mutably.
+fn resource_stats(
+    windows: &HashMap<xproto::Window, WindowState>,
+    background: &Background,
+    overlay_rects: &OverlayRects,
+) -> ResourceStats {
+    let textures = windows.values().filter_map(|state| state.texture.as_ref());
+    ResourceStats {
+        window_textures: textures.clone().count(),
+        window_texture_bytes: textures.map(WindowTexture::size_bytes).sum(),
+        background_bytes: background.texture_bytes(),
+        overlay_rects: overlay_rects.len(),
+    }
+}
+
+/// How windows should be redirected with the given options.
+fn redirect_mode(options: &Config) -> Redirect {
+    if options.manual_redirect {
+        Redirect::MANUAL
+    } else {
+        Redirect::AUTOMATIC
+    }
+}
+
+/// A child of the root that existed when we started.
+struct ExistingWindow {
+    window: xproto::Window,
+    geometry: xproto::Rectangle,
+    border_width: u16,
+    viewable: bool,
+}
+
+/// Redirect the root's children, start listening for events about them, and list the
+/// ones that already exist, bottom to top.
+async fn redirect_and_snapshot(x: &XSetup, mode: Redirect) -> Result<Vec<ExistingWindow>> {
+    let conn = &x.conn;
+    let root = x.root;
+
+    // Redirect all current and future children of the root window.
+    conn.composite_redirect_subwindows(root, mode)
+        .await?
+        .check()
+        .await?;
+
+    // Listen for children of the root being mapped, unmapped, and reconfigured, and for
+    // changes to root properties like `_NET_ACTIVE_WINDOW`.
+    conn.change_window_attributes(
+        root,
+        &ChangeWindowAttributesAux::new()
+            .event_mask(EventMask::SUBSTRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE),
+    )
+    .await?
+    .check()
+    .await?;
+
+    let tree = conn.query_tree(root).await?.reply().await?;
+
+    // Send every request before waiting for any replies, to keep this (and any server
+    // grab) short.
+    let mut cookies = Vec::with_capacity(tree.children.len());
+    for &window in &tree.children {
+        cookies.push((
+            window,
+            conn.get_window_attributes(window).await?,
+            conn.get_geometry(window).await?,
+        ));
+    }
+
+    let mut existing = Vec::with_capacity(cookies.len());
+    for (window, attributes, geometry) in cookies {
+        // Without a grab, windows can disappear while we're looking at them.
+        let (Ok(attributes), Ok(geometry)) = (attributes.reply().await, geometry.reply().await)
+        else {
+            continue;
+        };
+        if attributes.class == xproto::WindowClass::INPUT_ONLY {
+            continue;
+        }
+        existing.push(ExistingWindow {
+            window,
+            geometry: xproto::Rectangle {
+                x: geometry.x,
+                y: geometry.y,
+                width: geometry.width,
+                height: geometry.height,
+            },
+            border_width: geometry.border_width,
+            viewable: attributes.map_state == xproto::MapState::VIEWABLE,
+        });
+    }
+
+    Ok(existing)
+}
+
+/// Get the composite overlay window and make it transparent to input.
+async fn acquire_overlay(
+    conn: &XConn,
+    root: xproto::Window,
+    root_size: (u16, u16),
+) -> Result<Overlay> {
+    let win_id = conn
+        .composite_get_overlay_window(root)
+        .await?
+        .reply()
+        .await
+        .map_err(|source| Error::OverlayUnavailable { source })?
+        .overlay_win;
+    // The overlay normally covers the whole screen, but that isn't guaranteed on
+    // multi-monitor or otherwise unusual setups, so size the surface from its real
+    // geometry rather than the screen's.
+    let overlay_geometry = conn.get_geometry(win_id).await?.reply().await?;
+    let overlay_size = (overlay_geometry.width, overlay_geometry.height);
+    info!(
+        "Overlay window: {:?}, depth {}",
+        win_id, overlay_geometry.depth
+    );
+    if overlay_size != root_size {
+        warn!(
+            "Overlay window size {:?} does not match screen size {:?}",
+            overlay_size, root_size
+        );
+    }
+
+    // Allow event pass-through to the root window
+    set_input_region(conn, win_id, &[]).await?;
+
+    Ok(Overlay {
+        window: win_id,
+        size: overlay_size,
+        depth: overlay_geometry.depth,
+    })
+}
+
+/// Create the windows to composite inside of when nested, as children of `parent`.
+async fn create_nested_windows(
+    x: &XSetup,
+    parent: xproto::Window,
+    size: (u16, u16),
+) -> Result<NestedWindows> {
+    let conn = &x.conn;
+    let frame = conn.generate_id().await?;
+    let container = conn.generate_id().await?;
+    let output = conn.generate_id().await?;
+
+    for (win, parent) in [(frame, parent), (container, frame), (output, frame)] {
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            win,
+            parent,
+            0,
+            0,
+            size.0,
+            size.1,
+            0,
+            xproto::WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &xproto::CreateWindowAux::new(),
+        )
+        .await?
+        .check()
+        .await?;
+    }
+
+    let title = b"recomp (nested)";
+    conn.change_property(
+        xproto::PropMode::REPLACE,
+        frame,
+        AtomEnum::WM_NAME,
+        AtomEnum::STRING,
+        8,
+        title.len() as u32,
+        title,
+    )
+    .await?
+    .check()
+    .await?;
+
+    // The output was created last, so it's stacked above the container.
+    for win in [container, output, frame] {
+        conn.map_window(win).await?.check().await?;
+    }
+    info!(
+        "Compositing nested inside window {:?}, e.g. run `xterm -into {}`",
+        container, container
+    );
+
+    Ok(NestedWindows {
+        frame,
+        container,
+        output,
+    })
+}
+
+/// Use our own output window like the overlay, transparent to input so that clicks
+/// reach the windows in the container underneath it.
+async fn nested_overlay(x: &XSetup, nested: &NestedWindows) -> Result<Overlay> {
+    set_input_region(&x.conn, nested.output, &[]).await?;
+    let depth = x
+        .conn
+        .get_geometry(nested.output)
+        .await?
+        .reply()
+        .await?
+        .depth;
+    Ok(Overlay {
+        window: nested.output,
+        size: x.root_size,
+        depth,
+    })
+}
+
+/// Set the input shape of `win` to `rects`, so that it only receives input there.
+async fn set_input_region(
+    conn: &XConn,
+    win: xproto::Window,
+    rects: &[xproto::Rectangle],
+) -> Result<()> {
+    let region = conn.generate_id().await?;
+    conn.xfixes_create_region(region, rects)
+        .await?
+        .check()
+        .await?;
+    let result = conn
+        .xfixes_set_window_shape_region(win, SK::INPUT, 0, 0, region)
+        .await?
+        .check()
+        .await;
+    conn.xfixes_destroy_region(region).await?.check().await?;
+    result?;
+
+    Ok(())
+}
+
+/// Whether handling `ev` can change what's on screen, so a frame should be drawn after it.
+///
+/// Present completions only report on frames already drawn. Drawing another because of
+/// one would keep rendering forever, since every frame ends with a completion.
+fn needs_frame_after(ev: &x11rb::protocol::Event) -> bool {
+    !matches!(ev, x11rb::protocol::Event::PresentCompleteNotify(_))
+}
+
+/// Ask RandR to tell us when the screen is resized, if the server has it.
+async fn select_screen_changes(conn: &XConn, root: xproto::Window) -> Result<()> {
+    match conn
+        .randr_select_input(root, NotifyMask::SCREEN_CHANGE)
+        .await
+    {
+        Ok(cookie) => cookie.check().await?,
+        // Monitor detection has already warned about this.
+        Err(ConnectionError::UnsupportedExtension) => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Take the `_NET_WM_CM_Sn` selection for the screen, which is how EWMH compositing
+/// managers announce themselves, and listen for anyone taking it from us.
+///
+/// If another compositing manager holds it, we replace it. It's expected to notice losing
+/// the selection and stop, as we do.
+async fn acquire_cm_selection(x: &XSetup, atoms: &Atoms) -> Result<CmSelection> {
+    let conn = &x.conn;
+    let atom = atoms.net_wm_cm_s;
+    let name = format!("_NET_WM_CM_S{}", conn.screen());
+    let previous = conn.get_selection_owner(atom).await?.reply().await?.owner;
+    if previous != x11rb::NONE {
+        warn!(
+            "Replacing the compositing manager that owns _NET_WM_CM_S{}",
+            conn.screen()
+        );
+    }
+
+    let window = conn.generate_id().await?;
+    conn.create_window(
+        0,
+        window,
+        x.root,
+        -1,
+        -1,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        x11rb::COPY_FROM_PARENT,
+        &CreateWindowAux::new().override_redirect(1),
+    )
+    .await?
+    .check()
+    .await?;
+    conn.set_selection_owner(window, atom, x11rb::CURRENT_TIME)
+        .await?
+        .check()
+        .await?;
+    if conn.get_selection_owner(atom).await?.reply().await?.owner != window {
+        anyhow::bail!("Failed to take the {name} selection");
+    }
+    conn.xfixes_select_selection_input(
+        window,
+        atom,
+        SelectionEventMask::SET_SELECTION_OWNER
+            | SelectionEventMask::SELECTION_WINDOW_DESTROY
+            | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+    )
+    .await?
+    .check()
+    .await?;
+
+    Ok(CmSelection { atom, window })
+}
+
+/// Find the EWMH window manager running on `root`, and its name if it has one.
+///
+/// A window manager advertises itself by pointing `_NET_SUPPORTING_WM_CHECK` on the root
+/// at a window with the same property pointing at itself. Checking both ends catches a
+/// property left behind by a window manager that has since exited.
+async fn window_manager(
+    conn: &XConn,
+    root: xproto::Window,
+    atoms: &Atoms,
+) -> Result<Option<String>> {
+    let check_window = |win| async move {
+        let reply = conn
+            .get_property(
+                false,
+                win,
+                atoms.net_supporting_wm_check,
+                AtomEnum::WINDOW,
                 0,
+                1,
             )
             .await?
             .reply()
-            .await?;
-        info!("Composite extension version: {:?}", composite_version);
-
-        let xfixes_version = conn.xfixes_query_version(999, 0).await?.reply().await?;
-        info!("XFixes extension version: {:?}", xfixes_version);
-
-        let xdamage_version = conn.damage_query_version(999, 0).await?.reply().await?;
-        info!("XDamage extension version: {:?}", xdamage_version);
-
-        // let wid = conn.generate_id().await?;
-        // conn.create_window(
-        //     0,
-        //     wid,
-        //     root,
-        //     0,
-        //     0,
-        //     root_size.0,
-        //     root_size.1,
-        //     0,
-        //     WindowClass::COPY_FROM_PARENT,
-        //     0,
-        //     &CreateWindowAux::default(),
-        // )
-        // .await?
-        // .check()
-        // .await?;
-        //
-        // let selection = conn
-        //     .intern_atom(false, format!("REGISTER_PROP{}", conn.screen()).as_bytes())
-        //     .await?
-        //     .reply()
-        //     .await?;
-
-        // selection.atom
-
-        // conn.set_selection_owner(wid, selection.atom, 0u32)
-        //     .await?
-        //     .check()
-        //     .await?;
-
-        // conn.xutf
-
-        // Redirect all current and future children of the root window.
-        conn.composite_redirect_subwindows(root, Redirect::AUTOMATIC)
-            .await?
-            .check()
-            .await?;
+            .await;
+        // The window the root points at may be gone.
+        anyhow::Ok(reply.ok().and_then(|reply| reply.value32()?.next()))
+    };
 
-        let win_id = conn
-            .composite_get_overlay_window(root)
-            .await?
-            .reply()
-            .await?
-            .overlay_win;
-        info!("Overlay window: {:?}", win_id);
+    let Some(check) = check_window(root).await? else {
+        return Ok(None);
+    };
+    if check_window(check).await? != Some(check) {
+        return Ok(None);
+    }
 
-        // let tree = conn.query_tree(root).await?.reply().await?;
-        // info!("Tree: {:?}", tree);
+    let name = conn
+        .get_property(false, check, atoms.net_wm_name, atoms.utf8_string, 0, 256)
+        .await?
+        .reply()
+        .await
+        .ok()
+        .filter(|reply| !reply.value.is_empty())
+        .map(|reply| String::from_utf8_lossy(&reply.value).into_owned());
+    Ok(Some(name.unwrap_or_else(|| {
+        String::from("an unnamed window manager")
+    })))
+}
 
-        // Allow event pass-through to the root window
-        let region = conn.generate_id().await?;
-        conn.xfixes_create_region(region, &[])
-            .await?
-            .check()
-            .await?;
-        // conn.xfixes_set_window_shape_region(win_id, SK::BOUNDING, 0, 0, region)
-        //     .await?
-        //     .check()
-        //     .await?;
-        conn.xfixes_set_window_shape_region(win_id, SK::INPUT, 0, 0, region)
-            .await?
-            .check()
-            .await?;
-        conn.xfixes_destroy_region(region).await?.check().await?;
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        // backends: wgpu::Backends::GL, // setting this to GL fails for some reason
+        backends: wgpu::Backends::VULKAN,
+        ..Default::default()
+    })
+}
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            // backends: wgpu::Backends::GL, // setting this to GL fails for some reason
-            backends: wgpu::Backends::VULKAN,
-            ..Default::default()
-        });
+/// Poll for an EWMH window manager until one shows up or `timeout` passes.
+async fn wait_for_window_manager(
+    conn: &XConn,
+    root: xproto::Window,
+    atoms: &Atoms,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut logged = false;
+    loop {
+        if window_manager(conn, root, atoms).await?.is_some() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            warn!(
+                "No window manager showed up within {:?}, starting anyway",
+                timeout
+            );
+            return Ok(());
+        }
+        if !logged {
+            info!("Waiting up to {:?} for a window manager", timeout);
+            logged = true;
+        }
+        tokio::time::sleep(WM_POLL_INTERVAL.min(deadline - Instant::now())).await;
+    }
+}
+
+/// Set up rendering into the overlay window: a device, the render pipeline, and whichever
+/// way of presenting frames the options ask for.
+async fn init_wgpu(
+    instance: &wgpu::Instance,
+    conn: &XConn,
+    overlay: &Overlay,
+    options: &Config,
+) -> Result<Gpu> {
+    match options.present {
+        PresentBackend::Surface => init_surface(instance, conn, overlay, options).await,
+        PresentBackend::Dbe => init_dbe(instance, conn, overlay, options).await,
+        PresentBackend::Auto => match init_surface(instance, conn, overlay, options).await {
+            Ok(gpu) => Ok(gpu),
+            Err(e) => {
+                warn!("Can't present through a surface, falling back to DBE: {e:#}");
+                init_dbe(instance, conn, overlay, options).await
+            }
+        },
+    }
+}
+
+/// Create a wgpu surface for the overlay window, along with a device and the
+/// render pipeline.
+async fn init_surface(
+    instance: &wgpu::Instance,
+    conn: &XConn,
+    overlay: &Overlay,
+    options: &Config,
+) -> Result<Gpu> {
+    let surface = create_surface(instance, conn, overlay.window)?;
+
+    let adapter = find_adapter(instance, Some(&surface), options.adapter.as_ref()).await?;
 
-        // Safety: we get the raw connection from the XCBConnection, which is a valid XCB connection
-        // so this should be safe.
-        //
-        // We need this to convert the x11tb connection to something wgpu can use.
-        let surface = unsafe {
-            instance.create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
-                raw_display_handle: RawDisplayHandle::Xcb(XcbDisplayHandle::new(
-                    Some(conn.as_raw_connection()),
-                    conn.screen().try_into()?,
-                )),
-                raw_window_handle: RawWindowHandle::Xcb(XcbWindowHandle::new(win_id.try_into()?)),
-            })?
-        };
-
-        let adapter = instance
+    let device_lost = Arc::new(AtomicBool::new(false));
+    let (device, queue) = request_device(&adapter, &device_lost).await?;
+
+    let capabilities = surface.get_capabilities(&adapter);
+
+    let format = present::surface_format(
+        &capabilities.formats,
+        options.surface_format,
+        &adapter.get_info(),
+    )?;
+    #[cfg(feature = "hdr")]
+    let format = match options.hdr {
+        Some(_) if crate::hdr::supports_hdr(&capabilities.formats) => {
+            info!("Compositing in HDR with {:?}", crate::hdr::HDR_FORMAT);
+            crate::hdr::HDR_FORMAT
+        }
+        Some(_) => {
+            warn!(
+                "The surface can't present in HDR ({:?} supported), compositing in SDR",
+                capabilities.formats
+            );
+            format
+        }
+        None => format,
+    };
+
+    let has_alpha = overlay.depth == 32;
+    let alpha_mode = present::alpha_mode(&capabilities.alpha_modes, has_alpha)
+        .ok_or_else(|| anyhow::anyhow!("No supported usage found"))?;
+    debug!(
+        "Presenting with {:?} alpha to a depth {} overlay ({:?} supported)",
+        alpha_mode, overlay.depth, capabilities.alpha_modes
+    );
+    if !has_alpha && options.clear_color.a < 1.0 {
+        warn!(
+            "The clear color is translucent, but the overlay has depth {} and no alpha \
+             channel, so nothing below it will show through",
+            overlay.depth
+        );
+    }
+
+    // Blending is only correct in linear space, which the hardware does for us when the
+    // target is sRGB, and half floats are already. If the surface is neither, we render
+    // to an sRGB view of it.
+    let view_formats = if format.is_srgb() || format == wgpu::TextureFormat::Rgba16Float {
+        vec![]
+    } else if adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::VIEW_FORMATS)
+    {
+        vec![format.add_srgb_suffix()]
+    } else {
+        warn!(
+            "Surface format {:?} isn't sRGB, translucent windows will blend incorrectly",
+            format
+        );
+        vec![]
+    };
+
+    let present_mode = present::present_mode(&capabilities.present_modes, options.present_mode);
+    info!(
+        "Presenting with {:?} ({:?} requested, {:?} supported)",
+        present_mode, options.present_mode, capabilities.present_modes
+    );
+
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: overlay.size.0 as u32,
+        height: overlay.size.1 as u32,
+        present_mode,
+        alpha_mode,
+        view_formats,
+        desired_maximum_frame_latency: options.max_frames_in_flight.max(1),
+    };
+
+    // Some drivers can create a surface for the overlay but not present to it.
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    surface.configure(&device, &config);
+    if let Some(e) = device.pop_error_scope().await {
+        anyhow::bail!("Failed to configure the surface: {e}");
+    }
+
+    let format = render_format(&config);
+    let samples = sample_count(&adapter, format, options.msaa_samples);
+    let pipeline = WindowPipeline::with_samples(&device, format, samples);
+
+    Ok(Gpu {
+        presenter: OverlayPresenter::Surface(surface),
+        adapter,
+        device,
+        queue,
+        device_lost,
+        config,
+        pipeline,
+    })
+}
+
+/// Set up rendering offscreen and presenting through a DBE back buffer of the overlay,
+/// for when a surface doesn't work.
+async fn init_dbe(
+    instance: &wgpu::Instance,
+    conn: &XConn,
+    overlay: &Overlay,
+    options: &Config,
+) -> Result<Gpu> {
+    let adapter = find_adapter(instance, None, options.adapter.as_ref()).await?;
+
+    let device_lost = Arc::new(AtomicBool::new(false));
+    let (device, queue) = request_device(&adapter, &device_lost).await?;
+
+    let config = DbePresenter::config(overlay.size.0 as u32, overlay.size.1 as u32);
+    let dbe = DbePresenter::new(conn, overlay.window, &device, &config).await?;
+    let format = render_format(&config);
+    let samples = sample_count(&adapter, format, options.msaa_samples);
+    let pipeline = WindowPipeline::with_samples(&device, format, samples);
+
+    Ok(Gpu {
+        presenter: OverlayPresenter::Dbe(dbe),
+        adapter,
+        device,
+        queue,
+        device_lost,
+        config,
+        pipeline,
+    })
+}
+
+/// Describe every adapter wgpu can find on the X server at `display`, along with the
+/// formats, present modes and alpha modes each supports for presenting to a window on
+/// it, for choosing [`Config::adapter`] and [`Config::surface_format`].
+///
+/// This doesn't touch the overlay or redirect anything, so it's safe to run alongside
+/// another compositor.
+pub async fn describe_adapters(display: Option<&str>) -> Result<String> {
+    let x = screen_setup(connect_x(display)?)?;
+    let conn = &x.conn;
+
+    // The overlay may be taken, so present to a window of our own. It's never mapped,
+    // which doesn't change what a surface for it supports.
+    let window = conn.generate_id().await?;
+    conn.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        window,
+        x.root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        xproto::WindowClass::INPUT_OUTPUT,
+        x11rb::COPY_FROM_PARENT,
+        &xproto::CreateWindowAux::new(),
+    )
+    .await?
+    .check()
+    .await?;
+
+    let instance = create_instance();
+    let surface = create_surface(&instance, conn, window)
+        .inspect_err(|e| warn!("Can't create a surface, not listing what it supports: {e:#}"))
+        .ok();
+
+    let mut out = String::new();
+    for (i, adapter) in instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .into_iter()
+        .enumerate()
+    {
+        let info = adapter.get_info();
+        writeln!(
+            out,
+            "Adapter {i}: {} ({:04x}:{:04x}), {:?}, {:?}",
+            info.name, info.vendor, info.device, info.backend, info.device_type
+        )?;
+        if !info.driver.is_empty() {
+            writeln!(out, "  Driver: {} {}", info.driver, info.driver_info)?;
+        }
+        match &surface {
+            Some(surface) if adapter.is_surface_supported(surface) => {
+                let capabilities = surface.get_capabilities(&adapter);
+                writeln!(out, "  Surface formats: {:?}", capabilities.formats)?;
+                writeln!(out, "  Present modes: {:?}", capabilities.present_modes)?;
+                writeln!(out, "  Alpha modes: {:?}", capabilities.alpha_modes)?;
+            }
+            Some(_) => writeln!(out, "  Can't present to this X server")?,
+            None => {}
+        }
+    }
+    if out.is_empty() {
+        out.push_str("No adapters found\n");
+    }
+
+    drop(surface);
+    conn.destroy_window(window).await?.check().await?;
+    Ok(out)
+}
+
+/// Every adapter wgpu can find, without opening any of them or connecting to X, for
+/// checking [`Config::adapter`] against.
+pub fn adapter_infos() -> Vec<wgpu::AdapterInfo> {
+    create_instance()
+        .enumerate_adapters(wgpu::Backends::all())
+        .iter()
+        .map(wgpu::Adapter::get_info)
+        .collect()
+}
+
+/// Pick the adapter to render with, which must be able to present to `surface` if
+/// there is one.
+async fn find_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+    selector: Option<&AdapterMatch>,
+) -> Result<wgpu::Adapter> {
+    let adapter = match selector {
+        Some(selector) => select_adapter(instance, surface, selector)?,
+        None => instance
             .request_adapter({
                 &wgpu::RequestAdapterOptions {
                     // Should this be configurable at some point?
                     // Should high power be the default?
                     power_preference: wgpu::PowerPreference::default(),
-                    compatible_surface: Some(&surface),
+                    compatible_surface: surface,
                     force_fallback_adapter: false,
                 }
             })
             .await
-            .ok_or_else(|| anyhow::anyhow!("No adapter found"))?;
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::default(),
-                    required_limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await?;
-
-        let capabilities = surface.get_capabilities(&adapter);
+            .ok_or(Error::NoAdapter)?,
+    };
+    info!("Using adapter {:?}", adapter.get_info());
+    Ok(adapter)
+}
 
-        let format = capabilities
-            .formats
+/// Where the outputs `mirror` names are among `monitors`, the source first.
+fn find_mirror(
+    monitors: &[Monitor],
+    mirror: &Mirror,
+) -> Result<(xproto::Rectangle, xproto::Rectangle)> {
+    let find = |name: &str| {
+        monitors
             .iter()
-            .filter(|f| f.is_srgb())
-            .next()
-            .or_else(|| capabilities.formats.get(0))
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("No sRGB surface format found"))?;
-
-        let alpha_mode = capabilities
-            .alpha_modes
-            .get(0)
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("No supported usage found"))?;
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format,
-            width: root_size.0 as u32,
-            height: root_size.1 as u32,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode,
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2, // 2 is the default
-        };
-
-        surface.configure(&device, &config);
-
-        Ok(Self {
-            conn,
-            root_size,
-            surface,
-            queue,
-            device,
-            config,
-            overlay_win: win_id,
-            root_win: root,
-        })
+            .find(|monitor| monitor.name == name)
+            .map(|monitor| monitor.rect)
+            .with_context(|| format!("Output {name} not found"))
+    };
+    Ok((find(&mirror.source)?, find(&mirror.destination)?))
+}
+
+/// Scale `rect` about the origin of the screen.
+fn scale_rect(rect: xproto::Rectangle, scale: f32) -> xproto::Rectangle {
+    if scale == 1.0 {
+        return rect;
+    }
+    xproto::Rectangle {
+        x: (rect.x as f32 * scale).round() as i16,
+        y: (rect.y as f32 * scale).round() as i16,
+        width: (rect.width as f32 * scale).round() as u16,
+        height: (rect.height as f32 * scale).round() as u16,
     }
+}
 
-    pub fn resize(&mut self, width: u16, height: u16) {
-        // TBD: Can this actually happen in a compositor? not sure how screen attach/detach is
-        // handled.
-        self.config.width = width as u32;
-        self.config.height = height as u32;
-        self.surface.configure(&self.device, &self.config);
+/// The target to draw the scene into before scaling it up, if `options` renders below
+/// native resolution, streams frames, which are copied out of it, mirrors an output,
+/// which draws part of it twice, or brightens it for an HDR surface.
+fn scaled_target(
+    device: &wgpu::Device,
+    pipeline: &WindowPipeline,
+    config: &wgpu::SurfaceConfiguration,
+    options: &Config,
+) -> Option<ScaledTarget> {
+    #[cfg(feature = "capture-stream")]
+    let streaming = options.stream.is_some();
+    #[cfg(not(feature = "capture-stream"))]
+    let streaming = false;
+    // Windows are all SDR, so on an HDR surface they're scaled up to SDR white.
+    #[cfg(feature = "hdr")]
+    let brightness = options
+        .hdr
+        .filter(|_| config.format == crate::hdr::HDR_FORMAT)
+        .map(|hdr| hdr.sdr_scale());
+    #[cfg(not(feature = "hdr"))]
+    let brightness = None;
+
+    let scale = match options.render_scale {
+        scale if scale > 0.0 && scale < 1.0 => scale,
+        _ if streaming || options.mirror.is_some() || brightness.is_some() => 1.0,
+        _ => return None,
+    };
+    let mut target = ScaledTarget::new(
+        device,
+        pipeline,
+        render_format(config),
+        (config.width, config.height),
+        scale,
+    );
+    if let Some(brightness) = brightness {
+        target.set_brightness(brightness);
     }
+    Some(target)
+}
 
-    pub fn render(&self) -> Result<()> {
-        let output = self.surface.get_current_texture()?;
+/// The multisampled texture to draw the scene into before resolving it into the output
+/// or `scaled`, if `pipeline` draws with more than one sample per pixel.
+fn msaa_target(
+    device: &wgpu::Device,
+    pipeline: &WindowPipeline,
+    config: &wgpu::SurfaceConfiguration,
+    scaled: Option<&ScaledTarget>,
+) -> Option<wgpu::TextureView> {
+    if pipeline.sample_count() == 1 {
+        return None;
+    }
+    let (width, height) = scaled.map_or((config.width, config.height), |scaled| scaled.size());
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Multisampled Frame"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: pipeline.sample_count(),
+        dimension: wgpu::TextureDimension::D2,
+        format: render_format(config),
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
 
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+/// How many samples per pixel to render in `format` with, given that `requested` were
+/// asked for: as many as `adapter` supports, up to that.
+fn sample_count(adapter: &wgpu::Adapter, format: wgpu::TextureFormat, requested: u32) -> u32 {
+    let features = adapter.get_texture_format_features(format).flags;
+    let count = pipeline::supported_sample_count(features, requested);
+    if count < requested {
+        warn!("{format:?} can't be rendered with {requested}x MSAA here, using {count}x");
+    } else if count > 1 {
+        info!("Rendering with {count}x MSAA");
+    }
+    count
+}
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+/// The format we render in, which is always sRGB so that blending happens in linear
+/// space, even if the surface itself isn't.
+fn render_format(config: &wgpu::SurfaceConfiguration) -> wgpu::TextureFormat {
+    config
+        .view_formats
+        .first()
+        .copied()
+        .unwrap_or(config.format)
+}
 
-        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.5,
-                        a: 1.0,
-                    }),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+/// Find the first adapter matching `selector` that can present to `surface`, if given.
+fn select_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+    selector: &AdapterMatch,
+) -> Result<wgpu::Adapter> {
+    let mut available = Vec::new();
+    for adapter in instance.enumerate_adapters(wgpu::Backends::all()) {
+        let info = adapter.get_info();
+        if selector.matches(&info)
+            && surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+        {
+            return Ok(adapter);
+        }
+        available.push(format!(
+            "{} ({:04x}:{:04x}, {:?})",
+            info.name, info.vendor, info.device, info.backend
+        ));
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
+    anyhow::bail!(
+        "No adapter matches {:?}, available adapters: {}",
+        selector,
+        available.join(", ")
+    )
+}
 
-        output.present();
+/// Create a device and queue on `adapter`, which will set `device_lost` if the device is
+/// ever lost.
+async fn request_device(
+    adapter: &wgpu::Adapter,
+    device_lost: &Arc<AtomicBool>,
+) -> Result<(wgpu::Device, wgpu::Queue)> {
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::default(),
+                required_limits: wgpu::Limits::default(),
+                label: None,
+            },
+            None,
+        )
+        .await?;
 
-        Ok(())
+    let device_lost = Arc::clone(device_lost);
+    device.set_device_lost_callback(move |reason, message| {
+        // `Destroyed` means we dropped the device ourselves, which isn't a problem.
+        if let wgpu::DeviceLostReason::Unknown = reason {
+            error!("GPU device lost: {}", message);
+            device_lost.store(true, Ordering::SeqCst);
+        }
+    });
+
+    Ok((device, queue))
+}
+
+/// The main compositor state struct, which manages the [`XConn`],
+/// the [`wgpu`] surface, and the [`wgpu`] render pipeline.
+impl<'a> Compositor<'a> {
+    /// Create a new compositor instance. This will create a new overlay window and
+    /// initialize a wgpu surface and render pipeline for it.
+    ///
+    /// Note that this will show the window immediately, so it should not be called
+    /// until you are ready to start rendering.
+    pub async fn new(display: Option<&str>, options: Config) -> Result<Self> {
+        let conn = connect_x(display)?;
+        Self::setup(conn, options).await
     }
 
-    pub async fn run(&self) -> Result<()> {
-        loop {
-            self.render()?;
-            match self.conn.poll_for_event()? {
-                Some(ev) => {
-                    match ev {
-                        x11rb::protocol::Event::Unknown(_) => info!("Unknown event"),
-                        x11rb::protocol::Event::Error(err) => warn!("X11 Error: {:?}", err),
-                        x11rb::protocol::Event::ButtonPress(btn) => {
-                            info!("ButtonPress: {:?}", btn);
-                        }
-                        x11rb::protocol::Event::CreateNotify(ev) => {
-                            info!("CreateNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::DestroyNotify(ev) => {
-                            info!("DestroyNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::EnterNotify(ev) => {
-                            info!("EnterNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::FocusIn(ev) => {
-                            info!("FocusIn: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::FocusOut(ev) => {
-                            info!("FocusOut: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::KeyPress(ev) => {
-                            info!("KeyPress: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::KeyRelease(ev) => {
-                            info!("KeyRelease: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::LeaveNotify(ev) => {
-                            info!("LeaveNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::MapNotify(ev) => {
-                            info!("MapNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::MapRequest(ev) => {
-                            info!("MapRequest: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::MappingNotify(ev) => {
-                            info!("MappingNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::PropertyNotify(ev) => {
-                            info!("PropertyNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::ReparentNotify(ev) => {
-                            info!("ReparentNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::UnmapNotify(ev) => {
-                            info!("UnmapNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::VisibilityNotify(ev) => {
-                            info!("VisibilityNotify: {:?}", ev);
-                        }
-                        x11rb::protocol::Event::DamageNotify(ev) => {
-                            info!("DamageNotify: {:?}", ev);
-                        }
-                        ev => {
-                            warn!("Unhandled event: {:?}", ev);
-                        }
-                    };
-                    break;
-                }
-                None => {}
-            }
-            // if I.fetch_add(1, std::sync::atomic::Ordering::Relaxed) > 200 {
-            //     break;
-            // }
-            // println!("Event: {:?}", self.conn.wait_for_event().await?);
-        }
+    /// Create a new compositor on a connection owned by the caller, for example a window
+    /// manager that wants to composite without opening a second connection.
+    ///
+    /// `screen` is the index of the screen to composite. Like [`Compositor::new`], this
+    /// redirects the screen's windows and shows the overlay immediately.
+    pub async fn with_connection(
+        conn: Arc<XCBConnection>,
+        screen: usize,
+        options: Config,
+    ) -> Result<Self> {
+        Self::setup(XConn::new(conn, screen), options).await
+    }
 
-        Ok(())
+    /// Create a compositor that composites the children of a new window inside `parent`
+    /// (or the screen's root if `None`), instead of the whole screen.
+    ///
+    /// This is for running the compositor inside a normal session during development.
+    /// Only windows created inside the nested compositor's [`Compositor::root_window`]
+    /// are composited, for example by starting `xterm -into <id>` with its id.
+    pub async fn new_nested(
+        display: Option<&str>,
+        parent: Option<xproto::Window>,
+        size: (u16, u16),
+        options: Config,
+    ) -> Result<Self> {
+        let conn = connect_x(display)?;
+        let mut x = screen_setup(conn)?;
+        let nested = create_nested_windows(&x, parent.unwrap_or(x.root), size).await?;
+        x.root = nested.container;
+        x.root_size = size;
+        Self::setup_with(x, Some(nested), options).await
     }
-}
 
-impl Drop for Compositor<'_> {
-    fn drop(&mut self) {
-        let conn = self.conn.clone();
-        let root = self.root_win;
-        let overlay = self.overlay_win;
-        tokio::spawn(async move {
-            conn.composite_unredirect_subwindows(root, Redirect::AUTOMATIC)
+    async fn setup(conn: XConn, options: Config) -> Result<Self> {
+        Self::setup_with(screen_setup(conn)?, None, options).await
+    }
+
+    async fn setup_with(
+        x: XSetup,
+        nested: Option<NestedWindows>,
+        mut options: Config,
+    ) -> Result<Self> {
+        if let Some(delay) = options.startup_delay {
+            info!("Waiting {:?} before starting", delay);
+            tokio::time::sleep(delay).await;
+        }
+        let atoms = Atoms::intern(&x.conn, &options.prefetch_atoms).await?;
+        // Nothing manages the windows inside a nested compositor, so there's nothing to
+        // wait for.
+        if let (None, Some(timeout)) = (&nested, options.wait_for_wm) {
+            wait_for_window_manager(&x.conn, x.root, &atoms, timeout).await?;
+        }
+
+        let existing = setup_composite(
+            &x,
+            &options.extension_versions,
+            redirect_mode(&options),
+            options.grab_server,
+        )
+        .await?;
+        let overlay = match &nested {
+            Some(nested) => nested_overlay(&x, nested).await?,
+            None => acquire_overlay(&x.conn, x.root, x.root_size).await?,
+        };
+        let display_timer = select_complete_notify(&x.conn, overlay.window)
+            .await?
+            .then(DisplayTimer::default);
+        let instance = create_instance();
+        let gpu = init_wgpu(&instance, &x.conn, &overlay, &options).await?;
+        if gpu.adapter.get_info().device_type == wgpu::DeviceType::Cpu && !options.force_effects {
+            let disabled = options.disable_expensive_effects();
+            if !disabled.is_empty() {
+                warn!(
+                    "Rendering on the CPU, so turning off {}. Use --force-effects to keep them",
+                    disabled.join(", ")
+                );
+            }
+        }
+
+        // A nested compositor doesn't composite the screen, so it isn't the screen's
+        // compositing manager.
+        let cm_selection = match nested {
+            Some(_) => None,
+            None => Some(acquire_cm_selection(&x, &atoms).await?),
+        };
+        x.conn
+            .xfixes_select_cursor_input(x.root, CursorNotifyMask::DISPLAY_CURSOR)
+            .await?
+            .check()
+            .await?;
+
+        // A nested compositor's server is on someone else's display, VT and all.
+        let vt = match nested {
+            None if options.pause_on_vt_switch => VtWatcher::new(&x.conn, x.root).await?,
+            _ => None,
+        };
+        if options.pause_on_vt_switch && vt.is_none() {
+            warn!("The X server isn't on a VT that can be watched, so VT switches won't pause");
+        }
+
+        // Nothing manages the windows inside a nested compositor, and that's expected.
+        let window_manager = match nested {
+            Some(_) => None,
+            None => {
+                let found = window_manager(&x.conn, x.root, &atoms).await?;
+                match &found {
+                    Some(name) => info!("Running alongside {name}"),
+                    None => warn!(
+                        "No window manager found. Windows will be composited, but nothing will \
+                         place, focus, or decorate them"
+                    ),
+                }
+                found
+            }
+        };
+
+        let monitors = match nested {
+            // The real monitors have nothing to do with the nested window.
+            Some(_) => vec![Monitor::covering(x.root_size)],
+            None => {
+                select_screen_changes(&x.conn, x.root).await?;
+                query_monitors(&x.conn, x.root, x.root_size).await?
+            }
+        };
+        let output = match &options.output {
+            Some(name) => {
+                let Some(monitor) = monitors.iter().find(|monitor| &monitor.name == name) else {
+                    anyhow::bail!("Output {name} not found");
+                };
+                info!("Compositing only output {} at {:?}", name, monitor.rect);
+                if options.manual_redirect {
+                    warn!("With manual redirection, windows on other outputs won't be drawn");
+                }
+                Some(monitor.rect)
+            }
+            None => None,
+        };
+        let mirror = match &options.mirror {
+            Some(mirror) => {
+                let rects = find_mirror(&monitors, mirror)?;
+                info!("Mirroring {} onto {}", mirror.source, mirror.destination);
+                Some(rects)
+            }
+            None => None,
+        };
+        let active_border = options.active_border.map(|_| {
+            BorderLayer::with_samples(
+                &gpu.device,
+                render_format(&gpu.config),
+                gpu.pipeline.sample_count(),
+            )
+        });
+        let tint = TintLayer::new(&gpu.device, &gpu.queue, &gpu.pipeline, options.tint.color);
+        let tinted = options.tint_enabled;
+        let quality = options.frame_budget.map(QualityController::new);
+        let scaled = scaled_target(&gpu.device, &gpu.pipeline, &gpu.config, &options);
+        let msaa = msaa_target(&gpu.device, &gpu.pipeline, &gpu.config, scaled.as_ref());
+        #[cfg(feature = "capture-stream")]
+        let stream = options
+            .stream
+            .as_ref()
+            .map(|config| crate::stream::FrameStream::open(config, render_format(&gpu.config)))
+            .transpose()?;
+
+        let refresh_mhz = match &options.frame_pacing {
+            FramePacing::Present => None,
+            FramePacing::Fastest => monitors::pacing_refresh_mhz(&monitors, None),
+            FramePacing::Output(name) => monitors::pacing_refresh_mhz(&monitors, Some(name)),
+        };
+        let frame_interval = refresh_mhz.map(|mhz| {
+            info!("Pacing frames to {:.2}Hz", mhz as f32 / 1000.0);
+            Duration::from_secs(1000) / mhz
+        });
+        if options.frame_pacing != FramePacing::Present && frame_interval.is_none() {
+            warn!("No refresh rate to pace frames to, rendering as fast as presenting allows");
+        }
+        let frames_in_flight = options.max_frames_in_flight.max(1);
+        let output_name = options.output.as_deref();
+        match refresh_mhz.or_else(|| monitors::pacing_refresh_mhz(&monitors, output_name)) {
+            Some(mhz) => info!(
+                "Keeping up to {frames_in_flight} frames in flight, for up to {:.1}ms of latency",
+                frames_in_flight as f32 * 1_000_000.0 / mhz as f32
+            ),
+            None => info!("Keeping up to {frames_in_flight} frames in flight"),
+        }
+
+        let background = Background::new(
+            &gpu.device,
+            &gpu.queue,
+            &gpu.pipeline,
+            render_format(&gpu.config),
+            &options,
+            &monitors,
+        )?;
+
+        let mut compositor = Self {
+            conn: x.conn,
+            root_size: x.root_size,
+            nested,
+            presenter: Box::new(gpu.presenter),
+            instance,
+            adapter: gpu.adapter,
+            queue: gpu.queue,
+            device: gpu.device,
+            device_lost: gpu.device_lost,
+            frames_in_flight: FramesInFlight::new(options.max_frames_in_flight as usize),
+            config: gpu.config,
+            pipeline: gpu.pipeline,
+            windows: HashMap::new(),
+            stack: Vec::new(),
+            stats: FrameStats::default(),
+            pending_events: VecDeque::new(),
+            frame_count: 0,
+            needs_frame: true,
+            last_frame: Instant::now(),
+            watchdog: None,
+            frame_interval,
+            next_frame: Instant::now(),
+            pre_render: None,
+            post_render: None,
+            on_present: None,
+            on_display: None,
+            events: broadcast::channel(notify::CAPACITY).0,
+            display_timer,
+            idle: IdleTracker::new(options.idle_timeout),
+            overlay_win: overlay.window,
+            overlay_input: Vec::new(),
+            root_win: x.root,
+            options,
+            atoms,
+            cm_selection,
+            replaced: false,
+            cursor: None,
+            window_manager,
+            active_window: None,
+            output,
+            mirror,
+            argb_visuals: x.argb_visuals,
+            monitors,
+            reserved: Vec::new(),
+            background,
+            active_border,
+            scaled,
+            msaa,
+            overlay_rects: OverlayRects::default(),
+            tint,
+            tinted,
+            quality,
+            full_quality: None,
+            #[cfg(debug_assertions)]
+            damage_overlay: Default::default(),
+            animations: AnimationManager::default(),
+            expose: false,
+            #[cfg(all(feature = "shader-reload", debug_assertions))]
+            shader_watcher: crate::reload::ShaderWatcher::window_shader(),
+            keys: KeyGrabs::default(),
+            held: HeldInputs::default(),
+            #[cfg(feature = "systemd")]
+            systemd: crate::systemd::Notifier::from_env()?,
+            #[cfg(feature = "capture-stream")]
+            stream,
+            released: false,
+            suspended: false,
+            frozen: false,
+            upload_count: 0,
+            vt,
+            vt_suspended: false,
+        };
+
+        compositor.keys = KeyGrabs::grab(
+            &compositor.conn,
+            compositor.root_win,
+            &compositor.options.key_bindings,
+        )
+        .await?;
+        if !compositor.keys.is_empty() && !keys::detect_autorepeat(&compositor.conn).await? {
+            debug!("Can't tell repeated key presses apart, held keys will repeat too");
+        }
+        compositor.adopt_windows(existing).await?;
+        compositor.update_active_window().await?;
+        compositor.update_cursor().await?;
+        if compositor.output.is_some() {
+            compositor.update_overlay_shape().await?;
+        }
+
+        #[cfg(feature = "systemd")]
+        if let Some(systemd) = &compositor.systemd {
+            systemd.ready()?;
+        }
+
+        Ok(compositor)
+    }
+
+    /// Stop compositing and hand back the X connection.
+    ///
+    /// Unlike dropping the compositor, this frees everything created for tracked windows,
+    /// unredirects the screen and releases the overlay before returning, so the caller can
+    /// carry on using the connection (for example a window manager that only turns
+    /// compositing on some of the time).
+    pub async fn into_connection(mut self) -> Result<XConn> {
+        for win in self.stack.clone() {
+            self.drop_window(win).await?;
+        }
+        self.keys.release(&self.conn, self.root_win).await?;
+        if !self.suspended {
+            if let Some(overlay) = self.presenter.overlay() {
+                overlay.release().await?;
+            }
+        }
+        if let Some(selection) = self.cm_selection {
+            // Gives up the selection along with it.
+            self.conn
+                .destroy_window(selection.window)
+                .await?
+                .check()
+                .await?;
+        }
+
+        if let Some(nested) = self.nested {
+            // Takes the container and output window with it.
+            self.conn
+                .destroy_window(nested.frame)
+                .await?
+                .check()
+                .await?;
+        } else if !self.suspended {
+            self.conn
+                .composite_unredirect_subwindows(self.root_win, redirect_mode(&self.options))
+                .await?
+                .check()
+                .await?;
+            self.conn
+                .composite_release_overlay_window(self.overlay_win)
+                .await?
+                .check()
+                .await?;
+        }
+
+        self.released = true;
+        Ok(self.conn.clone())
+    }
+
+    /// Stop compositing for a while, e.g. across a VT switch or while a fullscreen game
+    /// runs: unredirect the screen and release the overlay, so the server draws windows
+    /// itself again.
+    ///
+    /// The GPU device, window textures and X connection are all kept, and windows are
+    /// still tracked, so [`Compositor::resume`] doesn't have to start over. Nothing is
+    /// rendered in the meantime.
+    pub async fn suspend(&mut self) -> Result<()> {
+        if self.suspended {
+            return Ok(());
+        }
+        if self.nested.is_some() {
+            anyhow::bail!("A nested compositor can't be suspended");
+        }
+
+        // Named pixmaps go stale once their windows are unredirected.
+        for state in self.windows.values_mut() {
+            if let Some(pixmap) = state.pixmap.take() {
+                self.conn.free_pixmap(pixmap).await?.check().await?;
+            }
+        }
+        if let Some(overlay) = self.presenter.overlay() {
+            overlay.release().await?;
+        }
+        self.conn
+            .composite_unredirect_subwindows(self.root_win, redirect_mode(&self.options))
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .composite_release_overlay_window(self.overlay_win)
+            .await?
+            .check()
+            .await?;
+
+        info!("Suspended compositing");
+        self.suspended = true;
+        Ok(())
+    }
+
+    /// Start compositing again after [`Compositor::suspend`]: redirect the screen, take the
+    /// overlay back and catch up with every window's contents.
+    ///
+    /// The overlay may be a new window, so [`Compositor::overlay_window`] can change and
+    /// an input region set with [`Compositor::set_overlay_input_region`] has to be set
+    /// again.
+    pub async fn resume(&mut self) -> Result<()> {
+        if !self.suspended {
+            return Ok(());
+        }
+
+        let mode = redirect_mode(&self.options);
+        self.conn
+            .composite_redirect_subwindows(self.root_win, mode)
+            .await?
+            .check()
+            .await?;
+        let excluded: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(_, state)| state.excluded)
+            .map(|(&win, _)| win)
+            .collect();
+        for win in excluded {
+            self.conn
+                .composite_unredirect_window(win, mode)
+                .await?
+                .check()
+                .await?;
+        }
+
+        let overlay = acquire_overlay(&self.conn, self.root_win, self.root_size).await?;
+        self.overlay_win = overlay.window;
+        self.overlay_input.clear();
+        if let Some(timer) = &mut self.display_timer {
+            timer.reset();
+            select_complete_notify(&self.conn, overlay.window).await?;
+        }
+        if let Some(presenter) = self.presenter.overlay() {
+            presenter
+                .recreate(
+                    &self.instance,
+                    &self.conn,
+                    overlay.window,
+                    &self.device,
+                    &self.config,
+                )
+                .await?;
+        }
+        self.suspended = false;
+        self.resize(overlay.size.0, overlay.size.1);
+        self.update_overlay_shape().await?;
+
+        let mapped: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(_, state)| state.mapped && !state.excluded)
+            .map(|(&win, _)| win)
+            .collect();
+        for win in mapped {
+            // Windows destroyed while we were suspended only go away once we get to their
+            // DestroyNotify.
+            let result = self.refresh_pixmap(win).await;
+            self.forget_if_gone(win, result).await?;
+        }
+
+        info!("Resumed compositing");
+        Ok(())
+    }
+
+    /// Whether compositing is stopped by [`Compositor::suspend`].
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// The window that the compositor renders into. This is the composite overlay window,
+    /// unless the compositor is nested.
+    pub fn overlay_window(&self) -> xproto::Window {
+        self.overlay_win
+    }
+
+    /// The window whose children are composited. This is the root window of the screen,
+    /// unless the compositor is nested.
+    pub fn root_window(&self) -> xproto::Window {
+        self.root_win
+    }
+
+    /// The EWMH window manager that was running when the compositor started, if any. This
+    /// is its `_NET_WM_NAME`, or a placeholder if it doesn't set one.
+    pub fn window_manager(&self) -> Option<&str> {
+        self.window_manager.as_deref()
+    }
+
+    /// The atoms interned at startup, including any from [`Config::prefetch_atoms`].
+    pub fn atoms(&self) -> &Atoms {
+        &self.atoms
+    }
+
+    /// The space panels and docks have reserved along the edges of the screen.
+    pub fn reserved(&self) -> &[Strut] {
+        &self.reserved
+    }
+
+    /// The current size of the root window in pixels, updated when RandR resizes the
+    /// screen.
+    pub fn screen_root_size(&self) -> (u16, u16) {
+        self.root_size
+    }
+
+    /// Make the overlay window receive input within `rects` (in screen coordinates), and
+    /// pass it through to the windows underneath everywhere else.
+    ///
+    /// This is for embedders drawing interactive content into the overlay. By default the
+    /// region is empty, so the overlay never receives input. The region is set again
+    /// after RandR resizes the screen, but not after [`Compositor::resume`].
+    pub async fn set_overlay_input_region(&mut self, rects: &[xproto::Rectangle]) -> Result<()> {
+        set_input_region(&self.conn, self.overlay_win, rects).await?;
+        self.overlay_input = rects.to_vec();
+        Ok(())
+    }
+
+    /// Carry out a command, e.g. one bound to a key.
+    pub fn run_command(&mut self, command: Command) {
+        debug!("Running {:?}", command);
+        match command {
+            Command::ToggleExpose => self.toggle_expose(),
+            Command::HoldExpose if !self.expose => self.toggle_expose(),
+            Command::HoldExpose => {}
+            Command::ToggleBypass => self.options.bypass = !self.options.bypass,
+            Command::ToggleDamageOverlay => self.toggle_damage_overlay(),
+            Command::Freeze => self.freeze(),
+            Command::Unfreeze => self.unfreeze(),
+            Command::ToggleFreeze if self.frozen => self.unfreeze(),
+            Command::ToggleFreeze => self.freeze(),
+            Command::ToggleTint => self.toggle_tint(),
+        }
+        self.schedule_frame();
+    }
+
+    /// Undo a command whose key or button was just released, for the ones that only last
+    /// while it's held.
+    fn end_command(&mut self, command: Command) {
+        if command == Command::HoldExpose && self.expose {
+            debug!("Ending {:?}", command);
+            self.toggle_expose();
+            self.schedule_frame();
+        }
+    }
+
+    /// Run the command bound to a key as it's pressed, and end it once it's released.
+    fn handle_input(&mut self, ev: InputEvent) {
+        trace!("{:?}", ev);
+        if !ev.pressed {
+            if let Some(command) = self.held.release(ev.input) {
+                self.end_command(command);
+            }
+            return;
+        }
+
+        let command = match ev.input {
+            Input::Key(keycode) => self.keys.command(keycode, ev.state),
+            Input::Button(_) => None,
+        };
+        if self.held.press(ev.input, command) {
+            if let Some(command) = command {
+                self.run_command(command);
+            }
+        }
+    }
+
+    /// Stop rendering, so the last frame stays on screen until [`Compositor::unfreeze`].
+    ///
+    /// Events are still handled and window contents still uploaded while frozen, so the
+    /// first frame after unfreezing shows everything as it is by then.
+    pub fn freeze(&mut self) {
+        if !self.frozen {
+            info!("Freezing on frame {}", self.frame_count);
+        }
+        self.frozen = true;
+    }
+
+    /// Go back to rendering after [`Compositor::freeze`].
+    pub fn unfreeze(&mut self) {
+        if self.frozen {
+            info!("Unfreezing");
+        }
+        self.frozen = false;
+        self.schedule_frame();
+    }
+
+    /// Whether [`Compositor::freeze`] is holding the last frame on screen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Fade the tint over the whole screen in, or out if it's on.
+    pub fn toggle_tint(&mut self) {
+        let faded = Appearance {
+            opacity: 0.0,
+            ..Appearance::default()
+        };
+        if self.tinted {
+            self.animations
+                .animate(TINT_ANIMATION, faded, TINT_FADE, self.options.easings.tint);
+        } else {
+            // Pick up from wherever fading out got to.
+            let from = if self.animations.is_animating_window(TINT_ANIMATION) {
+                self.animations.appearance(TINT_ANIMATION)
+            } else {
+                faded
+            };
+            self.animations.animate_from(
+                TINT_ANIMATION,
+                from,
+                Appearance::default(),
+                TINT_FADE,
+                self.options.easings.tint,
+            );
+        }
+        self.tinted = !self.tinted;
+        self.schedule_frame();
+    }
+
+    /// Whether the tint is on, or fading in.
+    pub fn is_tinted(&self) -> bool {
+        self.tinted
+    }
+
+    /// Change the tint's color and opacity, taking effect from the next frame.
+    pub fn set_tint(&mut self, tint: Tint) {
+        self.options.tint = tint;
+        self.tint = TintLayer::new(&self.device, &self.queue, &self.pipeline, tint.color);
+        self.schedule_frame();
+    }
+
+    /// How opaque to draw the tint this frame, if at all.
+    fn tint_opacity(&self) -> Option<f32> {
+        (self.tinted || self.animations.is_animating_window(TINT_ANIMATION))
+            .then(|| self.options.tint.opacity * self.animations.appearance(TINT_ANIMATION).opacity)
+    }
+
+    /// Start or stop tinting damaged regions. Only debug builds can draw them.
+    pub fn toggle_damage_overlay(&mut self) {
+        self.options.debug_damage = !self.options.debug_damage;
+        #[cfg(debug_assertions)]
+        self.damage_overlay.clear();
+        #[cfg(not(debug_assertions))]
+        warn!("The damage overlay is only available in debug builds");
+    }
+
+    /// Change the color of the uncovered background, taking effect from the next frame.
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.options.clear_color = color;
+        self.schedule_frame();
+    }
+
+    /// Toggle showing all windows scaled down in a grid on the primary monitor, which
+    /// animates them into or out of place.
+    pub fn toggle_expose(&mut self) {
+        self.expose = !self.expose;
+        if self.expose {
+            self.layout_expose();
+        } else {
+            for win in self.stack.clone() {
+                self.animations.animate(
+                    win,
+                    Appearance::default(),
+                    EXPOSE_DURATION,
+                    self.options.easings.expose,
+                );
+            }
+        }
+    }
+
+    /// Draw `win` as it currently looks, scaled to fit within `max_size` while keeping
+    /// its aspect ratio, and read back the pixels, e.g. for a taskbar or pager.
+    ///
+    /// Fails if the window isn't tracked or we don't have its contents, e.g. because it's
+    /// unmapped or excluded from compositing.
+    pub fn render_thumbnail(&self, win: xproto::Window, max_size: (u16, u16)) -> Result<Thumbnail> {
+        let Some(state) = self.windows.get(&win) else {
+            anyhow::bail!("Window {win:#x} isn't tracked");
+        };
+        let (Some(texture), Some(_)) = (&state.texture, state.pixmap) else {
+            anyhow::bail!("Window {win:#x} has no contents to draw");
+        };
+        let max = self
+            .device
+            .limits()
+            .max_texture_dimension_2d
+            .min(u16::MAX as u32) as u16;
+        let size = thumbnail::fit(texture.size(), (max_size.0.min(max), max_size.1.min(max)));
+
+        thumbnail::render(
+            &self.device,
+            &self.queue,
+            &self.pipeline,
+            render_format(&self.config),
+            texture,
+            WindowUniforms {
+                geometry: state.extent(),
+                screen: (self.config.width, self.config.height),
+                dim: 0.0,
+                opacity: 1.0,
+                has_alpha: state.has_alpha,
+                straight_alpha: state.alpha_mode == AlphaMode::Straight,
+                corner_radius: 0.0,
+                corner_smoothing: 0.0,
+                corner_aa_width: 1.0,
+            },
+            size,
+        )
+    }
+
+    /// Whether the expose grid is currently shown.
+    pub fn expose_active(&self) -> bool {
+        self.expose
+    }
+
+    /// Draw a solid-colored rectangle (in screen coordinates) that doesn't belong to any
+    /// X client, such as a selection rectangle or a dimming layer.
+    ///
+    /// `color` is non-premultiplied sRGB with components from 0 to 1. Rects with a
+    /// negative `z` are drawn underneath all windows and the rest on top, with higher `z`
+    /// drawn later.
+    pub fn add_overlay_rect(
+        &mut self,
+        rect: xproto::Rectangle,
+        color: [f32; 4],
+        z: i32,
+    ) -> OverlayRectId {
+        self.schedule_frame();
+        self.overlay_rects
+            .add(&self.device, &self.queue, &self.pipeline, rect, color, z)
+    }
+
+    /// Stop drawing a rect added with [`Self::add_overlay_rect`]. Returns `false` if
+    /// it had already been removed.
+    pub fn remove_overlay_rect(&mut self, id: OverlayRectId) -> bool {
+        self.schedule_frame();
+        self.overlay_rects.remove(id)
+    }
+
+    /// Set a hook that runs after the frame is cleared but before any windows are drawn,
+    /// for drawing content that should appear underneath windows.
+    pub fn on_pre_render(&mut self, hook: impl FnMut(&mut RenderContext<'_>) + Send + 'static) {
+        self.pre_render = Some(Box::new(hook));
+    }
+
+    /// Set a hook that runs after all windows have been drawn, for drawing content
+    /// (bars, widgets, etc.) on top of them.
+    pub fn on_post_render(&mut self, hook: impl FnMut(&mut RenderContext<'_>) + Send + 'static) {
+        self.post_render = Some(Box::new(hook));
+    }
+
+    /// Set a hook that runs after each frame is presented, for keeping things like bars
+    /// and animations in step with the compositor's frames.
+    pub fn on_present(&mut self, hook: impl FnMut(&PresentInfo) + Send + 'static) {
+        self.on_present = Some(Box::new(hook));
+    }
+
+    /// Subscribe to high-level events about windows, focus and frames from now on.
+    ///
+    /// The stream ends once the compositor is dropped.
+    pub fn events(&self) -> EventStream {
+        EventStream::new(self.events.subscribe())
+    }
+
+    /// Tell [`Compositor::events`] subscribers about `ev`.
+    fn notify(&self, ev: CompositorEvent) {
+        // Failing just means nobody's subscribed.
+        self.events.send(ev).ok();
+    }
+
+    /// Set a hook that runs when a frame reaches the screen, as reported by the X server.
+    /// Unlike [`Compositor::on_present`], this measures when frames are actually shown,
+    /// but it's never called without the Present extension, or when presenting with DBE.
+    pub fn on_display(&mut self, hook: impl FnMut(&DisplayInfo) + Send + 'static) {
+        self.on_display = Some(Box::new(hook));
+    }
+
+    /// Resize the surface, e.g. after the window it presents to was resized.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.config.width = width as u32;
+        self.config.height = height as u32;
+        self.presenter.configure(&self.device, &self.config);
+        self.scaled = scaled_target(&self.device, &self.pipeline, &self.config, &self.options);
+        self.msaa = msaa_target(
+            &self.device,
+            &self.pipeline,
+            &self.config,
+            self.scaled.as_ref(),
+        );
+        self.schedule_frame();
+    }
+
+    /// Catch up with RandR changing the screen: resize the overlay and the surface if the
+    /// screen's size changed, and pick up the new monitor layout.
+    ///
+    /// The server doesn't resize the overlay along with the root window, so without this
+    /// the surface and the window it presents to would disagree on their size.
+    async fn screen_changed(&mut self) -> Result<()> {
+        let root = self.conn.get_geometry(self.root_win).await?.reply().await?;
+        let size = (root.width, root.height);
+        self.monitors = query_monitors(&self.conn, self.root_win, size).await?;
+        if let Some(name) = &self.options.output {
+            match self.monitors.iter().find(|monitor| &monitor.name == name) {
+                Some(monitor) => self.output = Some(monitor.rect),
+                None => warn!("Output {name} is gone, still compositing where it was"),
+            }
+        }
+        if let Some(mirror) = &self.options.mirror {
+            match find_mirror(&self.monitors, mirror) {
+                Ok(rects) => self.mirror = Some(rects),
+                Err(e) => warn!("{e}, still mirroring where it was"),
+            }
+        }
+
+        let resized = size != self.root_size;
+        if resized {
+            info!("Screen resized to {}x{}", size.0, size.1);
+            self.root_size = size;
+        }
+        // There's no overlay while suspended, and the one we get on resuming will be the
+        // new size anyway.
+        if resized && !self.suspended {
+            self.conn
+                .configure_window(
+                    self.overlay_win,
+                    &ConfigureWindowAux::new()
+                        .width(size.0 as u32)
+                        .height(size.1 as u32),
+                )
+                .await?
+                .check()
+                .await?;
+            let overlay = self
+                .conn
+                .get_geometry(self.overlay_win)
+                .await?
+                .reply()
+                .await?;
+            if (overlay.width, overlay.height) != size {
+                warn!(
+                    "Overlay window is {}x{} after resizing it to {}x{}",
+                    overlay.width, overlay.height, size.0, size.1
+                );
+            }
+            self.resize(overlay.width, overlay.height);
+            // Resizing can leave the overlay with the default input shape covering all of
+            // it, which would swallow every click.
+            set_input_region(&self.conn, self.overlay_win, &self.overlay_input).await?;
+        }
+
+        self.background = Background::new(
+            &self.device,
+            &self.queue,
+            &self.pipeline,
+            render_format(&self.config),
+            &self.options,
+            &self.monitors,
+        )?;
+        if self.output.is_some() {
+            self.update_overlay_shape().await?;
+        }
+        // Windows stay put, but the monitors under them may not have.
+        let windows: Vec<_> = self.windows.keys().copied().collect();
+        for win in windows {
+            self.update_window_monitor(win);
+        }
+        self.schedule_frame();
+        Ok(())
+    }
+
+    /// Note that something on screen changed, so the next loop iteration should render.
+    fn schedule_frame(&mut self) {
+        self.needs_frame = true;
+        self.idle.activity();
+    }
+
+    /// Draw and present a frame, unless the GPU is still busy with the previous one.
+    ///
+    /// Skipping a frame loses nothing: damage has already been uploaded to the window
+    /// textures, so the next frame that does get drawn shows all of it.
+    pub fn render(&mut self) -> Result<()> {
+        if self.suspended || self.frozen {
+            return Ok(());
+        }
+        let _span = trace_span!(stats::RENDER_SPAN).entered();
+        let started = Instant::now();
+
+        if self.frames_in_flight.is_full() {
+            // Completion callbacks only run when the device is polled.
+            self.device.poll(wgpu::Maintain::Poll);
+            if self.frames_in_flight.is_full() {
+                trace!("GPU is still busy with earlier frames, skipping");
+                self.stats.frame_dropped();
+                return Ok(());
+            }
+        }
+
+        let Some(output) =
+            self.presenter
+                .acquire(&self.device, &self.config, render_format(&self.config))?
+        else {
+            // Nothing is lost, since the next frame draws everything as it is by then.
+            self.stats.frame_dropped();
+            return Ok(());
+        };
+        // With a render scale the scene goes into a smaller texture first. Everything is
+        // still laid out in output pixels, which the shaders map onto whatever they draw to.
+        let resolved = self
+            .scaled
+            .as_ref()
+            .map_or(&output.view, |scaled| scaled.view());
+        // With multisampling it's drawn into a multisampled texture, and only resolved once
+        // the hooks are done with it.
+        let view = self.msaa.as_ref().unwrap_or(resolved);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        // In bypass mode we present an empty, transparent frame so that overlay/surface
+        // issues can be told apart from problems with the window textures.
+        let clear_color = if self.options.bypass {
+            wgpu::Color::TRANSPARENT
+        } else {
+            self.options.clear_color
+        };
+
+        let screen = (self.config.width, self.config.height);
+        let global_scale = self.options.global_scale;
+        let mut bordered = None;
+        let mut cached = 0;
+        // What each window draws over and covers, for working out what's hidden.
+        let mut layers = Vec::new();
+        for (win, state) in self.visible_windows() {
+            // Without an active window (e.g. no EWMH window manager) nothing is dimmed.
+            let dim = match self.active_window {
+                Some(active) if active != win => self.options.inactive_dim,
+                _ => 0.0,
+            };
+            let appearance = self.animations.appearance(win);
+            let geometry = scale_rect(appearance.apply(state.extent()), global_scale);
+            let corner_radius = state.corner_radius(self.options.corner_radius) * global_scale;
+            // Scaled up at lower render scales, where a pixel of the scene is smaller than
+            // one of the output.
+            let corner_aa_width =
+                self.options.corner_aa_width * global_scale * self.window_scale(win).unwrap_or(1.0)
+                    / self.scaled.as_ref().map_or(1.0, ScaledTarget::scale);
+            let mut extent = geometry;
+            if let (Some(layer), Some(border)) = (&self.active_border, self.options.active_border) {
+                if self.active_window == Some(win) && state.wants_chrome() {
+                    let uniforms = BorderUniforms {
+                        geometry,
+                        screen,
+                        border: ActiveBorder {
+                            width: border.width * global_scale,
+                            glow_radius: border.glow_radius * global_scale,
+                            ..border
+                        },
+                        corner_radius,
+                        corner_smoothing: self.options.corner_smoothing,
+                        opacity: appearance.opacity,
+                        corner_aa_width,
+                    };
+                    layer.write_uniforms(&self.queue, &uniforms);
+                    extent = uniforms.extent();
+                    bordered = Some(win);
+                }
+            }
+            let opaque = state.texture.is_some()
+                && occlusion::covers(
+                    state.has_alpha,
+                    appearance.opacity,
+                    corner_radius,
+                    state.blend_mode(),
+                );
+            layers.push(Layer {
+                extent,
+                opaque: opaque.then_some(geometry),
+            });
+            if let Some(texture) = &state.texture {
+                cached += u64::from(!state.dirty);
+                texture.write_uniforms(
+                    &self.queue,
+                    &WindowUniforms {
+                        geometry,
+                        screen,
+                        dim,
+                        opacity: appearance.opacity,
+                        has_alpha: state.has_alpha,
+                        straight_alpha: state.alpha_mode == AlphaMode::Straight,
+                        corner_radius,
+                        corner_smoothing: self.options.corner_smoothing,
+                        corner_aa_width,
+                    },
+                );
+            }
+        }
+
+        // Where each window can be seen, in the same order as `visible_windows`.
+        let everywhere = [xproto::Rectangle {
+            x: 0,
+            y: 0,
+            width: screen.0 as u16,
+            height: screen.1 as u16,
+        }];
+        let regions = self
+            .options
+            .cull_occluded
+            .then(|| occlusion::visible_regions(&layers, everywhere[0]));
+        let target = self.scaled.as_ref().map_or(screen, |scaled| scaled.size());
+
+        let frame = FrameInfo {
+            frame: self.frame_count,
+            size: target,
+            format: render_format(&self.config),
+            sample_count: self.pipeline.sample_count(),
+        };
+
+        // Clear and draw the wallpaper in a separate pass, so a pre-render hook can draw
+        // on top of it before the windows go on top.
+        self.background.write_uniforms(&self.queue, screen);
+        self.overlay_rects.write_uniforms(&self.queue, screen);
+        let tint_opacity = self.tint_opacity();
+        if let Some(opacity) = tint_opacity {
+            self.tint.write_uniforms(&self.queue, screen, opacity);
+        }
+        #[cfg(debug_assertions)]
+        self.damage_overlay.write_uniforms(
+            &self.device,
+            &self.queue,
+            &self.pipeline,
+            screen,
+            |rect| scale_rect(rect, global_scale),
+        );
+        // Damage only decides what gets uploaded. Every frame clears and redraws the whole
+        // stack bottom to top, so new contents of a window are always covered again by
+        // whatever is above it, however many overlapping windows changed at once.
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Background Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if !self.options.bypass {
+            self.background.draw(&mut pass, &self.pipeline, screen);
+        }
+        drop(pass);
+
+        if let Some(hook) = &mut self.pre_render {
+            hook(&mut RenderContext {
+                device: &self.device,
+                queue: &self.queue,
+                view,
+                encoder: &mut encoder,
+                frame,
+            });
+        }
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        let mut occluded = 0;
+        if !self.options.bypass {
+            pass.set_pipeline(self.pipeline.pipeline());
+            self.overlay_rects.draw_below(&mut pass);
+            for (i, (win, state)) in self.visible_windows().enumerate() {
+                let region = regions
+                    .as_ref()
+                    .map_or(&everywhere[..], |regions| &regions[i]);
+                occluded += u64::from(region.is_empty());
+                for &rect in region {
+                    let (x, y, width, height) = occlusion::scissor(rect, screen, target);
+                    pass.set_scissor_rect(x, y, width, height);
+                    if let Some(texture) = &state.texture {
+                        let blend_mode = state.blend_mode();
+                        if blend_mode != BlendMode::Normal {
+                            pass.set_pipeline(self.pipeline.blended(blend_mode));
+                        }
+                        pass.set_bind_group(0, texture.bind_group(), &[]);
+                        pass.draw(0..6, 0..1);
+                        if blend_mode != BlendMode::Normal {
+                            pass.set_pipeline(self.pipeline.pipeline());
+                        }
+                    }
+                    // Right on top of the window, so that windows above it cover it too.
+                    if let (Some(layer), true) = (&self.active_border, bordered == Some(win)) {
+                        layer.draw(&mut pass);
+                        pass.set_pipeline(self.pipeline.pipeline());
+                    }
+                }
+            }
+            pass.set_scissor_rect(0, 0, target.0, target.1);
+            self.overlay_rects.draw_above(&mut pass);
+            if tint_opacity.is_some() {
+                self.tint.draw(&mut pass);
+            }
+            #[cfg(debug_assertions)]
+            self.damage_overlay.draw(&mut pass);
+        }
+        drop(pass);
+
+        if let Some(hook) = &mut self.post_render {
+            hook(&mut RenderContext {
+                device: &self.device,
+                queue: &self.queue,
+                view,
+                encoder: &mut encoder,
+                frame,
+            });
+        }
+
+        if let Some(msaa) = &self.msaa {
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Resolve Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: msaa,
+                    resolve_target: Some(resolved),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        // Every frame starts by clearing it anyway.
+                        store: wgpu::StoreOp::Discard,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+        }
+
+        // Streamed frames are copied before scaling up, so they're the size the scene was
+        // rendered at.
+        #[cfg(feature = "capture-stream")]
+        if let (Some(stream), Some(scaled)) = (&mut self.stream, &self.scaled) {
+            stream.capture(&self.device, &mut encoder, scaled.texture());
+        }
+
+        if let Some(scaled) = &self.scaled {
+            scaled.write_uniforms(&self.queue, screen);
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Upscale Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(self.pipeline.single_sampled());
+            scaled.draw(&mut pass);
+            if let Some((source, destination)) = self.mirror {
+                scaled.write_mirror_uniforms(&self.queue, screen, source, destination);
+                scaled.draw_mirror(&mut pass, screen, destination);
+            }
+        }
+
+        // submit will accept anything that implements IntoIter
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.frames_in_flight.submitted(&self.queue);
+        #[cfg(feature = "capture-stream")]
+        if let Some(stream) = &mut self.stream {
+            stream.submitted();
+        }
+
+        self.presenter.present(output, &self.device, &self.queue)?;
+        if let Some(hook) = &mut self.on_present {
+            hook(&PresentInfo {
+                frame: self.frame_count,
+                presented_at: Instant::now(),
+            });
+        }
+        self.notify(CompositorEvent::FrameRendered {
+            frame: self.frame_count,
+        });
+        self.frame_count += 1;
+        #[cfg(debug_assertions)]
+        self.damage_overlay.tick();
+        self.needs_frame = false;
+        self.last_frame = Instant::now();
+        self.stats.frame_rendered();
+        self.stats.cached_windows(cached);
+        self.stats.occluded_windows(occluded);
+        for state in self.windows.values_mut() {
+            state.dirty = false;
+        }
+        self.adapt_quality(started.elapsed());
+
+        Ok(())
+    }
+
+    /// Turn expensive effects off or back on if frames taking `frame_time` to render
+    /// call for it.
+    fn adapt_quality(&mut self, frame_time: Duration) {
+        let Some(quality) = &mut self.quality else {
+            return;
+        };
+        let Some(transition) = quality.frame(frame_time) else {
+            return;
+        };
+        let average = quality.average().unwrap_or(frame_time);
+        let budget = quality.budget();
+        match transition {
+            Transition::Degrade => {
+                let before = Box::new(self.options.clone());
+                let disabled = self.options.disable_expensive_effects();
+                if disabled.is_empty() {
+                    info!(
+                        "Frames take {:?} on average, over the {:?} budget, but there are no \
+                         effects to turn off",
+                        average, budget
+                    );
+                } else {
+                    info!(
+                        "Frames take {:?} on average, over the {:?} budget, turning off {}",
+                        average,
+                        budget,
+                        disabled.join(", ")
+                    );
+                }
+                self.full_quality = Some(before);
+            }
+            Transition::Restore => {
+                let Some(before) = self.full_quality.take() else {
+                    return;
+                };
+                info!(
+                    "Frames take {:?} on average, well within the {:?} budget, turning \
+                     effects back on",
+                    average, budget
+                );
+                self.options.restore_expensive_effects(&before);
+            }
+        }
+        self.schedule_frame();
+    }
+
+    /// Whether effects are turned off because frames were over [`Config::frame_budget`].
+    pub fn is_quality_degraded(&self) -> bool {
+        self.quality
+            .as_ref()
+            .is_some_and(QualityController::is_degraded)
+    }
+
+    /// Recreate the device, queue, and pipeline after the device was lost, and re-import
+    /// the contents of every mapped window.
+    ///
+    /// Any resources created by render hooks belong to the old device and will need to
+    /// be recreated by the embedder.
+    async fn recover_device(&mut self) -> Result<()> {
+        warn!("Recreating GPU device");
+
+        let (device, queue) = request_device(&self.adapter, &self.device_lost).await?;
+        self.pipeline = WindowPipeline::with_samples(
+            &device,
+            render_format(&self.config),
+            self.pipeline.sample_count(),
+        );
+        self.device = device;
+        self.queue = queue;
+        #[cfg(all(feature = "shader-reload", debug_assertions))]
+        self.shader_watcher.reset();
+        // Work on the old device will never complete.
+        self.frames_in_flight.reset();
+        self.presenter.configure(&self.device, &self.config);
+        self.background = Background::new(
+            &self.device,
+            &self.queue,
+            &self.pipeline,
+            render_format(&self.config),
+            &self.options,
+            &self.monitors,
+        )?;
+        self.overlay_rects
+            .recreate(&self.device, &self.queue, &self.pipeline);
+        self.tint
+            .recreate(&self.device, &self.queue, &self.pipeline);
+        #[cfg(debug_assertions)]
+        self.damage_overlay.clear();
+        if self.active_border.is_some() {
+            self.active_border = Some(BorderLayer::with_samples(
+                &self.device,
+                render_format(&self.config),
+                self.pipeline.sample_count(),
+            ));
+        }
+        self.scaled = scaled_target(&self.device, &self.pipeline, &self.config, &self.options);
+        self.msaa = msaa_target(
+            &self.device,
+            &self.pipeline,
+            &self.config,
+            self.scaled.as_ref(),
+        );
+        #[cfg(feature = "capture-stream")]
+        if let Some(stream) = &mut self.stream {
+            stream.reset();
+        }
+
+        let mapped: Vec<_> = self
+            .windows
+            .iter_mut()
+            .filter_map(|(&win, state)| {
+                state.texture = None;
+                (state.mapped && !state.excluded).then_some(win)
+            })
+            .collect();
+        for win in mapped {
+            let result = self.refresh_pixmap(win).await;
+            self.forget_if_gone(win, result).await?;
+        }
+        self.schedule_frame();
+
+        Ok(())
+    }
+
+    /// Iterate over mapped windows that aren't fully obscured, in stacking order from
+    /// bottom to top.
+    fn visible_windows(&self) -> impl Iterator<Item = (xproto::Window, &WindowState)> {
+        self.stack
+            .iter()
+            .filter_map(|&win| Some((win, self.windows.get(&win)?)))
+            .filter(|(_, state)| self.is_visible(state))
+    }
+
+    /// Whether a window is drawn in the next frame, as long as it has a texture.
+    fn is_visible(&self, state: &WindowState) -> bool {
+        state.mapped
+            && !state.excluded
+            && !state.wm_state.hidden
+            && state.visibility != xproto::Visibility::FULLY_OBSCURED
+            && self.on_output(state)
+    }
+
+    /// Make textures again for evicted windows that are drawn now, then evict textures of
+    /// windows that aren't until there are at most [`Config::max_window_textures`].
+    async fn limit_textures(&mut self) -> Result<()> {
+        let restore: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(_, state)| state.evicted && self.is_visible(state))
+            .map(|(&win, _)| win)
+            .collect();
+        for win in restore {
+            trace!("Restoring the texture of {:?}", win);
+            let result = self.refresh_pixmap(win).await;
+            self.forget_if_gone(win, result).await?;
+        }
+
+        let Some(limit) = self.options.max_window_textures else {
+            return Ok(());
+        };
+        let held = self
+            .windows
+            .values()
+            .filter(|state| state.texture.is_some())
+            .count();
+        let candidates = self
+            .windows
+            .iter()
+            .filter(|(_, state)| state.texture.is_some() && !self.is_visible(state))
+            .map(|(&win, state)| (win, state.last_upload));
+        let evicted = eviction::select(candidates, held, limit);
+        if !evicted.is_empty() {
+            debug!(
+                "Holding {held} window textures, evicting {} to stay under {limit}",
+                evicted.len()
+            );
+        }
+        for win in evicted {
+            if let Some(state) = self.windows.get_mut(&win) {
+                state.texture = None;
+                state.evicted = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// The part of the root window we're compositing.
+    fn composited_area(&self) -> xproto::Rectangle {
+        self.output.unwrap_or(xproto::Rectangle {
+            x: 0,
+            y: 0,
+            width: self.root_size.0,
+            height: self.root_size.1,
+        })
+    }
+
+    /// Whether any of the window is inside the composited area.
+    fn on_output(&self, state: &WindowState) -> bool {
+        let (a, b) = (state.extent(), self.composited_area());
+        let (a_right, a_bottom) = (a.x as i32 + a.width as i32, a.y as i32 + a.height as i32);
+        let (b_right, b_bottom) = (b.x as i32 + b.width as i32, b.y as i32 + b.height as i32);
+        (a.x as i32) < b_right
+            && (b.x as i32) < a_right
+            && (a.y as i32) < b_bottom
+            && (b.y as i32) < a_bottom
+    }
+
+    /// Animate every visible window into its slot in the expose grid.
+    fn layout_expose(&mut self) {
+        let monitor = match self.output {
+            Some(output) => self.monitors.iter().find(|monitor| monitor.rect == output),
+            None => self
+                .monitors
+                .iter()
+                .find(|monitor| monitor.primary)
+                .or(self.monitors.first()),
+        };
+        let area = monitor
+            .map(|monitor| monitor.rect)
+            .or(self.output)
+            .unwrap_or(self.composited_area());
+        // Keep clear of panels, which stay where they are.
+        let area = monitors::work_area(area, &self.reserved);
+        let scale = monitor.map_or(1.0, Monitor::scale);
+
+        let windows: Vec<_> = self
+            .visible_windows()
+            .filter(|(_, state)| state.struts.is_empty())
+            .map(|(win, state)| {
+                let extent = state.extent();
+                let from = monitors::monitor_at(&self.monitors, extent).map_or(1.0, Monitor::scale);
+                (win, extent, from)
+            })
+            .collect();
+        let cells = expose::grid(windows.len(), area, scale);
+
+        for ((win, extent, from), cell) in windows.into_iter().zip(cells) {
+            let target = expose::fit(extent, cell, scale / from);
+            let appearance = Appearance {
+                offset: (
+                    target.x as f32 - extent.x as f32,
+                    target.y as f32 - extent.y as f32,
+                ),
+                grow: (
+                    target.width as f32 - extent.width as f32,
+                    target.height as f32 - extent.height as f32,
+                ),
+                ..Default::default()
+            };
+            self.animations.animate(
+                win,
+                appearance,
+                EXPOSE_DURATION,
+                self.options.easings.expose,
+            );
+        }
+    }
+
+    /// Re-read `_NET_ACTIVE_WINDOW` from the root window.
+    ///
+    /// The property is optional (it's only set by EWMH window managers) and may point at
+    /// a client window nested inside a frame, so it's resolved to the tracked top-level
+    /// window containing it, if any.
+    async fn update_active_window(&mut self) -> Result<()> {
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                self.root_win,
+                self.atoms.net_active_window,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )
+            .await?
+            .reply()
+            .await?;
+
+        let active = match reply.value32().and_then(|mut v| v.next()) {
+            Some(win) if win != x11rb::NONE => self.toplevel_of(win).await,
+            _ => None,
+        };
+
+        if active != self.active_window {
+            trace!("Active window changed: {:?}", active);
+            self.active_window = active;
+            self.notify(CompositorEvent::FocusChanged { window: active });
+        }
+
+        Ok(())
+    }
+
+    /// Find the tracked child of the root that `win` is (or is inside of).
+    ///
+    /// Returns `None` if `win` isn't inside any tracked window, or no longer exists.
+    async fn toplevel_of(&self, mut win: xproto::Window) -> Option<xproto::Window> {
+        loop {
+            if self.windows.contains_key(&win) {
+                return Some(win);
+            }
+            let tree = self.conn.query_tree(win).await.ok()?.reply().await.ok()?;
+            if tree.parent == self.root_win || tree.parent == x11rb::NONE {
+                return None;
+            }
+            win = tree.parent;
+        }
+    }
+
+    /// Start tracking the windows that existed before we started.
+    async fn adopt_windows(&mut self, existing: Vec<ExistingWindow>) -> Result<()> {
+        for window in existing {
+            if window.window == self.overlay_win {
+                continue;
+            }
+
+            let result = self
+                .track_window(window.window, window.geometry, window.border_width)
+                .await;
+            self.forget_if_gone(window.window, result).await?;
+            if window.viewable {
+                let result = self.map_window(window.window).await;
+                self.forget_if_gone(window.window, result).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start tracking a newly created child of the root.
+    ///
+    /// The window is redirected automatically, but we don't have anything to draw until
+    /// it's mapped.
+    async fn create_window(&mut self, ev: xproto::CreateNotifyEvent) -> Result<()> {
+        if ev.parent != self.root_win || ev.window == self.overlay_win {
+            return Ok(());
+        }
+
+        let geometry = xproto::Rectangle {
+            x: ev.x,
+            y: ev.y,
+            width: ev.width,
+            height: ev.height,
+        };
+        self.track_window(ev.window, geometry, ev.border_width)
+            .await
+    }
+
+    /// Add a window to the tracking map and the top of the stack, and start listening for
+    /// damage to it.
+    ///
+    /// Input-only windows are ignored, since they have no contents to composite.
+    async fn track_window(
+        &mut self,
+        win: xproto::Window,
+        geometry: xproto::Rectangle,
+        border_width: u16,
+    ) -> Result<()> {
+        if self.windows.contains_key(&win) {
+            return Ok(());
+        }
+
+        // The window may already be gone by the time we get to it.
+        let Ok(attributes) = self.conn.get_window_attributes(win).await?.reply().await else {
+            return Ok(());
+        };
+        if attributes.class == xproto::WindowClass::INPUT_ONLY {
+            return Ok(());
+        }
+
+        let damage = self.conn.generate_id().await?;
+        self.conn
+            .damage_create(damage, win, ReportLevel::NON_EMPTY)
+            .await?
+            .check()
+            .await?;
+
+        // Visibility changes tell us when the window is completely covered and can be
+        // skipped, and property changes when its title changes.
+        self.conn
+            .change_window_attributes(
+                win,
+                &ChangeWindowAttributesAux::new()
+                    .event_mask(EventMask::VISIBILITY_CHANGE | EventMask::PROPERTY_CHANGE),
+            )
+            .await?
+            .check()
+            .await?;
+
+        let mut state = WindowState::new(geometry, border_width, damage);
+        state.has_alpha = self.argb_visuals.contains(&attributes.visual);
+        self.windows.insert(win, state);
+        self.stack.push(win);
+
+        self.apply_window_rules(win).await
+    }
+
+    /// Start tracking a window if we aren't already, and (re-)acquire its contents.
+    async fn map_window(&mut self, win: xproto::Window) -> Result<()> {
+        if win == self.overlay_win {
+            return Ok(());
+        }
+
+        if !self.windows.contains_key(&win) {
+            let reply = self.conn.get_geometry(win).await?.reply().await?;
+            let geometry = xproto::Rectangle {
+                x: reply.x,
+                y: reply.y,
+                width: reply.width,
+                height: reply.height,
+            };
+            self.track_window(win, geometry, reply.border_width).await?;
+        }
+
+        // By the time a frame is mapped, the window manager has put the client inside it.
+        self.update_client(win).await?;
+
+        let Some(state) = self.windows.get_mut(&win) else {
+            return Ok(());
+        };
+        state.mapped = true;
+        let geometry = state.geometry;
+        if !state.struts.is_empty() {
+            self.update_reserved();
+        }
+        self.notify(CompositorEvent::WindowMapped {
+            window: win,
+            geometry,
+        });
+        self.update_window_monitor(win);
+
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        // Excluded windows are drawn by the server, so we just need to keep the overlay
+        // out of their way.
+        if state.excluded {
+            return self.update_overlay_shape().await;
+        }
+        self.refresh_pixmap(win).await?;
+        if self.options.icon_placeholders {
+            self.show_placeholder(win).await?;
+        }
+        Ok(())
+    }
+
+    /// Draw the icon of a newly mapped window into its texture in place of its contents,
+    /// until the client draws something and damages it.
+    async fn show_placeholder(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let Some((width, height)) = state.texture.as_ref().map(WindowTexture::size) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                client,
+                self.atoms.net_wm_icon,
+                AtomEnum::CARDINAL,
+                0,
+                icon::MAX_ICON_LENGTH,
+            )
+            .await?
+            .reply()
+            .await?;
+        let data: Vec<u32> = reply.value32().into_iter().flatten().collect();
+        let icon = icon::choose(&data, (width, height));
+        trace!(
+            "Window {:?} placeholder icon: {:?}",
+            win,
+            icon.as_ref().map(|icon| (icon.width, icon.height))
+        );
+
+        let Some(state) = self.windows.get_mut(&win) else {
+            return Ok(());
+        };
+        if let Some(texture) = &state.texture {
+            let pixels = icon::placeholder(icon.as_ref(), width, height);
+            texture.write(
+                &self.queue,
+                xproto::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                },
+                &pixels,
+            );
+            state.placeholder = true;
+            state.dirty = true;
+        }
+        Ok(())
+    }
+
+    async fn unmap_window(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get_mut(&win) else {
+            return Ok(());
+        };
+        state.mapped = false;
+        let excluded = state.excluded;
+        let reserving = !state.struts.is_empty();
+
+        // The named pixmap is no longer updated once the window is unmapped.
+        if let Some(pixmap) = state.pixmap.take() {
+            self.conn.free_pixmap(pixmap).await?.check().await?;
+        }
+        self.notify(CompositorEvent::WindowUnmapped { window: win });
+
+        if reserving {
+            self.update_reserved();
+        }
+        if excluded {
+            self.update_overlay_shape().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the result of handling an event about `win`, and if it failed because the
+    /// window has since been destroyed, stop tracking it instead of failing.
+    ///
+    /// We'll usually get a `DestroyNotify` for it soon after anyway.
+    async fn forget_if_gone(&mut self, win: xproto::Window, result: Result<()>) -> Result<()> {
+        match result {
+            Err(e) if is_window_gone(&e) => {
+                debug!("Window {:?} went away while handling an event: {}", win, e);
+                self.drop_window(win).await
+            }
+            result => result,
+        }
+    }
+
+    /// Stop tracking a window and free everything we hold for it.
+    async fn drop_window(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.remove(&win) else {
+            return Ok(());
+        };
+        self.stack.retain(|&w| w != win);
+        self.animations.remove(win);
+        if state.mapped {
+            self.notify(CompositorEvent::WindowUnmapped { window: win });
+        }
+        if self.active_window == Some(win) {
+            self.active_window = None;
+            self.notify(CompositorEvent::FocusChanged { window: None });
+        }
+        if !state.struts.is_empty() {
+            self.update_reserved();
+        }
+
+        // Named pixmaps outlive the window, so this has to be freed explicitly.
+        if let Some(pixmap) = state.pixmap {
+            self.conn.free_pixmap(pixmap).await?.check().await?;
+        }
+
+        // If the window was destroyed, the server has already freed its damage object
+        // along with it.
+        match self.conn.damage_destroy(state.damage).await?.check().await {
+            Ok(()) | Err(ReplyError::X11Error(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if state.excluded {
+            self.update_overlay_shape().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn configure_window(&mut self, ev: xproto::ConfigureNotifyEvent) -> Result<()> {
+        let Some(state) = self.windows.get_mut(&ev.window) else {
+            return Ok(());
+        };
+
+        let old_extent = state.extent();
+        state.geometry = xproto::Rectangle {
+            x: ev.x,
+            y: ev.y,
+            width: ev.width,
+            height: ev.height,
+        };
+        state.border_width = ev.border_width;
+        let extent = state.extent();
+        let resized = (old_extent.width, old_extent.height) != (extent.width, extent.height);
+        let mapped = state.mapped;
+
+        if extent != old_extent {
+            let now = Instant::now();
+            // Windows being dragged or resized interactively get a stream of configures,
+            // and animating each one would just make the window lag behind the pointer.
+            let interactive = state
+                .last_configure
+                .is_some_and(|last| now - last < INTERACTIVE_CONFIGURE_INTERVAL);
+            state.last_configure = Some(now);
+
+            match self.options.geometry_animation {
+                // The expose layout is recomputed below instead.
+                _ if self.expose => {}
+                Some(duration) if mapped && !interactive => {
+                    // Start from wherever the window is currently drawn, which may be
+                    // part way through a previous animation.
+                    let current = self.animations.appearance(ev.window);
+                    let from = current.apply(old_extent);
+                    let start = Appearance {
+                        offset: (
+                            from.x as f32 - extent.x as f32,
+                            from.y as f32 - extent.y as f32,
+                        ),
+                        grow: (
+                            from.width as f32 - extent.width as f32,
+                            from.height as f32 - extent.height as f32,
+                        ),
+                        ..current
+                    };
+                    let end = Appearance {
+                        opacity: start.opacity,
+                        ..Default::default()
+                    };
+                    self.animations.animate_from(
+                        ev.window,
+                        start,
+                        end,
+                        duration,
+                        self.options.easings.geometry,
+                    );
+                }
+                _ => self.animations.remove(ev.window),
+            }
+        }
+
+        self.restack(ev.window, ev.above_sibling);
+        self.notify(CompositorEvent::WindowConfigured {
+            window: ev.window,
+            geometry: xproto::Rectangle {
+                x: ev.x,
+                y: ev.y,
+                width: ev.width,
+                height: ev.height,
+            },
+        });
+        self.update_window_monitor(ev.window);
+
+        if self
+            .windows
+            .get(&ev.window)
+            .is_some_and(|state| state.excluded)
+        {
+            if mapped {
+                self.update_overlay_shape().await?;
+            }
+            return Ok(());
+        }
+
+        // Resizing a window allocates a new backing pixmap, so the old one is stale.
+        if resized && mapped {
+            self.refresh_pixmap(ev.window).await?;
+        }
+
+        // The window may have moved onto the composited output.
+        self.catch_up(ev.window).await?;
+
+        Ok(())
+    }
+
+    async fn visibility_changed(&mut self, ev: xproto::VisibilityNotifyEvent) -> Result<()> {
+        let Some(state) = self.windows.get_mut(&ev.window) else {
+            return Ok(());
+        };
+        state.visibility = ev.state;
+
+        self.catch_up(ev.window).await
+    }
+
+    /// Whether the contents of a window would currently be seen, so that it's worth
+    /// uploading damage to it.
+    fn is_drawn(&self, state: &WindowState) -> bool {
+        state.visibility != xproto::Visibility::FULLY_OBSCURED && self.on_output(state)
+    }
+
+    /// Upload the full contents of a window if damage was skipped while it wasn't
+    /// drawn, and it's drawn again now.
+    async fn catch_up(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        if !state.stale || !self.is_drawn(state) {
+            return Ok(());
+        }
+
+        let size = state.texture.as_ref().map(WindowTexture::size);
+        if let Some(state) = self.windows.get_mut(&win) {
+            state.stale = false;
+            state.placeholder = false;
+        }
+        match size {
+            Some((width, height)) => {
+                self.upload(
+                    win,
+                    xproto::Rectangle {
+                        x: 0,
+                        y: 0,
+                        width,
+                        height,
+                    },
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The tracked window that is `win` or has it as its client.
+    fn frame_of(&self, win: xproto::Window) -> Option<xproto::Window> {
+        if self.windows.contains_key(&win) {
+            return Some(win);
+        }
+        self.windows
+            .iter()
+            .find(|(_, state)| state.client == Some(win))
+            .map(|(&frame, _)| frame)
+    }
+
+    /// Grab the bound keys again, after the keyboard mapping changed which key codes
+    /// produce their keysyms.
+    async fn regrab_keys(&mut self) -> Result<()> {
+        if self.options.key_bindings.is_empty() {
+            return Ok(());
+        }
+        self.keys.release(&self.conn, self.root_win).await?;
+        self.keys = KeyGrabs::grab(&self.conn, self.root_win, &self.options.key_bindings).await?;
+        Ok(())
+    }
+
+    /// Find the client window inside `win`, start watching its properties, and re-read
+    /// the ones we care about.
+    async fn update_client(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let old_client = state.client;
+
+        let client = self.find_client(win).await?;
+        if let Some(state) = self.windows.get_mut(&win) {
+            state.client = client;
+        }
+
+        // The frame itself already has property changes selected.
+        if let Some(client) = client.filter(|&client| client != win && Some(client) != old_client) {
+            self.conn
+                .change_window_attributes(
+                    client,
+                    &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+                )
+                .await?
+                .check()
+                .await?;
+        }
+
+        self.update_wm_state(win).await?;
+        self.update_decorations(win).await?;
+        self.update_struts(win).await?;
+        self.update_corner_radius(win).await?;
+        self.update_blend_mode(win).await?;
+        if client != old_client {
+            self.apply_window_rules(win).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the window with `WM_STATE` set at or below `win`, searching a few levels
+    /// down since some window managers nest clients more than one frame deep.
+    ///
+    /// Windows below `win` can be destroyed while we walk them, which says nothing about
+    /// `win` itself, so those are skipped rather than failing the search.
+    async fn find_client(&self, win: xproto::Window) -> Result<Option<xproto::Window>> {
+        const MAX_DEPTH: usize = 3;
+
+        let mut level = vec![win];
+        for _ in 0..MAX_DEPTH {
+            let mut next = Vec::new();
+            for candidate in level {
+                match self.children_unless_client(candidate).await {
+                    Ok(None) => return Ok(Some(candidate)),
+                    Ok(Some(children)) => next.extend(children),
+                    Err(e) if candidate != win && is_window_gone(&e) => {
+                        debug!(
+                            "Window {:?} went away while looking for a client: {}",
+                            candidate, e
+                        );
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            level = next;
+        }
+
+        Ok(None)
+    }
+
+    /// The children of `candidate`, or `None` if it has `WM_STATE` set and so is the
+    /// client window itself.
+    async fn children_unless_client(
+        &self,
+        candidate: xproto::Window,
+    ) -> Result<Option<Vec<xproto::Window>>> {
+        let reply = self
+            .conn
+            .get_property(false, candidate, self.atoms.wm_state, AtomEnum::ANY, 0, 0)
+            .await?
+            .reply()
+            .await?;
+        if reply.type_ != x11rb::NONE {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.conn
+                .query_tree(candidate)
+                .await?
+                .reply()
+                .await?
+                .children,
+        ))
+    }
+
+    /// Re-read `_NET_WM_STATE` from a window's client.
+    async fn update_wm_state(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                client,
+                self.atoms.net_wm_state,
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )
+            .await?
+            .reply()
+            .await?;
+        let mut wm_state = WmState::default();
+        for atom in reply.value32().into_iter().flatten() {
+            if atom == self.atoms.net_wm_state_hidden {
+                wm_state.hidden = true;
+            } else if atom == self.atoms.net_wm_state_fullscreen {
+                wm_state.fullscreen = true;
+            }
+        }
+
+        if let Some(state) = self.windows.get_mut(&win) {
+            if state.wm_state != wm_state {
+                trace!("Window {:?} state changed: {:?}", win, wm_state);
+                state.wm_state = wm_state;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-read `_MOTIF_WM_HINTS` from a window's client.
+    async fn update_decorations(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                client,
+                self.atoms.motif_wm_hints,
+                self.atoms.motif_wm_hints,
+                0,
+                5,
+            )
+            .await?
+            .reply()
+            .await?;
+        let hints: Vec<u32> = reply.value32().into_iter().flatten().collect();
+        let decorations = window::motif_decorations(&hints);
+
+        if let Some(state) = self.windows.get_mut(&win) {
+            if state.decorations != decorations {
+                trace!("Window {:?} decorations: {:?}", win, decorations);
+                state.decorations = decorations;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-read the corner radius the client of `win` asked for, if any.
+    async fn update_corner_radius(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                client,
+                self.atoms.recomp_corner_radius,
+                AtomEnum::CARDINAL,
+                0,
+                1,
+            )
+            .await?
+            .reply()
+            .await?;
+        let radius = reply.value32().and_then(|mut value| value.next());
+
+        if let Some(state) = self.windows.get_mut(&win) {
+            if state.corner_radius != radius {
+                trace!("Window {:?} corner radius: {:?}", win, radius);
+                state.corner_radius = radius;
+                self.schedule_frame();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-read the blend mode the client of `win` asked for, if any.
+    async fn update_blend_mode(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                client,
+                self.atoms.recomp_blend_mode,
+                AtomEnum::STRING,
+                0,
+                16,
+            )
+            .await?
+            .reply()
+            .await?;
+        let mode = if reply.value.is_empty() {
+            None
+        } else {
+            match String::from_utf8_lossy(&reply.value)
+                .trim_end_matches('\0')
+                .parse()
+            {
+                Ok(mode) => Some(mode),
+                Err(e) => {
+                    warn!("Ignoring _RECOMP_BLEND_MODE of window {:?}: {e}", win);
+                    None
+                }
+            }
+        };
+
+        if let Some(state) = self.windows.get_mut(&win) {
+            if state.requested_blend_mode != mode {
+                trace!("Window {:?} blend mode: {:?}", win, mode);
+                state.requested_blend_mode = mode;
+                self.schedule_frame();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-read the space `win` reserves along the screen's edges, preferring
+    /// `_NET_WM_STRUT_PARTIAL` over the older `_NET_WM_STRUT` as EWMH says to.
+    async fn update_struts(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let client = state.client.unwrap_or(win);
+
+        let mut struts = Vec::new();
+        for (atom, len) in [
+            (self.atoms.net_wm_strut_partial, 12),
+            (self.atoms.net_wm_strut, 4),
+        ] {
+            let reply = self
+                .conn
+                .get_property(false, client, atom, AtomEnum::CARDINAL, 0, len)
+                .await?
+                .reply()
+                .await?;
+            let value: Vec<u32> = reply.value32().into_iter().flatten().collect();
+            if !value.is_empty() {
+                struts = Strut::parse(&value, self.root_size);
+                break;
+            }
+        }
+
+        if let Some(state) = self.windows.get_mut(&win) {
+            if state.struts != struts {
+                trace!("Window {:?} struts: {:?}", win, struts);
+                state.struts = struts;
+                self.update_reserved();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gather the space reserved by every mapped window.
+    fn update_reserved(&mut self) {
+        let reserved: Vec<Strut> = self
+            .windows
+            .values()
+            .filter(|state| state.mapped)
+            .flat_map(|state| state.struts.iter().copied())
+            .collect();
+        if reserved != self.reserved {
+            debug!("Reserved screen space: {:?}", reserved);
+            self.reserved = reserved;
+        }
+    }
+
+    /// Read the properties window rules match on from `win`, or from its client window if
+    /// it's a frame, since that's where they're set.
+    async fn window_props(&self, win: xproto::Window) -> Result<WindowProps> {
+        let client = self
+            .windows
+            .get(&win)
+            .and_then(|state| state.client)
+            .unwrap_or(win);
+        let property = |atom: AtomEnum| {
+            self.conn
+                .get_property(false, client, atom, AtomEnum::ANY, 0, u32::MAX)
+        };
+        let name = property(AtomEnum::WM_NAME)
+            .await?
+            .reply()
+            .await
+            .ok()
+            .map(|reply| String::from_utf8_lossy(&reply.value).into_owned());
+        let class = property(AtomEnum::WM_CLASS)
+            .await?
+            .reply()
+            .await
+            .ok()
+            .and_then(|reply| WindowProps::parse_class(&reply.value));
+        Ok(WindowProps { name, class })
+    }
+
+    /// Check `win` against the configured window rules: pick its alpha mode, and
+    /// unredirect or redirect it if whether it's excluded changed.
+    ///
+    /// Excluded windows are drawn by the X server underneath the overlay, so the overlay's
+    /// bounding shape has a hole cut out wherever one is mapped. A fullscreen-unredirect
+    /// mode would work the same way, with the hole covering the whole screen.
+    async fn apply_window_rules(&mut self, win: xproto::Window) -> Result<()> {
+        if self.options.exclude.is_empty()
+            && self.options.straight_alpha.is_empty()
+            && self.options.blend_modes.is_empty()
+        {
+            return Ok(());
+        }
+        if !self.windows.contains_key(&win) {
+            return Ok(());
+        }
+
+        let props = self.window_props(win).await?;
+        let matches = |rules: &[WindowMatch]| rules.iter().any(|rule| rule.matches(win, &props));
+        let alpha_mode = if matches(&self.options.straight_alpha) {
+            AlphaMode::Straight
+        } else {
+            AlphaMode::Premultiplied
+        };
+        let excluded = matches(&self.options.exclude);
+        let blend_mode = self
+            .options
+            .blend_modes
+            .iter()
+            .find(|(rule, _)| rule.matches(win, &props))
+            .map_or(BlendMode::Normal, |&(_, mode)| mode);
+        let name = props.name;
+
+        let Some(state) = self.windows.get_mut(&win) else {
+            return Ok(());
+        };
+        if state.alpha_mode != alpha_mode {
+            debug!("Window {:?} ({:?}) has {:?} alpha", win, name, alpha_mode);
+            state.alpha_mode = alpha_mode;
+        }
+        if state.matched_blend_mode != blend_mode {
+            debug!("Window {:?} ({:?}) blends with {:?}", win, name, blend_mode);
+            state.matched_blend_mode = blend_mode;
+        }
+        if excluded == state.excluded {
+            return Ok(());
+        }
+        let mapped = state.mapped;
+
+        if excluded {
+            info!("Unredirecting excluded window {:?} ({:?})", win, name);
+            self.conn
+                .composite_unredirect_window(win, redirect_mode(&self.options))
+                .await?
+                .check()
+                .await?;
+            if let Some(state) = self.windows.get_mut(&win) {
+                state.excluded = true;
+                state.texture = None;
+                if let Some(pixmap) = state.pixmap.take() {
+                    self.conn.free_pixmap(pixmap).await?.check().await?;
+                }
+            }
+        } else {
+            info!("Redirecting window {:?} ({:?})", win, name);
+            self.conn
+                .composite_redirect_window(win, redirect_mode(&self.options))
+                .await?
+                .check()
+                .await?;
+            if let Some(state) = self.windows.get_mut(&win) {
+                state.excluded = false;
+            }
+            if mapped {
+                self.refresh_pixmap(win).await?;
+            }
+        }
+
+        if mapped {
+            self.update_overlay_shape().await?;
+        }
+        Ok(())
+    }
+
+    /// Shape the overlay so that it covers the composited area, except for mapped excluded
+    /// windows.
+    async fn update_overlay_shape(&self) -> Result<()> {
+        // There's no overlay to shape.
+        if self.suspended {
+            return Ok(());
+        }
+        let holes: Vec<_> = self
+            .stack
+            .iter()
+            .filter_map(|win| self.windows.get(win))
+            .filter(|state| state.mapped && state.excluded)
+            .map(WindowState::extent)
+            .collect();
+
+        if holes.is_empty() && self.output.is_none() {
+            // Resetting the shape makes the overlay rectangular again.
+            self.conn
+                .xfixes_set_window_shape_region(self.overlay_win, SK::BOUNDING, 0, 0, x11rb::NONE)
+                .await?
+                .check()
+                .await?;
+            return Ok(());
+        }
+
+        let area = self.composited_area();
+        let shape = self.conn.generate_id().await?;
+        let excluded = self.conn.generate_id().await?;
+        self.conn
+            .xfixes_create_region(shape, &[area])
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .xfixes_create_region(excluded, &holes)
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .xfixes_subtract_region(shape, excluded, shape)
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .xfixes_set_window_shape_region(self.overlay_win, SK::BOUNDING, 0, 0, shape)
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .xfixes_destroy_region(excluded)
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .xfixes_destroy_region(shape)
+            .await?
+            .check()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Move `win` to sit directly above `above` in the stack, or to the bottom if
+    /// `above` is `NONE`.
+    fn restack(&mut self, win: xproto::Window, above: xproto::Window) {
+        self.stack.retain(|&w| w != win);
+        let index = if above == x11rb::NONE {
+            0
+        } else {
+            self.stack
+                .iter()
+                .position(|&w| w == above)
+                .map(|i| i + 1)
+                .unwrap_or(self.stack.len())
+        };
+        self.stack.insert(index, win);
+    }
+
+    /// Name a new backing pixmap for the window, (re-)creating its texture if the size
+    /// changed, and upload its full contents.
+    async fn refresh_pixmap(&mut self, win: xproto::Window) -> Result<()> {
+        // Unredirected windows have no pixmap to name. Resuming refreshes them all.
+        if self.suspended {
+            return Ok(());
+        }
+        let Some(state) = self.windows.get_mut(&win) else {
+            return Ok(());
+        };
+        state.evicted = false;
+        state.placeholder = false;
+
+        if let Some(pixmap) = state.pixmap.take() {
+            self.conn.free_pixmap(pixmap).await?.check().await?;
+        }
+
+        // Don't try to import windows we couldn't make a texture for. They just won't be
+        // drawn until they're resized to something sensible.
+        let extent = state.extent();
+        let max = self.device.limits().max_texture_dimension_2d;
+        if extent.width == 0 || extent.height == 0 {
+            debug!("Not drawing empty window {:?}", win);
+            state.texture = None;
+            return Ok(());
+        }
+        if extent.width as u32 > max || extent.height as u32 > max {
+            warn!(
+                "Not drawing window {:?}: {}x{} is larger than the maximum texture size {}",
+                win, extent.width, extent.height, max
+            );
+            state.texture = None;
+            return Ok(());
+        }
+
+        let pixmap = self.conn.generate_id().await?;
+        self.conn
+            .composite_name_window_pixmap(win, pixmap)
+            .await?
+            .check()
+            .await?;
+        state.pixmap = Some(pixmap);
+
+        let (width, height) = (extent.width, extent.height);
+        if state.texture.as_ref().map(WindowTexture::size) != Some((width, height)) {
+            state.texture = Some(WindowTexture::new(
+                &self.device,
+                self.pipeline.bind_group_layout(),
+                self.pipeline.sampler(),
+                width,
+                height,
+            ));
+        }
+
+        self.upload(
+            win,
+            xproto::Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        )
+        .await
+    }
+
+    /// Apply pending damage to a window's texture, uploading only the damaged
+    /// rectangles when they're small relative to the window.
+    async fn damage_window(&mut self, win: xproto::Window) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+
+        // Move the accumulated damage into a region so we can look at its extents.
+        // This also resets the damage object, so we'll get notified of the next change.
+        let region = self.conn.generate_id().await?;
+        self.conn
+            .xfixes_create_region(region, &[])
+            .await?
+            .check()
+            .await?;
+        self.conn
+            .damage_subtract(state.damage, x11rb::NONE, region)
+            .await?
+            .check()
+            .await?;
+        let damaged = self
+            .conn
+            .xfixes_fetch_region(region)
+            .await?
+            .reply()
+            .await?
+            .rectangles;
+        self.conn
+            .xfixes_destroy_region(region)
+            .await?
+            .check()
+            .await?;
+
+        let Some((width, height)) = state.texture.as_ref().map(WindowTexture::size) else {
+            return Ok(());
+        };
+
+        // There's no point uploading contents nobody can see. Catch up once the window
+        // is visible again instead.
+        if !self.is_drawn(state) {
+            if let Some(state) = self.windows.get_mut(&win) {
+                state.stale = true;
+            }
+            return Ok(());
+        }
+
+        // Damage is relative to the window's origin, inside the border, while the pixmap
+        // includes the border.
+        let border = state.border_width as i16;
+        let damage: Vec<_> = damaged
+            .into_iter()
+            .map(|rect| xproto::Rectangle {
+                x: rect.x.saturating_add(border),
+                y: rect.y.saturating_add(border),
+                ..rect
+            })
+            .collect();
+
+        // The first damage after a placeholder replaces all of it, not just what was drawn.
+        let regions = if state.placeholder {
+            vec![xproto::Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }]
+        } else {
+            texture::upload_regions(&damage, width, height, &self.options.damage)
+        };
+        #[cfg(debug_assertions)]
+        if self.options.debug_damage {
+            let origin = state.extent();
+            for rect in &regions {
+                self.damage_overlay.add(xproto::Rectangle {
+                    x: origin.x.saturating_add(rect.x),
+                    y: origin.y.saturating_add(rect.y),
+                    ..*rect
+                });
+            }
+        }
+        for rect in regions {
+            self.upload(win, rect).await?;
+        }
+        if let Some(state) = self.windows.get_mut(&win) {
+            state.placeholder = false;
+        }
+        Ok(())
+    }
+
+    /// Copy `rect` (in window-local coordinates) from the window's pixmap into its texture.
+    async fn upload(&mut self, win: xproto::Window, rect: xproto::Rectangle) -> Result<()> {
+        let Some(state) = self.windows.get(&win) else {
+            return Ok(());
+        };
+        let (Some(pixmap), Some(texture)) = (state.pixmap, &state.texture) else {
+            return Ok(());
+        };
+
+        let image = self
+            .conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                pixmap,
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                !0,
+            )
+            .await?
+            .reply()
+            .await?;
+
+        let pixels = match ImageLayout::from_setup(self.conn.setup(), image.depth)
+            .and_then(|layout| layout.to_bgra(&image.data, rect.width, rect.height))
+        {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                warn!("Can't upload window {:?}: {e:#}", win);
+                return Ok(());
+            }
+        };
+        texture.write(&self.queue, rect, &pixels);
+        self.stats.upload(pixels.len() as u64);
+        self.upload_count += 1;
+        if let Some(state) = self.windows.get_mut(&win) {
+            state.dirty = true;
+            state.last_upload = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    async fn handle_event(&mut self, ev: x11rb::protocol::Event) -> Result<()> {
+        use x11rb::protocol::Event;
+        let changes_layout = matches!(
+            ev,
+            Event::MapNotify(_)
+                | Event::UnmapNotify(_)
+                | Event::ConfigureNotify(_)
+                | Event::DestroyNotify(_)
+        );
+
+        if let Some(input) = InputEvent::from_event(&ev) {
+            self.handle_input(input);
+            return Ok(());
+        }
+
+        match ev {
+            x11rb::protocol::Event::Unknown(_) => trace!("Unknown event"),
+            x11rb::protocol::Event::Error(err) => warn!("X11 Error: {:?}", err),
+            x11rb::protocol::Event::CreateNotify(ev) => {
+                trace!("CreateNotify: {:?}", ev);
+                let result = self.create_window(ev).await;
+                self.forget_if_gone(ev.window, result).await?;
+            }
+            x11rb::protocol::Event::DestroyNotify(ev) => {
+                trace!("DestroyNotify: {:?}", ev);
+                self.drop_window(ev.window).await?;
+            }
+            x11rb::protocol::Event::EnterNotify(ev) => {
+                trace!("EnterNotify: {:?}", ev);
+            }
+            x11rb::protocol::Event::FocusIn(ev) => {
+                trace!("FocusIn: {:?}", ev);
+            }
+            x11rb::protocol::Event::FocusOut(ev) => {
+                trace!("FocusOut: {:?}", ev);
+            }
+            x11rb::protocol::Event::LeaveNotify(ev) => {
+                trace!("LeaveNotify: {:?}", ev);
+            }
+            x11rb::protocol::Event::MapNotify(ev) => {
+                trace!("MapNotify: {:?}", ev);
+                let result = self.map_window(ev.window).await;
+                self.forget_if_gone(ev.window, result).await?;
+            }
+            x11rb::protocol::Event::MapRequest(ev) => {
+                trace!("MapRequest: {:?}", ev);
+            }
+            x11rb::protocol::Event::PresentCompleteNotify(ev) => {
+                trace!("PresentCompleteNotify: {:?}", ev);
+                if ev.window == self.overlay_win {
+                    self.frame_displayed(&ev);
+                }
+            }
+            x11rb::protocol::Event::RandrScreenChangeNotify(ev) => {
+                trace!("RandrScreenChangeNotify: {:?}", ev);
+                // A nested compositor's size follows its own window, not the screen.
+                if ev.root == self.root_win && self.nested.is_none() {
+                    self.screen_changed().await?;
+                }
+            }
+            x11rb::protocol::Event::MappingNotify(ev) => {
+                trace!("MappingNotify: {:?}", ev);
+                // Our bindings name keysyms and modifier bits, so only a change in which
+                // key codes produce which keysyms moves them.
+                if ev.request == xproto::Mapping::KEYBOARD {
+                    debug!("Keyboard mapping changed, grabbing bound keys again");
+                    self.regrab_keys().await?;
+                } else {
+                    debug!("{:?} mapping changed", ev.request);
+                }
+            }
+            x11rb::protocol::Event::PropertyNotify(ev) => {
+                trace!("PropertyNotify: {:?}", ev);
+                if ev.window == self.root_win && ev.atom == self.atoms.net_active_window {
+                    self.update_active_window().await?;
+                } else if let Some(frame) = self.frame_of(ev.window) {
+                    let rules_changed = ev.atom == u32::from(AtomEnum::WM_NAME)
+                        || ev.atom == u32::from(AtomEnum::WM_CLASS);
+                    let result = if rules_changed {
+                        self.apply_window_rules(frame).await
+                    } else if ev.atom == self.atoms.net_wm_state {
+                        self.update_wm_state(frame).await
+                    } else if ev.atom == self.atoms.motif_wm_hints {
+                        self.update_decorations(frame).await
+                    } else if ev.atom == self.atoms.net_wm_strut
+                        || ev.atom == self.atoms.net_wm_strut_partial
+                    {
+                        self.update_struts(frame).await
+                    } else if ev.atom == self.atoms.recomp_corner_radius {
+                        self.update_corner_radius(frame).await
+                    } else if ev.atom == self.atoms.recomp_blend_mode {
+                        self.update_blend_mode(frame).await
+                    } else {
+                        Ok(())
+                    };
+                    self.forget_if_gone(frame, result).await?;
+                }
+            }
+            x11rb::protocol::Event::ReparentNotify(ev) => {
+                trace!("ReparentNotify: {:?}", ev);
+            }
+            x11rb::protocol::Event::UnmapNotify(ev) => {
+                trace!("UnmapNotify: {:?}", ev);
+                self.unmap_window(ev.window).await?;
+            }
+            x11rb::protocol::Event::ConfigureNotify(ev) => {
+                trace!("ConfigureNotify: {:?}", ev);
+                let result = self.configure_window(ev).await;
+                self.forget_if_gone(ev.window, result).await?;
+            }
+            x11rb::protocol::Event::VisibilityNotify(ev) => {
+                trace!("VisibilityNotify: {:?}", ev);
+                let result = self.visibility_changed(ev).await;
+                self.forget_if_gone(ev.window, result).await?;
+            }
+            x11rb::protocol::Event::SelectionClear(ev) => {
+                trace!("SelectionClear: {:?}", ev);
+                self.check_cm_selection(ev.selection, x11rb::NONE);
+            }
+            x11rb::protocol::Event::XfixesSelectionNotify(ev) => {
+                trace!("XfixesSelectionNotify: {:?}", ev);
+                self.check_cm_selection(ev.selection, ev.owner);
+            }
+            x11rb::protocol::Event::XfixesCursorNotify(ev) => {
+                trace!("XfixesCursorNotify: {:?}", ev);
+                if self.cursor.as_ref().map(|cursor| cursor.serial) != Some(ev.cursor_serial) {
+                    self.update_cursor().await?;
+                }
+            }
+            x11rb::protocol::Event::DamageNotify(ev) => {
+                trace!("DamageNotify: {:?}", ev);
+                self.stats.damage_event();
+                let result = self.damage_window(ev.drawable).await;
+                self.forget_if_gone(ev.drawable, result).await?;
+            }
+            ev => {
+                warn!("Unhandled event: {:?}", ev);
+            }
+        };
+
+        // Keep the grid up to date as windows come, go, and change size.
+        if changes_layout && self.expose {
+            self.layout_expose();
+        }
+
+        Ok(())
+    }
+
+    /// Record that a frame reached the screen.
+    fn frame_displayed(&mut self, ev: &CompleteNotifyEvent) {
+        let Some(info) = self
+            .display_timer
+            .as_mut()
+            .and_then(|timer| timer.complete(ev))
+        else {
+            return;
+        };
+        self.stats.frame_displayed(&info);
+        if let Some(hook) = &mut self.on_display {
+            hook(&info);
+        }
+    }
+
+    /// Stop the event loop if `selection` is our compositing manager selection and
+    /// `owner` isn't us, meaning another compositing manager has taken over the screen.
+    fn check_cm_selection(&mut self, selection: xproto::Atom, owner: xproto::Window) {
+        let Some(ours) = self.cm_selection else {
+            return;
+        };
+        if selection == ours.atom && owner != ours.window && !self.replaced {
+            info!("Another compositing manager took over, stopping");
+            self.replaced = true;
+        }
+    }
+
+    /// Fetch the current cursor image from the server.
+    async fn update_cursor(&mut self) -> Result<()> {
+        let reply = self.conn.xfixes_get_cursor_image().await?.reply().await?;
+        self.cursor = Some(CursorImage::from_reply(&reply));
+        Ok(())
+    }
+
+    /// Handle all events that are currently queued on the connection, without blocking.
+    ///
+    /// Returns the number of events handled.
+    pub async fn process_events(&mut self) -> Result<usize> {
+        let mut conn = self.conn.clone();
+        self.process_events_from(&mut conn).await
+    }
+
+    /// Handle all events that are currently queued in `events`, without blocking.
+    ///
+    /// Returns the number of events handled.
+    pub async fn process_events_from(&mut self, events: &mut impl EventSource) -> Result<usize> {
+        self.process_some_events_from(events, None).await
+    }
+
+    /// Like [`Compositor::process_events_from`], but stopping after `limit` events if set.
+    /// The rest are kept, in order, for next time.
+    async fn process_some_events_from(
+        &mut self,
+        events: &mut impl EventSource,
+        limit: Option<usize>,
+    ) -> Result<usize> {
+        let mut count = 0;
+        let mut changed = false;
+        while limit.is_none_or(|limit| count < limit) {
+            // Take everything that's arrived at once, so that how long each event waits
+            // behind the others, or behind a frame once the budget runs out, is measured.
+            if self.pending_events.is_empty() {
+                while let Some(ev) = events.poll_event()? {
+                    self.pending_events.push_back((ev, Instant::now()));
+                }
+            }
+            let Some((ev, received)) = self.pending_events.pop_front() else {
+                break;
+            };
+            self.stats.event_handled(received.elapsed());
+            changed |= needs_frame_after(&ev);
+            self.handle_event(ev).await?;
+            count += 1;
+        }
+        if changed {
+            self.schedule_frame();
+        }
+        Ok(count)
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let mut conn = self.conn.clone();
+        self.run_with(&mut conn).await
+    }
+
+    /// Composite a single frame of the windows as they are now, and wait for the GPU to
+    /// finish it. Unlike [`Compositor::step`] this never waits for events, so it's meant
+    /// for scripts and tests that want the current desktop drawn once and then exit.
+    pub async fn render_once(&mut self) -> Result<()> {
+        let mut conn = self.conn.clone();
+        self.process_events_from(&mut conn).await?;
+        self.limit_textures().await?;
+        if self.device_lost.swap(false, Ordering::SeqCst) {
+            self.recover_device().await?;
+        }
+
+        let rendered = self.frame_count;
+        self.render()?;
+        if self.frame_count == rendered {
+            anyhow::bail!("Couldn't render a frame");
+        }
+        self.device.poll(wgpu::Maintain::Wait);
+        Ok(())
+    }
+
+    /// Run the event loop like [`Compositor::run`], writing every event to a trace file at
+    /// `path` that [`Compositor::replay`] can play back.
+    pub async fn record(&mut self, path: &Path) -> Result<()> {
+        let mut recorder = Recorder::create(path, self.conn.clone()).await?;
+        self.run_with(&mut recorder).await
+    }
+
+    /// Run the event loop on the events in a trace file made by [`Compositor::record`],
+    /// instead of the ones arriving on the X connection.
+    ///
+    /// Returns once every event in the trace has been handled.
+    pub async fn replay(&mut self, path: &Path) -> Result<()> {
+        let mut replay = Replay::open(path, self.conn.clone()).await?;
+        self.run_with(&mut replay).await
+    }
+
+    /// Run the event loop, taking events from `events` rather than the X connection.
+    ///
+    /// Returns once `events` is closed.
+    pub async fn run_with(&mut self, events: &mut impl EventSource) -> Result<()> {
+        let result = loop {
+            match self.step_with(events).await {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(())) => break Ok(()),
+                Err(e) => {
+                    error!(state = %self.snapshot().to_json(), "Stopped on an error");
+                    break Err(e);
+                }
+            }
+        };
+        // Nothing is driving the compositor any more, so there are no heartbeats to miss.
+        self.watchdog = None;
+        result
+    }
+
+    /// Run one iteration of the event loop, for embedders driving the compositor from a
+    /// loop of their own: handle pending X events, then render a frame if one is due.
+    ///
+    /// While nothing is changing this waits for the next event (up to the idle refresh
+    /// interval) instead, just like [`Compositor::run`]. Returns whether to keep going.
+    pub async fn step(&mut self) -> Result<ControlFlow<()>> {
+        let mut conn = self.conn.clone();
+        self.step_with(&mut conn).await
+    }
+
+    /// Like [`Compositor::step`], but taking events from `events` rather than the X
+    /// connection. Breaks once `events` is closed.
+    ///
+    /// With a watchdog configured, it starts on the first step and counts the time
+    /// between steps as part of the loop.
+    pub async fn step_with(&mut self, events: &mut impl EventSource) -> Result<ControlFlow<()>> {
+        if let (None, Some(config)) = (&self.watchdog, self.options.watchdog) {
+            self.watchdog = Some(Watchdog::spawn(config));
+        }
+        self.beat();
+        self.check_vt().await?;
+
+        if self.device_lost.swap(false, Ordering::SeqCst) {
+            self.recover_device().await?;
+        }
+
+        // Anything past the budget is only deferred to the next step, after this frame.
+        let limit = self.options.max_events_per_frame;
+        self.process_some_events_from(events, limit).await?;
+        if (events.is_closed() && self.pending_events.is_empty()) || self.replaced {
+            return Ok(ControlFlow::Break(()));
+        }
+        self.limit_textures().await?;
+
+        if self.animations.is_animating() || self.damage_fading() {
+            self.schedule_frame();
+        }
+
+        // Nothing has changed (in a while, unless we only render on damage), so there's
+        // no point rendering until something does.
+        let idle = self.idle.update() == PowerState::Idle;
+        if idle
+            || self.suspended
+            || self.frozen
+            || (self.options.damage_driven && !self.needs_frame)
+        {
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.pause();
+            }
+            let refresh = self
+                .options
+                .idle_refresh
+                .map(|interval| interval.saturating_sub(self.last_frame.elapsed()));
+            // The service manager's watchdog expects to hear from us even while idle, and
+            // switching VTs has no event to wake us up.
+            let vt_poll = self.vt.as_ref().map(|_| vt::POLL_INTERVAL);
+            let timeout = [refresh, self.systemd_ping_interval(), vt_poll]
+                .into_iter()
+                .flatten()
+                .min();
+            // Events left over from the last batch don't need waiting for.
+            let ev = match self.pending_events.pop_front() {
+                Some((ev, received)) => {
+                    self.stats.event_handled(received.elapsed());
+                    Some(ev)
+                }
+                None => {
+                    let ev = match timeout {
+                        Some(wait) => events.wait_event_timeout(wait).await?,
+                        None => Some(events.wait_event().await?),
+                    };
+                    if ev.is_some() {
+                        // Handled as soon as it arrived.
+                        self.stats.event_handled(Duration::ZERO);
+                    }
+                    ev
+                }
+            };
+            self.beat();
+
+            match ev {
+                Some(ev) => {
+                    let changed = needs_frame_after(&ev);
+                    self.handle_event(ev).await?;
+                    if changed {
+                        self.schedule_frame();
+                    }
+                    return Ok(if self.replaced {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    });
+                }
+                // Only woken up to ping the watchdog.
+                None if self.suspended
+                    || self.frozen
+                    || self
+                        .options
+                        .idle_refresh
+                        .is_none_or(|interval| self.last_frame.elapsed() < interval) =>
+                {
+                    return Ok(ControlFlow::Continue(()));
+                }
+                // Draw a frame anyway, in case we missed some damage.
+                None => trace!("Nothing to draw for a while, refreshing anyway"),
+            }
+        }
+
+        #[cfg(all(feature = "shader-reload", debug_assertions))]
+        self.reload_shaders().await;
+
+        if let Some(interval) = self.frame_interval {
+            tokio::time::sleep_until(self.next_frame.into()).await;
+            // Don't try to catch up on frames we were too slow for.
+            self.next_frame = (self.next_frame + interval).max(Instant::now());
+        }
+
+        // Everything animated in this frame is shown as of the same moment.
+        self.animations.advance_to(Instant::now());
+
+        let frames = self.frame_count;
+        self.render()?;
+        let (windows, background, overlay_rects) =
+            (&self.windows, &self.background, &self.overlay_rects);
+        self.stats.maybe_report(windows.len(), || {
+            resource_stats(windows, background, overlay_rects)
+        });
+
+        // The frame was skipped because the GPU is behind. Give it a moment rather than
+        // spinning until it catches up.
+        if self.frame_count == frames {
+            tokio::time::sleep(BACKPRESSURE_RETRY).await;
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Suspend compositing if the user switched away from the server's VT, and resume
+    /// once they're back.
+    async fn check_vt(&mut self) -> Result<()> {
+        match self.vt.as_mut().and_then(VtWatcher::poll) {
+            Some(false) if !self.suspended => {
+                info!("Switched away from the X server's VT");
+                self.suspend().await?;
+                self.vt_suspended = true;
+            }
+            Some(true) if std::mem::take(&mut self.vt_suspended) => {
+                info!("Switched back to the X server's VT");
+                self.resume().await?;
+                self.schedule_frame();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn beat(&mut self) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.beat();
+        }
+        #[cfg(feature = "systemd")]
+        if let Some(systemd) = &mut self.systemd {
+            if let Err(e) = systemd.ping() {
+                warn!("{e:#}");
+            }
+        }
+    }
+
+    /// Whether recently damaged regions are still being tinted, so more frames are needed
+    /// to fade them out.
+    #[cfg(debug_assertions)]
+    fn damage_fading(&self) -> bool {
+        !self.damage_overlay.is_empty()
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn damage_fading(&self) -> bool {
+        false
+    }
+
+    /// How often the service manager's watchdog needs pinging, if it has one.
+    #[cfg(feature = "systemd")]
+    fn systemd_ping_interval(&self) -> Option<Duration> {
+        self.systemd
+            .as_ref()
+            .and_then(crate::systemd::Notifier::ping_interval)
+    }
+
+    #[cfg(not(feature = "systemd"))]
+    fn systemd_ping_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Rebuild the window pipeline if its shader changed on disk, keeping the old one if
+    /// the new shader doesn't compile.
+    #[cfg(all(feature = "shader-reload", debug_assertions))]
+    async fn reload_shaders(&mut self) {
+        let Some(source) = self.shader_watcher.poll() else {
+            return;
+        };
+        match self
+            .pipeline
+            .reload(&self.device, render_format(&self.config), &source)
+            .await
+        {
+            Ok(()) => self.schedule_frame(),
+            Err(e) => error!("Shader failed to compile, keeping the last good one: {e}"),
+        }
+    }
+
+    /// The current cursor image.
+    pub fn cursor(&self) -> Option<&CursorImage> {
+        self.cursor.as_ref()
+    }
+
+    /// Whether another compositing manager has taken over the screen, which stops the
+    /// event loop.
+    pub fn replaced(&self) -> bool {
+        self.replaced
+    }
+
+    /// How many frames have been presented so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// How many times window contents have been uploaded to their textures. Only damage,
+    /// and windows being mapped or resized, cause uploads, so this stays put while every
+    /// window is static.
+    pub fn upload_count(&self) -> u64 {
+        self.upload_count
+    }
+
+    /// The GPU resources currently held for windows, wallpapers, and overlay rects.
+    pub fn resource_stats(&self) -> ResourceStats {
+        resource_stats(&self.windows, &self.background, &self.overlay_rects)
+    }
+
+    /// The state of a tracked window, if we're tracking it.
+    pub fn window(&self, win: xproto::Window) -> Option<&WindowState> {
+        self.windows.get(&win)
+    }
+
+    /// All tracked windows in stacking order, bottom to top.
+    pub fn stacking_order(&self) -> &[xproto::Window] {
+        &self.stack
+    }
+
+    /// The rate frames are paced to in hertz, if they're paced with a timer rather than
+    /// by presenting.
+    pub fn frame_rate(&self) -> Option<f32> {
+        self.frame_interval
+            .map(|interval| 1.0 / interval.as_secs_f32())
+    }
+
+    /// The monitors we know of, or a single one covering the screen without RandR.
+    pub fn monitors(&self) -> &[Monitor] {
+        &self.monitors
+    }
+
+    /// The monitor showing most of a window, or `None` if the window isn't tracked or is
+    /// entirely off screen.
+    pub fn window_monitor(&self, win: xproto::Window) -> Option<&Monitor> {
+        let state = self.windows.get(&win)?;
+        monitors::monitor_at(&self.monitors, state.extent())
+    }
+
+    /// The scale of the monitor showing most of a window, or `None` if the window isn't
+    /// tracked or is entirely off screen.
+    pub fn window_scale(&self, win: xproto::Window) -> Option<f32> {
+        self.window_monitor(win).map(Monitor::scale)
+    }
+
+    /// Work out which monitor `win` is on again, after it moved or the monitors changed,
+    /// and tell [`Compositor::events`] subscribers if that's a different one.
+    fn update_window_monitor(&mut self, win: xproto::Window) {
+        let monitor = self.window_monitor(win).map(|monitor| monitor.name.clone());
+        let Some(state) = self.windows.get_mut(&win) else {
+            return;
+        };
+        if state.monitor == monitor {
+            return;
+        }
+        debug!(
+            "Window {:?} moved from monitor {:?} to {:?}",
+            win, state.monitor, monitor
+        );
+        state.monitor = monitor.clone();
+        self.notify(CompositorEvent::WindowMonitorChanged {
+            window: win,
+            monitor,
+        });
+    }
+
+    /// What the compositor is tracking right now, without any window contents, for
+    /// including in bug reports.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            root_size: self.root_size,
+            active_window: self.active_window,
+            frame_count: self.frame_count,
+            expose: self.expose,
+            suspended: self.suspended,
+            windows: self.windows(),
+        }
+    }
+
+    /// A snapshot of every tracked window, in stacking order from bottom to top.
+    pub fn windows(&self) -> Vec<WindowInfo> {
+        self.stack
+            .iter()
+            .enumerate()
+            .filter_map(|(stacking_index, &id)| {
+                let state = self.windows.get(&id)?;
+                Some(WindowInfo {
+                    id,
+                    geometry: state.geometry,
+                    border_width: state.border_width,
+                    mapped: state.mapped,
+                    opacity: self.animations.appearance(id).opacity,
+                    stacking_index,
+                    wm_state: state.wm_state,
+                    decorations: state.decorations,
+                    corner_radius: state.corner_radius,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Drop for Compositor<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            error!(state = %self.snapshot().to_json(), "Panicked while compositing");
+        }
+        if self.released {
+            return;
+        }
+
+        let conn = self.conn.clone();
+        let root = self.root_win;
+        let overlay = self.overlay_win;
+        let mode = redirect_mode(&self.options);
+        let ungrab_keys = !self.keys.is_empty();
+        let nested = self.nested;
+        let cm_selection = self.cm_selection;
+        let suspended = self.suspended;
+        tokio::spawn(async move {
+            if ungrab_keys {
+                conn.ungrab_key(xproto::Grab::ANY, root, xproto::ModMask::ANY)
+                    .await
+                    .ok();
+            }
+            if let Some(selection) = cm_selection {
+                conn.destroy_window(selection.window).await.ok();
+            }
+
+            if let Some(nested) = nested {
+                conn.destroy_window(nested.frame).await.ok();
+                return;
+            }
+            // Already done by suspending.
+            if suspended {
+                return;
+            }
+
+            conn.composite_unredirect_subwindows(root, mode)
                 .await
                 .map_err(|e| anyhow::anyhow!("{e}"))
                 .ok();
@@ -0,0 +1,847 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::{
+    animation::Easings, extensions::RequestedVersions, keys::KeyBinding, texture::DamagePolicy,
+    watchdog::WatchdogConfig, window::BlendMode,
+};
+
+/// Runtime options for the compositor.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Skip drawing windows entirely and just present a transparent frame.
+    ///
+    /// The overlay and surface are still set up as usual, so this is useful for telling
+    /// apart problems with the overlay/surface from problems with window textures.
+    pub bypass: bool,
+    /// Tint what was just damaged, fading it out over a few frames, to see what each
+    /// frame uploads. Only has an effect in debug builds.
+    pub debug_damage: bool,
+    /// Stop rendering after this long without any events or damage, until the next event
+    /// arrives. `None` disables this and renders every frame.
+    pub idle_timeout: Option<Duration>,
+    /// Only render when something has changed (damage, a window moving, an animation),
+    /// rather than every frame until `idle_timeout` passes.
+    pub damage_driven: bool,
+    /// Render at least this often while idle or waiting for damage, to recover from
+    /// damage we might have missed. `None` waits as long as it takes.
+    pub idle_refresh: Option<Duration>,
+    /// How much to darken windows other than the active one, from 0 (disabled) to 1.
+    pub inactive_dim: f32,
+    /// Wallpaper (a binary PPM image) for monitors without one of their own.
+    pub background: Option<PathBuf>,
+    /// Wallpapers for specific monitors, keyed by RandR output name.
+    pub monitor_backgrounds: HashMap<String, PathBuf>,
+    /// The color of any part of the screen not covered by a window, wallpaper, or
+    /// gradient. Like all `wgpu::Color`s this is linear and premultiplied, so a fully
+    /// transparent color lets whatever is below the overlay show through.
+    ///
+    /// Ignored in bypass mode, which always presents a transparent frame.
+    pub clear_color: wgpu::Color,
+    /// A gradient drawn across the whole screen underneath any wallpapers.
+    pub gradient: Option<Gradient>,
+    /// How long to animate windows moving or resizing for. `None` makes them snap to
+    /// their new geometry.
+    pub geometry_animation: Option<Duration>,
+    /// How each kind of animation eases from start to end.
+    pub easings: Easings,
+    /// How much bigger to draw every window than its actual size, scaled about the
+    /// top left corner of the screen. Sizes like the corner radius are scaled too.
+    pub global_scale: f32,
+    /// Fraction of the output's resolution to composite at, scaling the result up to fill
+    /// it. Below 1 this trades sharpness for speed on GPUs that can't keep up otherwise.
+    pub render_scale: f32,
+    /// Samples per pixel to render with. Above 1 this smooths the edges of windows that
+    /// land between pixels, e.g. while animating or scaled, at the cost of fill rate.
+    /// Falls back to the most the GPU supports, if it's fewer.
+    pub msaa_samples: u32,
+    /// A border around the active window. Never drawn around fullscreen windows, or ones
+    /// that asked for no decorations.
+    pub active_border: Option<ActiveBorder>,
+    /// A translucent color over everything, which the `toggle-tint` command turns on and
+    /// off.
+    pub tint: Tint,
+    /// Start with the tint on.
+    pub tint_enabled: bool,
+    /// Radius of rounded window corners in pixels. Zero leaves them square. Clients can
+    /// pick their own by setting `_RECOMP_CORNER_RADIUS` to a CARDINAL on their window.
+    pub corner_radius: f32,
+    /// How far rounded corners are from circular (0) towards a continuous, squircle-like
+    /// curve (1).
+    pub corner_smoothing: f32,
+    /// How wide the edges of rounded corners fade out over, in pixels. Wider is smoother
+    /// but blurrier. This is scaled along with everything else by `global_scale`, by the
+    /// scale of the window's monitor on HiDPI screens, and up to stay as wide in output
+    /// pixels at lower render scales.
+    pub corner_aa_width: f32,
+    /// Windows to leave unredirected and let the X server draw directly, e.g. to work
+    /// around a misbehaving GL application.
+    pub exclude: Vec<WindowMatch>,
+    /// ARGB windows whose contents have straight rather than premultiplied alpha. Almost
+    /// every client premultiplies, but the few that don't get bright halos around
+    /// translucent edges unless they're listed here.
+    pub straight_alpha: Vec<WindowMatch>,
+    /// Blend modes for windows other than normal alpha compositing, from the first rule
+    /// each window matches. Clients can pick their own by setting `_RECOMP_BLEND_MODE` to
+    /// the mode's name as a STRING on their window.
+    pub blend_modes: Vec<(WindowMatch, BlendMode)>,
+    /// Only composite this RandR output, leaving the others to show their normal,
+    /// uncomposited contents.
+    pub output: Option<String>,
+    /// Show another copy of one output on a second one, e.g. a laptop's screen on a
+    /// projector, scaled to fill it.
+    pub mirror: Option<Mirror>,
+    /// Detect the event loop getting stuck, e.g. on a GPU hang or an unresponsive X
+    /// server. Disabled by default.
+    pub watchdog: Option<WatchdogConfig>,
+    /// Extension versions to ask the X server for instead of the newest it has, for
+    /// simulating an older server.
+    pub extension_versions: RequestedVersions,
+    /// The GPU to use. By default wgpu picks one.
+    pub adapter: Option<AdapterMatch>,
+    /// How rendered frames are shown on the overlay.
+    pub present: PresentBackend,
+    /// How the surface presents frames. Has no effect with DBE.
+    pub present_mode: PresentMode,
+    /// What decides how often frames are rendered.
+    pub frame_pacing: FramePacing,
+    /// How many frames can be queued up on the GPU at once. More smooth over hitches at
+    /// the cost of latency, and fewer get each frame on screen sooner.
+    pub max_frames_in_flight: u32,
+    /// How many events the event loop handles before rendering a frame, leaving the rest
+    /// for after it, so that a burst of clients mapping and configuring at once can't hold
+    /// up frames. `None` handles every pending event first.
+    pub max_events_per_frame: Option<usize>,
+    /// How many window textures to hold at most. Past that, the textures of windows that
+    /// aren't drawn (unmapped, obscured or off the output) are dropped, least recently
+    /// damaged first, and made again when they're drawn. `None` holds one per window.
+    pub max_window_textures: Option<usize>,
+    /// Only draw windows where they can be seen past the opaque windows above them,
+    /// rather than drawing each one whole. See [`crate::occlusion`].
+    pub cull_occluded: bool,
+    /// How long frames should take to render at most. Once they keep taking longer, the
+    /// same effects [`Config::disable_expensive_effects`] turns off for software
+    /// rendering are turned off, until frames are well within it again. `None` never
+    /// turns them off.
+    pub frame_budget: Option<Duration>,
+    /// Write every frame out as raw video, for recording the screen.
+    #[cfg(feature = "capture-stream")]
+    pub stream: Option<crate::stream::StreamConfig>,
+    /// Composite in HDR, if the surface can present in it. Otherwise, or without this,
+    /// everything is SDR.
+    #[cfg(feature = "hdr")]
+    pub hdr: Option<crate::hdr::HdrConfig>,
+    /// The format to configure the surface with, instead of the first sRGB one it
+    /// supports. For drivers that advertise formats they can't actually present.
+    pub surface_format: Option<wgpu::TextureFormat>,
+    /// How damaged parts of windows are grouped into texture uploads.
+    pub damage: DamagePolicy,
+    /// Draw newly mapped windows as their `_NET_WM_ICON` on a plain background until they
+    /// draw something, rather than whatever their pixmap starts out with.
+    pub icon_placeholders: bool,
+    /// Keys to grab globally and the commands they trigger. Empty by default, since any
+    /// key we grab can't be used by the window manager or other clients.
+    pub key_bindings: Vec<KeyBinding>,
+    /// Redirect windows manually instead of automatically.
+    ///
+    /// With automatic redirection the X server still paints windows onto the screen
+    /// itself, underneath the overlay, so anything the overlay doesn't cover (other
+    /// outputs, holes for excluded windows) is kept up to date without us. With manual
+    /// redirection nothing reaches the screen unless we draw it, which gives full
+    /// control over when window contents are presented, e.g. to sync them with
+    /// animations, but leaves uncomposited areas showing only the root background.
+    pub manual_redirect: bool,
+    /// Grab the server while redirecting windows at startup, so that windows created or
+    /// destroyed at the same time can't leave us with an inconsistent view of them. This
+    /// briefly freezes every other client.
+    pub grab_server: bool,
+    /// Suspend compositing while the X server's virtual terminal isn't the active one,
+    /// and resume once it is again. Some drivers corrupt the surface or crash when it's
+    /// presented to from another VT.
+    pub pause_on_vt_switch: bool,
+    /// Wait this long before doing anything at startup, for sessions that start us
+    /// before the window manager and where it can't be waited for by name.
+    pub startup_delay: Option<Duration>,
+    /// Before redirecting any windows at startup, wait up to this long for an EWMH window
+    /// manager to show up, for sessions that start us before it. Carries on without one
+    /// once the time is up.
+    pub wait_for_wm: Option<Duration>,
+    /// Extra atoms to intern at startup along with the ones we use, so that embedders can
+    /// look them up with `Atoms::get` without a round trip.
+    pub prefetch_atoms: Vec<String>,
+    /// Keep expensive effects on even when rendering on the CPU, e.g. with llvmpipe,
+    /// where they're otherwise turned off.
+    pub force_effects: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bypass: false,
+            debug_damage: false,
+            idle_timeout: Some(Duration::from_secs(5)),
+            damage_driven: false,
+            idle_refresh: None,
+            inactive_dim: 0.0,
+            background: None,
+            monitor_backgrounds: HashMap::new(),
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.5,
+                a: 1.0,
+            },
+            gradient: None,
+            geometry_animation: None,
+            easings: Easings::default(),
+            global_scale: 1.0,
+            render_scale: 1.0,
+            msaa_samples: 1,
+            active_border: None,
+            tint: Tint::default(),
+            tint_enabled: false,
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            corner_aa_width: 1.0,
+            exclude: Vec::new(),
+            straight_alpha: Vec::new(),
+            blend_modes: Vec::new(),
+            output: None,
+            mirror: None,
+            watchdog: None,
+            extension_versions: RequestedVersions::default(),
+            adapter: None,
+            present: PresentBackend::Surface,
+            present_mode: PresentMode::Auto,
+            frame_pacing: FramePacing::Present,
+            max_frames_in_flight: 2,
+            max_events_per_frame: None,
+            max_window_textures: None,
+            cull_occluded: false,
+            frame_budget: None,
+            #[cfg(feature = "capture-stream")]
+            stream: None,
+            #[cfg(feature = "hdr")]
+            hdr: None,
+            surface_format: None,
+            damage: DamagePolicy::default(),
+            icon_placeholders: false,
+            key_bindings: Vec::new(),
+            manual_redirect: false,
+            grab_server: false,
+            pause_on_vt_switch: false,
+            startup_delay: None,
+            wait_for_wm: None,
+            prefetch_atoms: Vec::new(),
+            force_effects: false,
+        }
+    }
+}
+
+impl Config {
+    /// Turn off the effects that are too slow for a software rasterizer: the glow around
+    /// the active window, continuous corner curves, and geometry animations. Returns the
+    /// names of the ones that were on, for logging.
+    pub fn disable_expensive_effects(&mut self) -> Vec<&'static str> {
+        let mut disabled = Vec::new();
+        if let Some(border) = &mut self.active_border {
+            if border.glow_radius > 0.0 {
+                border.glow_radius = 0.0;
+                disabled.push("active border glow");
+            }
+        }
+        if self.corner_radius > 0.0 && self.corner_smoothing > 0.0 {
+            self.corner_smoothing = 0.0;
+            disabled.push("corner smoothing");
+        }
+        if self.geometry_animation.take().is_some() {
+            disabled.push("geometry animations");
+        }
+        disabled
+    }
+
+    /// Turn the effects [`Config::disable_expensive_effects`] turned off back on, as they
+    /// were in `before`.
+    pub fn restore_expensive_effects(&mut self, before: &Config) {
+        if let (Some(border), Some(before)) = (&mut self.active_border, before.active_border) {
+            border.glow_radius = before.glow_radius;
+        }
+        self.corner_smoothing = before.corner_smoothing;
+        self.geometry_animation = before.geometry_animation;
+    }
+
+    /// Everything that can't work the way it's configured, like wallpapers that can't be
+    /// loaded, an adapter that none of `adapters` matches, or options that do nothing
+    /// without another one. Doesn't need an X server, for checking a configuration
+    /// before starting with it.
+    pub fn problems(&self, adapters: &[wgpu::AdapterInfo]) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let wallpapers = self
+            .background
+            .iter()
+            .chain(self.monitor_backgrounds.values());
+        for path in wallpapers {
+            if let Err(e) = crate::background::load_ppm(path) {
+                problems.push(format!("{e:#}"));
+            }
+        }
+        if let Some(selector) = &self.adapter {
+            if adapters.is_empty() {
+                problems.push(format!("no adapter matches {selector:?}, none were found"));
+            } else if !adapters.iter().any(|info| selector.matches(info)) {
+                let available: Vec<_> = adapters.iter().map(|info| info.name.as_str()).collect();
+                problems.push(format!(
+                    "no adapter matches {selector:?}, available adapters: {}",
+                    available.join(", ")
+                ));
+            }
+        }
+        for (i, binding) in self.key_bindings.iter().enumerate() {
+            let earlier = self.key_bindings[..i]
+                .iter()
+                .find(|earlier| earlier.combo == binding.combo);
+            if let Some(earlier) = earlier {
+                problems.push(format!(
+                    "the same key is bound to both {:?} and {:?}",
+                    earlier.command, binding.command
+                ));
+            }
+        }
+        if self.corner_smoothing > 0.0 && self.corner_radius == 0.0 {
+            problems.push("corner smoothing does nothing without a corner radius".to_owned());
+        }
+        if self.idle_refresh.is_some() && self.idle_timeout.is_none() && !self.damage_driven {
+            problems.push(
+                "the idle refresh does nothing without an idle timeout or damage-driven rendering"
+                    .to_owned(),
+            );
+        }
+
+        problems
+    }
+}
+
+/// Convert an sRGB color component to linear, which is what we render with since the
+/// surface is sRGB.
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// A procedural background between two colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gradient {
+    /// sRGB color at the top, left, or center, with components from 0 to 1.
+    pub from: [f32; 3],
+    /// sRGB color at the bottom, right, or edges.
+    pub to: [f32; 3],
+    pub direction: GradientDirection,
+}
+
+/// One output shown on another, both by RandR output name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    pub source: String,
+    pub destination: String,
+}
+
+/// A color drawn over the whole screen, windows and all, e.g. to dim it for focus assist.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tint {
+    /// Non-premultiplied sRGB, with components from 0 to 1.
+    pub color: [f32; 3],
+    /// How opaque the tint is once it's faded in, from 0 to 1.
+    pub opacity: f32,
+}
+
+impl Default for Tint {
+    fn default() -> Self {
+        Self {
+            color: [0.0, 0.0, 0.0],
+            opacity: 0.4,
+        }
+    }
+}
+
+/// A border drawn around the active window, outside its edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveBorder {
+    /// Width of the solid part of the border in pixels.
+    pub width: f32,
+    /// Like all `wgpu::Color`s this is linear and premultiplied.
+    pub color: wgpu::Color,
+    /// How far past the solid border a glow fades out, in pixels. Zero for no glow.
+    pub glow_radius: f32,
+}
+
+impl Default for ActiveBorder {
+    fn default() -> Self {
+        Self {
+            width: 2.0,
+            // #5e81ac
+            color: wgpu::Color {
+                r: 0.112,
+                g: 0.22,
+                b: 0.413,
+                a: 1.0,
+            },
+            glow_radius: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GradientDirection {
+    /// Top to bottom.
+    #[default]
+    Vertical,
+    /// Left to right.
+    Horizontal,
+    /// From the center of the screen out to the corners.
+    Radial,
+}
+
+/// Selects windows by id, title, or class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowMatch {
+    /// A specific window id.
+    Id(u32),
+    /// A pattern matched against the window's `WM_NAME`, where `*` matches any run of
+    /// characters and `?` matches any single character.
+    Name(String),
+    /// A pattern like [`WindowMatch::Name`], matched against both the instance and the
+    /// class name in the window's `WM_CLASS`.
+    Class(String),
+}
+
+impl WindowMatch {
+    pub fn matches(&self, id: u32, props: &WindowProps) -> bool {
+        match self {
+            WindowMatch::Id(want) => *want == id,
+            WindowMatch::Name(pattern) => props
+                .name
+                .as_deref()
+                .is_some_and(|name| glob_match(pattern, name)),
+            WindowMatch::Class(pattern) => props.class.as_ref().is_some_and(|(instance, class)| {
+                glob_match(pattern, instance) || glob_match(pattern, class)
+            }),
+        }
+    }
+}
+
+/// The properties of a window that [`WindowMatch`] rules look at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowProps {
+    /// The window's `WM_NAME`.
+    pub name: Option<String>,
+    /// The instance and class names from the window's `WM_CLASS`.
+    pub class: Option<(String, String)>,
+}
+
+impl WindowProps {
+    /// Split the value of a `WM_CLASS` property, which is the instance and class names
+    /// each followed by a null byte.
+    pub fn parse_class(value: &[u8]) -> Option<(String, String)> {
+        let mut parts = value.split(|&b| b == 0);
+        let instance = parts.next()?;
+        let class = parts.next()?;
+        Some((
+            String::from_utf8_lossy(instance).into_owned(),
+            String::from_utf8_lossy(class).into_owned(),
+        ))
+    }
+}
+
+/// Selects which GPU to render with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterMatch {
+    /// Adapters whose name contains this, ignoring case.
+    Name(String),
+    /// Adapters with this PCI vendor id and, if given, device id. Unlike the name, these
+    /// don't change between driver versions.
+    PciId { vendor: u32, device: Option<u32> },
+}
+
+impl AdapterMatch {
+    pub fn matches(&self, info: &wgpu::AdapterInfo) -> bool {
+        match self {
+            AdapterMatch::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+            AdapterMatch::PciId { vendor, device } => {
+                info.vendor == *vendor && device.is_none_or(|device| info.device == device)
+            }
+        }
+    }
+}
+
+/// How rendered frames get onto the overlay window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentBackend {
+    /// A wgpu surface on the overlay.
+    #[default]
+    Surface,
+    /// A surface if one can be set up, otherwise DBE.
+    Auto,
+    /// Render offscreen and copy each frame into a back buffer from the X DOUBLE-BUFFER
+    /// extension. Much slower, since every frame goes through the CPU, but it doesn't
+    /// depend on the driver being able to present to X windows.
+    Dbe,
+}
+
+impl FromStr for PresentBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "surface" => PresentBackend::Surface,
+            "auto" => PresentBackend::Auto,
+            "dbe" => PresentBackend::Dbe,
+            _ => anyhow::bail!("unknown present backend {s}, expected surface, auto, or dbe"),
+        })
+    }
+}
+
+/// Which present mode the surface is configured with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// `Mailbox` if the surface supports it, otherwise `Fifo`. Never `Immediate`, since
+    /// that tears.
+    #[default]
+    Auto,
+    /// Wait for vblank, queueing frames up. Always supported.
+    Fifo,
+    /// Wait for vblank, replacing the queued frame with newer ones, so there's less
+    /// latency without tearing. Presenting doesn't block, so with [`FramePacing::Present`]
+    /// frames are rendered as fast as the GPU can draw them while anything changes.
+    Mailbox,
+    /// Present straight away, which can tear.
+    Immediate,
+}
+
+impl FromStr for PresentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => PresentMode::Auto,
+            "fifo" => PresentMode::Fifo,
+            "mailbox" => PresentMode::Mailbox,
+            "immediate" => PresentMode::Immediate,
+            _ => anyhow::bail!(
+                "unknown present mode {s}, expected auto, fifo, mailbox, or immediate"
+            ),
+        })
+    }
+}
+
+/// Formats that surfaces are presented in, and so that can be asked for by name.
+const SURFACE_FORMATS: [wgpu::TextureFormat; 8] = [
+    wgpu::TextureFormat::Bgra8Unorm,
+    wgpu::TextureFormat::Bgra8UnormSrgb,
+    wgpu::TextureFormat::Rgba8Unorm,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgb10a2Unorm,
+    wgpu::TextureFormat::Rgba16Float,
+    wgpu::TextureFormat::Rgba16Unorm,
+    wgpu::TextureFormat::Rgba32Float,
+];
+
+/// Parse the name of a surface format, either as wgpu spells it (`Bgra8UnormSrgb`) or as
+/// WebGPU does (`bgra8unorm-srgb`).
+pub fn parse_texture_format(name: &str) -> anyhow::Result<wgpu::TextureFormat> {
+    let normalize = |name: &str| name.replace(['-', '_'], "").to_ascii_lowercase();
+    let wanted = normalize(name);
+    SURFACE_FORMATS
+        .into_iter()
+        .find(|format| normalize(&format!("{format:?}")) == wanted)
+        .ok_or_else(|| {
+            anyhow::anyhow!("unknown surface format {name}, expected one of {SURFACE_FORMATS:?}")
+        })
+}
+
+/// What rendering is paced to.
+///
+/// There's only one surface, so frames can't be synced to each output separately. With a
+/// timer, frames are rendered at the refresh rate of a single output, read from RandR,
+/// and the others show whichever frame is newest when they refresh.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FramePacing {
+    /// Render as fast as presenting allows, which with a surface is usually the refresh
+    /// rate of whichever output the driver syncs to. DBE isn't synced to anything.
+    #[default]
+    Present,
+    /// Render at the refresh rate of the fastest output.
+    Fastest,
+    /// Render at the refresh rate of the named RandR output.
+    Output(String),
+}
+
+impl FromStr for FramePacing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "present" => FramePacing::Present,
+            "fastest" => FramePacing::Fastest,
+            _ => match s.strip_prefix("output:") {
+                Some(name) if !name.is_empty() => FramePacing::Output(name.to_owned()),
+                _ => anyhow::bail!(
+                    "unknown frame pacing {s}, expected present, fastest, or output:NAME"
+                ),
+            },
+        })
+    }
+}
+
+/// Match `text` against a pattern containing `*` and `?` wildcards.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume from if the match fails after the most recent `*`.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the `*` swallow one more character and try again.
+                Some((star, star_t)) => {
+                    p = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(name: &str, vendor: u32, device: u32) -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: name.to_owned(),
+            vendor,
+            device,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        }
+    }
+
+    #[test]
+    fn disables_only_enabled_expensive_effects() {
+        let mut config = Config::default();
+        assert!(config.disable_expensive_effects().is_empty());
+
+        let mut config = Config {
+            active_border: Some(ActiveBorder {
+                glow_radius: 12.0,
+                ..Default::default()
+            }),
+            corner_radius: 8.0,
+            corner_smoothing: 0.6,
+            geometry_animation: Some(Duration::from_millis(150)),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.disable_expensive_effects(),
+            [
+                "active border glow",
+                "corner smoothing",
+                "geometry animations"
+            ]
+        );
+        // The solid border and plain rounded corners are cheap, so they stay.
+        assert_eq!(config.active_border.unwrap().width, 2.0);
+        assert_eq!(config.active_border.unwrap().glow_radius, 0.0);
+        assert_eq!(config.corner_radius, 8.0);
+        assert_eq!(config.corner_smoothing, 0.0);
+        assert_eq!(config.geometry_animation, None);
+    }
+
+    #[test]
+    fn restores_expensive_effects() {
+        let before = Config {
+            active_border: Some(ActiveBorder {
+                glow_radius: 12.0,
+                ..Default::default()
+            }),
+            corner_radius: 8.0,
+            corner_smoothing: 0.6,
+            geometry_animation: Some(Duration::from_millis(150)),
+            ..Default::default()
+        };
+        let mut config = before.clone();
+        config.disable_expensive_effects();
+        // Changed meanwhile, which restoring leaves alone.
+        config.inactive_dim = 0.3;
+
+        config.restore_expensive_effects(&before);
+        assert_eq!(config.active_border, before.active_border);
+        assert_eq!(config.corner_smoothing, 0.6);
+        assert_eq!(config.geometry_animation, before.geometry_animation);
+        assert_eq!(config.inactive_dim, 0.3);
+    }
+
+    #[test]
+    fn finds_problems_with_the_configuration() {
+        let amd = [adapter("AMD Radeon RX 7900 XTX", 0x1002, 0x744c)];
+        assert!(Config::default().problems(&[]).is_empty());
+
+        let binding = |command| KeyBinding {
+            combo: "Mod4+t".parse().unwrap(),
+            command,
+        };
+        let config = Config {
+            background: Some("/nonexistent/wallpaper.ppm".into()),
+            adapter: Some(AdapterMatch::Name("nvidia".to_owned())),
+            key_bindings: vec![
+                binding(crate::command::Command::ToggleBypass),
+                binding(crate::command::Command::Freeze),
+            ],
+            corner_smoothing: 0.6,
+            idle_timeout: None,
+            idle_refresh: Some(Duration::from_secs(1)),
+            ..Config::default()
+        };
+        assert_eq!(
+            config.problems(&amd),
+            [
+                "Failed to read /nonexistent/wallpaper.ppm: No such file or directory (os error 2)",
+                "no adapter matches Name(\"nvidia\"), available adapters: AMD Radeon RX 7900 XTX",
+                "the same key is bound to both ToggleBypass and Freeze",
+                "corner smoothing does nothing without a corner radius",
+                "the idle refresh does nothing without an idle timeout or damage-driven rendering",
+            ]
+        );
+
+        let config = Config {
+            adapter: Some(AdapterMatch::Name("radeon".to_owned())),
+            corner_radius: 8.0,
+            corner_smoothing: 0.6,
+            ..Config::default()
+        };
+        assert!(config.problems(&amd).is_empty());
+    }
+
+    #[test]
+    fn parses_present_modes() {
+        assert_eq!("auto".parse::<PresentMode>().unwrap(), PresentMode::Auto);
+        assert_eq!(
+            "mailbox".parse::<PresentMode>().unwrap(),
+            PresentMode::Mailbox
+        );
+        assert!("vsync".parse::<PresentMode>().is_err());
+    }
+
+    #[test]
+    fn parses_frame_pacing() {
+        assert_eq!(
+            "present".parse::<FramePacing>().unwrap(),
+            FramePacing::Present
+        );
+        assert_eq!(
+            "fastest".parse::<FramePacing>().unwrap(),
+            FramePacing::Fastest
+        );
+        assert_eq!(
+            "output:DP-1".parse::<FramePacing>().unwrap(),
+            FramePacing::Output("DP-1".to_owned())
+        );
+        assert!("output:".parse::<FramePacing>().is_err());
+        assert!("vsync".parse::<FramePacing>().is_err());
+    }
+
+    #[test]
+    fn parses_texture_formats() {
+        use wgpu::TextureFormat::*;
+
+        assert_eq!(
+            parse_texture_format("Bgra8UnormSrgb").unwrap(),
+            Bgra8UnormSrgb
+        );
+        assert_eq!(
+            parse_texture_format("bgra8unorm-srgb").unwrap(),
+            Bgra8UnormSrgb
+        );
+        assert_eq!(parse_texture_format("rgb10a2unorm").unwrap(), Rgb10a2Unorm);
+        assert_eq!(parse_texture_format("RGBA16_FLOAT").unwrap(), Rgba16Float);
+        assert!(parse_texture_format("Depth32Float").is_err());
+        assert!(parse_texture_format("").is_err());
+    }
+
+    #[test]
+    fn matches_adapters() {
+        let info = adapter("AMD Radeon RX 7900 XTX (RADV NAVI31)", 0x1002, 0x744c);
+
+        assert!(AdapterMatch::Name("radeon".into()).matches(&info));
+        assert!(!AdapterMatch::Name("nvidia".into()).matches(&info));
+
+        let pci = |vendor, device| AdapterMatch::PciId { vendor, device };
+        assert!(pci(0x1002, None).matches(&info));
+        assert!(pci(0x1002, Some(0x744c)).matches(&info));
+        assert!(!pci(0x1002, Some(0x73bf)).matches(&info));
+        assert!(!pci(0x10de, None).matches(&info));
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("Firefox", "Firefox"));
+        assert!(!glob_match("Firefox", "Firefox Nightly"));
+        assert!(glob_match("*Firefox*", "Mozilla Firefox Nightly"));
+        assert!(glob_match("Fi?efox", "Firefox"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn matches_by_id_or_name() {
+        let none = WindowProps::default();
+        let named = WindowProps {
+            name: Some("glxgears 2".to_owned()),
+            ..Default::default()
+        };
+        assert!(WindowMatch::Id(42).matches(42, &none));
+        assert!(!WindowMatch::Id(42).matches(43, &named));
+
+        let by_name = WindowMatch::Name("glxgears*".to_owned());
+        assert!(by_name.matches(1, &named));
+        assert!(!by_name.matches(1, &none));
+    }
+
+    #[test]
+    fn matches_by_class() {
+        let props = WindowProps {
+            name: None,
+            class: WindowProps::parse_class(b"navigator\0Firefox\0"),
+        };
+        assert_eq!(
+            props.class,
+            Some(("navigator".to_owned(), "Firefox".to_owned()))
+        );
+
+        assert!(WindowMatch::Class("Firefox".into()).matches(1, &props));
+        assert!(WindowMatch::Class("nav*".into()).matches(1, &props));
+        assert!(!WindowMatch::Class("Chromium".into()).matches(1, &props));
+        assert!(!WindowMatch::Class("Firefox".into()).matches(1, &WindowProps::default()));
+        assert_eq!(WindowProps::parse_class(b"broken"), None);
+    }
+}
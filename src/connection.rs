@@ -1,8 +1,28 @@
-use std::{ptr::NonNull, sync::Arc};
+use std::{
+    ffi::CString,
+    os::fd::{AsRawFd, RawFd},
+    ptr::NonNull,
+    sync::Arc,
+};
 
 use x11rb::xcb_ffi::XCBConnection;
 use x11rb_async::blocking::BlockingConnection;
 
+use crate::error::Error;
+
+/// The display name to hand XCB for `display`. An empty one means the default, from
+/// `$DISPLAY`, just like no display at all.
+pub fn display_name(display: Option<&str>) -> Result<Option<CString>, Error> {
+    match display {
+        None | Some("") => Ok(None),
+        Some(display) => CString::new(display)
+            .map(Some)
+            .map_err(|_| Error::InvalidDisplay {
+                display: display.to_owned(),
+            }),
+    }
+}
+
 #[derive(Clone)]
 pub struct XConn(Arc<XConnInner>);
 
@@ -26,12 +46,24 @@ impl XConn {
         self.0.screen_num
     }
 
+    /// The underlying XCB connection, for making requests from synchronous code.
+    /// Requests are sent without blocking, but waiting for their replies blocks.
+    pub fn xcb(&self) -> &XCBConnection {
+        &self.0.raw
+    }
+
     pub fn as_raw_connection(&self) -> NonNull<std::ffi::c_void> {
         // Safety: XCB should hopefully never hand us a null pointer.
         unsafe { NonNull::new_unchecked(self.0.raw.get_raw_xcb_connection()) }
     }
 }
 
+impl AsRawFd for XConn {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.raw.as_raw_fd()
+    }
+}
+
 impl std::ops::Deref for XConn {
     type Target = BlockingConnection<XCBConnection>;
 
@@ -39,3 +71,25 @@ impl std::ops::Deref for XConn {
         &self.0.conn
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_display_names() {
+        assert_eq!(display_name(None).unwrap(), None);
+        assert_eq!(display_name(Some("")).unwrap(), None);
+        assert_eq!(
+            display_name(Some(":1")).unwrap(),
+            Some(CString::new(":1").unwrap())
+        );
+
+        let err = display_name(Some(":1\0:2")).unwrap_err();
+        assert!(matches!(err, Error::InvalidDisplay { .. }));
+        assert_eq!(
+            err.to_string(),
+            "invalid display string \":1\\0:2\": contains a NUL byte"
+        );
+    }
+}
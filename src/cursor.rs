@@ -0,0 +1,65 @@
+//! The pointer cursor's image, as reported by XFixes.
+
+use x11rb::protocol::xfixes::GetCursorImageReply;
+
+/// The image of the current cursor.
+///
+/// The server draws the cursor on top of the overlay by itself, so this isn't needed to
+/// composite the screen. It's for embedders that show the composited output somewhere the
+/// server's cursor isn't, like a screenshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorImage {
+    /// Changes whenever the cursor image does.
+    pub serial: u32,
+    pub size: (u16, u16),
+    /// The point of the image at the pointer's position.
+    pub hotspot: (u16, u16),
+    /// Premultiplied pixels, row by row, in the same BGRA layout as window textures.
+    pub pixels: Vec<u8>,
+}
+
+impl CursorImage {
+    pub fn from_reply(reply: &GetCursorImageReply) -> Self {
+        Self {
+            serial: reply.cursor_serial,
+            size: (reply.width, reply.height),
+            hotspot: (reply.xhot, reply.yhot),
+            // Each pixel is a premultiplied ARGB word, which is BGRA in little-endian order.
+            pixels: reply
+                .cursor_image
+                .iter()
+                .flat_map(|pixel| pixel.to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_cursor_pixels_to_bgra() {
+        let reply = GetCursorImageReply {
+            sequence: 0,
+            length: 0,
+            x: 100,
+            y: 100,
+            width: 2,
+            height: 1,
+            xhot: 1,
+            yhot: 0,
+            cursor_serial: 7,
+            cursor_image: vec![0xff11_2233, 0x8040_0000],
+        };
+        let image = CursorImage::from_reply(&reply);
+
+        assert_eq!(image.serial, 7);
+        assert_eq!(image.size, (2, 1));
+        assert_eq!(image.hotspot, (1, 0));
+        assert_eq!(
+            image.pixels,
+            [0x33, 0x22, 0x11, 0xff, 0x00, 0x00, 0x40, 0x80]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Tinting regions of the screen that were just damaged, fading them out over a few
+//! frames, for seeing what the damage tracking and coalescing actually upload.
+//!
+//! Only available in debug builds.
+
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    overlay,
+    pipeline::WindowPipeline,
+    texture::{WindowTexture, WindowUniforms},
+};
+
+/// How many frames a damaged region stays tinted for.
+const FADE_FRAMES: u32 = 8;
+
+/// The tint, as non-premultiplied sRGB, at its most opaque.
+const TINT: [f32; 4] = [1.0, 0.0, 0.0, 0.4];
+
+/// At most this many regions are tinted at once, so that a flood of damage can't grow
+/// the overlay without bound. The oldest go first.
+const MAX_FLASHES: usize = 256;
+
+/// A damaged region, in screen coordinates, and how many frames ago it was damaged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Flash {
+    rect: Rectangle,
+    age: u32,
+}
+
+/// The regions being tinted, and the quads they're drawn with.
+#[derive(Default)]
+pub struct DamageOverlay {
+    flashes: Vec<Flash>,
+    /// One quad per flash, kept across frames and only grown when more are needed.
+    quads: Vec<WindowTexture>,
+}
+
+impl DamageOverlay {
+    /// Start tinting `rect`, in screen coordinates.
+    pub fn add(&mut self, rect: Rectangle) {
+        if self.flashes.len() == MAX_FLASHES {
+            self.flashes.remove(0);
+        }
+        self.flashes.push(Flash { rect, age: 0 });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flashes.is_empty()
+    }
+
+    /// Age every region by a frame, forgetting the ones that have faded out.
+    pub fn tick(&mut self) {
+        for flash in &mut self.flashes {
+            flash.age += 1;
+        }
+        self.flashes.retain(|flash| flash.age < FADE_FRAMES);
+    }
+
+    /// Forget every region and quad, e.g. when turned off or after the device was lost.
+    pub fn clear(&mut self) {
+        self.flashes.clear();
+        self.quads.clear();
+    }
+
+    /// Each region with how opaque to draw it, fading linearly with its age.
+    fn tinted(&self) -> impl Iterator<Item = (Rectangle, f32)> + '_ {
+        self.flashes.iter().map(|flash| {
+            let fade = 1.0 - flash.age as f32 / FADE_FRAMES as f32;
+            (flash.rect, TINT[3] * fade)
+        })
+    }
+
+    /// Make sure there's a quad for every region and write their uniforms. `map` takes
+    /// each region to where it's drawn, e.g. to apply the global scale.
+    pub fn write_uniforms(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+        screen: (u32, u32),
+        map: impl Fn(Rectangle) -> Rectangle,
+    ) {
+        while self.quads.len() < self.flashes.len() {
+            self.quads
+                .push(overlay::solid_texture(device, queue, pipeline, TINT));
+        }
+        for ((rect, opacity), quad) in self.tinted().zip(&self.quads) {
+            quad.write_uniforms(
+                queue,
+                &WindowUniforms {
+                    geometry: map(rect),
+                    screen,
+                    dim: 0.0,
+                    opacity,
+                    has_alpha: false,
+                    straight_alpha: false,
+                    corner_radius: 0.0,
+                    corner_smoothing: 0.0,
+                    corner_aa_width: 1.0,
+                },
+            );
+        }
+    }
+
+    /// Draw the regions. The pass must already be using the window pipeline.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        for quad in self.quads.iter().take(self.flashes.len()) {
+            pass.set_bind_group(0, quad.bind_group(), &[]);
+            pass.draw(0..6, 0..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i16) -> Rectangle {
+        Rectangle {
+            x,
+            y: 0,
+            width: 10,
+            height: 10,
+        }
+    }
+
+    #[test]
+    fn fades_out_over_a_few_frames() {
+        let mut overlay = DamageOverlay::default();
+        overlay.add(rect(0));
+        overlay.tick();
+        overlay.add(rect(20));
+
+        let opacities: Vec<f32> = overlay.tinted().map(|(_, opacity)| opacity).collect();
+        assert!(opacities[0] < opacities[1]);
+        assert_eq!(opacities[1], TINT[3]);
+
+        for _ in 1..FADE_FRAMES {
+            overlay.tick();
+        }
+        let left: Vec<_> = overlay.tinted().map(|(rect, _)| rect.x).collect();
+        assert_eq!(left, [20]);
+        overlay.tick();
+        assert!(overlay.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_regions_first() {
+        let mut overlay = DamageOverlay::default();
+        for x in 0..=MAX_FLASHES as i16 {
+            overlay.add(rect(x));
+        }
+        assert_eq!(overlay.flashes.len(), MAX_FLASHES);
+        assert_eq!(overlay.flashes[0].rect.x, 1);
+    }
+}
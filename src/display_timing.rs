@@ -0,0 +1,174 @@
+//! When frames actually reach the screen, from the X Present extension.
+//!
+//! There are two clocks for frame timing. On the CPU side, [`PresentInfo::presented_at`]
+//! and the `render` span in the perf histogram say when the compositor handed a frame
+//! over, which is when it was queued. On the display side, the server sends a
+//! `CompleteNotify` once each frame is on screen, with the number of the vblank it went
+//! out at (the MSC) and when that vblank happened (the UST). Only the display side shows
+//! frames the driver held back for a vblank or more.
+//!
+//! Drivers present wgpu surfaces with Present on a connection of their own. The server
+//! sends the events to every client that selected them on the window, though, so we
+//! hear about them by selecting them on the overlay. DBE swaps don't go through Present,
+//! so there are no display-side timings when presenting that way.
+//!
+//! [`PresentInfo::presented_at`]: crate::hooks::PresentInfo::presented_at
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{debug, trace};
+use x11rb::{
+    errors::ConnectionError,
+    protocol::{
+        present::{CompleteKind, CompleteMode, CompleteNotifyEvent, EventMask},
+        xproto,
+    },
+};
+use x11rb_async::{connection::Connection as _, protocol::present::ConnectionExt as _};
+
+use crate::{connection::XConn, hooks::DisplayInfo};
+
+/// Ask the server to tell us when frames presented to `window` reach the screen.
+///
+/// Returns whether it will, which it can't without the Present extension.
+pub async fn select_complete_notify(conn: &XConn, window: xproto::Window) -> Result<bool> {
+    // Present has to be told which version we speak before it takes other requests.
+    match conn.present_query_version(1, 0).await {
+        Ok(cookie) => cookie.reply().await?,
+        Err(ConnectionError::UnsupportedExtension) => {
+            debug!("No Present extension, so no display-side frame timings");
+            return Ok(false);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let eid = conn.generate_id().await?;
+    conn.present_select_input(eid, window, EventMask::COMPLETE_NOTIFY)
+        .await?
+        .check()
+        .await?;
+    Ok(true)
+}
+
+/// Turns `CompleteNotify` events into when each frame was shown, relative to the one
+/// before.
+#[derive(Debug, Default)]
+pub struct DisplayTimer {
+    /// The UST and MSC of the last frame shown.
+    last: Option<(u64, u64)>,
+}
+
+impl DisplayTimer {
+    /// Record a completed presentation, returning when it was shown if it was.
+    pub fn complete(&mut self, ev: &CompleteNotifyEvent) -> Option<DisplayInfo> {
+        // Completions of `NotifyMSC` requests aren't frames, and skipped frames never
+        // made it to the screen.
+        if ev.kind != CompleteKind::PIXMAP || ev.mode == CompleteMode::SKIP {
+            trace!("Ignoring {:?} completion with mode {:?}", ev.kind, ev.mode);
+            return None;
+        }
+
+        let previous = self.last.replace((ev.ust, ev.msc));
+        Some(DisplayInfo {
+            msc: ev.msc,
+            ust: ev.ust,
+            interval: previous
+                .filter(|&(ust, _)| ev.ust >= ust)
+                .map(|(ust, _)| Duration::from_micros(ev.ust - ust)),
+            missed_vblanks: previous
+                .map_or(0, |(_, msc)| ev.msc.saturating_sub(msc).saturating_sub(1)),
+        })
+    }
+
+    /// Forget the last frame shown, e.g. because the window frames are presented to was
+    /// replaced.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(
+        kind: CompleteKind,
+        mode: CompleteMode,
+        ust: u64,
+        msc: u64,
+    ) -> CompleteNotifyEvent {
+        CompleteNotifyEvent {
+            response_type: 35,
+            extension: 0,
+            sequence: 0,
+            length: 0,
+            event_type: 1,
+            kind,
+            mode,
+            event: 0,
+            window: 0,
+            serial: 0,
+            ust,
+            msc,
+        }
+    }
+
+    #[test]
+    fn measures_time_between_shown_frames() {
+        let mut timer = DisplayTimer::default();
+        let first = timer
+            .complete(&completion(
+                CompleteKind::PIXMAP,
+                CompleteMode::FLIP,
+                1_000,
+                10,
+            ))
+            .unwrap();
+        assert_eq!(first.interval, None);
+        assert_eq!(first.missed_vblanks, 0);
+
+        let next = timer
+            .complete(&completion(
+                CompleteKind::PIXMAP,
+                CompleteMode::COPY,
+                17_667,
+                11,
+            ))
+            .unwrap();
+        assert_eq!(next.interval, Some(Duration::from_micros(16_667)));
+        assert_eq!(next.missed_vblanks, 0);
+
+        // Two vblanks went by without a new frame.
+        let late = timer
+            .complete(&completion(
+                CompleteKind::PIXMAP,
+                CompleteMode::FLIP,
+                67_667,
+                14,
+            ))
+            .unwrap();
+        assert_eq!(late.missed_vblanks, 2);
+    }
+
+    #[test]
+    fn ignores_frames_that_werent_shown() {
+        let mut timer = DisplayTimer::default();
+        assert!(timer
+            .complete(&completion(
+                CompleteKind::PIXMAP,
+                CompleteMode::SKIP,
+                1_000,
+                10
+            ))
+            .is_none());
+        assert!(timer
+            .complete(&completion(
+                CompleteKind::NOTIFY_MSC,
+                CompleteMode::COPY,
+                1_000,
+                10
+            ))
+            .is_none());
+        assert_eq!(timer.last, None);
+    }
+}
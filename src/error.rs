@@ -0,0 +1,74 @@
+use x11rb::{errors::ReplyError, protocol::ErrorKind, x11_utils::X11Error};
+
+use crate::extensions::Version;
+
+/// Errors with a specific cause that callers may want to handle or report differently.
+///
+/// Everything else is propagated as a plain [`anyhow::Error`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the X server does not support the {name} extension")]
+    MissingExtension {
+        name: &'static str,
+        /// The minimum version we need.
+        required: Version,
+        /// The version the server supports, if it supports the extension at all.
+        found: Option<Version>,
+    },
+    #[error(
+        "couldn't get the Composite overlay window; another compositor may already be \
+         running, or the server may not support it"
+    )]
+    OverlayUnavailable {
+        #[source]
+        source: ReplyError,
+    },
+    #[error("invalid display string {display:?}: contains a NUL byte")]
+    InvalidDisplay { display: String },
+    #[error("no GPU adapter found")]
+    NoAdapter,
+}
+
+/// Whether `err` is an X error meaning the window a request was about no longer exists
+/// (or is no longer in a state the request makes sense for).
+///
+/// Windows can be destroyed at any time, including between us receiving an event about
+/// one and making requests about it, so these errors are expected rather than fatal.
+pub fn is_window_gone(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<ReplyError>(),
+        Some(ReplyError::X11Error(X11Error {
+            error_kind: ErrorKind::Window | ErrorKind::Drawable | ErrorKind::Match,
+            ..
+        }))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn x11_error(error_kind: ErrorKind) -> anyhow::Error {
+        ReplyError::X11Error(X11Error {
+            error_kind,
+            error_code: 0,
+            sequence: 0,
+            bad_value: 0,
+            minor_opcode: 0,
+            major_opcode: 0,
+            extension_name: None,
+            request_name: None,
+        })
+        .into()
+    }
+
+    #[test]
+    fn recognizes_window_gone_errors() {
+        assert!(is_window_gone(&x11_error(ErrorKind::Window)));
+        assert!(is_window_gone(&x11_error(ErrorKind::Drawable)));
+        assert!(is_window_gone(&x11_error(ErrorKind::Match)));
+
+        assert!(!is_window_gone(&x11_error(ErrorKind::Alloc)));
+        assert!(!is_window_gone(&anyhow::anyhow!("something else")));
+    }
+}
@@ -0,0 +1,82 @@
+use std::{future::Future, os::fd::AsRawFd, time::Duration};
+
+use anyhow::Result;
+use tokio::{io::unix::AsyncFd, time::Instant};
+use x11rb::protocol::Event;
+use x11rb_async::connection::Connection;
+
+use crate::connection::XConn;
+
+/// Somewhere the run loop can get X events from.
+///
+/// This is normally the X connection itself, but it lets tests drive the compositor with
+/// a scripted sequence of events instead.
+pub trait EventSource {
+    /// Return the next queued event, if there is one, without blocking.
+    fn poll_event(&mut self) -> Result<Option<Event>>;
+
+    /// Wait until the next event arrives.
+    fn wait_event(&mut self) -> impl Future<Output = Result<Event>>;
+
+    /// Wait until the next event arrives, or return `None` once `timeout` has passed.
+    ///
+    /// The default implementation only checks for an event before and after sleeping
+    /// for the whole timeout, so sources that can do better should.
+    fn wait_event_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Option<Event>>> {
+        async move {
+            if let Some(ev) = self.poll_event()? {
+                return Ok(Some(ev));
+            }
+            tokio::time::sleep(timeout).await;
+            self.poll_event()
+        }
+    }
+
+    /// Whether the source has run out of events for good, meaning the run loop should
+    /// stop. A live connection never closes this way.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+impl EventSource for XConn {
+    fn poll_event(&mut self) -> Result<Option<Event>> {
+        Ok(self.poll_for_event()?)
+    }
+
+    async fn wait_event(&mut self) -> Result<Event> {
+        Ok(self.wait_for_event().await?)
+    }
+
+    async fn wait_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(ev) = self.poll_for_event()? {
+                return Ok(Some(ev));
+            }
+            if !wait_readable(self, deadline).await? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Wait until there's something to read from `conn`, returning `false` if `deadline`
+/// passes first.
+///
+/// `wait_event` blocks a thread that can't be interrupted, so giving up on it would lose
+/// whatever event it eventually reads. Waiting for the socket to become readable can be
+/// abandoned safely.
+pub(crate) async fn wait_readable(conn: &XConn, deadline: Instant) -> Result<bool> {
+    let fd = AsyncFd::new(conn.as_raw_fd())?;
+    match tokio::time::timeout_at(deadline, fd.readable()).await {
+        Ok(ready) => {
+            ready?.clear_ready();
+            Ok(true)
+        }
+        Err(_) => Ok(false),
+    }
+}
@@ -0,0 +1,56 @@
+//! Choosing which window textures to drop when there are more than the configured limit,
+//! so that sessions with hundreds of windows don't run the GPU out of memory.
+//!
+//! Only windows that aren't being drawn are candidates, least recently uploaded to
+//! first. Their textures are made again from their pixmaps once they're drawn again.
+
+use std::time::Instant;
+
+/// The windows to drop textures of so that at most `limit` of the `held` textures are
+/// left, out of `candidates` along with when each texture was last uploaded to.
+///
+/// Returns fewer than that when there aren't enough candidates.
+pub fn select<W>(
+    candidates: impl IntoIterator<Item = (W, Instant)>,
+    held: usize,
+    limit: usize,
+) -> Vec<W> {
+    let excess = held.saturating_sub(limit);
+    if excess == 0 {
+        return Vec::new();
+    }
+    let mut candidates: Vec<_> = candidates.into_iter().collect();
+    candidates.sort_by_key(|&(_, last_upload)| last_upload);
+    candidates
+        .into_iter()
+        .take(excess)
+        .map(|(win, _)| win)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_uploaded() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let candidates = [(1, at(5)), (2, at(1)), (3, at(9)), (4, at(3))];
+
+        assert_eq!(select(candidates, 6, 4), [2, 4]);
+        assert_eq!(select(candidates, 5, 4), [2]);
+        assert!(select(candidates, 4, 4).is_empty());
+        assert!(select(candidates, 2, 4).is_empty());
+    }
+
+    #[test]
+    fn evicts_what_it_can() {
+        let now = Instant::now();
+        // The rest of the textures are being drawn, so they have to stay.
+        assert_eq!(select([(7, now)], 10, 2), [7]);
+        assert!(select(Vec::<(u32, Instant)>::new(), 10, 2).is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use x11rb::protocol::xproto::Rectangle;
+
+/// How long windows take to move into or out of the grid.
+pub const EXPOSE_DURATION: Duration = Duration::from_millis(300);
+
+/// Space between grid cells, and around the edge of the grid, in pixels at a scale of 1.
+const GAP: u16 = 32;
+
+/// Lay out `count` equally-sized cells in a roughly square grid filling `area`, in
+/// row-major order. `scale` is that of the monitor showing `area`, which the gaps
+/// between cells are scaled by.
+pub fn grid(count: usize, area: Rectangle, scale: f32) -> Vec<Rectangle> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let gap = (GAP as f32 * scale).round() as u16;
+
+    let cols = (count as f32).sqrt().ceil() as u16;
+    let rows = count.div_ceil(cols as usize) as u16;
+    let cell_width = area.width.saturating_sub(gap * (cols + 1)) / cols;
+    let cell_height = area.height.saturating_sub(gap * (rows + 1)) / rows;
+
+    (0..count as u16)
+        .map(|i| {
+            let (col, row) = (i % cols, i / cols);
+            Rectangle {
+                x: area.x + (gap + col * (cell_width + gap)) as i16,
+                y: area.y + (gap + row * (cell_height + gap)) as i16,
+                width: cell_width,
+                height: cell_height,
+            }
+        })
+        .collect()
+}
+
+/// Scale `rect` to fit inside `cell`, keeping its aspect ratio, and center it there.
+///
+/// `scale` is how much bigger the monitor showing `cell` draws things than the one the
+/// window is on. The window is drawn at most that much bigger, so it keeps its physical
+/// size when moving between monitors of different densities, and only shrinks from
+/// there if it has to.
+pub fn fit(rect: Rectangle, cell: Rectangle, scale: f32) -> Rectangle {
+    if rect.width == 0 || rect.height == 0 {
+        return Rectangle {
+            width: 0,
+            height: 0,
+            ..cell
+        };
+    }
+
+    let scale = (cell.width as f32 / rect.width as f32)
+        .min(cell.height as f32 / rect.height as f32)
+        .min(scale);
+    let width = (rect.width as f32 * scale).round() as u16;
+    let height = (rect.height as f32 * scale).round() as u16;
+
+    Rectangle {
+        x: cell.x + ((cell.width - width) / 2) as i16,
+        y: cell.y + ((cell.height - height) / 2) as i16,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rectangle = Rectangle {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+
+    #[test]
+    fn grid_is_roughly_square() {
+        assert!(grid(0, SCREEN, 1.0).is_empty());
+        assert_eq!(grid(1, SCREEN, 1.0).len(), 1);
+
+        let cells = grid(5, SCREEN, 1.0);
+        assert_eq!(cells.len(), 5);
+        // Three columns, two rows.
+        assert_eq!(cells[0].y, cells[2].y);
+        assert!(cells[3].y > cells[0].y);
+        assert_eq!(cells[3].x, cells[0].x);
+    }
+
+    #[test]
+    fn grid_stays_inside_area() {
+        let area = Rectangle {
+            x: 1920,
+            y: 0,
+            width: 1280,
+            height: 1024,
+        };
+        for count in 1..20 {
+            for cell in grid(count, area, 1.0) {
+                assert!(cell.x >= area.x && cell.y >= area.y);
+                assert!(cell.x as i32 + cell.width as i32 <= area.x as i32 + area.width as i32);
+                assert!(cell.y as i32 + cell.height as i32 <= area.y as i32 + area.height as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn fit_keeps_aspect_ratio() {
+        let cell = Rectangle {
+            x: 100,
+            y: 100,
+            width: 400,
+            height: 400,
+        };
+        let wide = Rectangle {
+            x: 0,
+            y: 0,
+            width: 1600,
+            height: 800,
+        };
+        assert_eq!(
+            fit(wide, cell, 1.0),
+            Rectangle {
+                x: 100,
+                y: 200,
+                width: 400,
+                height: 200,
+            }
+        );
+
+        // Small windows aren't blown up.
+        let small = Rectangle {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 50,
+        };
+        assert_eq!(fit(small, cell, 1.0).width, 100);
+    }
+
+    #[test]
+    fn grid_gaps_follow_scale() {
+        let cells = grid(1, SCREEN, 2.0);
+        assert_eq!(
+            cells[0],
+            Rectangle {
+                x: 64,
+                y: 64,
+                width: 1920 - 128,
+                height: 1080 - 128,
+            }
+        );
+    }
+
+    #[test]
+    fn fit_keeps_physical_size_across_scales() {
+        let cell = Rectangle {
+            x: 0,
+            y: 0,
+            width: 1000,
+            height: 1000,
+        };
+        let window = Rectangle {
+            x: 0,
+            y: 0,
+            width: 300,
+            height: 200,
+        };
+        // Moving onto a monitor that draws twice as big.
+        assert_eq!(fit(window, cell, 2.0).width, 600);
+        // Still limited by the cell.
+        assert_eq!(fit(window, cell, 4.0).width, 1000);
+        // And onto one that draws half as big.
+        assert_eq!(fit(window, cell, 0.5).width, 150);
+    }
+}
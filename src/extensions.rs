@@ -0,0 +1,264 @@
+use anyhow::Result;
+use tracing::info;
+use x11rb::errors::ConnectionError;
+use x11rb_async::protocol::{
+    composite::ConnectionExt as _, damage::ConnectionExt as _, xfixes::ConnectionExt as _,
+};
+
+use crate::{connection::XConn, error::Error};
+
+/// A `major.minor` extension version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+        Ok(Self::new(major.parse()?, minor.parse()?))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Composite 0.3 is the first version with `GetOverlayWindow`.
+pub const COMPOSITE_MIN_VERSION: Version = Version::new(0, 3);
+/// XFixes 2.0 is needed for regions and `SetWindowShapeRegion`.
+pub const XFIXES_MIN_VERSION: Version = Version::new(2, 0);
+/// Damage 1.1 is the first version that reports damage through XFixes regions.
+pub const DAMAGE_MIN_VERSION: Version = Version::new(1, 1);
+
+/// Asking for this makes the server reply with the newest version it supports.
+const NEWEST_VERSION: Version = Version::new(999, 0);
+
+/// The versions to ask the server for, instead of the newest it supports. `None` means
+/// the newest.
+///
+/// Servers reply with the older of what was asked for and what they support, so asking
+/// for an old version is a way of pretending to be on a server that only supports that
+/// one, for reproducing version-specific behavior. Versions older than we require fail
+/// negotiation like a real old server would.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestedVersions {
+    pub composite: Option<Version>,
+    pub xfixes: Option<Version>,
+    pub damage: Option<Version>,
+}
+
+impl RequestedVersions {
+    /// Ask for `version` of the extension called `name`, as in `composite`, `xfixes` or
+    /// `damage`.
+    pub fn set(&mut self, name: &str, version: Version) -> Result<()> {
+        let slot = match name.to_ascii_lowercase().as_str() {
+            "composite" => &mut self.composite,
+            "xfixes" => &mut self.xfixes,
+            "damage" => &mut self.damage,
+            _ => anyhow::bail!("unknown extension {name}, expected composite, xfixes, or damage"),
+        };
+        *slot = Some(version);
+        Ok(())
+    }
+}
+
+/// The versions of each extension we depend on that the server agreed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionVersions {
+    pub composite: Version,
+    pub xfixes: Version,
+    pub damage: Version,
+}
+
+impl ExtensionVersions {
+    /// Check the versions reported by the server against our minimums.
+    ///
+    /// `None` means the server doesn't support that extension at all.
+    pub fn from_replies(
+        composite: Option<Version>,
+        xfixes: Option<Version>,
+        damage: Option<Version>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            composite: require("Composite", composite, COMPOSITE_MIN_VERSION)?,
+            xfixes: require("XFixes", xfixes, XFIXES_MIN_VERSION)?,
+            damage: require("Damage", damage, DAMAGE_MIN_VERSION)?,
+        })
+    }
+}
+
+fn require(
+    name: &'static str,
+    found: Option<Version>,
+    required: Version,
+) -> Result<Version, Error> {
+    match found {
+        Some(version) if version >= required => Ok(version),
+        found => Err(Error::MissingExtension {
+            name,
+            required,
+            found,
+        }),
+    }
+}
+
+/// Query the versions of the extensions we depend on, and make sure they're new enough.
+///
+/// It is required to query each extension's version before making any other requests to
+/// it, or those requests will fail with BadRequest.
+pub async fn negotiate_extensions(
+    conn: &XConn,
+    requested: &RequestedVersions,
+) -> Result<ExtensionVersions> {
+    // Unless told otherwise, we ask for a big version number so the server replies with
+    // the highest version it supports.
+    let version = |requested: Option<Version>| requested.unwrap_or(NEWEST_VERSION);
+
+    let Version { major, minor } = version(requested.composite);
+    let composite = match conn.composite_query_version(major, minor).await {
+        Ok(cookie) => {
+            let reply = cookie.reply().await?;
+            Some(Version::new(reply.major_version, reply.minor_version))
+        }
+        Err(ConnectionError::UnsupportedExtension) => None,
+        Err(e) => return Err(e.into()),
+    };
+    info!("Composite extension version: {:?}", composite);
+
+    let Version { major, minor } = version(requested.xfixes);
+    let xfixes = match conn.xfixes_query_version(major, minor).await {
+        Ok(cookie) => {
+            let reply = cookie.reply().await?;
+            Some(Version::new(reply.major_version, reply.minor_version))
+        }
+        Err(ConnectionError::UnsupportedExtension) => None,
+        Err(e) => return Err(e.into()),
+    };
+    info!("XFixes extension version: {:?}", xfixes);
+
+    let Version { major, minor } = version(requested.damage);
+    let damage = match conn.damage_query_version(major, minor).await {
+        Ok(cookie) => {
+            let reply = cookie.reply().await?;
+            Some(Version::new(reply.major_version, reply.minor_version))
+        }
+        Err(ConnectionError::UnsupportedExtension) => None,
+        Err(e) => return Err(e.into()),
+    };
+    info!("XDamage extension version: {:?}", damage);
+
+    Ok(ExtensionVersions::from_replies(composite, xfixes, damage)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing_extension(
+        result: Result<ExtensionVersions, Error>,
+    ) -> (&'static str, Option<Version>) {
+        match result {
+            Err(Error::MissingExtension { name, found, .. }) => (name, found),
+            Ok(versions) => panic!("expected MissingExtension, got {versions:?}"),
+            Err(e) => panic!("expected MissingExtension, got {e}"),
+        }
+    }
+
+    #[test]
+    fn accepts_exact_minimum_versions() {
+        let versions = ExtensionVersions::from_replies(
+            Some(COMPOSITE_MIN_VERSION),
+            Some(XFIXES_MIN_VERSION),
+            Some(DAMAGE_MIN_VERSION),
+        )
+        .unwrap();
+        assert_eq!(versions.composite, COMPOSITE_MIN_VERSION);
+        assert_eq!(versions.xfixes, XFIXES_MIN_VERSION);
+        assert_eq!(versions.damage, DAMAGE_MIN_VERSION);
+    }
+
+    #[test]
+    fn accepts_newer_versions() {
+        let versions = ExtensionVersions::from_replies(
+            Some(Version::new(0, 4)),
+            Some(Version::new(6, 0)),
+            Some(Version::new(1, 3)),
+        )
+        .unwrap();
+        assert_eq!(versions.composite, Version::new(0, 4));
+        assert_eq!(versions.xfixes, Version::new(6, 0));
+        assert_eq!(versions.damage, Version::new(1, 3));
+    }
+
+    #[test]
+    fn rejects_too_old_versions() {
+        let result = ExtensionVersions::from_replies(
+            Some(Version::new(0, 2)),
+            Some(XFIXES_MIN_VERSION),
+            Some(DAMAGE_MIN_VERSION),
+        );
+        assert_eq!(
+            missing_extension(result),
+            ("Composite", Some(Version::new(0, 2)))
+        );
+
+        let result = ExtensionVersions::from_replies(
+            Some(COMPOSITE_MIN_VERSION),
+            Some(Version::new(1, 9)),
+            Some(DAMAGE_MIN_VERSION),
+        );
+        assert_eq!(
+            missing_extension(result),
+            ("XFixes", Some(Version::new(1, 9)))
+        );
+
+        let result = ExtensionVersions::from_replies(
+            Some(COMPOSITE_MIN_VERSION),
+            Some(XFIXES_MIN_VERSION),
+            Some(Version::new(1, 0)),
+        );
+        assert_eq!(
+            missing_extension(result),
+            ("Damage", Some(Version::new(1, 0)))
+        );
+    }
+
+    #[test]
+    fn parses_requested_versions() {
+        let mut requested = RequestedVersions::default();
+        requested.set("Composite", "0.2".parse().unwrap()).unwrap();
+        requested.set("damage", "1".parse().unwrap()).unwrap();
+        assert_eq!(
+            requested,
+            RequestedVersions {
+                composite: Some(Version::new(0, 2)),
+                xfixes: None,
+                damage: Some(Version::new(1, 0)),
+            }
+        );
+        assert!(requested.set("render", Version::new(0, 11)).is_err());
+        assert!("1.x".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_extensions() {
+        let result = ExtensionVersions::from_replies(
+            Some(COMPOSITE_MIN_VERSION),
+            Some(XFIXES_MIN_VERSION),
+            None,
+        );
+        assert_eq!(missing_extension(result), ("Damage", None));
+    }
+}
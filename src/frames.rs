@@ -0,0 +1,90 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Counts the frames submitted to the GPU that it hasn't finished yet, so that no more
+/// than a set number are ever queued up.
+///
+/// More frames in flight smooth over hitches on the CPU side, at the cost of each frame
+/// reaching the screen later. wgpu recycles command encoders and staging memory itself
+/// once their submission completes, so this count is all we need to bound them too.
+pub struct FramesInFlight {
+    max: usize,
+    pending: Arc<AtomicUsize>,
+}
+
+impl FramesInFlight {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// How many submitted frames haven't finished yet.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Whether another frame has to wait for one of the pending ones to finish.
+    pub fn is_full(&self) -> bool {
+        self.pending() >= self.max
+    }
+
+    /// Count a frame that was just submitted to `queue` until the GPU finishes it.
+    pub fn submitted(&self, queue: &wgpu::Queue) {
+        queue.on_submitted_work_done(self.track());
+    }
+
+    /// Count a new frame, returning what to call once it's done.
+    fn track(&self) -> impl FnOnce() + Send + 'static {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+        let pending = Arc::clone(&self.pending);
+        move || {
+            pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Forget every pending frame, e.g. because the device they were submitted to was
+    /// lost and they'll never finish.
+    ///
+    /// Completion callbacks from the old device may still run, so this swaps in a new
+    /// counter rather than zeroing the shared one.
+    pub fn reset(&mut self) {
+        self.pending = Arc::new(AtomicUsize::new(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_up_until_frames_finish() {
+        let mut frames = FramesInFlight::new(2);
+        let first = frames.track();
+        assert!(!frames.is_full());
+        let second = frames.track();
+        assert!(frames.is_full());
+
+        first();
+        assert_eq!(frames.pending(), 1);
+        assert!(!frames.is_full());
+
+        // A frame from before the reset finishing late doesn't count against new ones.
+        frames.reset();
+        let _third = frames.track();
+        second();
+        assert_eq!(frames.pending(), 1);
+    }
+
+    #[test]
+    fn allows_at_least_one_frame() {
+        assert_eq!(FramesInFlight::new(0).max(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+//! Compositing for HDR outputs, in extended linear sRGB (scRGB).
+//!
+//! Only available with the `hdr` feature. wgpu presents half-float surfaces in scRGB,
+//! which has the same primaries and white point as sRGB but is linear and goes past 1.0
+//! for colors brighter than SDR white. Compositing in it blends exactly like the usual
+//! sRGB path does, so all that changes is the surface format and a final scale of every
+//! window, since all of them are SDR, from scRGB's 80 nit white up to the brightness
+//! SDR content should be shown at.
+
+/// The surface format wgpu presents in scRGB.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How bright 1.0 is in scRGB, in nits.
+const SCRGB_WHITE_NITS: f32 = 80.0;
+
+/// How bright to show SDR white by default, in nits. This is the reference white of
+/// ITU-R BT.2408, which keeps SDR content about as bright next to HDR content as it
+/// was on its own.
+pub const DEFAULT_SDR_WHITE_NITS: f32 = 203.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HdrConfig {
+    /// How bright to show SDR white, in nits.
+    pub sdr_white_nits: f32,
+}
+
+impl Default for HdrConfig {
+    fn default() -> Self {
+        Self {
+            sdr_white_nits: DEFAULT_SDR_WHITE_NITS,
+        }
+    }
+}
+
+impl HdrConfig {
+    /// How much to scale SDR colors by to show them at `sdr_white_nits`.
+    pub fn sdr_scale(&self) -> f32 {
+        self.sdr_white_nits / SCRGB_WHITE_NITS
+    }
+}
+
+/// Whether a surface supporting `supported` formats can present in HDR.
+pub fn supports_hdr(supported: &[wgpu::TextureFormat]) -> bool {
+    supported.contains(&HDR_FORMAT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_sdr_white_to_its_brightness() {
+        assert_eq!(
+            HdrConfig {
+                sdr_white_nits: 80.0
+            }
+            .sdr_scale(),
+            1.0
+        );
+        assert_eq!(HdrConfig::default().sdr_scale(), 203.0 / 80.0);
+    }
+
+    #[test]
+    fn needs_a_half_float_surface() {
+        use wgpu::TextureFormat::*;
+
+        assert!(!supports_hdr(&[Bgra8UnormSrgb, Bgra8Unorm, Rgb10a2Unorm]));
+        assert!(supports_hdr(&[Bgra8UnormSrgb, Rgba16Float]));
+    }
+}
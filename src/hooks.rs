@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// Information about the frame currently being rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo {
+    /// Number of frames rendered before this one.
+    pub frame: u64,
+    /// Size of the view being drawn to in pixels. This is smaller than the output when
+    /// rendering at a render scale below 1.
+    pub size: (u32, u32),
+    /// Format of the output texture, needed to create compatible pipelines.
+    pub format: wgpu::TextureFormat,
+    /// Samples per pixel of the view, which pipelines drawing into it need to match.
+    /// More than 1 with multisampling turned on.
+    pub sample_count: u32,
+}
+
+/// What a render hook gets to draw with.
+///
+/// Hooks share the compositor's command encoder, so anything they record is submitted
+/// along with the rest of the frame. To draw on top of what's already there, begin a
+/// render pass on `view` with [`wgpu::LoadOp::Load`].
+pub struct RenderContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub view: &'a wgpu::TextureView,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    pub frame: FrameInfo,
+}
+
+/// A callback run as part of rendering each frame.
+pub type RenderHook = Box<dyn FnMut(&mut RenderContext<'_>) + Send>;
+
+/// A frame that was just handed to the display.
+#[derive(Debug, Clone, Copy)]
+pub struct PresentInfo {
+    /// Number of frames presented before this one, matching [`FrameInfo::frame`].
+    pub frame: u64,
+    /// When presenting returned. The frame reaches the screen at the next vblank at the
+    /// earliest, so this is when it was queued rather than when it was shown. For that,
+    /// see [`DisplayInfo`].
+    pub presented_at: Instant,
+}
+
+/// A callback run after each frame is presented.
+pub type PresentHook = Box<dyn FnMut(&PresentInfo) + Send>;
+
+/// A frame that reached the screen, as reported by the X server with the Present
+/// extension.
+///
+/// The server doesn't say which of our frames it was, since the driver presents them
+/// under serials of its own, but they're shown in the order they were presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// The vblank the frame went out at, counted by the server.
+    pub msc: u64,
+    /// When that vblank happened, in microseconds on the server's clock, which on Linux
+    /// is `CLOCK_MONOTONIC`.
+    pub ust: u64,
+    /// How long after the previous frame shown this one was, by the display's clock.
+    /// `None` for the first.
+    pub interval: Option<Duration>,
+    /// How many vblanks went by between the previous frame and this one without either
+    /// being shown.
+    pub missed_vblanks: u64,
+}
+
+/// A callback run when a frame reaches the screen.
+pub type DisplayHook = Box<dyn FnMut(&DisplayInfo) + Send>;
@@ -0,0 +1,149 @@
+//! Placeholders for windows that have been mapped but haven't drawn anything yet.
+//!
+//! Until a client draws into a newly mapped window, its pixmap holds whatever the
+//! server left there, usually black, which flashes on screen for a frame or two.
+//! Instead the window's `_NET_WM_ICON` is drawn centered on a neutral background until
+//! the first damage arrives.
+
+/// The background placeholders are drawn on, in BGRA.
+const BACKGROUND: [u8; 4] = [0x30, 0x30, 0x30, 0xff];
+
+/// How much of `_NET_WM_ICON` to read at most, in 32-bit values. Enough for a 512x512
+/// icon along with the smaller sizes usually set alongside it.
+pub const MAX_ICON_LENGTH: u32 = 1 << 19;
+
+/// One of the icons in `_NET_WM_ICON`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Icon {
+    pub width: u16,
+    pub height: u16,
+    /// Row by row, in ARGB with the alpha in the high byte, and not premultiplied.
+    pub pixels: Vec<u32>,
+}
+
+/// The icons in `_NET_WM_ICON` data, which is any number of widths and heights each
+/// followed by that many pixels. Stops at the first one that's cut off or empty.
+fn icons(mut data: &[u32]) -> Vec<Icon> {
+    let mut icons = Vec::new();
+    while let [width, height, rest @ ..] = data {
+        let (Ok(width), Ok(height)) = (u16::try_from(*width), u16::try_from(*height)) else {
+            break;
+        };
+        let len = width as usize * height as usize;
+        if len == 0 || rest.len() < len {
+            break;
+        }
+        icons.push(Icon {
+            width,
+            height,
+            pixels: rest[..len].to_vec(),
+        });
+        data = &rest[len..];
+    }
+    icons
+}
+
+/// The largest icon in `_NET_WM_ICON` data that fits in `size`, or the smallest one if
+/// none do.
+pub fn choose(data: &[u32], size: (u16, u16)) -> Option<Icon> {
+    let area = |icon: &Icon| icon.width as u32 * icon.height as u32;
+    let (fitting, too_big): (Vec<_>, Vec<_>) = icons(data)
+        .into_iter()
+        .partition(|icon| icon.width <= size.0 && icon.height <= size.1);
+    match fitting.into_iter().max_by_key(area) {
+        Some(icon) => Some(icon),
+        None => too_big.into_iter().min_by_key(area),
+    }
+}
+
+/// An opaque `width` by `height` placeholder in BGRA, with `icon` centered on it and
+/// cropped if it doesn't fit.
+pub fn placeholder(icon: Option<&Icon>, width: u16, height: u16) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = BACKGROUND.repeat(width * height);
+    let Some(icon) = icon else {
+        return pixels;
+    };
+
+    let (icon_width, icon_height) = (icon.width as usize, icon.height as usize);
+    // Where the icon's top left corner lands, negative if it's bigger than the window.
+    let left = (width as isize - icon_width as isize) / 2;
+    let top = (height as isize - icon_height as isize) / 2;
+    for y in 0..height {
+        let Ok(icon_y) = usize::try_from(y as isize - top) else {
+            continue;
+        };
+        if icon_y >= icon_height {
+            continue;
+        }
+        for x in 0..width {
+            let Ok(icon_x) = usize::try_from(x as isize - left) else {
+                continue;
+            };
+            if icon_x >= icon_width {
+                continue;
+            }
+            let [b, g, r, a] = icon.pixels[icon_y * icon_width + icon_x].to_le_bytes();
+            let texel = &mut pixels[(y * width + x) * 4..][..3];
+            for (out, color) in texel.iter_mut().zip([b, g, r]) {
+                let over = color as u32 * a as u32 + *out as u32 * (255 - a as u32);
+                *out = ((over + 127) / 255) as u8;
+            }
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icon(size: u32, argb: u32) -> Vec<u32> {
+        let mut data = vec![size, size];
+        data.extend(std::iter::repeat_n(argb, (size * size) as usize));
+        data
+    }
+
+    #[test]
+    fn chooses_the_largest_icon_that_fits() {
+        let data = [icon(16, 1), icon(48, 2), icon(32, 3)].concat();
+        assert_eq!(choose(&data, (100, 100)).unwrap().width, 48);
+        assert_eq!(choose(&data, (40, 100)).unwrap().width, 32);
+        assert_eq!(choose(&data, (8, 8)).unwrap().width, 16);
+        assert_eq!(choose(&[], (100, 100)), None);
+    }
+
+    #[test]
+    fn ignores_truncated_icons() {
+        let mut data = [icon(16, 1), icon(32, 2)].concat();
+        data.truncate(data.len() - 1);
+        assert_eq!(choose(&data, (100, 100)).unwrap().width, 16);
+        assert_eq!(choose(&[0, 0, 4, 4], (100, 100)), None);
+    }
+
+    #[test]
+    fn centers_the_icon_on_the_background() {
+        let opaque_red = Icon {
+            width: 2,
+            height: 2,
+            pixels: vec![0xffff0000; 4],
+        };
+        let pixels = placeholder(Some(&opaque_red), 4, 3);
+        let texel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..4];
+        assert_eq!(texel(0, 0), BACKGROUND);
+        assert_eq!(texel(1, 0), [0, 0, 0xff, 0xff]);
+        assert_eq!(texel(2, 1), [0, 0, 0xff, 0xff]);
+        assert_eq!(texel(3, 1), BACKGROUND);
+        assert_eq!(texel(1, 2), BACKGROUND);
+
+        // Translucent icons are blended over the background, and crop when too big.
+        let translucent_white = Icon {
+            width: 8,
+            height: 8,
+            pixels: vec![0x80ffffff; 64],
+        };
+        let pixels = placeholder(Some(&translucent_white), 2, 2);
+        assert_eq!(&pixels[..4], [0x98, 0x98, 0x98, 0xff]);
+        assert_eq!(placeholder(None, 1, 1), BACKGROUND);
+    }
+}
@@ -0,0 +1,192 @@
+//! Working out how the server lays out window contents in `ZPixmap` images, and
+//! converting them into the BGRA rows that window textures take.
+//!
+//! Most servers hand out little-endian 32bpp images with no row padding, which already
+//! are that. The layout is still read from the connection setup rather than assumed, so
+//! that big-endian servers or ones with unusual padding don't end up with swapped colors
+//! or sheared windows.
+
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+use x11rb::protocol::xproto::{ImageOrder, Setup};
+
+/// How the server lays out `ZPixmap` images of one depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLayout {
+    pub bits_per_pixel: u8,
+    /// Rows are padded to a multiple of this many bits.
+    pub scanline_pad: u8,
+    pub byte_order: ImageOrder,
+}
+
+impl ImageLayout {
+    /// The layout of `depth` bit images on the server described by `setup`.
+    ///
+    /// Only 24 and 32 bit images can be uploaded, with 24 or 32 bits per pixel.
+    pub fn from_setup(setup: &Setup, depth: u8) -> Result<Self> {
+        if depth != 24 && depth != 32 {
+            bail!("Unsupported depth {depth}");
+        }
+        let Some(format) = setup.pixmap_formats.iter().find(|f| f.depth == depth) else {
+            bail!("The server has no pixmap format for depth {depth}");
+        };
+        let layout = Self {
+            bits_per_pixel: format.bits_per_pixel,
+            scanline_pad: format.scanline_pad,
+            byte_order: setup.image_byte_order,
+        };
+        if layout.bits_per_pixel != 32 && !(depth == 24 && layout.bits_per_pixel == 24) {
+            bail!(
+                "Unsupported {} bits per pixel for depth {depth}",
+                layout.bits_per_pixel
+            );
+        }
+        if layout.scanline_pad == 0 || !layout.scanline_pad.is_multiple_of(8) {
+            bail!("Unsupported scanline pad of {} bits", layout.scanline_pad);
+        }
+        Ok(layout)
+    }
+
+    /// How many bytes each row of a `width` pixel wide image takes, padding included.
+    pub fn row_bytes(&self, width: u16) -> usize {
+        let bits = width as usize * self.bits_per_pixel as usize;
+        bits.next_multiple_of(self.scanline_pad as usize) / 8
+    }
+
+    /// Whether images already are tightly packed BGRA, which is the common case.
+    fn is_bgra(&self, width: u16) -> bool {
+        self.bits_per_pixel == 32
+            && self.byte_order == ImageOrder::LSB_FIRST
+            && self.row_bytes(width) == width as usize * 4
+    }
+
+    /// Convert `data`, a `width` by `height` image in this layout, into tightly packed
+    /// BGRA. Borrows `data` when it already is.
+    pub fn to_bgra<'a>(&self, data: &'a [u8], width: u16, height: u16) -> Result<Cow<'a, [u8]>> {
+        let row_bytes = self.row_bytes(width);
+        if data.len() < row_bytes * height as usize {
+            bail!(
+                "Image is {} bytes, expected {} for {width}x{height}",
+                data.len(),
+                row_bytes * height as usize
+            );
+        }
+        if self.is_bgra(width) {
+            return Ok(Cow::Borrowed(&data[..row_bytes * height as usize]));
+        }
+
+        let bytes_per_pixel = self.bits_per_pixel as usize / 8;
+        let mut bgra = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in data.chunks(row_bytes).take(height as usize) {
+            for pixel in row[..width as usize * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+                let pixel = match (self.byte_order, pixel) {
+                    (ImageOrder::LSB_FIRST, &[b, g, r, a]) => [b, g, r, a],
+                    (ImageOrder::LSB_FIRST, &[b, g, r]) => [b, g, r, 0xff],
+                    (_, &[a, r, g, b]) => [b, g, r, a],
+                    (_, &[r, g, b]) => [b, g, r, 0xff],
+                    _ => unreachable!("pixels are 3 or 4 bytes"),
+                };
+                bgra.extend_from_slice(&pixel);
+            }
+        }
+        Ok(Cow::Owned(bgra))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::xproto::Format;
+
+    use super::*;
+
+    fn setup(byte_order: ImageOrder, formats: &[(u8, u8, u8)]) -> Setup {
+        Setup {
+            image_byte_order: byte_order,
+            pixmap_formats: formats
+                .iter()
+                .map(|&(depth, bits_per_pixel, scanline_pad)| Format {
+                    depth,
+                    bits_per_pixel,
+                    scanline_pad,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_the_layout_from_the_setup() {
+        let server = setup(
+            ImageOrder::LSB_FIRST,
+            &[(1, 1, 32), (24, 32, 32), (32, 32, 32)],
+        );
+        let layout = ImageLayout::from_setup(&server, 24).unwrap();
+        assert_eq!(
+            layout,
+            ImageLayout {
+                bits_per_pixel: 32,
+                scanline_pad: 32,
+                byte_order: ImageOrder::LSB_FIRST,
+            }
+        );
+        assert!(layout.is_bgra(7));
+        assert!(ImageLayout::from_setup(&server, 16).is_err());
+        // Missing from the server's list.
+        assert!(ImageLayout::from_setup(&setup(ImageOrder::LSB_FIRST, &[]), 32).is_err());
+        assert!(
+            ImageLayout::from_setup(&setup(ImageOrder::LSB_FIRST, &[(32, 24, 32)]), 32).is_err()
+        );
+    }
+
+    #[test]
+    fn passes_bgra_through() {
+        let layout =
+            ImageLayout::from_setup(&setup(ImageOrder::LSB_FIRST, &[(32, 32, 32)]), 32).unwrap();
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert!(matches!(
+            layout.to_bgra(&data, 2, 1).unwrap(),
+            Cow::Borrowed(&[1, 2, 3, 4, 5, 6, 7, 8])
+        ));
+        assert!(layout.to_bgra(&data, 2, 2).is_err());
+    }
+
+    #[test]
+    fn swaps_big_endian_pixels() {
+        let layout =
+            ImageLayout::from_setup(&setup(ImageOrder::MSB_FIRST, &[(32, 32, 32)]), 32).unwrap();
+        // ARGB in memory.
+        let data = [0x80, 0xff, 0x40, 0x00];
+        assert_eq!(
+            &*layout.to_bgra(&data, 1, 1).unwrap(),
+            [0x00, 0x40, 0xff, 0x80]
+        );
+    }
+
+    #[test]
+    fn unpads_and_expands_packed_rows() {
+        // 24 bits per pixel, with rows padded to 32 bits.
+        let layout =
+            ImageLayout::from_setup(&setup(ImageOrder::LSB_FIRST, &[(24, 24, 32)]), 24).unwrap();
+        assert_eq!(layout.row_bytes(3), 12);
+        assert_eq!(layout.row_bytes(1), 4);
+        let data = [
+            1, 2, 3, 0, // first row, then padding
+            4, 5, 6, 0,
+        ];
+        assert_eq!(
+            &*layout.to_bgra(&data, 1, 2).unwrap(),
+            [1, 2, 3, 0xff, 4, 5, 6, 0xff]
+        );
+
+        // 32 bits per pixel, with rows padded to 64 bits.
+        let layout =
+            ImageLayout::from_setup(&setup(ImageOrder::LSB_FIRST, &[(32, 32, 64)]), 32).unwrap();
+        assert!(!layout.is_bgra(1));
+        let data = [1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0];
+        assert_eq!(
+            &*layout.to_bgra(&data, 1, 2).unwrap(),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Key and button events, normalized into presses and releases so that each release can
+//! be paired with the press before it.
+
+use std::collections::HashMap;
+
+use x11rb::protocol::{
+    xproto::{Button, KeyButMask, Keycode, Timestamp},
+    Event,
+};
+
+use crate::command::Command;
+
+/// A key or mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(Keycode),
+    Button(Button),
+}
+
+/// A key or button being pressed or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub input: Input,
+    pub pressed: bool,
+    /// The modifiers and buttons held just before the event.
+    pub state: KeyButMask,
+    pub time: Timestamp,
+}
+
+impl InputEvent {
+    /// The input event `ev` is, if it's a key or button press or release.
+    pub fn from_event(ev: &Event) -> Option<Self> {
+        let (input, pressed, state, time) = match ev {
+            Event::KeyPress(ev) => (Input::Key(ev.detail), true, ev.state, ev.time),
+            Event::KeyRelease(ev) => (Input::Key(ev.detail), false, ev.state, ev.time),
+            Event::ButtonPress(ev) => (Input::Button(ev.detail), true, ev.state, ev.time),
+            Event::ButtonRelease(ev) => (Input::Button(ev.detail), false, ev.state, ev.time),
+            _ => return None,
+        };
+        Some(Self {
+            input,
+            pressed,
+            state,
+            time,
+        })
+    }
+}
+
+/// The keys and buttons being held, with the command each one's press ran.
+///
+/// Releases are matched to presses by key or button alone, since the modifiers that
+/// picked the command may well be let go of first.
+#[derive(Debug, Default)]
+pub struct HeldInputs {
+    held: HashMap<Input, Option<Command>>,
+}
+
+impl HeldInputs {
+    /// Note that `input` was pressed and ran `command`. Returns `false` if it was already
+    /// held, i.e. the press is a key repeating.
+    pub fn press(&mut self, input: Input, command: Option<Command>) -> bool {
+        if self.held.contains_key(&input) {
+            return false;
+        }
+        self.held.insert(input, command);
+        true
+    }
+
+    /// Note that `input` was released, returning the command its press ran, if any.
+    pub fn release(&mut self, input: Input) -> Option<Command> {
+        self.held.remove(&input).flatten()
+    }
+
+    pub fn is_held(&self, input: Input) -> bool {
+        self.held.contains_key(&input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_releases_with_presses() {
+        let mut held = HeldInputs::default();
+        let key = Input::Key(38);
+        assert!(held.press(key, Some(Command::HoldExpose)));
+        // Repeats only count once.
+        assert!(!held.press(key, None));
+        assert!(held.press(Input::Button(1), None));
+
+        assert_eq!(held.release(Input::Button(1)), None);
+        assert!(held.is_held(key));
+        assert_eq!(held.release(key), Some(Command::HoldExpose));
+        assert!(!held.is_held(key));
+        // A release without a press, e.g. of a key held since before startup.
+        assert_eq!(held.release(key), None);
+    }
+
+    #[test]
+    fn normalizes_x_events() {
+        let press = x11rb::protocol::xproto::KeyPressEvent {
+            detail: 38,
+            state: KeyButMask::MOD4,
+            time: 42,
+            ..Default::default()
+        };
+        assert_eq!(
+            InputEvent::from_event(&Event::KeyRelease(press)),
+            Some(InputEvent {
+                input: Input::Key(38),
+                pressed: false,
+                state: KeyButMask::MOD4,
+                time: 42,
+            })
+        );
+        assert_eq!(
+            InputEvent::from_event(&Event::ButtonPress(Default::default())).map(|ev| ev.pressed),
+            Some(true)
+        );
+        assert_eq!(InputEvent::from_event(&Event::Unknown(Vec::new())), None);
+    }
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{bail, Context, Error, Result};
+use tracing::{debug, warn};
+use x11rb::{
+    errors::{ConnectionError, ReplyError},
+    protocol::xproto::{self, GrabMode, Keycode, ModMask},
+    x11_utils::X11Error,
+};
+use x11rb_async::{
+    connection::Connection as _,
+    protocol::{
+        xkb::{self, ConnectionExt as _},
+        xproto::ConnectionExt as _,
+    },
+};
+
+use crate::{command::Command, connection::XConn};
+
+/// Lock modifiers that shouldn't stop a binding from matching, i.e. Caps Lock and
+/// (usually) Num Lock.
+const IGNORED_MODIFIERS: [ModMask; 2] = [ModMask::LOCK, ModMask::M2];
+
+/// A keysym plus the modifiers that must be held with it, like `Mod4+Shift+e`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub modifiers: ModMask,
+    pub keysym: u32,
+}
+
+impl FromStr for KeyCombo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').collect();
+        let Some(key) = parts.pop().filter(|key| !key.is_empty()) else {
+            bail!("expected a key like Mod4+e, got {s}");
+        };
+
+        let mut modifiers = ModMask::from(0u16);
+        for part in parts {
+            modifiers |= match part.to_ascii_lowercase().as_str() {
+                "shift" => ModMask::SHIFT,
+                "control" | "ctrl" => ModMask::CONTROL,
+                "mod1" | "alt" => ModMask::M1,
+                "mod2" => ModMask::M2,
+                "mod3" => ModMask::M3,
+                "mod4" | "super" => ModMask::M4,
+                "mod5" => ModMask::M5,
+                _ => bail!("unknown modifier {part}"),
+            };
+        }
+
+        let keysym = keysym_from_name(key).with_context(|| format!("unknown key {key}"))?;
+        Ok(Self { modifiers, keysym })
+    }
+}
+
+/// Look up the keysym for a key name. This only knows about the keys that are likely to
+/// be bound to compositor commands, rather than the whole keysym table.
+fn keysym_from_name(name: &str) -> Option<u32> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // Latin-1 keysyms are the same as their code points, and letters are bound by
+        // their lowercase keysym.
+        return (c.is_ascii_graphic()).then(|| c.to_ascii_lowercase() as u32);
+    }
+
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u32>().ok()) {
+        return (1..=35).contains(&n).then(|| 0xffbe + n - 1);
+    }
+
+    Some(match name {
+        "space" => 0x0020,
+        "BackSpace" => 0xff08,
+        "Tab" => 0xff09,
+        "Return" => 0xff0d,
+        "Pause" => 0xff13,
+        "Scroll_Lock" => 0xff14,
+        "Escape" => 0xff1b,
+        "Home" => 0xff50,
+        "Left" => 0xff51,
+        "Up" => 0xff52,
+        "Right" => 0xff53,
+        "Down" => 0xff54,
+        "Page_Up" | "Prior" => 0xff55,
+        "Page_Down" | "Next" => 0xff56,
+        "End" => 0xff57,
+        "Print" => 0xff61,
+        "Insert" => 0xff63,
+        "Delete" => 0xffff,
+        _ => return None,
+    })
+}
+
+/// Binds a key combination to a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub combo: KeyCombo,
+    pub command: Command,
+}
+
+/// The passive key grabs we hold on the root window, and what they do.
+#[derive(Debug, Default)]
+pub struct KeyGrabs {
+    grabs: HashMap<(Keycode, u16), Command>,
+}
+
+impl KeyGrabs {
+    /// Grab every key code that produces each binding's keysym.
+    ///
+    /// Bindings that another client (typically the window manager) has already grabbed
+    /// are skipped with a warning rather than failing.
+    pub async fn grab(conn: &XConn, root: xproto::Window, bindings: &[KeyBinding]) -> Result<Self> {
+        let mut grabs = Self::default();
+        if bindings.is_empty() {
+            return Ok(grabs);
+        }
+
+        let setup = conn.setup();
+        let (min, max) = (setup.min_keycode, setup.max_keycode);
+        let mapping = conn
+            .get_keyboard_mapping(min, max - min + 1)
+            .await?
+            .reply()
+            .await?;
+        let per_keycode = mapping.keysyms_per_keycode.max(1) as usize;
+
+        for binding in bindings {
+            let keycodes = mapping
+                .keysyms
+                .chunks(per_keycode)
+                .zip(min..=max)
+                .filter(|(keysyms, _)| keysyms.contains(&binding.combo.keysym))
+                .map(|(_, keycode)| keycode);
+
+            let mut found = false;
+            for keycode in keycodes {
+                found = true;
+                for modifiers in lock_variants(binding.combo.modifiers) {
+                    let result = conn
+                        .grab_key(
+                            false,
+                            root,
+                            modifiers,
+                            keycode,
+                            GrabMode::ASYNC,
+                            GrabMode::ASYNC,
+                        )
+                        .await?
+                        .check()
+                        .await;
+                    match result {
+                        Ok(()) => {}
+                        Err(ReplyError::X11Error(X11Error { error_kind, .. })) => {
+                            warn!(
+                                "Couldn't grab {:?} for {:?}: {:?}",
+                                binding.combo, binding.command, error_kind
+                            );
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                    grabs
+                        .grabs
+                        .insert((keycode, u16::from(modifiers)), binding.command);
+                }
+            }
+
+            if found {
+                debug!("Bound {:?} to {:?}", binding.combo, binding.command);
+            } else {
+                warn!("No key produces {:?}, not binding it", binding.combo);
+            }
+        }
+
+        Ok(grabs)
+    }
+
+    /// The command bound to a key press, if any.
+    pub fn command(&self, keycode: Keycode, state: xproto::KeyButMask) -> Option<Command> {
+        // Mouse buttons held at the same time don't matter.
+        let modifiers = u16::from(state) & 0xff;
+        self.grabs.get(&(keycode, modifiers)).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grabs.is_empty()
+    }
+
+    /// Release every key grab we hold on `root`. Grabs held by other clients aren't
+    /// affected.
+    pub async fn release(&mut self, conn: &XConn, root: xproto::Window) -> Result<()> {
+        if self.grabs.is_empty() {
+            return Ok(());
+        }
+        conn.ungrab_key(xproto::Grab::ANY, root, ModMask::ANY)
+            .await?
+            .check()
+            .await?;
+        self.grabs.clear();
+        Ok(())
+    }
+}
+
+/// Ask XKB not to send a release before each repeated press of a held key, so that
+/// holding a key down reads as a single press. Returns whether the server agreed.
+pub async fn detect_autorepeat(conn: &XConn) -> Result<bool> {
+    let reply = match conn.xkb_use_extension(1, 0).await {
+        Ok(cookie) => cookie.reply().await?,
+        Err(ConnectionError::UnsupportedExtension) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if !reply.supported {
+        return Ok(false);
+    }
+
+    let flag = xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT;
+    let reply = conn
+        .xkb_per_client_flags(
+            xkb::ID::USE_CORE_KBD.into(),
+            flag,
+            flag,
+            xkb::BoolCtrl::from(0u32),
+            xkb::BoolCtrl::from(0u32),
+            xkb::BoolCtrl::from(0u32),
+        )
+        .await?
+        .reply()
+        .await?;
+    Ok(reply.value.contains(flag))
+}
+
+/// `modifiers` combined with every combination of the ignored lock modifiers.
+fn lock_variants(modifiers: ModMask) -> impl Iterator<Item = ModMask> {
+    (0..1 << IGNORED_MODIFIERS.len()).map(move |bits: u32| {
+        IGNORED_MODIFIERS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| bits & (1 << i) != 0)
+            .fold(modifiers, |acc, (_, &lock)| acc | lock)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_combos() {
+        assert_eq!(
+            "Mod4+Shift+E".parse::<KeyCombo>().unwrap(),
+            KeyCombo {
+                modifiers: ModMask::M4 | ModMask::SHIFT,
+                keysym: 'e' as u32,
+            }
+        );
+        assert_eq!(
+            "ctrl+alt+F12".parse::<KeyCombo>().unwrap(),
+            KeyCombo {
+                modifiers: ModMask::CONTROL | ModMask::M1,
+                keysym: 0xffc9,
+            }
+        );
+        assert_eq!("Print".parse::<KeyCombo>().unwrap().keysym, 0xff61);
+    }
+
+    #[test]
+    fn rejects_bad_combos() {
+        assert!("Mod4+".parse::<KeyCombo>().is_err());
+        assert!("Hyper+e".parse::<KeyCombo>().is_err());
+        assert!("Mod4+NoSuchKey".parse::<KeyCombo>().is_err());
+    }
+
+    #[test]
+    fn grabs_every_lock_combination() {
+        let variants: Vec<u16> = lock_variants(ModMask::M4).map(u16::from).collect();
+        assert_eq!(
+            variants,
+            [
+                u16::from(ModMask::M4),
+                u16::from(ModMask::M4 | ModMask::LOCK),
+                u16::from(ModMask::M4 | ModMask::M2),
+                u16::from(ModMask::M4 | ModMask::LOCK | ModMask::M2),
+            ]
+        );
+    }
+}
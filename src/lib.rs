@@ -0,0 +1,52 @@
+pub mod animation;
+pub mod atoms;
+pub mod background;
+pub mod border;
+pub mod child;
+pub mod cli;
+pub mod command;
+pub mod compositor;
+pub mod config;
+pub mod connection;
+pub mod cursor;
+#[cfg(debug_assertions)]
+pub mod damage_overlay;
+pub mod display_timing;
+pub mod error;
+pub mod events;
+pub mod eviction;
+pub mod expose;
+pub mod extensions;
+pub mod frames;
+#[cfg(feature = "hdr")]
+pub mod hdr;
+pub mod hooks;
+pub mod icon;
+pub mod image;
+pub mod input;
+pub mod keys;
+pub mod logging;
+pub mod monitors;
+pub mod notify;
+pub mod occlusion;
+pub mod overlay;
+pub mod pipeline;
+pub mod power;
+pub mod present;
+pub mod quality;
+#[cfg(all(feature = "shader-reload", debug_assertions))]
+pub mod reload;
+pub mod scaled;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "capture-stream")]
+pub mod stream;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod texture;
+pub mod thumbnail;
+pub mod tint;
+pub mod trace;
+pub mod vt;
+pub mod watchdog;
+pub mod window;
@@ -0,0 +1,369 @@
+//! Log output setup for the `recomp` binary.
+
+use std::{
+    fmt::{self, Write as _},
+    fs::OpenOptions,
+    path::PathBuf,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context, Error, Result};
+use tracing::{field::Field, Event, Subscriber};
+use tracing_subscriber::{
+    filter::{targets::Targets, LevelFilter},
+    fmt::{
+        format::Writer,
+        time::{FormatTime, UtcTime},
+        FmtContext, FormatEvent, FormatFields,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
+
+/// Environment variable that picks the log format, if not given on the command line.
+pub const FORMAT_ENV: &str = "RECOMP_LOG_FORMAT";
+/// Environment variable that picks the log file, if not given on the command line.
+pub const FILE_ENV: &str = "RECOMP_LOG_FILE";
+/// Environment variable that turns the perf-timing layer on (`1`) or off (`0`), if not
+/// chosen on the command line.
+pub const PERF_ENV: &str = "RECOMP_PERF";
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable, colored when writing to a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "pretty" => LogFormat::Pretty,
+            "json" => LogFormat::Json,
+            _ => bail!("unknown log format {s}, expected pretty or json"),
+        })
+    }
+}
+
+/// Where and how to log.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Defaults to `$RECOMP_LOG_FORMAT`, or pretty if that isn't set either.
+    pub format: Option<LogFormat>,
+    /// Append logs to this file instead of writing them to stderr. The file isn't
+    /// rotated, so use something like logrotate's `copytruncate` for long-running
+    /// sessions.
+    pub file: Option<PathBuf>,
+    /// Whether to time spans for the frame stats. This costs a little on every span, so
+    /// defaults to `$RECOMP_PERF`, or on only in debug builds if that isn't set either.
+    pub perf: Option<bool>,
+}
+
+impl LogOptions {
+    /// Fill in anything not given on the command line from the environment.
+    pub fn with_env_defaults(mut self) -> Result<Self> {
+        if self.format.is_none() {
+            if let Ok(format) = std::env::var(FORMAT_ENV) {
+                self.format = Some(
+                    format
+                        .parse()
+                        .with_context(|| format!("Invalid value for {FORMAT_ENV}"))?,
+                );
+            }
+        }
+        if self.file.is_none() {
+            self.file = std::env::var_os(FILE_ENV).map(PathBuf::from);
+        }
+        if self.perf.is_none() {
+            if let Ok(perf) = std::env::var(PERF_ENV) {
+                self.perf = Some(
+                    parse_switch(&perf).with_context(|| format!("Invalid value for {PERF_ENV}"))?,
+                );
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Install the global subscriber: the log output described by `options`, plus the
+/// perf-timing layer used for frame stats if it's enabled.
+///
+/// The level for our own logs comes from `RUST_LOG` (e.g. `RUST_LOG=debug`), defaulting
+/// to `trace` in debug builds and `info` in release builds.
+pub fn setup(options: &LogOptions) -> Result<()> {
+    let default_level = if cfg!(debug_assertions) {
+        LevelFilter::TRACE
+    } else {
+        LevelFilter::INFO
+    };
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(default_level);
+    let targets = Targets::new().with_targets([
+        ("recomp", level),
+        ("wgpu", LevelFilter::WARN),
+        ("tokio", LevelFilter::WARN),
+    ]);
+
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_file(true)
+        .with_timer(UtcTime::rfc_3339());
+    let fmt_layer: Box<dyn Layer<Registry> + Send + Sync> =
+        match (&options.file, options.format.unwrap_or_default()) {
+            (None, LogFormat::Pretty) => fmt_layer.with_ansi(true).with_filter(targets).boxed(),
+            (None, LogFormat::Json) => fmt_layer
+                .event_format(JsonFormat)
+                .with_filter(targets)
+                .boxed(),
+            (Some(path), format) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open log file {}", path.display()))?;
+                let fmt_layer = fmt_layer.with_ansi(false).with_writer(Mutex::new(file));
+                match format {
+                    LogFormat::Pretty => fmt_layer.with_filter(targets).boxed(),
+                    LogFormat::Json => fmt_layer
+                        .event_format(JsonFormat)
+                        .with_filter(targets)
+                        .boxed(),
+                }
+            }
+        };
+
+    let perf_layer = options.perf.unwrap_or(cfg!(debug_assertions)).then(|| {
+        tracing_timing::Builder::default()
+            .span_close_events()
+            .layer(|| tracing_timing::Histogram::new(2).expect("to create histogram"))
+    });
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(perf_layer)
+        .init();
+
+    Ok(())
+}
+
+/// Parse an on/off switch from the environment.
+fn parse_switch(value: &str) -> Result<bool> {
+    Ok(match value {
+        "1" | "true" | "on" => true,
+        "0" | "false" | "off" => false,
+        _ => bail!("expected 1 or 0, got {value}"),
+    })
+}
+
+/// Formats each event as a single-line JSON object with its timestamp, level, target,
+/// source location, fields, and the names of the spans it's in.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+
+        let mut timestamp = String::new();
+        UtcTime::rfc_3339().format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut line = String::from("{");
+        write_key(&mut line, "timestamp");
+        write_string(&mut line, &timestamp);
+        line.push(',');
+        write_key(&mut line, "level");
+        write_string(&mut line, meta.level().as_str());
+        line.push(',');
+        write_key(&mut line, "target");
+        write_string(&mut line, meta.target());
+        if let (Some(file), Some(number)) = (meta.file(), meta.line()) {
+            line.push(',');
+            write_key(&mut line, "file");
+            write_string(&mut line, file);
+            write!(line, ",\"line\":{number}")?;
+        }
+
+        line.push(',');
+        write_key(&mut line, "fields");
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        line.push('{');
+        line.push_str(&fields.0);
+        line.push('}');
+
+        if let Some(scope) = ctx.event_scope() {
+            line.push(',');
+            write_key(&mut line, "spans");
+            line.push('[');
+            for (i, span) in scope.from_root().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                write_string(&mut line, span.name());
+            }
+            line.push(']');
+        }
+
+        line.push('}');
+        writeln!(writer, "{line}")
+    }
+}
+
+/// Collects an event's fields as the members of a JSON object.
+#[derive(Default)]
+struct JsonFields(String);
+
+impl JsonFields {
+    fn key(&mut self, field: &Field) {
+        if !self.0.is_empty() {
+            self.0.push(',');
+        }
+        write_key(&mut self.0, field.name());
+    }
+}
+
+impl tracing::field::Visit for JsonFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.key(field);
+        write_string(&mut self.0, &format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.key(field);
+        write_string(&mut self.0, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.key(field);
+        write!(self.0, "{value}").ok();
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.key(field);
+        write!(self.0, "{value}").ok();
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.key(field);
+        write!(self.0, "{value}").ok();
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.key(field);
+        // JSON has no representation for infinities or NaN.
+        if value.is_finite() {
+            write!(self.0, "{value}").ok();
+        } else {
+            self.0.push_str("null");
+        }
+    }
+}
+
+fn write_key(out: &mut String, key: &str) {
+    write_string(out, key);
+    out.push(':');
+}
+
+/// Append `value` as a quoted and escaped JSON string.
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                write!(out, "\\u{:04x}", c as u32).ok();
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_json_strings() {
+        let mut out = String::new();
+        write_string(&mut out, "a \"quoted\"\\path\nwith\u{1b}escape");
+        assert_eq!(out, r#""a \"quoted\"\\path\nwith\u001bescape""#);
+    }
+
+    #[test]
+    fn formats_events_as_json() {
+        use std::{io, sync::Arc};
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .event_format(JsonFormat)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("render").entered();
+            tracing::info!(frames = 3, ok = true, "Frame \"stats\"");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.ends_with('\n') && output.lines().count() == 1,
+            "{output}"
+        );
+        assert!(output.contains(r#""level":"INFO""#), "{output}");
+        assert!(
+            output.contains(r#""fields":{"message":"Frame \"stats\"","frames":3,"ok":true}"#),
+            "{output}"
+        );
+        assert!(output.contains(r#""spans":["render"]"#), "{output}");
+    }
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("pretty".parse::<LogFormat>().unwrap(), LogFormat::Pretty);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn parses_switches() {
+        assert!(parse_switch("1").unwrap());
+        assert!(parse_switch("on").unwrap());
+        assert!(!parse_switch("0").unwrap());
+        assert!(!parse_switch("false").unwrap());
+        assert!(parse_switch("maybe").is_err());
+    }
+}
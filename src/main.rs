@@ -1,50 +1,82 @@
+use std::process::ExitCode;
+
 use anyhow::Result;
 
 use tracing::info;
-use tracing_subscriber::{
-    filter::LevelFilter, fmt::time::UtcTime, layer::SubscriberExt, util::SubscriberInitExt, Layer,
-};
 
-use crate::compositor::Compositor;
-
-pub mod compositor;
-pub mod connection;
-
-fn setup_tracing() {
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_ansi(true)
-        .with_file(true)
-        .with_timer(UtcTime::rfc_3339())
-        .with_filter(
-            tracing_subscriber::filter::targets::Targets::new().with_targets([
-                (
-                    "recomp",
-                    #[cfg(debug_assertions)]
-                    LevelFilter::TRACE,
-                    #[cfg(not(debug_assertions))]
-                    tracing_subscriber::filter::EnvFilter::from_default_env(),
-                ),
-                ("wgpu", LevelFilter::WARN),
-                ("tokio", LevelFilter::WARN),
-            ]),
-        );
-    let perf_layer = tracing_timing::Builder::default()
-        .layer(|| tracing_timing::Histogram::new(2).expect("to create histogram"));
-    tracing_subscriber::registry()
-        .with(fmt_layer)
-        .with(perf_layer)
-        .init();
-}
+use recomp::{
+    child,
+    cli::Args,
+    compositor::{self, Compositor},
+    logging,
+};
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    setup_tracing();
+async fn main() -> Result<ExitCode> {
+    let args = Args::parse()?;
+
+    logging::setup(&args.log.with_env_defaults()?)?;
 
-    let session = Compositor::new(None).await?;
+    if args.check_config {
+        println!("{:#?}", args.config);
+        let adapters = match args.config.adapter {
+            Some(_) => compositor::adapter_infos(),
+            None => Vec::new(),
+        };
+        let problems = args.config.problems(&adapters);
+        for problem in &problems {
+            eprintln!("error: {problem}");
+        }
+        return Ok(if problems.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+
+    let display = args.display.as_deref();
+    if args.list_adapters {
+        print!("{}", compositor::describe_adapters(display).await?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut session = match args.nested {
+        Some(size) => Compositor::new_nested(display, None, size, args.config).await?,
+        None => Compositor::new(display, args.config).await?,
+    };
 
     info!("Connected to X11 server");
 
-    session.run().await?;
+    if args.once {
+        session.render_once().await?;
+        info!("Rendered a frame, exiting");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Started only now, so that its windows are composited from the first frame.
+    let mut child = match &args.exec {
+        Some(command) => Some(child::spawn(command, display)?),
+        None => None,
+    };
+
+    let run = async {
+        match (&args.record, &args.replay) {
+            (Some(path), _) => session.record(path).await,
+            (_, Some(path)) => session.replay(path).await,
+            (None, None) => session.run().await,
+        }
+    };
+    match &mut child {
+        Some(process) if args.exit_with_child => tokio::select! {
+            result = run => result?,
+            status = process.wait() => {
+                let status = status?;
+                info!("Child exited with {status}, exiting");
+                return Ok(ExitCode::from(child::exit_code(status)));
+            }
+        },
+        _ => run.await?,
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
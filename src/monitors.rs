@@ -0,0 +1,382 @@
+use anyhow::Result;
+use tracing::{info, warn};
+use x11rb::{
+    errors::ConnectionError,
+    protocol::{randr, xproto},
+};
+use x11rb_async::protocol::{randr::ConnectionExt as _, xproto::ConnectionExt as _};
+
+use crate::{
+    connection::XConn,
+    window::{Edge, Strut},
+};
+
+/// The density that a scale of 1 corresponds to, as X and most toolkits assume.
+const BASE_DPI: f32 = 96.0;
+
+const MM_PER_INCH: f32 = 25.4;
+
+/// Reported physical sizes giving densities outside this range are assumed to be wrong.
+const MIN_PLAUSIBLE_DPI: f32 = 50.0;
+const MAX_PLAUSIBLE_DPI: f32 = 600.0;
+
+/// A region of the root window shown on a physical display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// The RandR monitor name, which is usually the output name (e.g. `DP-1`).
+    pub name: String,
+    /// Where the monitor is on the root window.
+    pub rect: xproto::Rectangle,
+    pub primary: bool,
+    /// Physical size in millimeters, if known.
+    pub size_mm: (u32, u32),
+    /// Refresh rate in millihertz of the mode the monitor's first output is using, if
+    /// known.
+    pub refresh_mhz: Option<u32>,
+}
+
+impl Monitor {
+    /// A single primary monitor covering an area of `size` at the origin.
+    pub fn covering(size: (u16, u16)) -> Self {
+        Monitor {
+            name: String::from("default"),
+            rect: xproto::Rectangle {
+                x: 0,
+                y: 0,
+                width: size.0,
+                height: size.1,
+            },
+            primary: true,
+            size_mm: (0, 0),
+            refresh_mhz: None,
+        }
+    }
+
+    /// Horizontal pixel density in dots per inch, if the physical size is known.
+    ///
+    /// Some displays report nonsense sizes (or their aspect ratio in centimeters), so
+    /// densities outside a plausible range are treated as unknown too.
+    pub fn dpi(&self) -> Option<f32> {
+        if self.size_mm.0 == 0 {
+            return None;
+        }
+        let dpi = self.rect.width as f32 / self.size_mm.0 as f32 * MM_PER_INCH;
+        (MIN_PLAUSIBLE_DPI..=MAX_PLAUSIBLE_DPI)
+            .contains(&dpi)
+            .then_some(dpi)
+    }
+
+    /// How much bigger than at 96 DPI things should be drawn to look the same size on
+    /// this monitor, in steps of a quarter. Monitors of unknown or low density get 1.
+    pub fn scale(&self) -> f32 {
+        let Some(dpi) = self.dpi() else {
+            return 1.0;
+        };
+        ((dpi / BASE_DPI * 4.0).round() / 4.0).max(1.0)
+    }
+}
+
+/// The monitor showing most of `rect`, if any of it is shown at all.
+pub fn monitor_at(monitors: &[Monitor], rect: xproto::Rectangle) -> Option<&Monitor> {
+    monitors
+        .iter()
+        .map(|monitor| (monitor, overlap(monitor.rect, rect)))
+        .filter(|(_, area)| *area > 0)
+        .max_by_key(|(_, area)| *area)
+        .map(|(monitor, _)| monitor)
+}
+
+/// The area of the intersection of two rectangles.
+fn overlap(a: xproto::Rectangle, b: xproto::Rectangle) -> u32 {
+    let span = |a0: i16, a_len: u16, b0: i16, b_len: u16| {
+        let start = (a0 as i32).max(b0 as i32);
+        let end = (a0 as i32 + a_len as i32).min(b0 as i32 + b_len as i32);
+        (end - start).max(0) as u32
+    };
+    span(a.x, a.width, b.x, b.width) * span(a.y, a.height, b.y, b.height)
+}
+
+/// What's left of `monitor` once the space `struts` reserve along its edges is taken out.
+///
+/// Struts only affect the monitors they overlap, so a panel along the top of one
+/// monitor leaves the others alone.
+pub fn work_area(monitor: xproto::Rectangle, struts: &[Strut]) -> xproto::Rectangle {
+    let edges = |rect: xproto::Rectangle| {
+        let (x, y) = (rect.x as i32, rect.y as i32);
+        (x, y, x + rect.width as i32, y + rect.height as i32)
+    };
+    let (mut x0, mut y0, mut x1, mut y1) = edges(monitor);
+    for strut in struts {
+        let (sx0, sy0, sx1, sy1) = edges(strut.area);
+        if sx0 >= x1 || sx1 <= x0 || sy0 >= y1 || sy1 <= y0 {
+            continue;
+        }
+        match strut.edge {
+            Edge::Left => x0 = x0.max(sx1),
+            Edge::Right => x1 = x1.min(sx0),
+            Edge::Top => y0 = y0.max(sy1),
+            Edge::Bottom => y1 = y1.min(sy0),
+        }
+    }
+    xproto::Rectangle {
+        x: x0 as i16,
+        y: y0 as i16,
+        width: (x1 - x0).max(0) as u16,
+        height: (y1 - y0).max(0) as u16,
+    }
+}
+
+/// Query the active monitors from RandR.
+///
+/// If RandR 1.5 isn't available, this falls back to a single monitor covering the whole
+/// root window.
+pub async fn query_monitors(
+    conn: &XConn,
+    root: xproto::Window,
+    root_size: (u16, u16),
+) -> Result<Vec<Monitor>> {
+    let fallback = || vec![Monitor::covering(root_size)];
+
+    let version = match conn.randr_query_version(1, 5).await {
+        Ok(cookie) => cookie.reply().await?,
+        Err(ConnectionError::UnsupportedExtension) => {
+            warn!("RandR is not available, assuming a single monitor");
+            return Ok(fallback());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if (version.major_version, version.minor_version) < (1, 5) {
+        warn!(
+            "RandR {}.{} does not support monitors, assuming a single monitor",
+            version.major_version, version.minor_version
+        );
+        return Ok(fallback());
+    }
+
+    let reply = conn.randr_get_monitors(root, true).await?.reply().await?;
+    let resources = conn
+        .randr_get_screen_resources_current(root)
+        .await?
+        .reply()
+        .await?;
+
+    let mut monitors = Vec::with_capacity(reply.monitors.len());
+    for info in reply.monitors {
+        let name = conn.get_atom_name(info.name).await?.reply().await?.name;
+        let refresh_mhz = match info.outputs.first() {
+            Some(&output) => output_refresh_mhz(conn, output, &resources).await?,
+            None => None,
+        };
+        monitors.push(Monitor {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            rect: xproto::Rectangle {
+                x: info.x,
+                y: info.y,
+                width: info.width,
+                height: info.height,
+            },
+            primary: info.primary,
+            size_mm: (info.width_in_millimeters, info.height_in_millimeters),
+            refresh_mhz,
+        });
+    }
+
+    if monitors.is_empty() {
+        return Ok(fallback());
+    }
+
+    info!("Monitors: {:?}", monitors);
+
+    Ok(monitors)
+}
+
+/// The refresh rate of the mode `output` is currently showing, if it's showing one.
+async fn output_refresh_mhz(
+    conn: &XConn,
+    output: randr::Output,
+    resources: &randr::GetScreenResourcesCurrentReply,
+) -> Result<Option<u32>> {
+    let output = conn
+        .randr_get_output_info(output, resources.config_timestamp)
+        .await?
+        .reply()
+        .await?;
+    if output.crtc == x11rb::NONE {
+        return Ok(None);
+    }
+    let crtc = conn
+        .randr_get_crtc_info(output.crtc, resources.config_timestamp)
+        .await?
+        .reply()
+        .await?;
+    Ok(resources
+        .modes
+        .iter()
+        .find(|mode| mode.id == crtc.mode)
+        .and_then(mode_refresh_mhz))
+}
+
+/// The refresh rate of a mode in millihertz, from its timings.
+fn mode_refresh_mhz(mode: &randr::ModeInfo) -> Option<u32> {
+    let mut pixels = mode.htotal as u64 * mode.vtotal as u64;
+    let mut clock_mhz = mode.dot_clock as u64 * 1000;
+    // Double scanned modes show every line twice, and interlaced ones refresh half the
+    // lines each time.
+    if mode.mode_flags.contains(randr::ModeFlag::DOUBLE_SCAN) {
+        pixels *= 2;
+    }
+    if mode.mode_flags.contains(randr::ModeFlag::INTERLACE) {
+        clock_mhz *= 2;
+    }
+    if pixels == 0 || clock_mhz == 0 {
+        return None;
+    }
+    Some((clock_mhz / pixels) as u32)
+}
+
+/// The refresh rate to pace frames at: that of the monitor named `output` if given, or
+/// the fastest one otherwise, since slower monitors just show every other frame.
+pub fn pacing_refresh_mhz(monitors: &[Monitor], output: Option<&str>) -> Option<u32> {
+    match output {
+        Some(name) => {
+            monitors
+                .iter()
+                .find(|monitor| monitor.name == name)?
+                .refresh_mhz
+        }
+        None => monitors
+            .iter()
+            .filter_map(|monitor| monitor.refresh_mhz)
+            .max(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: i16, width: u16, height: u16, width_mm: u32) -> Monitor {
+        Monitor {
+            name: format!("OUT-{x}"),
+            rect: xproto::Rectangle {
+                x,
+                y: 0,
+                width,
+                height,
+            },
+            primary: false,
+            size_mm: (width_mm, width_mm * height as u32 / width as u32),
+            refresh_mhz: None,
+        }
+    }
+
+    #[test]
+    fn computes_scale_from_physical_size() {
+        // A 24" 1080p monitor and a 27" 4K one.
+        let low = monitor(0, 1920, 1080, 531);
+        let high = monitor(1920, 3840, 2160, 597);
+        assert_eq!(low.dpi().map(f32::round), Some(92.0));
+        assert_eq!(low.scale(), 1.0);
+        assert_eq!(high.dpi().map(f32::round), Some(163.0));
+        assert_eq!(high.scale(), 1.75);
+
+        // A 13" 2560x1600 laptop panel.
+        assert_eq!(monitor(0, 2560, 1600, 286).scale(), 2.25);
+    }
+
+    #[test]
+    fn ignores_implausible_sizes() {
+        assert_eq!(Monitor::covering((1920, 1080)).dpi(), None);
+        assert_eq!(Monitor::covering((1920, 1080)).scale(), 1.0);
+        // Aspect ratio reported as centimeters.
+        assert_eq!(monitor(0, 1920, 1080, 16).dpi(), None);
+        assert_eq!(monitor(0, 1920, 1080, 16).scale(), 1.0);
+    }
+
+    #[test]
+    fn finds_monitor_showing_most_of_a_window() {
+        let monitors = [monitor(0, 1920, 1080, 531), monitor(1920, 3840, 2160, 597)];
+        let rect = |x, width| xproto::Rectangle {
+            x,
+            y: 100,
+            width,
+            height: 300,
+        };
+
+        assert_eq!(monitor_at(&monitors, rect(100, 400)), Some(&monitors[0]));
+        assert_eq!(monitor_at(&monitors, rect(1800, 400)), Some(&monitors[1]));
+        assert_eq!(monitor_at(&monitors, rect(1500, 400)), Some(&monitors[0]));
+        assert_eq!(monitor_at(&monitors, rect(-500, 400)), None);
+    }
+
+    #[test]
+    fn computes_refresh_from_mode_timings() {
+        // 1920x1080 at 60Hz and 144Hz, with CVT reduced blanking.
+        let mode = |dot_clock, htotal, vtotal| randr::ModeInfo {
+            dot_clock,
+            htotal,
+            vtotal,
+            ..Default::default()
+        };
+        assert_eq!(
+            mode_refresh_mhz(&mode(138_500_000, 2080, 1111)),
+            Some(59_933)
+        );
+        assert_eq!(
+            mode_refresh_mhz(&mode(325_080_000, 2000, 1128)),
+            Some(144_095)
+        );
+
+        let interlaced = randr::ModeInfo {
+            mode_flags: randr::ModeFlag::INTERLACE,
+            ..mode(74_250_000, 2200, 1125)
+        };
+        assert_eq!(mode_refresh_mhz(&interlaced), Some(60_000));
+        assert_eq!(mode_refresh_mhz(&mode(0, 0, 0)), None);
+    }
+
+    #[test]
+    fn paces_to_fastest_or_chosen_monitor() {
+        let mut monitors = [
+            monitor(0, 1920, 1080, 531),
+            monitor(1920, 3840, 2160, 597),
+            monitor(5760, 1920, 1080, 531),
+        ];
+        monitors[0].refresh_mhz = Some(60_000);
+        monitors[1].refresh_mhz = Some(144_000);
+
+        assert_eq!(pacing_refresh_mhz(&monitors, None), Some(144_000));
+        assert_eq!(pacing_refresh_mhz(&monitors, Some("OUT-0")), Some(60_000));
+        assert_eq!(pacing_refresh_mhz(&monitors, Some("OUT-5760")), None);
+        assert_eq!(pacing_refresh_mhz(&monitors, Some("HDMI-9")), None);
+        assert_eq!(pacing_refresh_mhz(&[], None), None);
+    }
+
+    #[test]
+    fn takes_struts_out_of_the_monitors_they_overlap() {
+        let rect = |x, y, width, height| xproto::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+        let struts = [
+            // A panel along the top of the left monitor.
+            Strut {
+                edge: Edge::Top,
+                area: rect(0, 0, 1920, 30),
+            },
+            // A dock along the bottom of the right one.
+            Strut {
+                edge: Edge::Bottom,
+                area: rect(1920, 1032, 1920, 48),
+            },
+        ];
+
+        let left = rect(0, 0, 1920, 1080);
+        let right = rect(1920, 0, 1920, 1080);
+        assert_eq!(work_area(left, &struts), rect(0, 30, 1920, 1050));
+        assert_eq!(work_area(right, &struts), rect(1920, 0, 1920, 1032));
+        assert_eq!(work_area(left, &[]), left);
+    }
+}
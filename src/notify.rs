@@ -0,0 +1,158 @@
+//! High-level events about what the compositor is doing, for embedders like bars and
+//! pagers that want to follow along without handling X events themselves.
+//!
+//! Subscribe with [`Compositor::events`]. Every subscriber gets every event from when it
+//! subscribed. One that falls more than [`CAPACITY`] events behind skips the oldest
+//! rather than holding up the compositor.
+//!
+//! [`Compositor::events`]: crate::compositor::Compositor::events
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+use x11rb::protocol::xproto;
+
+/// How many events can wait for the slowest subscriber before it starts missing them.
+pub const CAPACITY: usize = 256;
+
+/// Something that happened in the compositor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompositorEvent {
+    /// A window was mapped, and is now drawn.
+    WindowMapped {
+        window: xproto::Window,
+        geometry: xproto::Rectangle,
+    },
+    /// A window was unmapped, or destroyed while mapped.
+    WindowUnmapped { window: xproto::Window },
+    /// A window moved, was resized, or was restacked.
+    WindowConfigured {
+        window: xproto::Window,
+        geometry: xproto::Rectangle,
+    },
+    /// The monitor showing most of a window changed, e.g. because it was dragged onto
+    /// another one. `monitor` is the RandR output's name, or `None` once the window is
+    /// entirely off screen.
+    WindowMonitorChanged {
+        window: xproto::Window,
+        monitor: Option<String>,
+    },
+    /// The active window changed, following `_NET_ACTIVE_WINDOW`.
+    FocusChanged { window: Option<xproto::Window> },
+    /// A frame was presented. `frame` matches [`PresentInfo::frame`].
+    ///
+    /// [`PresentInfo::frame`]: crate::hooks::PresentInfo::frame
+    FrameRendered { frame: u64 },
+}
+
+type Recv = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<CompositorEvent, RecvError>,
+                    broadcast::Receiver<CompositorEvent>,
+                ),
+            > + Send,
+    >,
+>;
+
+/// A subscription to [`CompositorEvent`]s, which ends once the compositor is dropped.
+pub struct EventStream {
+    recv: Recv,
+}
+
+impl EventStream {
+    pub(crate) fn new(receiver: broadcast::Receiver<CompositorEvent>) -> Self {
+        Self {
+            recv: recv(receiver),
+        }
+    }
+}
+
+/// Wait for the next event, handing the receiver back along with it so that the next
+/// wait can pick up where this one left off.
+fn recv(mut receiver: broadcast::Receiver<CompositorEvent>) -> Recv {
+    Box::pin(async move {
+        let result = receiver.recv().await;
+        (result, receiver)
+    })
+}
+
+impl Stream for EventStream {
+    type Item = CompositorEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let (result, receiver) = std::task::ready!(self.recv.as_mut().poll(cx));
+            self.recv = recv(receiver);
+            match result {
+                Ok(ev) => return Poll::Ready(Some(ev)),
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Event subscriber fell behind, skipping {missed} events");
+                }
+                Err(RecvError::Closed) => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+
+    async fn next(stream: &mut EventStream) -> Option<CompositorEvent> {
+        poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn streams_events_until_closed() {
+        let (sender, receiver) = broadcast::channel(4);
+        let mut stream = EventStream::new(receiver);
+
+        sender
+            .send(CompositorEvent::FocusChanged { window: Some(7) })
+            .unwrap();
+        sender
+            .send(CompositorEvent::FrameRendered { frame: 0 })
+            .unwrap();
+        assert_eq!(
+            next(&mut stream).await,
+            Some(CompositorEvent::FocusChanged { window: Some(7) })
+        );
+        assert_eq!(
+            next(&mut stream).await,
+            Some(CompositorEvent::FrameRendered { frame: 0 })
+        );
+
+        drop(sender);
+        assert_eq!(next(&mut stream).await, None);
+    }
+
+    #[tokio::test]
+    async fn skips_what_it_fell_behind_on() {
+        let (sender, receiver) = broadcast::channel(2);
+        let mut stream = EventStream::new(receiver);
+
+        for frame in 0..5 {
+            sender
+                .send(CompositorEvent::FrameRendered { frame })
+                .unwrap();
+        }
+        assert_eq!(
+            next(&mut stream).await,
+            Some(CompositorEvent::FrameRendered { frame: 3 })
+        );
+        assert_eq!(
+            next(&mut stream).await,
+            Some(CompositorEvent::FrameRendered { frame: 4 })
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Skipping the parts of windows that opaque windows above them cover.
+//!
+//! Every frame still draws the whole stack bottom to top, but first a pass over it from
+//! the top down works out where each window shows through what's above it. Drawing a
+//! window is then scissored to that, so windows under a fullscreen one aren't drawn at
+//! all, and ones under a few others only where they can be seen. Since whatever covers a
+//! window is drawn after it anyway, this only saves work and never changes the frame.
+//!
+//! Only windows that hide every pixel of their rectangle count as covering what's under
+//! them, see [`covers`].
+
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::window::BlendMode;
+
+/// Above this many rectangles, a window is drawn in its visible region's bounding box
+/// instead, so that one cut up by lots of windows above it doesn't take as many draws.
+const MAX_RECTS: usize = 8;
+
+/// A window about to be drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layer {
+    /// Everything drawing the window touches, including its border, in screen pixels.
+    pub extent: Rectangle,
+    /// The part of it that hides whatever is underneath, if any.
+    pub opaque: Option<Rectangle>,
+}
+
+/// Whether a window drawn like this hides every pixel of its rectangle: no alpha channel,
+/// fully opaque, square corners, and blended normally.
+pub fn covers(has_alpha: bool, opacity: f32, corner_radius: f32, blend_mode: BlendMode) -> bool {
+    !has_alpha && opacity >= 1.0 && corner_radius <= 0.0 && blend_mode == BlendMode::Normal
+}
+
+/// Where each of `layers`, from bottom to top, can be seen on `screen` past the opaque
+/// layers above it. Layers that can't be seen at all get no rectangles.
+pub fn visible_regions(layers: &[Layer], screen: Rectangle) -> Vec<Vec<Rectangle>> {
+    let mut covered: Vec<Rectangle> = Vec::new();
+    let mut regions = vec![Vec::new(); layers.len()];
+    for (layer, region) in layers.iter().zip(&mut regions).rev() {
+        let mut visible: Vec<_> = intersect(layer.extent, screen).into_iter().collect();
+        for &cover in &covered {
+            if visible.is_empty() {
+                break;
+            }
+            visible = visible
+                .into_iter()
+                .flat_map(|rect| subtract(rect, cover))
+                .collect();
+        }
+        if visible.len() > MAX_RECTS {
+            visible = vec![bounds(&visible)];
+        }
+        *region = visible;
+
+        if let Some(opaque) = layer.opaque.and_then(|opaque| intersect(opaque, screen)) {
+            covered.push(opaque);
+        }
+    }
+    regions
+}
+
+/// The scissor rectangle, as x, y, width and height, covering `rect` in a `target` sized
+/// texture that a `screen` sized scene is drawn into. It's rounded outwards, so every
+/// pixel `rect` touches is drawn.
+pub fn scissor(rect: Rectangle, screen: (u32, u32), target: (u32, u32)) -> (u32, u32, u32, u32) {
+    let axis = |start: i16, len: u16, screen: u32, target: u32| {
+        let scale = target as f64 / screen as f64;
+        let end = (start as i32 + len as i32).clamp(0, screen as i32) as u32;
+        let start = (start.max(0) as u32).min(end);
+        let from = ((start as f64 * scale).floor() as u32).min(target);
+        let to = ((end as f64 * scale).ceil() as u32).min(target);
+        (from, to - from)
+    };
+    let (x, width) = axis(rect.x, rect.width, screen.0, target.0);
+    let (y, height) = axis(rect.y, rect.height, screen.1, target.1);
+    (x, y, width, height)
+}
+
+/// The left, top, right and bottom edges of `rect`.
+fn edges(rect: Rectangle) -> (i32, i32, i32, i32) {
+    let (x, y) = (rect.x as i32, rect.y as i32);
+    (x, y, x + rect.width as i32, y + rect.height as i32)
+}
+
+/// The rectangle between `left`, `top`, `right` and `bottom`, unless it's empty.
+fn from_edges(left: i32, top: i32, right: i32, bottom: i32) -> Option<Rectangle> {
+    (right > left && bottom > top).then(|| Rectangle {
+        x: left as i16,
+        y: top as i16,
+        width: (right - left) as u16,
+        height: (bottom - top) as u16,
+    })
+}
+
+fn intersect(a: Rectangle, b: Rectangle) -> Option<Rectangle> {
+    let (a_left, a_top, a_right, a_bottom) = edges(a);
+    let (b_left, b_top, b_right, b_bottom) = edges(b);
+    from_edges(
+        a_left.max(b_left),
+        a_top.max(b_top),
+        a_right.min(b_right),
+        a_bottom.min(b_bottom),
+    )
+}
+
+/// What's left of `rect` outside of `cut`: the bands above and below it, then what's
+/// beside it in between.
+fn subtract(rect: Rectangle, cut: Rectangle) -> Vec<Rectangle> {
+    let Some(overlap) = intersect(rect, cut) else {
+        return vec![rect];
+    };
+    let (left, top, right, bottom) = edges(rect);
+    let (cut_left, cut_top, cut_right, cut_bottom) = edges(overlap);
+    [
+        from_edges(left, top, right, cut_top),
+        from_edges(left, cut_bottom, right, bottom),
+        from_edges(left, cut_top, cut_left, cut_bottom),
+        from_edges(cut_right, cut_top, right, cut_bottom),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// The smallest rectangle containing all of `rects`, which mustn't be empty.
+fn bounds(rects: &[Rectangle]) -> Rectangle {
+    let (left, top, right, bottom) = rects.iter().map(|&rect| edges(rect)).fold(
+        (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+        |(left, top, right, bottom), (l, t, r, b)| {
+            (left.min(l), top.min(t), right.max(r), bottom.max(b))
+        },
+    );
+    from_edges(left, top, right, bottom).expect("no rectangles to bound")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rectangle = Rectangle {
+        x: 0,
+        y: 0,
+        width: 100,
+        height: 100,
+    };
+
+    fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn opaque(rect: Rectangle) -> Layer {
+        Layer {
+            extent: rect,
+            opaque: Some(rect),
+        }
+    }
+
+    fn translucent(rect: Rectangle) -> Layer {
+        Layer {
+            extent: rect,
+            opaque: None,
+        }
+    }
+
+    /// How many pixels of `region` there are, which only adds up if none overlap.
+    fn area(region: &[Rectangle]) -> u32 {
+        region
+            .iter()
+            .map(|rect| rect.width as u32 * rect.height as u32)
+            .sum()
+    }
+
+    #[test]
+    fn skips_windows_under_an_opaque_one() {
+        let regions = visible_regions(
+            &[
+                opaque(rect(10, 10, 50, 50)),
+                translucent(rect(0, 0, 100, 100)),
+                opaque(rect(0, 0, 100, 100)),
+            ],
+            SCREEN,
+        );
+        assert_eq!(regions, [vec![], vec![], vec![SCREEN]]);
+    }
+
+    #[test]
+    fn draws_what_shows_past_the_windows_above() {
+        let regions = visible_regions(
+            &[opaque(rect(0, 0, 40, 40)), opaque(rect(20, 20, 40, 40))],
+            SCREEN,
+        );
+        assert_eq!(
+            regions[0],
+            [rect(0, 0, 40, 20), rect(0, 20, 20, 20)],
+            "the L the window above leaves uncovered"
+        );
+        assert_eq!(regions[1], [rect(20, 20, 40, 40)]);
+    }
+
+    #[test]
+    fn sees_through_translucent_windows() {
+        let regions = visible_regions(
+            &[
+                opaque(rect(0, 0, 50, 50)),
+                translucent(rect(0, 0, 100, 100)),
+            ],
+            SCREEN,
+        );
+        assert_eq!(regions[0], [rect(0, 0, 50, 50)]);
+
+        // Its border sticks out past what it covers.
+        let bordered = Layer {
+            extent: rect(8, 8, 24, 24),
+            opaque: Some(rect(10, 10, 20, 20)),
+        };
+        let regions = visible_regions(&[opaque(rect(0, 0, 40, 40)), bordered], SCREEN);
+        assert_eq!(area(&regions[0]), 40 * 40 - 20 * 20);
+    }
+
+    #[test]
+    fn clips_to_the_screen() {
+        let regions = visible_regions(
+            &[
+                opaque(rect(-20, 90, 40, 40)),
+                opaque(rect(200, 0, 10, 10)),
+                opaque(rect(-50, -50, 70, 70)),
+            ],
+            SCREEN,
+        );
+        assert_eq!(regions[0], [rect(0, 90, 20, 10)]);
+        assert_eq!(regions[1], []);
+        assert_eq!(regions[2], [rect(0, 0, 20, 20)]);
+    }
+
+    #[test]
+    fn bounds_regions_cut_into_many_pieces() {
+        // A row of small windows over a wide one cuts it up into lots of rectangles.
+        let mut layers = vec![opaque(rect(0, 0, 100, 20))];
+        layers.extend((0..10).map(|i| opaque(rect(i * 10 + 2, 5, 6, 10))));
+        let regions = visible_regions(&layers, SCREEN);
+        assert_eq!(regions[0], [rect(0, 0, 100, 20)]);
+    }
+
+    #[test]
+    fn only_fully_opaque_square_windows_cover() {
+        assert!(covers(false, 1.0, 0.0, BlendMode::Normal));
+        assert!(!covers(true, 1.0, 0.0, BlendMode::Normal));
+        assert!(!covers(false, 0.99, 0.0, BlendMode::Normal));
+        assert!(!covers(false, 1.0, 4.0, BlendMode::Normal));
+        assert!(!covers(false, 1.0, 0.0, BlendMode::Multiply));
+    }
+
+    #[test]
+    fn rounds_scissors_outwards() {
+        assert_eq!(
+            scissor(rect(10, 20, 30, 40), (100, 100), (100, 100)),
+            (10, 20, 30, 40)
+        );
+        // At half the resolution, odd edges land half way into a pixel.
+        assert_eq!(
+            scissor(rect(11, 20, 30, 41), (100, 100), (50, 50)),
+            (5, 10, 16, 21)
+        );
+        assert_eq!(
+            scissor(rect(90, 95, 20, 20), (100, 100), (75, 75)),
+            (67, 71, 8, 4)
+        );
+    }
+}
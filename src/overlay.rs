@@ -0,0 +1,156 @@
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    pipeline::WindowPipeline,
+    texture::{WindowTexture, WindowUniforms},
+};
+
+/// Identifies a rectangle added with [`Compositor::add_overlay_rect`].
+///
+/// [`Compositor::add_overlay_rect`]: crate::compositor::Compositor::add_overlay_rect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OverlayRectId(u64);
+
+/// A solid-colored quad drawn by the compositor itself rather than by an X client.
+struct OverlayRect {
+    id: OverlayRectId,
+    rect: Rectangle,
+    /// Non-premultiplied sRGB color, with components from 0 to 1.
+    color: [f32; 4],
+    z: i32,
+    /// A single pixel of `color`, so that the rect can be drawn with the window pipeline.
+    texture: WindowTexture,
+}
+
+/// A single pixel of `color`, used to draw a solid quad with the window pipeline.
+pub(crate) fn solid_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pipeline: &WindowPipeline,
+    color: [f32; 4],
+) -> WindowTexture {
+    let texture = WindowTexture::new(
+        device,
+        pipeline.bind_group_layout(),
+        pipeline.sampler(),
+        1,
+        1,
+    );
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    // Alpha is applied through the uniforms, since the shader ignores texture alpha.
+    let pixel = [channel(color[2]), channel(color[1]), channel(color[0]), 255];
+    texture.write(
+        queue,
+        Rectangle {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        },
+        &pixel,
+    );
+    texture
+}
+
+/// The set of overlay rectangles, ordered by z.
+///
+/// Rects with a negative z are drawn underneath all windows, and the rest on top of them.
+/// Rects with equal z are drawn in the order they were added.
+#[derive(Default)]
+pub struct OverlayRects {
+    rects: Vec<OverlayRect>,
+    next_id: u64,
+}
+
+impl OverlayRects {
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+        rect: Rectangle,
+        color: [f32; 4],
+        z: i32,
+    ) -> OverlayRectId {
+        let id = OverlayRectId(self.next_id);
+        self.next_id += 1;
+
+        let index = self.rects.partition_point(|other| other.z <= z);
+        self.rects.insert(
+            index,
+            OverlayRect {
+                id,
+                rect,
+                color,
+                z,
+                texture: solid_texture(device, queue, pipeline, color),
+            },
+        );
+        id
+    }
+
+    /// Remove a rect, returning whether it existed.
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn remove(&mut self, id: OverlayRectId) -> bool {
+        let len = self.rects.len();
+        self.rects.retain(|rect| rect.id != id);
+        self.rects.len() != len
+    }
+
+    /// Re-upload every rect's texture, e.g. after the device was recreated.
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+    ) {
+        for rect in &mut self.rects {
+            rect.texture = solid_texture(device, queue, pipeline, rect.color);
+        }
+    }
+
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, screen: (u32, u32)) {
+        for rect in &self.rects {
+            rect.texture.write_uniforms(
+                queue,
+                &WindowUniforms {
+                    geometry: rect.rect,
+                    screen,
+                    dim: 0.0,
+                    opacity: rect.color[3],
+                    has_alpha: false,
+                    straight_alpha: false,
+                    corner_radius: 0.0,
+                    corner_smoothing: 0.0,
+                    corner_aa_width: 1.0,
+                },
+            );
+        }
+    }
+
+    /// Draw the rects that go underneath windows. The pass must already be using the
+    /// window pipeline.
+    pub fn draw_below<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        Self::draw(self.rects.iter().filter(|rect| rect.z < 0), pass);
+    }
+
+    /// Draw the rects that go on top of windows. The pass must already be using the
+    /// window pipeline.
+    pub fn draw_above<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        Self::draw(self.rects.iter().filter(|rect| rect.z >= 0), pass);
+    }
+
+    fn draw<'a>(rects: impl Iterator<Item = &'a OverlayRect>, pass: &mut wgpu::RenderPass<'a>) {
+        for rect in rects {
+            pass.set_bind_group(0, rect.texture.bind_group(), &[]);
+            pass.draw(0..6, 0..1);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use anyhow::{bail, Result};
+
+use crate::window::BlendMode;
+
+/// The shader used to draw windows.
+const WINDOW_SHADER: &str = include_str!("shaders/window.wgsl");
+
+/// The render pipeline used to draw window textures onto the overlay surface, along with
+/// the shared resources every window's bind group is created from.
+pub struct WindowPipeline {
+    pipeline: wgpu::RenderPipeline,
+    /// The same pipeline for single-sampled targets, when `pipeline` is multisampled.
+    single_sampled: Option<wgpu::RenderPipeline>,
+    /// Like `pipeline`, for the blend modes other than normal, in the order of
+    /// [`BLENDED_MODES`].
+    blended: [wgpu::RenderPipeline; 3],
+    sample_count: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl WindowPipeline {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self::with_samples(device, format, 1)
+    }
+
+    /// A pipeline for drawing into targets with `sample_count` samples per pixel, which
+    /// must be supported for `format`.
+    pub fn with_samples(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Window Shader"),
+            source: wgpu::ShaderSource::Wgsl(WINDOW_SHADER.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Window Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline = create_window_pipeline(
+            device,
+            format,
+            &bind_group_layout,
+            &shader,
+            sample_count,
+            BlendMode::Normal,
+        );
+        let single_sampled = (sample_count > 1).then(|| {
+            create_window_pipeline(
+                device,
+                format,
+                &bind_group_layout,
+                &shader,
+                1,
+                BlendMode::Normal,
+            )
+        });
+        let blended = BLENDED_MODES.map(|mode| {
+            create_window_pipeline(
+                device,
+                format,
+                &bind_group_layout,
+                &shader,
+                sample_count,
+                mode,
+            )
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Window Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            single_sampled,
+            blended,
+            sample_count,
+            bind_group_layout,
+            sampler,
+        }
+    }
+
+    /// Rebuild the pipeline from new shader source, keeping the bind group layout so
+    /// existing window bind groups stay valid.
+    ///
+    /// If the shader doesn't compile, the current pipeline is kept and the error returned.
+    pub async fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        source: &str,
+    ) -> Result<()> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Window Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let layout = &self.bind_group_layout;
+        let samples = self.sample_count;
+        let pipeline =
+            create_window_pipeline(device, format, layout, &shader, samples, BlendMode::Normal);
+        let single_sampled = (samples > 1)
+            .then(|| create_window_pipeline(device, format, layout, &shader, 1, BlendMode::Normal));
+        let blended = BLENDED_MODES
+            .map(|mode| create_window_pipeline(device, format, layout, &shader, samples, mode));
+        if let Some(e) = device.pop_error_scope().await {
+            bail!("{e}");
+        }
+
+        self.pipeline = pipeline;
+        self.single_sampled = single_sampled;
+        self.blended = blended;
+        Ok(())
+    }
+
+    /// The pipeline for drawing into targets with [`WindowPipeline::sample_count`]
+    /// samples.
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    /// The pipeline for drawing windows with `mode` into targets with
+    /// [`WindowPipeline::sample_count`] samples.
+    pub fn blended(&self, mode: BlendMode) -> &wgpu::RenderPipeline {
+        match BLENDED_MODES.iter().position(|&blended| blended == mode) {
+            Some(i) => &self.blended[i],
+            None => &self.pipeline,
+        }
+    }
+
+    /// The pipeline for drawing into single-sampled targets, like thumbnails.
+    pub fn single_sampled(&self) -> &wgpu::RenderPipeline {
+        self.single_sampled.as_ref().unwrap_or(&self.pipeline)
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+}
+
+/// The most samples per pixel, up to `requested`, that a format with `features` can be
+/// rendered with. Falls back to 1, which every format supports.
+pub fn supported_sample_count(features: wgpu::TextureFormatFeatureFlags, requested: u32) -> u32 {
+    [16, 8, 4, 2]
+        .into_iter()
+        .find(|&count| count <= requested && features.sample_count_supported(count))
+        .unwrap_or(1)
+}
+
+/// The blend modes that get a pipeline of their own, besides normal.
+const BLENDED_MODES: [BlendMode; 3] = [BlendMode::Multiply, BlendMode::Screen, BlendMode::Add];
+
+/// How to blend the premultiplied output of the window shader with the target for `mode`.
+///
+/// Alpha always accumulates as it does for normal compositing. The formulas for the
+/// color assume an opaque target, which the screen is.
+fn blend_state(mode: BlendMode) -> wgpu::BlendState {
+    let color = |src_factor, dst_factor| wgpu::BlendComponent {
+        src_factor,
+        dst_factor,
+        operation: wgpu::BlendOperation::Add,
+    };
+    use wgpu::BlendFactor::{Dst, One, OneMinusSrc, OneMinusSrcAlpha};
+    let color = match mode {
+        BlendMode::Normal => return wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        // src * dst + dst * (1 - src alpha)
+        BlendMode::Multiply => color(Dst, OneMinusSrcAlpha),
+        // src + dst - src * dst
+        BlendMode::Screen => color(One, OneMinusSrc),
+        BlendMode::Add => color(One, One),
+    };
+    wgpu::BlendState {
+        color,
+        alpha: wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING.alpha,
+    }
+}
+
+fn create_window_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    sample_count: u32,
+    mode: BlendMode,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Window Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Window Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend_state(mode)),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// The render pipeline for procedural gradient backgrounds, which cover the whole screen
+/// and don't need a texture.
+pub struct GradientPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GradientPipeline {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Gradient Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/gradient.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Gradient Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gradient Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Gradient Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+}
+
+/// The render pipeline for the border around the active window, which is drawn
+/// procedurally and doesn't need a texture.
+pub struct BorderPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl BorderPipeline {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Border Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/border.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Border Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Border Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Border Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_supported_sample_counts() {
+        let four = wgpu::TextureFormatFeatureFlags::MULTISAMPLE_X2
+            | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_X4;
+        assert_eq!(supported_sample_count(four, 4), 4);
+        assert_eq!(supported_sample_count(four, 2), 2);
+        assert_eq!(supported_sample_count(four, 8), 4);
+        // Not a power of two, so the next one down.
+        assert_eq!(supported_sample_count(four, 3), 2);
+        assert_eq!(supported_sample_count(four, 1), 1);
+        assert_eq!(
+            supported_sample_count(wgpu::TextureFormatFeatureFlags::empty(), 4),
+            1
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+/// Whether the compositor is actively rendering frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    /// Rendering every frame.
+    Active,
+    /// Nothing has changed for a while, so rendering is paused until the next event.
+    Idle,
+}
+
+/// Tracks how long it's been since anything happened, so that we can stop rendering
+/// frames when nothing on screen is changing.
+pub struct IdleTracker {
+    state: PowerState,
+    idle_since: Instant,
+    timeout: Option<Duration>,
+}
+
+impl IdleTracker {
+    /// Create a tracker that goes idle after `timeout` without activity, or never if
+    /// `timeout` is `None`.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            state: PowerState::Active,
+            idle_since: Instant::now(),
+            timeout,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    /// Record that something happened (an event, damage, etc.), waking up if idle.
+    pub fn activity(&mut self) {
+        self.idle_since = Instant::now();
+        if self.state == PowerState::Idle {
+            debug!("Resuming rendering");
+            self.state = PowerState::Active;
+        }
+    }
+
+    /// Transition to [`PowerState::Idle`] if there's been no activity for longer than the
+    /// timeout. Returns the (possibly updated) state.
+    pub fn update(&mut self) -> PowerState {
+        if let (PowerState::Active, Some(timeout)) = (self.state, self.timeout) {
+            if self.idle_since.elapsed() >= timeout {
+                debug!("No activity for {:?}, pausing rendering", timeout);
+                self.state = PowerState::Idle;
+            }
+        }
+        self.state
+    }
+}
@@ -0,0 +1,668 @@
+//! Getting rendered frames onto the screen.
+//!
+//! Frames go wherever a [`PresentTarget`] puts them, which is the overlay window.
+//! Normally that's a wgpu surface, but when the driver can't present to X windows
+//! reliably, frames can be rendered offscreen and drawn into a back buffer from the X
+//! DOUBLE-BUFFER extension (DBE) instead.
+
+use anyhow::{bail, Context, Result};
+use tracing::{debug, info, warn};
+use wgpu::{
+    rwh::{RawDisplayHandle, RawWindowHandle, XcbDisplayHandle, XcbWindowHandle},
+    SurfaceTargetUnsafe,
+};
+use x11rb::{
+    connection::RequestConnection as _,
+    protocol::{
+        dbe::{self, ConnectionExt as _},
+        xproto::{self, ConnectionExt as _},
+    },
+};
+use x11rb_async::{
+    connection::Connection as _,
+    protocol::{dbe::ConnectionExt as _, xproto::ConnectionExt as _},
+};
+
+use crate::{config::PresentMode, connection::XConn, error::Error, extensions::Version};
+
+/// DBE 1.0 is the only version there is.
+pub const DBE_MIN_VERSION: Version = Version::new(1, 0);
+
+/// The format frames are rendered in for DBE, which matches the byte order of 24 and 32
+/// bit ZPixmap images on little-endian servers.
+const DBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// Size of a `PutImage` request without its image data.
+const PUT_IMAGE_HEADER_BYTES: usize = 24;
+
+/// Somewhere rendered frames can be shown.
+///
+/// The compositor only renders into the views these hand out, so anything that can
+/// provide textures and show them once they're rendered can be one. The X overlay, as an
+/// [`OverlayPresenter`], is the only one for now, but a DRM/KMS output presenting
+/// straight to a CRTC would be another.
+pub trait PresentTarget {
+    /// (Re)create whatever frames are rendered into, e.g. after a resize or on a new
+    /// device.
+    fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration);
+
+    /// Get the next frame to render into, with a view in `format`, or `None` if there
+    /// isn't one ready yet and this frame should be skipped.
+    fn acquire(
+        &self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Result<Option<Frame>>;
+
+    /// Show a frame from [`Self::acquire`] once everything rendering it has been
+    /// submitted.
+    fn present(&self, frame: Frame, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()>;
+
+    /// This target as the X overlay, which has to be freed when compositing is suspended
+    /// and made again for the new overlay window on resuming. `None` for targets that
+    /// don't present to the overlay.
+    fn overlay(&mut self) -> Option<&mut OverlayPresenter> {
+        None
+    }
+}
+
+/// Where rendered frames go on the X overlay.
+pub enum OverlayPresenter {
+    /// A wgpu surface on the overlay window.
+    Surface(wgpu::Surface<'static>),
+    /// An offscreen texture that's copied into a DBE back buffer of the overlay.
+    Dbe(DbePresenter),
+}
+
+/// A frame being rendered, which must be handed back to the [`PresentTarget`] it came
+/// from.
+pub struct Frame {
+    /// The view to render into.
+    pub view: wgpu::TextureView,
+    surface: Option<wgpu::SurfaceTexture>,
+}
+
+impl Frame {
+    /// A frame rendered into `view`, for targets that keep their own textures rather than
+    /// presenting a surface's.
+    pub fn new(view: wgpu::TextureView) -> Self {
+        Self {
+            view,
+            surface: None,
+        }
+    }
+}
+
+impl PresentTarget for OverlayPresenter {
+    fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        match self {
+            OverlayPresenter::Surface(surface) => surface.configure(device, config),
+            OverlayPresenter::Dbe(dbe) => dbe.configure(device, config),
+        }
+    }
+
+    fn acquire(
+        &self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Result<Option<Frame>> {
+        match self {
+            OverlayPresenter::Surface(surface) => surface.acquire(device, config, format),
+            OverlayPresenter::Dbe(dbe) => dbe.acquire(device, config, format),
+        }
+    }
+
+    fn present(&self, frame: Frame, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        match self {
+            OverlayPresenter::Surface(surface) => surface.present(frame, device, queue),
+            OverlayPresenter::Dbe(dbe) => dbe.present(frame, device, queue),
+        }
+    }
+
+    fn overlay(&mut self) -> Option<&mut OverlayPresenter> {
+        Some(self)
+    }
+}
+
+impl OverlayPresenter {
+    /// Present to the new overlay `window` the same way as before, e.g. after resuming.
+    pub async fn recreate(
+        &mut self,
+        instance: &wgpu::Instance,
+        conn: &XConn,
+        window: xproto::Window,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<()> {
+        *self = match self {
+            OverlayPresenter::Surface(_) => {
+                OverlayPresenter::Surface(create_surface(instance, conn, window)?)
+            }
+            OverlayPresenter::Dbe(_) => {
+                OverlayPresenter::Dbe(DbePresenter::new(conn, window, device, config).await?)
+            }
+        };
+        Ok(())
+    }
+
+    /// Free the X resources held for presenting, before handing the connection back.
+    pub async fn release(&self) -> Result<()> {
+        if let OverlayPresenter::Dbe(dbe) = self {
+            dbe.conn
+                .dbe_deallocate_back_buffer(dbe.back_buffer)
+                .await?
+                .check()
+                .await?;
+            dbe.conn.free_gc(dbe.gc).await?.check().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Create a wgpu surface presenting to `window`.
+pub fn create_surface(
+    instance: &wgpu::Instance,
+    conn: &XConn,
+    window: xproto::Window,
+) -> Result<wgpu::Surface<'static>> {
+    // Safety: we get the raw connection from the XCBConnection, which is a valid XCB connection
+    // so this should be safe.
+    //
+    // We need this to convert the x11tb connection to something wgpu can use.
+    let surface = unsafe {
+        instance.create_surface_unsafe(SurfaceTargetUnsafe::RawHandle {
+            raw_display_handle: RawDisplayHandle::Xcb(XcbDisplayHandle::new(
+                Some(conn.as_raw_connection()),
+                conn.screen().try_into()?,
+            )),
+            raw_window_handle: RawWindowHandle::Xcb(XcbWindowHandle::new(window.try_into()?)),
+        })?
+    };
+    Ok(surface)
+}
+
+fn view_descriptor(format: wgpu::TextureFormat) -> wgpu::TextureViewDescriptor<'static> {
+    wgpu::TextureViewDescriptor {
+        format: Some(format),
+        ..Default::default()
+    }
+}
+
+impl PresentTarget for wgpu::Surface<'_> {
+    fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        wgpu::Surface::configure(self, device, config);
+    }
+
+    fn acquire(
+        &self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Result<Option<Frame>> {
+        let mut result = self.get_current_texture();
+        // If reconfiguring doesn't fix it, give up.
+        if let Err(e) = &result {
+            if recovery(e) == Recovery::Reconfigure {
+                warn!("Surface error: {}, reconfiguring", e);
+                wgpu::Surface::configure(self, device, config);
+                result = self.get_current_texture();
+            }
+        }
+        let output = match result {
+            Ok(output) => output,
+            Err(e) if recovery(&e) == Recovery::Skip => {
+                debug!("Surface error: {}, skipping the frame", e);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(Frame {
+            view: output.texture.create_view(&view_descriptor(format)),
+            surface: Some(output),
+        }))
+    }
+
+    fn present(&self, frame: Frame, _: &wgpu::Device, _: &wgpu::Queue) -> Result<()> {
+        let Some(output) = frame.surface else {
+            bail!("Can't present a frame that didn't come from this surface");
+        };
+        output.present();
+        Ok(())
+    }
+}
+
+/// What to do when a surface can't hand out a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// Try again next frame. Some drivers time out when a frame just isn't ready yet,
+    /// e.g. while the output is off.
+    Skip,
+    /// Configure the surface again and retry. This happens after a resize, a
+    /// suspend/resume, or when the output changes.
+    Reconfigure,
+    /// Out of memory, which there's no coming back from.
+    Fail,
+}
+
+fn recovery(error: &wgpu::SurfaceError) -> Recovery {
+    match error {
+        wgpu::SurfaceError::Timeout => Recovery::Skip,
+        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => Recovery::Reconfigure,
+        wgpu::SurfaceError::OutOfMemory => Recovery::Fail,
+    }
+}
+
+/// Presents frames by reading them back from the GPU and drawing them into a DBE back
+/// buffer with `PutImage`, then swapping it onto the window.
+///
+/// Every frame makes a round trip through the CPU, so this is much slower than a surface.
+/// It's a fallback for drivers whose surfaces don't work with X.
+pub struct DbePresenter {
+    conn: XConn,
+    window: xproto::Window,
+    depth: u8,
+    back_buffer: dbe::BackBuffer,
+    gc: xproto::Gcontext,
+    texture: wgpu::Texture,
+    readback: wgpu::Buffer,
+    size: (u32, u32),
+}
+
+impl DbePresenter {
+    /// Allocate a back buffer for `window` and an offscreen texture of the size in
+    /// `config`.
+    pub async fn new(
+        conn: &XConn,
+        window: xproto::Window,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<Self> {
+        let found = match conn.dbe_query_version(1, 0).await {
+            Ok(cookie) => {
+                let reply = cookie.reply().await?;
+                Some(Version::new(
+                    reply.major_version.into(),
+                    reply.minor_version.into(),
+                ))
+            }
+            Err(x11rb::errors::ConnectionError::UnsupportedExtension) => None,
+            Err(e) => return Err(e.into()),
+        };
+        if found.is_none_or(|version| version < DBE_MIN_VERSION) {
+            return Err(Error::MissingExtension {
+                name: "DOUBLE-BUFFER",
+                required: DBE_MIN_VERSION,
+                found,
+            }
+            .into());
+        }
+
+        if conn.setup().image_byte_order != xproto::ImageOrder::LSB_FIRST {
+            bail!("Presenting through DBE needs a server with little-endian images");
+        }
+        let depth = conn.get_geometry(window).await?.reply().await?.depth;
+        if depth != 24 && depth != 32 {
+            bail!("Presenting through DBE needs a 24 or 32 bit window, not {depth} bit");
+        }
+
+        let back_buffer = conn.generate_id().await?;
+        conn.dbe_allocate_back_buffer(window, back_buffer, dbe::SwapAction::UNDEFINED.into())
+            .await?
+            .check()
+            .await
+            .context("Failed to allocate a DBE back buffer")?;
+        let gc = conn.generate_id().await?;
+        conn.create_gc(gc, window, &xproto::CreateGCAux::new())
+            .await?
+            .check()
+            .await?;
+        info!("Presenting through DBE back buffer {:?}", back_buffer);
+
+        let (texture, readback) = create_targets(device, config);
+        Ok(Self {
+            conn: conn.clone(),
+            window,
+            depth,
+            back_buffer,
+            gc,
+            texture,
+            readback,
+            size: (config.width, config.height),
+        })
+    }
+
+    /// The configuration to render with, since there's no surface to ask.
+    pub fn config(width: u32, height: u32) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: DBE_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        }
+    }
+}
+
+impl PresentTarget for DbePresenter {
+    fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.texture, self.readback) = create_targets(device, config);
+        self.size = (config.width, config.height);
+    }
+
+    fn acquire(
+        &self,
+        _: &wgpu::Device,
+        _: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Result<Option<Frame>> {
+        Ok(Some(Frame::new(
+            self.texture.create_view(&view_descriptor(format)),
+        )))
+    }
+
+    fn present(&self, frame: Frame, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        if frame.surface.is_some() {
+            bail!("Can't present a surface's frame through DBE");
+        }
+        let (width, height) = self.size;
+        let padded_row = padded_row_bytes(width);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("DBE Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            self.texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()?.context("Failed to read back the frame")?;
+
+        let xcb = self.conn.xcb();
+        let row_bytes = width as usize * 4;
+        let rows_per_request = rows_per_request(xcb.maximum_request_bytes(), width);
+        let result = (|| -> Result<()> {
+            let data = slice.get_mapped_range();
+            let rows: Vec<_> = data
+                .chunks(padded_row as usize)
+                .map(|row| &row[..row_bytes])
+                .collect();
+            for (i, chunk) in rows.chunks(rows_per_request).enumerate() {
+                xcb.put_image(
+                    xproto::ImageFormat::Z_PIXMAP,
+                    self.back_buffer,
+                    self.gc,
+                    width as u16,
+                    chunk.len() as u16,
+                    0,
+                    (i * rows_per_request) as i16,
+                    0,
+                    self.depth,
+                    &chunk.concat(),
+                )?;
+            }
+            xcb.dbe_swap_buffers(&[dbe::SwapInfo {
+                window: self.window,
+                swap_action: dbe::SwapAction::UNDEFINED,
+            }])?;
+            Ok(())
+        })();
+        self.readback.unmap();
+        result
+    }
+}
+
+/// Create the texture frames are rendered into, and the buffer they're read back with.
+fn create_targets(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> (wgpu::Texture, wgpu::Buffer) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("DBE Frame"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("DBE Readback"),
+        size: padded_row_bytes(config.width) as u64 * config.height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    (texture, readback)
+}
+
+/// Pick the format to configure a surface with from the `supported` ones, preferring
+/// sRGB, or use `forced` if the options ask for one.
+pub fn surface_format(
+    supported: &[wgpu::TextureFormat],
+    forced: Option<wgpu::TextureFormat>,
+    adapter: &wgpu::AdapterInfo,
+) -> Result<wgpu::TextureFormat> {
+    if supported.is_empty() {
+        bail!(
+            "{} ({:?}) doesn't support any formats for presenting to the overlay. Try \
+             another adapter with --adapter, or presenting without a surface with \
+             --present dbe",
+            adapter.name,
+            adapter.backend
+        );
+    }
+
+    if let Some(format) = forced {
+        if !supported.contains(&format) {
+            bail!(
+                "Surface format {:?} was requested, but {} ({:?}) only supports {:?}",
+                format,
+                adapter.name,
+                adapter.backend,
+                supported
+            );
+        }
+        return Ok(format);
+    }
+
+    Ok(match supported.iter().find(|f| f.is_srgb()) {
+        Some(format) => *format,
+        None => {
+            warn!(
+                "{} ({:?}) has no sRGB surface format, using {:?} out of {:?}",
+                adapter.name, adapter.backend, supported[0], supported
+            );
+            supported[0]
+        }
+    })
+}
+
+/// The present mode to configure a surface supporting `supported` with, as `wanted`
+/// asks. Falls back to `Fifo`, which every surface supports, if the one asked for isn't.
+pub fn present_mode(supported: &[wgpu::PresentMode], wanted: PresentMode) -> wgpu::PresentMode {
+    let mode = match wanted {
+        PresentMode::Auto => {
+            if supported.contains(&wgpu::PresentMode::Mailbox) {
+                wgpu::PresentMode::Mailbox
+            } else {
+                wgpu::PresentMode::Fifo
+            }
+        }
+        PresentMode::Fifo => wgpu::PresentMode::Fifo,
+        PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+        PresentMode::Immediate => wgpu::PresentMode::Immediate,
+    };
+    if !supported.contains(&mode) && mode != wgpu::PresentMode::Fifo {
+        warn!(
+            "Present mode {:?} was requested, but the surface only supports {:?}, using Fifo",
+            mode, supported
+        );
+        return wgpu::PresentMode::Fifo;
+    }
+    mode
+}
+
+/// How a surface supporting `supported` should tell the presentation engine to treat the
+/// alpha of what we render, given whether the window it presents to `has_alpha`.
+///
+/// We render premultiplied alpha, which only means anything to a window whose visual
+/// has an alpha channel. Without one, the server ignores alpha anyway, but drivers may
+/// still do extra work to honour it unless told the surface is opaque.
+pub fn alpha_mode(
+    supported: &[wgpu::CompositeAlphaMode],
+    has_alpha: bool,
+) -> Option<wgpu::CompositeAlphaMode> {
+    let preferred = if has_alpha {
+        wgpu::CompositeAlphaMode::PreMultiplied
+    } else {
+        wgpu::CompositeAlphaMode::Opaque
+    };
+    [preferred, wgpu::CompositeAlphaMode::Inherit]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .or_else(|| supported.first().copied())
+}
+
+/// Bytes per row of a texture copy `width` pixels wide, padded as wgpu requires.
+pub(crate) fn padded_row_bytes(width: u32) -> u32 {
+    (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// How many rows of a `width` pixel wide 32bpp image fit in one `PutImage` request.
+fn rows_per_request(max_request_bytes: usize, width: u32) -> usize {
+    ((max_request_bytes - PUT_IMAGE_HEADER_BYTES) / (width as usize * 4)).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_a_present_mode() {
+        use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+
+        assert_eq!(present_mode(&[Fifo, Mailbox], PresentMode::Auto), Mailbox);
+        assert_eq!(present_mode(&[Fifo, Immediate], PresentMode::Auto), Fifo);
+        assert_eq!(present_mode(&[Fifo, Mailbox], PresentMode::Fifo), Fifo);
+        assert_eq!(
+            present_mode(&[Fifo, Immediate], PresentMode::Immediate),
+            Immediate
+        );
+        // Falls back when what was asked for isn't there.
+        assert_eq!(present_mode(&[Fifo], PresentMode::Mailbox), Fifo);
+        assert_eq!(present_mode(&[Fifo], PresentMode::Immediate), Fifo);
+    }
+
+    #[test]
+    fn picks_an_alpha_mode_for_the_visual() {
+        use wgpu::CompositeAlphaMode::{Inherit, Opaque, PostMultiplied, PreMultiplied};
+
+        assert_eq!(
+            alpha_mode(&[Opaque, PreMultiplied], true),
+            Some(PreMultiplied)
+        );
+        assert_eq!(alpha_mode(&[PreMultiplied, Opaque], false), Some(Opaque));
+        assert_eq!(alpha_mode(&[Opaque, Inherit], true), Some(Inherit));
+        assert_eq!(alpha_mode(&[PostMultiplied], false), Some(PostMultiplied));
+        assert_eq!(alpha_mode(&[], true), None);
+    }
+
+    #[test]
+    fn skips_frames_on_timeouts() {
+        assert_eq!(recovery(&wgpu::SurfaceError::Timeout), Recovery::Skip);
+        assert_eq!(recovery(&wgpu::SurfaceError::Lost), Recovery::Reconfigure);
+        assert_eq!(
+            recovery(&wgpu::SurfaceError::Outdated),
+            Recovery::Reconfigure
+        );
+        assert_eq!(recovery(&wgpu::SurfaceError::OutOfMemory), Recovery::Fail);
+    }
+
+    fn adapter() -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: "llvmpipe".to_owned(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::Cpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        }
+    }
+
+    #[test]
+    fn prefers_srgb_surface_formats() {
+        use wgpu::TextureFormat::*;
+
+        assert_eq!(
+            surface_format(&[Bgra8Unorm, Bgra8UnormSrgb], None, &adapter()).unwrap(),
+            Bgra8UnormSrgb
+        );
+        assert_eq!(
+            surface_format(&[Rgb10a2Unorm, Bgra8Unorm], None, &adapter()).unwrap(),
+            Rgb10a2Unorm
+        );
+    }
+
+    #[test]
+    fn forces_surface_formats() {
+        use wgpu::TextureFormat::*;
+
+        let supported = [Bgra8UnormSrgb, Bgra8Unorm];
+        assert_eq!(
+            surface_format(&supported, Some(Bgra8Unorm), &adapter()).unwrap(),
+            Bgra8Unorm
+        );
+        let err = surface_format(&supported, Some(Rgba16Float), &adapter()).unwrap_err();
+        assert!(err.to_string().contains("Rgba16Float"));
+    }
+
+    #[test]
+    fn explains_missing_surface_formats() {
+        let err = surface_format(&[], None, &adapter())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("llvmpipe (Vulkan)"), "{err}");
+        assert!(err.contains("--present dbe"), "{err}");
+    }
+
+    #[test]
+    fn pads_rows_for_copies() {
+        assert_eq!(padded_row_bytes(64), 256);
+        assert_eq!(padded_row_bytes(65), 512);
+        assert_eq!(padded_row_bytes(1920), 7680);
+    }
+
+    #[test]
+    fn splits_images_to_fit_requests() {
+        // The core protocol limit of 256KiB, and a typical BIG-REQUESTS limit of 16MiB.
+        assert_eq!(rows_per_request(262_140, 1920), 34);
+        assert_eq!(rows_per_request(16_777_212, 1920), 2184);
+        // A row that doesn't fit at all is still sent one at a time.
+        assert_eq!(rows_per_request(262_140, 100_000), 1);
+    }
+}
@@ -0,0 +1,145 @@
+//! Turning expensive effects off while frames take longer to render than they should,
+//! and back on once there's room for them again.
+//!
+//! Frame times are smoothed so that one slow frame (e.g. a big upload) doesn't change
+//! anything. Quality only drops after frames have been over budget for a while, and only
+//! comes back once they're well under it for longer still, so that it doesn't flip back
+//! and forth around the budget.
+
+use std::time::Duration;
+
+/// How much each frame moves the smoothed frame time towards its own.
+const SMOOTHING: f64 = 0.1;
+
+/// How many frames in a row the smoothed frame time has to be over budget to drop quality.
+const DEGRADE_AFTER: u32 = 30;
+
+/// How many frames in a row the smoothed frame time has to be under
+/// [`RESTORE_BELOW`] of the budget to bring quality back.
+const RESTORE_AFTER: u32 = 120;
+
+/// The fraction of the budget frames have to take less than for quality to come back,
+/// leaving room for what the effects cost.
+const RESTORE_BELOW: f64 = 0.5;
+
+/// A change in quality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// Frames are over budget, so expensive effects should be turned off.
+    Degrade,
+    /// There's room for the effects again.
+    Restore,
+}
+
+/// Decides when to change quality from how long frames take to render.
+#[derive(Debug)]
+pub struct QualityController {
+    budget: Duration,
+    /// The smoothed frame time, in seconds.
+    average: Option<f64>,
+    degraded: bool,
+    /// Frames in a row that counted towards the next transition.
+    streak: u32,
+}
+
+impl QualityController {
+    /// Keep frames under `budget` to render.
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            average: None,
+            degraded: false,
+            streak: 0,
+        }
+    }
+
+    /// Record that a frame took `time` to render, returning how quality should change
+    /// if it should.
+    pub fn frame(&mut self, time: Duration) -> Option<Transition> {
+        let time = time.as_secs_f64();
+        let average = match self.average {
+            Some(average) => average + (time - average) * SMOOTHING,
+            None => time,
+        };
+        self.average = Some(average);
+
+        let budget = self.budget.as_secs_f64();
+        let (counts, needed, transition) = if self.degraded {
+            (
+                average < budget * RESTORE_BELOW,
+                RESTORE_AFTER,
+                Transition::Restore,
+            )
+        } else {
+            (average > budget, DEGRADE_AFTER, Transition::Degrade)
+        };
+        if !counts {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < needed {
+            return None;
+        }
+
+        self.streak = 0;
+        self.degraded = !self.degraded;
+        Some(transition)
+    }
+
+    /// The smoothed frame time, once a frame has been rendered.
+    pub fn average(&self) -> Option<Duration> {
+        self.average.map(Duration::from_secs_f64)
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// Whether effects are currently turned off.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_millis(16);
+
+    fn run(controller: &mut QualityController, time: Duration, frames: u32) -> Vec<Transition> {
+        (0..frames).filter_map(|_| controller.frame(time)).collect()
+    }
+
+    #[test]
+    fn degrades_only_when_frames_stay_over_budget() {
+        let mut controller = QualityController::new(BUDGET);
+        assert!(run(&mut controller, Duration::from_millis(8), 100).is_empty());
+
+        // One slow frame doesn't do it.
+        assert_eq!(controller.frame(Duration::from_millis(100)), None);
+        assert!(run(&mut controller, Duration::from_millis(8), 100).is_empty());
+
+        assert_eq!(
+            run(&mut controller, Duration::from_millis(30), 100),
+            [Transition::Degrade]
+        );
+        assert!(controller.is_degraded());
+    }
+
+    #[test]
+    fn restores_once_there_is_headroom() {
+        let mut controller = QualityController::new(BUDGET);
+        run(&mut controller, Duration::from_millis(30), 100);
+        assert!(controller.is_degraded());
+
+        // Just under budget isn't enough room to turn the effects back on.
+        assert!(run(&mut controller, Duration::from_millis(14), 500).is_empty());
+        assert_eq!(
+            run(&mut controller, Duration::from_millis(4), 500),
+            [Transition::Restore]
+        );
+        assert!(!controller.is_degraded());
+    }
+}
@@ -0,0 +1,64 @@
+//! Reloading shaders from the source tree while the compositor is running, for working
+//! on effects without restarting it each time.
+//!
+//! Only available in debug builds with the `shader-reload` feature enabled. Release
+//! builds always use the shaders embedded at compile time.
+
+use std::{path::PathBuf, time::SystemTime};
+
+use tracing::{info, warn};
+
+/// Watches a shader file for changes by checking its modification time.
+pub struct ShaderWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    /// Watch the window shader in the source tree this was built from.
+    pub fn window_shader() -> Self {
+        Self {
+            path: concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/window.wgsl").into(),
+            modified: None,
+        }
+    }
+
+    /// The new contents of the shader, if it changed since the last call.
+    ///
+    /// Always returns the contents on the first call, since the file may have changed
+    /// since it was embedded.
+    pub fn poll(&mut self) -> Option<String> {
+        let modified = match std::fs::metadata(&self.path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                // Only complain once, rather than every frame.
+                if self.modified != Some(SystemTime::UNIX_EPOCH) {
+                    warn!("Can't watch {}: {}", self.path.display(), e);
+                    self.modified = Some(SystemTime::UNIX_EPOCH);
+                }
+                return None;
+            }
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) => {
+                info!("Reloading {}", self.path.display());
+                Some(source)
+            }
+            Err(e) => {
+                warn!("Can't read {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Forget the last modification time, so the next poll reloads the shader. Used
+    /// after the pipeline has been rebuilt from the embedded shader.
+    pub fn reset(&mut self) {
+        self.modified = None;
+    }
+}
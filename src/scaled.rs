@@ -0,0 +1,254 @@
+//! Drawing the scene at a fraction of the output's resolution and scaling it up to fill
+//! the output, trading sharpness for fill rate on weak GPUs.
+//!
+//! The scene can go through the same kind of texture at full resolution too, when
+//! something needs to copy finished frames out of it, or draw part of it again somewhere
+//! else to mirror one output onto another.
+
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    pipeline::WindowPipeline,
+    texture::{WindowTexture, WindowUniforms, WINDOW_UNIFORMS_SIZE},
+};
+
+/// The size to draw an `output` sized scene at with a render scale of `scale`, at least a
+/// pixel in each direction.
+pub fn scaled_size(output: (u32, u32), scale: f32) -> (u16, u16) {
+    let scaled = |len: u32| ((len as f32 * scale).round() as u32).clamp(1, u16::MAX as u32) as u16;
+    (scaled(output.0), scaled(output.1))
+}
+
+/// Where to draw the whole `output` sized scene so that `source` lands on `destination`.
+pub fn mirror_geometry(output: (u32, u32), source: Rectangle, destination: Rectangle) -> Rectangle {
+    let scale_x = destination.width as f32 / source.width.max(1) as f32;
+    let scale_y = destination.height as f32 / source.height.max(1) as f32;
+    let clamp_len = |len: f32| len.round().clamp(0.0, u16::MAX as f32) as u16;
+    let clamp_pos = |pos: f32| pos.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    Rectangle {
+        x: clamp_pos(destination.x as f32 - source.x as f32 * scale_x),
+        y: clamp_pos(destination.y as f32 - source.y as f32 * scale_y),
+        width: clamp_len(output.0 as f32 * scale_x),
+        height: clamp_len(output.1 as f32 * scale_y),
+    }
+}
+
+/// The texture the scene is drawn into before being scaled up to the output.
+pub struct ScaledTarget {
+    target: WindowTexture,
+    view: wgpu::TextureView,
+    scale: f32,
+    /// How much to brighten the scene by when drawing it onto the output.
+    brightness: f32,
+    /// A second binding of the target with uniforms of its own, for mirroring.
+    mirror_uniforms: wgpu::Buffer,
+    mirror_bind_group: wgpu::BindGroup,
+}
+
+impl ScaledTarget {
+    /// A target for drawing an `output` sized scene at `scale`, in `format`, the format
+    /// `pipeline` draws in.
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline: &WindowPipeline,
+        format: wgpu::TextureFormat,
+        output: (u32, u32),
+        scale: f32,
+    ) -> Self {
+        let (width, height) = scaled_size(output, scale);
+        let target = WindowTexture::render_target(
+            device,
+            pipeline.bind_group_layout(),
+            pipeline.sampler(),
+            format,
+            width,
+            height,
+        );
+        let view = target.create_view();
+
+        let mirror_uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Mirror Uniforms"),
+            size: WINDOW_UNIFORMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mirror_view = target.create_view();
+        let mirror_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Mirror Bind Group"),
+            layout: pipeline.bind_group_layout(),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: mirror_uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&mirror_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(pipeline.sampler()),
+                },
+            ],
+        });
+
+        Self {
+            target,
+            view,
+            scale,
+            brightness: 1.0,
+            mirror_uniforms,
+            mirror_bind_group,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height) = self.target.size();
+        (width as u32, height as u32)
+    }
+
+    /// The render scale the target was made for.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Brighten the scene by `brightness` when drawing it onto the output, e.g. to show
+    /// SDR content at the right brightness on an HDR output.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+    }
+
+    /// The texture the scene is drawn into, for copying out of.
+    pub fn texture(&self) -> &wgpu::Texture {
+        self.target.texture()
+    }
+
+    /// The view to draw the scene into.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Write the uniforms for drawing the target stretched over an `output` sized screen.
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, output: (u32, u32)) {
+        self.target.write_uniforms(
+            queue,
+            &WindowUniforms {
+                geometry: Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: output.0 as u16,
+                    height: output.1 as u16,
+                },
+                screen: output,
+                dim: 1.0 - self.brightness,
+                opacity: 1.0,
+                // Whatever the clear color left translucent stays that way, already
+                // premultiplied since it was blended that way.
+                has_alpha: true,
+                straight_alpha: false,
+                corner_radius: 0.0,
+                corner_smoothing: 0.0,
+                corner_aa_width: 1.0,
+            },
+        );
+    }
+
+    /// Draw the target over the whole output. The pass must already be using the window
+    /// pipeline's single-sampled variant, and should clear the output first since the
+    /// target is blended onto it.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, self.target.bind_group(), &[]);
+        pass.draw(0..6, 0..1);
+    }
+
+    /// Write the uniforms for drawing what's at `source` in the scene again, scaled to
+    /// fill `destination`, on an `output` sized screen.
+    pub fn write_mirror_uniforms(
+        &self,
+        queue: &wgpu::Queue,
+        output: (u32, u32),
+        source: Rectangle,
+        destination: Rectangle,
+    ) {
+        let uniforms = WindowUniforms {
+            geometry: mirror_geometry(output, source, destination),
+            screen: output,
+            dim: 1.0 - self.brightness,
+            opacity: 1.0,
+            // The copy replaces what's on the destination rather than showing it through
+            // whatever the clear color left translucent.
+            has_alpha: false,
+            straight_alpha: false,
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            corner_aa_width: 1.0,
+        };
+        queue.write_buffer(&self.mirror_uniforms, 0, &uniforms.pack());
+    }
+
+    /// Draw the mirror set up by [`ScaledTarget::write_mirror_uniforms`] into
+    /// `destination`, after [`ScaledTarget::draw`] in the same pass.
+    pub fn draw_mirror<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        output: (u32, u32),
+        destination: Rectangle,
+    ) {
+        // The scissor rect has to be within the output.
+        let left = (destination.x.max(0) as u32).min(output.0);
+        let top = (destination.y.max(0) as u32).min(output.1);
+        let right = (destination.x as i32 + destination.width as i32).clamp(0, output.0 as i32);
+        let bottom = (destination.y as i32 + destination.height as i32).clamp(0, output.1 as i32);
+        if right as u32 <= left || bottom as u32 <= top {
+            return;
+        }
+        pass.set_scissor_rect(left, top, right as u32 - left, bottom as u32 - top);
+        pass.set_bind_group(0, &self.mirror_bind_group, &[]);
+        pass.draw(0..6, 0..1);
+        pass.set_scissor_rect(0, 0, output.0, output.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_mirrored_output_onto_the_destination() {
+        // A 1920x1080 laptop screen left of a 1280x720 projector.
+        let output = (3200, 1080);
+        let laptop = Rectangle {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        let projector = Rectangle {
+            x: 1920,
+            y: 0,
+            width: 1280,
+            height: 720,
+        };
+        let geometry = mirror_geometry(output, laptop, projector);
+        assert_eq!(
+            (geometry.x, geometry.y, geometry.width, geometry.height),
+            (1920, 0, 2133, 720)
+        );
+
+        // The other way round, the projector's top left corner lands on the laptop's.
+        let geometry = mirror_geometry(output, projector, laptop);
+        assert_eq!(
+            (geometry.x, geometry.y, geometry.width, geometry.height),
+            (-2880, 0, 4800, 1620)
+        );
+    }
+
+    #[test]
+    fn scales_the_output_size() {
+        assert_eq!(scaled_size((1920, 1080), 1.0), (1920, 1080));
+        assert_eq!(scaled_size((1920, 1080), 0.75), (1440, 810));
+        assert_eq!(scaled_size((1921, 1081), 0.5), (961, 541));
+        // Never collapses to nothing.
+        assert_eq!(scaled_size((100, 100), 0.001), (1, 1));
+    }
+}
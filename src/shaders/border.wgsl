@@ -0,0 +1,86 @@
+struct BorderUniforms {
+    // x, y, width, height of the window in screen pixels
+    rect: vec4<f32>,
+    // linear and premultiplied
+    color: vec4<f32>,
+    // width, height of the screen in pixels
+    screen: vec2<f32>,
+    // width of the solid border in pixels
+    width: f32,
+    // how far past the solid border the glow fades out, in pixels
+    glow: f32,
+    // the window's corners, as in window.wgsl
+    corner_radius: f32,
+    corner_smoothing: f32,
+    // 0 for fully transparent, 1 for opaque
+    opacity: f32,
+    // how wide edges fade out over, in pixels, as in window.wgsl
+    corner_aa_width: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> border: BorderUniforms;
+
+// How bright the glow is where it meets the solid border.
+const GLOW_STRENGTH: f32 = 0.6;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // position relative to the window's top left corner in pixels
+    @location(0) local: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // Two triangles covering the window, grown by the border and glow on every side.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let reach = border.width + border.glow + 1.0;
+    let local = vec2<f32>(-reach) + corners[index] * (border.rect.zw + 2.0 * reach);
+
+    let pixel = border.rect.xy + local;
+    let ndc = vec2<f32>(
+        pixel.x / border.screen.x * 2.0 - 1.0,
+        1.0 - pixel.y / border.screen.y * 2.0,
+    );
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(ndc, 0.0, 1.0);
+    out.local = local;
+    return out;
+}
+
+// Signed distance from `local` to the edge of the window, negative inside. The corners
+// are the same superellipses as `corner_coverage` in window.wgsl.
+fn window_distance(local: vec2<f32>) -> f32 {
+    let size = border.rect.zw;
+    let half_size = size * 0.5;
+    let radius = max(min(border.corner_radius, min(size.x, size.y) * 0.5), 0.0);
+    let q = abs(local - half_size) - (half_size - radius);
+
+    let n = 2.0 + 3.0 * clamp(border.corner_smoothing, 0.0, 1.0);
+    let outside = max(q, vec2<f32>(0.0));
+    let corner = pow(pow(outside.x, n) + pow(outside.y, n), 1.0 / n);
+    return corner + min(max(q.x, q.y), 0.0) - radius;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dist = window_distance(in.local);
+    let aa_width = max(border.corner_aa_width, 0.001);
+    // The window covers everything inside its edge, so only draw outside it.
+    let outside = clamp(dist / aa_width + 0.5, 0.0, 1.0);
+    let solid = clamp((border.width - dist) / aa_width + 0.5, 0.0, 1.0);
+    var glow = 0.0;
+    if border.glow > 0.0 {
+        let t = clamp((dist - border.width) / border.glow, 0.0, 1.0);
+        glow = (1.0 - t) * (1.0 - t) * GLOW_STRENGTH;
+    }
+    return border.color * max(solid, glow) * outside * border.opacity;
+}
@@ -0,0 +1,44 @@
+struct GradientUniforms {
+    // linear colors at the start and end of the gradient
+    start: vec4<f32>,
+    end: vec4<f32>,
+    // 0 for top to bottom, 1 for left to right, 2 for center outwards
+    direction: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> gradient: GradientUniforms;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // A single triangle covering the whole screen.
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var t: f32;
+    switch gradient.direction {
+        case 1u: {
+            t = in.uv.x;
+        }
+        case 2u: {
+            // 0 at the center and 1 in the corners.
+            t = length(in.uv - vec2<f32>(0.5)) / length(vec2<f32>(0.5));
+        }
+        default: {
+            t = in.uv.y;
+        }
+    }
+    return mix(gradient.start, gradient.end, clamp(t, 0.0, 1.0));
+}
@@ -0,0 +1,95 @@
+struct WindowUniforms {
+    // x, y, width, height of the window in screen pixels
+    rect: vec4<f32>,
+    // width, height of the screen in pixels
+    screen: vec2<f32>,
+    // how much to darken the window, 0 to 1
+    dim: f32,
+    // 0 for fully transparent, 1 for opaque
+    opacity: f32,
+    // 1 if the texture's alpha channel should be used, 0 if it's undefined
+    has_alpha: f32,
+    // radius of the rounded corners in pixels, 0 for square corners
+    corner_radius: f32,
+    // 0 for circular corners, up to 1 for squircle-like continuous corners
+    corner_smoothing: f32,
+    // 1 if the texture's color isn't premultiplied by its alpha, 0 if it is
+    straight_alpha: f32,
+    // how wide the corners' edges fade out over, in pixels
+    corner_aa_width: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> window: WindowUniforms;
+@group(0) @binding(1)
+var window_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var window_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    // position within the window in pixels
+    @location(1) local: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // Two triangles covering the unit square.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let uv = corners[index];
+
+    // Pixel coordinates have the origin in the top left, clip space in the center.
+    let pixel = window.rect.xy + uv * window.rect.zw;
+    let ndc = vec2<f32>(
+        pixel.x / window.screen.x * 2.0 - 1.0,
+        1.0 - pixel.y / window.screen.y * 2.0,
+    );
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = uv;
+    out.local = uv * window.rect.zw;
+    return out;
+}
+
+// How much of the pixel at `local` is inside the window's rounded corners, from 0 to 1.
+fn corner_coverage(local: vec2<f32>) -> f32 {
+    let size = window.rect.zw;
+    let radius = min(window.corner_radius, min(size.x, size.y) * 0.5);
+    if radius <= 0.0 {
+        return 1.0;
+    }
+
+    // Distance into the corner region, measured from the center of the corner's curve.
+    let half_size = size * 0.5;
+    let q = max(abs(local - half_size) - (half_size - radius), vec2<f32>(0.0));
+    if q.x <= 0.0 && q.y <= 0.0 {
+        return 1.0;
+    }
+
+    // A superellipse, which is a circle with an exponent of 2 and gets squarer
+    // (with a more gradual curve) as it grows.
+    let n = 2.0 + 3.0 * clamp(window.corner_smoothing, 0.0, 1.0);
+    let dist = pow(pow(q.x, n) + pow(q.y, n), 1.0 / n) - radius;
+    return clamp(0.5 - dist / max(window.corner_aa_width, 0.001), 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(window_texture, window_sampler, in.uv);
+    // The alpha channel of 24-bit windows is undefined, so treat them as opaque. ARGB
+    // windows are normally premultiplied already, as is our output, but some clients
+    // use straight alpha.
+    let alpha = select(1.0, color.a, window.has_alpha > 0.5);
+    let rgb = select(color.rgb, color.rgb * alpha, window.straight_alpha > 0.5);
+    let coverage = corner_coverage(in.local);
+    return vec4<f32>(rgb * (1.0 - window.dim), alpha) * window.opacity * coverage;
+}
@@ -0,0 +1,141 @@
+//! What the compositor was tracking, as JSON, for making sense of crash reports.
+//!
+//! This only covers the bookkeeping (windows, their geometry, stacking and effects), never
+//! window contents, so it stays small enough to log.
+
+use std::fmt::Write as _;
+
+use x11rb::protocol::xproto;
+
+use crate::window::WindowInfo;
+
+/// The state of the compositor at some point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub root_size: (u16, u16),
+    pub active_window: Option<xproto::Window>,
+    pub frame_count: u64,
+    pub expose: bool,
+    pub suspended: bool,
+    /// Every tracked window, from the bottom of the stack to the top.
+    pub windows: Vec<WindowInfo>,
+}
+
+impl Snapshot {
+    /// The snapshot as a single-line JSON object.
+    ///
+    /// Field names and their meanings are kept stable, so that tools reading reports
+    /// from different versions don't break.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(
+            out,
+            "{{\"root_size\":[{},{}],\"active_window\":{},\"frame_count\":{},\
+             \"expose\":{},\"suspended\":{},\"windows\":[",
+            self.root_size.0,
+            self.root_size.1,
+            optional(self.active_window),
+            self.frame_count,
+            self.expose,
+            self.suspended,
+        )
+        .ok();
+        for (i, window) in self.windows.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_window(&mut out, window);
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+fn write_window(out: &mut String, window: &WindowInfo) {
+    let xproto::Rectangle {
+        x,
+        y,
+        width,
+        height,
+    } = window.geometry;
+    write!(
+        out,
+        "{{\"id\":{},\"geometry\":{{\"x\":{x},\"y\":{y},\"width\":{width},\"height\":{height}}},\
+         \"border_width\":{},\"mapped\":{},\"opacity\":{},\"stacking_index\":{},\
+         \"hidden\":{},\"fullscreen\":{},\"decorations\":{},\"corner_radius\":{}}}",
+        window.id,
+        window.border_width,
+        window.mapped,
+        // JSON has no representation for NaN.
+        if window.opacity.is_finite() {
+            window.opacity.to_string()
+        } else {
+            String::from("null")
+        },
+        window.stacking_index,
+        window.wm_state.hidden,
+        window.wm_state.fullscreen,
+        optional(window.decorations),
+        optional(window.corner_radius),
+    )
+    .ok();
+}
+
+/// `value` as JSON, or `null`.
+fn optional(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| String::from("null"), |value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WmState;
+
+    #[test]
+    fn serializes_to_json() {
+        let snapshot = Snapshot {
+            root_size: (1920, 1080),
+            active_window: Some(42),
+            frame_count: 7,
+            expose: false,
+            suspended: false,
+            windows: vec![WindowInfo {
+                id: 42,
+                geometry: xproto::Rectangle {
+                    x: -10,
+                    y: 20,
+                    width: 300,
+                    height: 200,
+                },
+                border_width: 1,
+                mapped: true,
+                opacity: 0.5,
+                stacking_index: 0,
+                wm_state: WmState {
+                    hidden: false,
+                    fullscreen: true,
+                },
+                decorations: None,
+                corner_radius: Some(8),
+            }],
+        };
+        let json = snapshot.to_json();
+        assert_eq!(
+            json,
+            "{\"root_size\":[1920,1080],\"active_window\":42,\"frame_count\":7,\
+             \"expose\":false,\"suspended\":false,\"windows\":[{\"id\":42,\
+             \"geometry\":{\"x\":-10,\"y\":20,\"width\":300,\"height\":200},\
+             \"border_width\":1,\"mapped\":true,\"opacity\":0.5,\"stacking_index\":0,\
+             \"hidden\":false,\"fullscreen\":true,\"decorations\":null,\
+             \"corner_radius\":8}]}"
+        );
+
+        let empty = Snapshot {
+            active_window: None,
+            windows: Vec::new(),
+            ..snapshot
+        };
+        assert!(empty.to_json().contains("\"active_window\":null"));
+        assert!(empty.to_json().ends_with("\"windows\":[]}"));
+    }
+}
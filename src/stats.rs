@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+use tracing_timing::{Histogram, TimingLayer};
+
+use crate::hooks::DisplayInfo;
+
+/// How often the summary line is emitted.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Name of the span wrapping each call to `render`, used to look up frame times in the
+/// perf histogram.
+pub const RENDER_SPAN: &str = "render";
+
+/// Aggregated counters for the periodic `debug!` summary, so that behavior over time can
+/// be observed without enabling per-event logging.
+pub struct FrameStats {
+    frames: u64,
+    /// Frames skipped because the GPU hadn't finished the previous one.
+    dropped_frames: u64,
+    damage_events: u64,
+    /// Uploads of damaged window contents to their textures, and how many bytes of
+    /// pixels they copied.
+    uploads: u64,
+    upload_bytes: u64,
+    /// Windows drawn from a texture that nothing was uploaded to since the frame before,
+    /// summed over frames.
+    cached_windows: u64,
+    /// Windows skipped because opaque windows above them covered them entirely, summed
+    /// over frames.
+    occluded_windows: u64,
+    /// Frames the server said reached the screen, the time between them by the
+    /// display's clock, and vblanks in between where nothing new was shown.
+    displayed_frames: u64,
+    display_intervals: Duration,
+    missed_vblanks: u64,
+    /// How long events waited between being taken from the X connection and being
+    /// handled, in microseconds.
+    event_latency: Histogram<u64>,
+    last_report: Instant,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            frames: 0,
+            dropped_frames: 0,
+            damage_events: 0,
+            uploads: 0,
+            upload_bytes: 0,
+            cached_windows: 0,
+            occluded_windows: 0,
+            displayed_frames: 0,
+            display_intervals: Duration::ZERO,
+            missed_vblanks: 0,
+            event_latency: Histogram::new(2).expect("to create histogram"),
+            last_report: Instant::now(),
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn frame_rendered(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn frame_dropped(&mut self) {
+        self.dropped_frames += 1;
+    }
+
+    pub fn damage_event(&mut self) {
+        self.damage_events += 1;
+    }
+
+    pub fn upload(&mut self, bytes: u64) {
+        self.uploads += 1;
+        self.upload_bytes += bytes;
+    }
+
+    /// Count `windows` drawn without anything uploaded to them since the last frame.
+    pub fn cached_windows(&mut self, windows: u64) {
+        self.cached_windows += windows;
+    }
+
+    /// Count `windows` that weren't drawn because they were covered.
+    pub fn occluded_windows(&mut self, windows: u64) {
+        self.occluded_windows += windows;
+    }
+
+    pub fn frame_displayed(&mut self, info: &DisplayInfo) {
+        // The first frame has nothing to measure from, so it's left out of the average.
+        if let Some(interval) = info.interval {
+            self.displayed_frames += 1;
+            self.display_intervals += interval;
+        }
+        self.missed_vblanks += info.missed_vblanks;
+    }
+
+    /// Record that an event was handled `latency` after it was taken from X.
+    pub fn event_handled(&mut self, latency: Duration) {
+        self.event_latency
+            .saturating_record(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    /// Emit a summary line and reset the counters if at least [`REPORT_INTERVAL`] has
+    /// passed since the last one. `resources` is only called when reporting.
+    pub fn maybe_report(&mut self, windows: usize, resources: impl FnOnce() -> ResourceStats) {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+
+        let avg_frame_time_us = average_frame_time().map(|t| t.as_micros() as u64);
+        let avg_display_interval_us = (self.displayed_frames > 0)
+            .then(|| (self.display_intervals / self.displayed_frames as u32).as_micros() as u64);
+        let latency = |quantile| {
+            (!self.event_latency.is_empty()).then(|| self.event_latency.value_at_quantile(quantile))
+        };
+        let resources = resources();
+        debug!(
+            frames = self.frames,
+            dropped_frames = self.dropped_frames,
+            damage_events = self.damage_events,
+            uploads = self.uploads,
+            upload_bytes = self.upload_bytes,
+            cached_windows = self.cached_windows,
+            occluded_windows = self.occluded_windows,
+            windows,
+            avg_frame_time_us,
+            avg_display_interval_us,
+            missed_vblanks = self.missed_vblanks,
+            event_latency_p50_us = latency(0.5),
+            event_latency_p99_us = latency(0.99),
+            event_latency_max_us = latency(1.0),
+            window_textures = resources.window_textures,
+            texture_bytes = resources.texture_bytes(),
+            "Frame stats"
+        );
+
+        *self = Self::default();
+    }
+}
+
+/// GPU resources currently held by the compositor, for spotting leaks and memory growth.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceStats {
+    /// Textures holding window contents.
+    pub window_textures: usize,
+    /// Estimated size of the window textures, from their dimensions and format.
+    pub window_texture_bytes: u64,
+    /// Estimated size of the wallpaper textures.
+    pub background_bytes: u64,
+    pub overlay_rects: usize,
+}
+
+impl ResourceStats {
+    /// Estimated size of every texture we hold.
+    pub fn texture_bytes(&self) -> u64 {
+        self.window_texture_bytes + self.background_bytes
+    }
+}
+
+/// Read the mean duration of the render span from the perf histogram, and reset it
+/// so the next report only covers the frames rendered since.
+///
+/// Returns `None` if the perf layer isn't installed or no frames have been recorded.
+fn average_frame_time() -> Option<Duration> {
+    tracing::dispatcher::get_default(|dispatch| {
+        let layer = dispatch.downcast_ref::<TimingLayer>()?;
+        layer.with_histograms(|histograms| {
+            let histogram = histograms.get_mut(RENDER_SPAN)?.get_mut("close")?;
+            histogram.refresh();
+            if histogram.is_empty() {
+                return None;
+            }
+            let mean = histogram.mean();
+            histogram.reset();
+            Some(Duration::from_nanos(mean as u64))
+        })
+    })
+}
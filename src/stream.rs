@@ -0,0 +1,290 @@
+//! Streaming composited frames out as raw video, for recording the screen without a
+//! separate capture tool.
+//!
+//! Only available with the `capture-stream` feature. Frames are written back to back
+//! with no header: each is 8-bit sRGB BGRA, row by row from the top with no padding, at
+//! the size the scene is rendered at. That's what ffmpeg calls `bgra` rawvideo, so
+//!
+//! ```text
+//! recomp --stream - | ffmpeg -f rawvideo -pixel_format bgra -video_size 1920x1080 \
+//!     -framerate 30 -i - out.mkv
+//! ```
+//!
+//! records the screen. If the screen is resized, later frames are the new size.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use tracing::{debug, error, info};
+
+use crate::present::padded_row_bytes;
+
+/// How many frames can wait to be written before new ones are dropped.
+const QUEUED_FRAMES: usize = 2;
+
+/// Options for streaming frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Where to write frames, or `-` for stdout.
+    pub path: PathBuf,
+    /// How many frames to write a second, at most. Frames are only ever written when the
+    /// compositor renders one, so this is lower while nothing changes on screen.
+    pub fps: u32,
+}
+
+/// The buffer a frame is read back into, and whether it's still in use.
+struct Readback {
+    buffer: Arc<wgpu::Buffer>,
+    size: (u32, u32),
+    busy: Arc<AtomicBool>,
+}
+
+/// Reads frames back from the GPU and hands them to a thread that writes them out.
+///
+/// Whenever the previous frame is still being read back, or the sink hasn't kept up with
+/// the ones before it, frames are dropped rather than holding up the compositor.
+pub struct FrameStream {
+    interval: Duration,
+    last_frame: Option<Instant>,
+    swap_red_blue: bool,
+    readback: Option<Readback>,
+    /// Whether a copy was recorded this frame, to map once it's submitted.
+    copied: bool,
+    frames: SyncSender<Vec<u8>>,
+    /// Set once the writer gives up, e.g. because the reading end of a pipe went away.
+    closed: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl FrameStream {
+    /// Start streaming frames rendered in `format` as `config` says.
+    pub fn open(config: &StreamConfig, format: wgpu::TextureFormat) -> Result<Self> {
+        let swap_red_blue = match format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => false,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => true,
+            _ => bail!("Can't stream frames rendered in {format:?}"),
+        };
+        if config.fps == 0 {
+            bail!("Can't stream at 0 frames per second");
+        }
+
+        let mut sink: Box<dyn Write + Send> = if config.path.as_os_str() == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                File::create(&config.path)
+                    .with_context(|| format!("Failed to create {}", config.path.display()))?,
+            )
+        };
+        let (frames, queued) = mpsc::sync_channel::<Vec<u8>>(QUEUED_FRAMES);
+        let closed = Arc::new(AtomicBool::new(false));
+        thread::Builder::new()
+            .name(String::from("recomp-stream"))
+            .spawn({
+                let closed = Arc::clone(&closed);
+                let path = config.path.clone();
+                move || {
+                    for frame in queued {
+                        if let Err(e) = sink.write_all(&frame).and_then(|()| sink.flush()) {
+                            error!("Stopped streaming frames to {}: {}", path.display(), e);
+                            break;
+                        }
+                    }
+                    closed.store(true, Ordering::Release);
+                }
+            })?;
+        info!(
+            "Streaming frames to {} at up to {} fps",
+            config.path.display(),
+            config.fps
+        );
+
+        Ok(Self {
+            interval: Duration::from_secs(1) / config.fps,
+            last_frame: None,
+            swap_red_blue,
+            readback: None,
+            copied: false,
+            frames,
+            closed,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// How many frames were due but dropped because reading back or writing out the
+    /// ones before them hadn't finished.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether it's been long enough since the last streamed frame for another at `now`.
+    fn is_due(&self, now: Instant) -> bool {
+        self.last_frame
+            .is_none_or(|last| now.duration_since(last) >= self.interval)
+    }
+
+    /// Record copying `texture`, the finished frame, into `encoder` if a frame is due.
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        let now = Instant::now();
+        if self.closed.load(Ordering::Acquire) || !self.is_due(now) {
+            return;
+        }
+        self.last_frame = Some(now);
+
+        let size = (texture.width(), texture.height());
+        if self
+            .readback
+            .as_ref()
+            .is_none_or(|readback| readback.size != size)
+        {
+            self.readback = Some(Readback {
+                buffer: Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Stream Readback"),
+                    size: padded_row_bytes(size.0) as u64 * size.1 as u64,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                })),
+                size,
+                busy: Arc::new(AtomicBool::new(false)),
+            });
+        }
+        let Some(readback) = &self.readback else {
+            return;
+        };
+        if readback.busy.swap(true, Ordering::AcqRel) {
+            debug!("Still reading back the last streamed frame, dropping this one");
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes(size.0)),
+                    rows_per_image: Some(size.1),
+                },
+            },
+            texture.size(),
+        );
+        self.copied = true;
+    }
+
+    /// Start reading back the frame copied by [`FrameStream::capture`], once the commands
+    /// copying it have been submitted. It's handed to the writer when the device is next
+    /// polled after the GPU finishes it.
+    pub fn submitted(&mut self) {
+        if !std::mem::take(&mut self.copied) {
+            return;
+        }
+        let Some(readback) = &self.readback else {
+            return;
+        };
+
+        let buffer = Arc::clone(&readback.buffer);
+        let busy = Arc::clone(&readback.busy);
+        let frames = self.frames.clone();
+        let dropped = Arc::clone(&self.dropped);
+        let (width, height) = readback.size;
+        let swap_red_blue = self.swap_red_blue;
+        readback
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    let frame = unpad(
+                        &buffer.slice(..).get_mapped_range(),
+                        width,
+                        height,
+                        swap_red_blue,
+                    );
+                    buffer.unmap();
+                    // If the writer gave up, capture notices before the next frame.
+                    if let Err(TrySendError::Full(_)) = frames.try_send(frame) {
+                        debug!("Stream sink is falling behind, dropping a frame");
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                busy.store(false, Ordering::Release);
+            });
+    }
+
+    /// Forget the readback buffer, e.g. because the device it was created on was lost.
+    pub fn reset(&mut self) {
+        self.readback = None;
+        self.copied = false;
+    }
+}
+
+/// Strip the padding wgpu adds to each row of a `width` by `height` frame read back into
+/// `data`, turning RGBA into BGRA if `swap_red_blue` is set.
+fn unpad(data: &[u8], width: u32, height: u32, swap_red_blue: bool) -> Vec<u8> {
+    let row_bytes = width as usize * 4;
+    let mut frame = Vec::with_capacity(row_bytes * height as usize);
+    for row in data
+        .chunks(padded_row_bytes(width) as usize)
+        .take(height as usize)
+    {
+        frame.extend_from_slice(&row[..row_bytes]);
+    }
+    if swap_red_blue {
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_row_padding() {
+        let padded = padded_row_bytes(2) as usize;
+        let mut data = vec![0; padded * 2];
+        data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        data[padded..padded + 8].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+
+        assert_eq!(
+            unpad(&data, 2, 2, false),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]
+        );
+        assert_eq!(unpad(&data, 1, 1, true), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn limits_the_frame_rate() {
+        let path = std::env::temp_dir().join(format!("recomp-stream-{}", std::process::id()));
+        let config = StreamConfig { path, fps: 10 };
+        let mut stream = FrameStream::open(&config, wgpu::TextureFormat::Bgra8UnormSrgb).unwrap();
+
+        let start = Instant::now();
+        assert!(stream.is_due(start));
+        stream.last_frame = Some(start);
+        assert!(!stream.is_due(start + Duration::from_millis(50)));
+        assert!(stream.is_due(start + Duration::from_millis(100)));
+
+        assert!(FrameStream::open(&config, wgpu::TextureFormat::Rgb10a2Unorm).is_err());
+        drop(stream);
+        std::fs::remove_file(&config.path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Telling systemd when the compositor is ready and still alive, when it runs as a
+//! `Type=notify` service.
+//!
+//! Only available with the `systemd` feature. This speaks the `sd_notify` protocol
+//! directly, which is a datagram per message on the socket named by `$NOTIFY_SOCKET`.
+
+use std::{
+    ffi::OsStr,
+    os::unix::{
+        ffi::OsStrExt,
+        net::{SocketAddr, UnixDatagram},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+/// A connection to the service manager's notification socket.
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// How often to ping the watchdog, if the unit has one.
+    ping_interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Notifier {
+    /// Connect to the socket the service manager gave us, or `None` if we're not running
+    /// under one.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let addr = notify_addr(&path)
+            .with_context(|| format!("Invalid NOTIFY_SOCKET {}", path.to_string_lossy()))?;
+        let ping_interval = ping_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+            std::process::id(),
+        );
+
+        Ok(Some(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            ping_interval,
+            last_ping: None,
+        }))
+    }
+
+    /// Tell the service manager that startup has finished.
+    pub fn ready(&self) -> Result<()> {
+        self.send("READY=1")
+    }
+
+    /// How often [`Notifier::ping`] needs to be called, if the unit has a watchdog.
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval
+    }
+
+    /// Ping the watchdog, if the unit has one and the last ping was long enough ago.
+    pub fn ping(&mut self) -> Result<()> {
+        let Some(interval) = self.ping_interval else {
+            return Ok(());
+        };
+        if self.last_ping.is_some_and(|last| last.elapsed() < interval) {
+            return Ok(());
+        }
+        self.send("WATCHDOG=1")?;
+        self.last_ping = Some(Instant::now());
+        Ok(())
+    }
+
+    fn send(&self, state: &str) -> Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.addr)
+            .context("Failed to notify the service manager")?;
+        Ok(())
+    }
+}
+
+/// The address of the socket in `$NOTIFY_SOCKET`, which names an abstract socket if it
+/// starts with `@`.
+fn notify_addr(path: &OsStr) -> Result<SocketAddr> {
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            Ok(SocketAddr::from_abstract_name(name)?)
+        }
+        None => Ok(SocketAddr::from_pathname(path)?),
+    }
+}
+
+/// How often to ping a watchdog with a timeout of `usec`, which is half of it as
+/// `sd_watchdog_enabled` recommends. If `pid` is set the watchdog is only meant for that
+/// process.
+fn ping_interval(usec: Option<&str>, pid: Option<&str>, our_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(our_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_at_half_the_watchdog_timeout() {
+        assert_eq!(
+            ping_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            ping_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        // Meant for another process.
+        assert_eq!(ping_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(ping_interval(Some("0"), None, 42), None);
+        assert_eq!(ping_interval(Some("soon"), None, 42), None);
+        assert_eq!(ping_interval(None, None, 42), None);
+    }
+
+    #[test]
+    fn sends_to_the_notify_socket() {
+        let path = std::env::temp_dir().join(format!("recomp-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        let mut notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            addr: notify_addr(path.as_os_str()).unwrap(),
+            ping_interval: Some(Duration::from_secs(60)),
+            last_ping: None,
+        };
+        notifier.ready().unwrap();
+        notifier.ping().unwrap();
+        // Too soon after the last one.
+        notifier.ping().unwrap();
+
+        listener.set_nonblocking(true).unwrap();
+        let mut buf = [0; 64];
+        let mut received = Vec::new();
+        while let Ok(len) = listener.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        assert_eq!(received, ["READY=1", "WATCHDOG=1"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,487 @@
+use x11rb::protocol::xproto::Rectangle;
+
+/// If a damaged region covers more than this fraction of a window, it's cheaper to
+/// just re-upload the whole thing than to fetch and write a sub-rectangle.
+const PARTIAL_UPLOAD_MAX_RATIO: f32 = 0.5;
+
+/// Damaged rectangles closer together than this are uploaded as one by default.
+const DEFAULT_MERGE_DISTANCE: u16 = 16;
+
+/// Beyond this many damaged rectangles, a window's damage is uploaded as its bounding box.
+const MAX_UPLOAD_RECTS: usize = 64;
+
+/// The texture format used for window contents.
+///
+/// X hands us 32bpp ZPixmap data in BGRX/BGRA byte order, which maps directly onto this.
+pub const WINDOW_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// A persistent GPU texture holding the contents of a single window's backing pixmap,
+/// along with the uniform buffer and bind group used to draw it.
+///
+/// The texture lives for as long as the window keeps the same size, so damage can be
+/// applied to it incrementally rather than re-creating it every frame.
+pub struct WindowTexture {
+    texture: wgpu::Texture,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    format: wgpu::TextureFormat,
+    width: u16,
+    height: u16,
+}
+
+impl WindowTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u16,
+        height: u16,
+    ) -> Self {
+        Self::with_format(
+            device,
+            layout,
+            sampler,
+            width,
+            height,
+            WINDOW_TEXTURE_FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        )
+    }
+
+    /// A texture in `format` that can be rendered into as well as drawn, e.g. to draw the
+    /// scene at a lower resolution and then scale it up. It can be copied out of too.
+    pub fn render_target(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        width: u16,
+        height: u16,
+    ) -> Self {
+        Self::with_format(
+            device,
+            layout,
+            sampler,
+            width,
+            height,
+            format,
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+        )
+    }
+
+    fn with_format(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u16,
+        height: u16,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Window Texture"),
+            size: wgpu::Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Window Uniforms"),
+            size: WINDOW_UNIFORMS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Window Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        Self {
+            texture,
+            uniforms,
+            bind_group,
+            format,
+            width,
+            height,
+        }
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Estimated GPU memory used by the texture, ignoring any padding the driver adds.
+    pub fn size_bytes(&self) -> u64 {
+        let texel = self.format.block_copy_size(None).unwrap_or(4);
+        self.width as u64 * self.height as u64 * texel as u64
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// A view of the whole texture, for rendering into a [`WindowTexture::render_target`].
+    pub fn create_view(&self) -> wgpu::TextureView {
+        self.texture
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Write tightly-packed 32bpp pixel data for `rect` into the texture.
+    ///
+    /// `rect` is in window-local coordinates and must lie within the texture.
+    pub fn write(&self, queue: &wgpu::Queue, rect: Rectangle, data: &[u8]) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: rect.x as u32,
+                    y: rect.y as u32,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(rect.width as u32 * 4),
+                rows_per_image: Some(rect.height as u32),
+            },
+            wgpu::Extent3d {
+                width: rect.width as u32,
+                height: rect.height as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Update the uniforms used to draw this window.
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, uniforms: &WindowUniforms) {
+        queue.write_buffer(&self.uniforms, 0, &uniforms.pack());
+    }
+}
+
+/// Size of the `WindowUniforms` struct in `window.wgsl`, including padding.
+pub(crate) const WINDOW_UNIFORMS_SIZE: u64 = 64;
+
+/// Per-window parameters for `window.wgsl`.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowUniforms {
+    /// Where to draw the window, in screen pixels.
+    pub geometry: Rectangle,
+    /// Size of the output in pixels.
+    pub screen: (u32, u32),
+    /// How much to darken the window, from 0 (not at all) to 1 (black). Below 0 this
+    /// brightens it instead.
+    pub dim: f32,
+    /// How opaque to draw the window, from 0 (invisible) to 1.
+    pub opacity: f32,
+    /// Whether to use the alpha channel of the texture. Otherwise it's treated as opaque.
+    pub has_alpha: bool,
+    /// Whether the texture has straight alpha, rather than being premultiplied.
+    pub straight_alpha: bool,
+    /// Radius of the rounded corners in pixels. Zero leaves them square.
+    pub corner_radius: f32,
+    /// How far the corners are from circular (0) towards a squircle (1).
+    pub corner_smoothing: f32,
+    /// How wide the corners' edges fade out over, in pixels.
+    pub corner_aa_width: f32,
+}
+
+impl WindowUniforms {
+    /// Pack the uniforms into the layout expected by `window.wgsl`.
+    pub fn pack(&self) -> [u8; WINDOW_UNIFORMS_SIZE as usize] {
+        let values = [
+            self.geometry.x as f32,
+            self.geometry.y as f32,
+            self.geometry.width as f32,
+            self.geometry.height as f32,
+            self.screen.0 as f32,
+            self.screen.1 as f32,
+            self.dim,
+            self.opacity,
+            if self.has_alpha { 1.0 } else { 0.0 },
+            self.corner_radius,
+            self.corner_smoothing,
+            if self.straight_alpha { 1.0 } else { 0.0 },
+            self.corner_aa_width,
+        ];
+
+        let mut bytes = [0; WINDOW_UNIFORMS_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+/// How a window's damage is turned into texture uploads.
+///
+/// Every upload costs a `GetImage` round trip and a texture write on top of the pixels
+/// themselves, so lots of tiny, scattered uploads (a blinking cursor, a spinner, a few
+/// changed characters) can be slower than fewer, larger ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamagePolicy {
+    /// Damaged rectangles whose bounding boxes are within this many pixels of each other
+    /// are uploaded as one rectangle covering both.
+    pub merge_distance: u16,
+    /// If the damage covers more than this fraction of a window, the whole window is
+    /// uploaded instead. This is per window, and only about uploads: the screen is
+    /// redrawn in full either way.
+    pub window_full_upload_ratio: f32,
+}
+
+impl Default for DamagePolicy {
+    fn default() -> Self {
+        Self {
+            merge_distance: DEFAULT_MERGE_DISTANCE,
+            window_full_upload_ratio: PARTIAL_UPLOAD_MAX_RATIO,
+        }
+    }
+}
+
+/// Decide which part of a window needs to be re-uploaded given the bounding box of its
+/// damaged region.
+///
+/// The damage extents are clipped to the window, and if what remains is large relative
+/// to the window we fall back to uploading the whole thing. Returns `None` if the damage
+/// doesn't overlap the window at all.
+pub fn upload_region(damage: Rectangle, width: u16, height: u16) -> Option<Rectangle> {
+    upload_regions(&[damage], width, height, &DamagePolicy::default()).pop()
+}
+
+/// Decide which parts of a window need to be re-uploaded given the rectangles of its
+/// damaged region.
+///
+/// Nearby rectangles are merged according to `policy`, and everything is clipped to the
+/// window. If the result covers more of the window than `policy` allows, it's replaced
+/// by the whole window. Rectangles that don't overlap the window are dropped.
+pub fn upload_regions(
+    damage: &[Rectangle],
+    width: u16,
+    height: u16,
+    policy: &DamagePolicy,
+) -> Vec<Rectangle> {
+    let mut boxes: Vec<_> = damage
+        .iter()
+        .filter_map(|rect| Edges::clipped(rect, width, height))
+        .collect();
+
+    if boxes.len() > MAX_UPLOAD_RECTS {
+        // Merging is quadratic, and this many uploads would be slow anyway.
+        let bounds = boxes.iter().copied().reduce(Edges::union);
+        boxes = bounds.into_iter().collect();
+    }
+    merge_nearby(&mut boxes, policy.merge_distance.into());
+
+    let damaged_area: i64 = boxes.iter().map(Edges::area).sum();
+    let window_area = width as i64 * height as i64;
+    if !boxes.is_empty()
+        && damaged_area as f32 > window_area as f32 * policy.window_full_upload_ratio
+    {
+        return vec![Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }];
+    }
+
+    boxes.into_iter().map(Edges::to_rectangle).collect()
+}
+
+/// Merge boxes within `distance` of each other until no two are that close.
+fn merge_nearby(boxes: &mut Vec<Edges>, distance: i32) {
+    let mut merged = true;
+    while merged {
+        merged = false;
+        let mut i = 0;
+        while i < boxes.len() {
+            let mut j = i + 1;
+            while j < boxes.len() {
+                if boxes[i].near(&boxes[j], distance) {
+                    let other = boxes.swap_remove(j);
+                    boxes[i] = boxes[i].union(other);
+                    merged = true;
+                } else {
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+/// A rectangle as its edges, which is easier to merge and clip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Edges {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+}
+
+impl Edges {
+    /// The part of `rect` inside a `width` by `height` window, if any.
+    fn clipped(rect: &Rectangle, width: u16, height: u16) -> Option<Self> {
+        let x0 = (rect.x as i32).clamp(0, width as i32);
+        let y0 = (rect.y as i32).clamp(0, height as i32);
+        let x1 = (rect.x as i32 + rect.width as i32).clamp(0, width as i32);
+        let y1 = (rect.y as i32 + rect.height as i32).clamp(0, height as i32);
+        (x1 > x0 && y1 > y0).then_some(Self { x0, y0, x1, y1 })
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    /// Whether the gap between the boxes is at most `distance` on both axes.
+    fn near(&self, other: &Self, distance: i32) -> bool {
+        self.x0 <= other.x1 + distance
+            && other.x0 <= self.x1 + distance
+            && self.y0 <= other.y1 + distance
+            && other.y0 <= self.y1 + distance
+    }
+
+    fn area(&self) -> i64 {
+        (self.x1 - self.x0) as i64 * (self.y1 - self.y0) as i64
+    }
+
+    fn to_rectangle(self) -> Rectangle {
+        Rectangle {
+            x: self.x0 as i16,
+            y: self.y0 as i16,
+            width: (self.x1 - self.x0) as u16,
+            height: (self.y1 - self.y0) as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn clips_damage_to_the_window() {
+        assert_eq!(
+            upload_region(rect(-10, 90, 40, 40), 200, 100),
+            Some(rect(0, 90, 30, 10))
+        );
+        assert_eq!(upload_region(rect(300, 0, 10, 10), 200, 100), None);
+    }
+
+    #[test]
+    fn merges_nearby_damage() {
+        let policy = DamagePolicy {
+            merge_distance: 8,
+            window_full_upload_ratio: 0.5,
+        };
+        // Two characters of a text cursor a few pixels apart, and one far away.
+        let damage = [
+            rect(100, 100, 8, 16),
+            rect(112, 100, 8, 16),
+            rect(600, 400, 8, 16),
+        ];
+        assert_eq!(
+            upload_regions(&damage, 1280, 720, &policy),
+            [rect(100, 100, 20, 16), rect(600, 400, 8, 16)]
+        );
+
+        // Merging can bring a box close enough to one it wasn't near before.
+        let damage = [rect(0, 0, 10, 10), rect(40, 0, 10, 10), rect(18, 0, 14, 10)];
+        assert_eq!(
+            upload_regions(&damage, 1280, 720, &policy),
+            [rect(0, 0, 50, 10)]
+        );
+
+        let apart = DamagePolicy {
+            merge_distance: 0,
+            ..policy
+        };
+        assert_eq!(
+            upload_regions(&damage[..2], 1280, 720, &apart),
+            [rect(0, 0, 10, 10), rect(40, 0, 10, 10)]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_full_uploads() {
+        let policy = DamagePolicy {
+            merge_distance: 0,
+            window_full_upload_ratio: 0.25,
+        };
+        let full = [rect(0, 0, 100, 100)];
+
+        // Neither rectangle is big enough alone, but together they are.
+        let damage = [rect(0, 0, 100, 20), rect(0, 50, 100, 10)];
+        assert_eq!(upload_regions(&damage, 100, 100, &policy), full);
+        assert_eq!(upload_regions(&damage[..1], 100, 100, &policy), damage[..1]);
+
+        // Merging counts the gap between rectangles as damaged.
+        let damage = [rect(0, 0, 10, 10), rect(80, 80, 10, 10)];
+        let merging = DamagePolicy {
+            merge_distance: 100,
+            ..policy
+        };
+        assert_eq!(upload_regions(&damage, 100, 100, &merging), full);
+
+        assert!(upload_regions(&[], 100, 100, &policy).is_empty());
+    }
+
+    #[test]
+    fn bounds_lots_of_damage() {
+        let policy = DamagePolicy {
+            merge_distance: 0,
+            window_full_upload_ratio: 1.0,
+        };
+        let damage: Vec<_> = (0..100).map(|i| rect(i * 4, 0, 1, 1)).collect();
+        assert_eq!(
+            upload_regions(&damage, 1000, 1000, &policy),
+            [rect(0, 0, 397, 1)]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Drawing a single window into an offscreen texture at any size and reading it back, for
+//! taskbars and pagers.
+
+use anyhow::{bail, Context, Result};
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    pipeline::WindowPipeline,
+    present::padded_row_bytes,
+    texture::{WindowTexture, WindowUniforms},
+};
+
+/// A window's contents drawn at some size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    /// 8-bit sRGB RGBA with premultiplied alpha, row by row from the top, with no
+    /// padding between rows.
+    pub pixels: Vec<u8>,
+}
+
+/// The largest size with the aspect ratio of `size` that fits within `max`, at least a
+/// pixel in each direction.
+pub fn fit(size: (u16, u16), max: (u16, u16)) -> (u16, u16) {
+    let scale = f32::min(
+        max.0 as f32 / size.0.max(1) as f32,
+        max.1 as f32 / size.1.max(1) as f32,
+    );
+    let scaled = |len: u16, max: u16| ((len as f32 * scale).round() as u16).clamp(1, max.max(1));
+    (scaled(size.0, max.0), scaled(size.1, max.1))
+}
+
+/// Draw `texture` filling a `size` target in `format`, the format `pipeline` draws in,
+/// and read the result back.
+///
+/// `uniforms` says how to draw the window. Its geometry and screen size are replaced to
+/// fill the target. This overwrites the window's uniforms, which is harmless since every
+/// frame writes them again before drawing it.
+pub fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pipeline: &WindowPipeline,
+    format: wgpu::TextureFormat,
+    texture: &WindowTexture,
+    uniforms: WindowUniforms,
+    size: (u16, u16),
+) -> Result<Thumbnail> {
+    let swap_red_blue = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("Can't read back thumbnails rendered in {format:?}"),
+    };
+    let (width, height) = (size.0 as u32, size.1 as u32);
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Thumbnail"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let padded_row = padded_row_bytes(width);
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Thumbnail Readback"),
+        size: padded_row as u64 * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    texture.write_uniforms(
+        queue,
+        &WindowUniforms {
+            geometry: Rectangle {
+                x: 0,
+                y: 0,
+                width: size.0,
+                height: size.1,
+            },
+            screen: (width, height),
+            ..uniforms
+        },
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Thumbnail Encoder"),
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Thumbnail Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline.single_sampled());
+    pass.set_bind_group(0, texture.bind_group(), &[]);
+    pass.draw(0..6, 0..1);
+    drop(pass);
+
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(height),
+            },
+        },
+        target.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        tx.send(result).ok();
+    });
+    device.poll(wgpu::Maintain::Wait);
+    rx.recv()?.context("Failed to read back the thumbnail")?;
+
+    let data = slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in data.chunks(padded_row as usize) {
+        pixels.extend_from_slice(&row[..width as usize * 4]);
+    }
+    drop(data);
+    readback.unmap();
+    if swap_red_blue {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    Ok(Thumbnail {
+        width: size.0,
+        height: size.1,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_within_the_bounds() {
+        assert_eq!(fit((1920, 1080), (256, 256)), (256, 144));
+        assert_eq!(fit((600, 1200), (256, 256)), (128, 256));
+        // Small windows are scaled up to fill it.
+        assert_eq!(fit((64, 32), (256, 256)), (256, 128));
+        // Never collapses to nothing.
+        assert_eq!(fit((4000, 1), (100, 100)), (100, 1));
+        assert_eq!(fit((100, 100), (0, 0)), (1, 1));
+    }
+}
@@ -0,0 +1,73 @@
+//! A translucent color drawn over everything, e.g. to dim the whole screen for focus
+//! assist.
+
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::{
+    overlay::solid_texture,
+    pipeline::WindowPipeline,
+    texture::{WindowTexture, WindowUniforms},
+};
+
+/// A solid quad covering the whole output, drawn with the window pipeline.
+pub struct TintLayer {
+    texture: WindowTexture,
+    /// Non-premultiplied sRGB, with components from 0 to 1.
+    color: [f32; 3],
+}
+
+impl TintLayer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+        color: [f32; 3],
+    ) -> Self {
+        let [r, g, b] = color;
+        Self {
+            texture: solid_texture(device, queue, pipeline, [r, g, b, 1.0]),
+            color,
+        }
+    }
+
+    /// Upload the color again, e.g. after the device was recreated.
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &WindowPipeline,
+    ) {
+        *self = Self::new(device, queue, pipeline, self.color);
+    }
+
+    /// Write the uniforms for covering an `output` sized screen with the tint at
+    /// `opacity`.
+    pub fn write_uniforms(&self, queue: &wgpu::Queue, output: (u32, u32), opacity: f32) {
+        self.texture.write_uniforms(
+            queue,
+            &WindowUniforms {
+                geometry: Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: output.0 as u16,
+                    height: output.1 as u16,
+                },
+                screen: output,
+                dim: 0.0,
+                opacity,
+                has_alpha: false,
+                straight_alpha: false,
+                corner_radius: 0.0,
+                corner_smoothing: 0.0,
+                corner_aa_width: 1.0,
+            },
+        );
+    }
+
+    /// Draw the tint over the whole output. The pass must already be using the window
+    /// pipeline.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, self.texture.bind_group(), &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
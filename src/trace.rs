@@ -0,0 +1,377 @@
+//! Recording the X events the compositor receives and playing them back later, for
+//! reproducing bugs that depend on the order or timing of events.
+//!
+//! A trace is a text file that starts with a `recomp-trace 1` line and the first event
+//! number of each extension we take events from. After that comes one line per event,
+//! with the time since recording started in microseconds and the raw event in hex:
+//!
+//! ```text
+//! recomp-trace 1
+//! ext DAMAGE 91
+//! 1520 10000200...
+//! ```
+//!
+//! The server picks extension event numbers, so they are renumbered on replay to match
+//! the server being replayed on. Replayed events still refer to the windows of the
+//! recording session. The handlers ask the server about them as usual, and windows that
+//! don't exist on the replay server are forgotten, just like windows destroyed while
+//! we're handling their events.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::time::Instant;
+use tracing::{info, warn};
+use x11rb::protocol::{damage, randr, shape, xfixes, Event};
+use x11rb_async::connection::{Connection, RequestConnection};
+
+use crate::{
+    connection::XConn,
+    events::{wait_readable, EventSource},
+};
+
+/// The first line of every trace, with the version of the format.
+const HEADER: &str = "recomp-trace 1";
+
+/// Extensions whose events we might receive, and so need renumbering on replay.
+const EVENT_EXTENSIONS: [&str; 4] = [
+    damage::X11_EXTENSION_NAME,
+    xfixes::X11_EXTENSION_NAME,
+    shape::X11_EXTENSION_NAME,
+    randr::X11_EXTENSION_NAME,
+];
+
+/// Event numbers below this are core events, which are the same on every server.
+const FIRST_EXTENSION_EVENT: u8 = 64;
+
+/// The first event number of each of [`EVENT_EXTENSIONS`] the server supports.
+async fn extension_events(conn: &XConn) -> Result<Vec<(String, u8)>> {
+    let mut found = Vec::new();
+    for name in EVENT_EXTENSIONS {
+        if let Some(info) = conn.extension_information(name).await? {
+            found.push((name.to_owned(), info.first_event));
+        }
+    }
+    Ok(found)
+}
+
+/// An event source that passes on events from the X connection, writing each of them to
+/// a trace file on the way.
+pub struct Recorder {
+    conn: XConn,
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Start recording to `path`, replacing anything already there.
+    pub async fn create(path: &Path, conn: XConn) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create trace file {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{HEADER}")?;
+        for (name, first_event) in extension_events(&conn).await? {
+            writeln!(out, "ext {name} {first_event}")?;
+        }
+        out.flush()?;
+        info!("Recording events to {}", path.display());
+
+        Ok(Self {
+            conn,
+            out,
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, raw: &[u8]) -> Result<Event> {
+        write!(self.out, "{} ", self.start.elapsed().as_micros())?;
+        for byte in raw {
+            write!(self.out, "{byte:02x}")?;
+        }
+        writeln!(self.out)?;
+        Ok(self.conn.parse_event(raw)?)
+    }
+}
+
+impl EventSource for Recorder {
+    fn poll_event(&mut self) -> Result<Option<Event>> {
+        match self.conn.poll_for_raw_event()? {
+            Some(raw) => self.record(raw.as_ref()).map(Some),
+            None => {
+                // Write out everything drained so far, so that the trace is complete up
+                // to here if we crash before the next batch.
+                self.out.flush()?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn wait_event(&mut self) -> Result<Event> {
+        self.out.flush()?;
+        let raw = self.conn.wait_for_raw_event().await?;
+        self.record(raw.as_ref())
+    }
+
+    async fn wait_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(ev) = self.poll_event()? {
+                return Ok(Some(ev));
+            }
+            if !wait_readable(&self.conn, deadline).await? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// An event source that plays back a trace file made by [`Recorder`], with the same
+/// timing as when it was recorded.
+///
+/// Time starts when the first event is asked for, and the source closes once every event
+/// has been handed out.
+pub struct Replay {
+    conn: XConn,
+    trace: Trace,
+    start: Option<Instant>,
+}
+
+impl Replay {
+    /// Load the trace at `path`, renumbering its events for the server `conn` is
+    /// connected to.
+    pub async fn open(path: &Path, conn: XConn) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read trace file {}", path.display()))?;
+        let mut trace = Trace::parse(&text)
+            .with_context(|| format!("Failed to parse trace file {}", path.display()))?;
+        trace.renumber(&extension_events(&conn).await?);
+        info!(
+            "Replaying {} events from {}",
+            trace.events.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            conn,
+            trace,
+            start: None,
+        })
+    }
+
+    /// When the next event is due, if there are any left.
+    fn next_due(&mut self) -> Option<Instant> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.trace.events.front().map(|(time, _)| start + *time)
+    }
+
+    fn next_event(&mut self) -> Result<Event> {
+        let Some((_, raw)) = self.trace.events.pop_front() else {
+            bail!("The trace has no more events");
+        };
+        Ok(self.conn.parse_event(&raw)?)
+    }
+}
+
+impl EventSource for Replay {
+    fn poll_event(&mut self) -> Result<Option<Event>> {
+        match self.next_due() {
+            Some(due) if due <= Instant::now() => self.next_event().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    async fn wait_event(&mut self) -> Result<Event> {
+        if let Some(due) = self.next_due() {
+            tokio::time::sleep_until(due).await;
+        }
+        self.next_event()
+    }
+
+    async fn wait_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        match self.next_due() {
+            Some(due) if due <= deadline => {
+                tokio::time::sleep_until(due).await;
+                self.next_event().map(Some)
+            }
+            _ => {
+                tokio::time::sleep_until(deadline).await;
+                Ok(None)
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.trace.events.is_empty()
+    }
+}
+
+/// The contents of a trace file.
+#[derive(Debug, Default, PartialEq)]
+struct Trace {
+    /// The first event number of each extension on the recording server.
+    extensions: Vec<(String, u8)>,
+    /// Each raw event, with how long after the start of recording it arrived.
+    events: VecDeque<(Duration, Vec<u8>)>,
+}
+
+impl Trace {
+    fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            bail!("Not a trace, or made by an incompatible version");
+        }
+
+        let mut trace = Trace::default();
+        for (i, line) in lines {
+            let n = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(ext) = line.strip_prefix("ext ") {
+                let Some((name, first_event)) = ext.rsplit_once(' ') else {
+                    bail!("Line {n}: expected ext NAME FIRST_EVENT");
+                };
+                let first_event = first_event
+                    .parse()
+                    .with_context(|| format!("Line {n}: invalid event number {first_event}"))?;
+                trace.extensions.push((name.to_owned(), first_event));
+                continue;
+            }
+
+            let Some((time, hex)) = line.split_once(' ') else {
+                bail!("Line {n}: expected TIME EVENT");
+            };
+            let time = time
+                .parse()
+                .with_context(|| format!("Line {n}: invalid time {time}"))?;
+            let raw = parse_hex(hex).with_context(|| format!("Line {n}: invalid event"))?;
+            if raw.len() < 32 {
+                bail!("Line {n}: events are at least 32 bytes, got {}", raw.len());
+            }
+            trace.events.push_back((Duration::from_micros(time), raw));
+        }
+
+        Ok(trace)
+    }
+
+    /// Renumber extension events from the recording server's numbering to `current`,
+    /// dropping events from extensions that `current` doesn't have.
+    fn renumber(&mut self, current: &[(String, u8)]) {
+        let recorded = std::mem::replace(&mut self.extensions, current.to_vec());
+        let mut dropped = 0;
+        self.events.retain_mut(|(_, raw)| {
+            // The top bit marks events sent with SendEvent.
+            let code = raw[0] & 0x7f;
+            if code < FIRST_EXTENSION_EVENT {
+                return true;
+            }
+            let target = recorded
+                .iter()
+                .filter(|(_, first)| *first <= code)
+                .max_by_key(|(_, first)| *first)
+                .and_then(|(name, first)| {
+                    let (_, new_first) = current.iter().find(|(other, _)| other == name)?;
+                    (code - first).checked_add(*new_first)
+                });
+            match target {
+                Some(code) => {
+                    raw[0] = (raw[0] & 0x80) | code;
+                    true
+                }
+                None => {
+                    dropped += 1;
+                    false
+                }
+            }
+        });
+        if dropped > 0 {
+            warn!("Dropped {dropped} events from extensions this server doesn't support");
+        }
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        bail!("expected an even number of hex digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .with_context(|| format!("invalid hex byte {}", &hex[i..i + 2]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: u8) -> String {
+        format!("{code:02x}{}", "00".repeat(31))
+    }
+
+    #[test]
+    fn parses_traces() {
+        let text = format!(
+            "{HEADER}\next DAMAGE 91\next RANDR 89\n\n0 {}\n1500 {}\n",
+            event(16),
+            event(91)
+        );
+        let trace = Trace::parse(&text).unwrap();
+
+        assert_eq!(
+            trace.extensions,
+            [("DAMAGE".to_owned(), 91), ("RANDR".to_owned(), 89)]
+        );
+        let events: Vec<_> = trace
+            .events
+            .iter()
+            .map(|(time, raw)| (*time, raw[0], raw.len()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (Duration::ZERO, 16, 32),
+                (Duration::from_micros(1500), 91, 32)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_traces() {
+        assert!(Trace::parse("").is_err());
+        assert!(Trace::parse("recomp-trace 2\n").is_err());
+        assert!(Trace::parse(&format!("{HEADER}\nnonsense\n")).is_err());
+        assert!(Trace::parse(&format!("{HEADER}\n0 1000\n")).is_err());
+        assert!(Trace::parse(&format!("{HEADER}\n0 {}0\n", event(16))).is_err());
+        assert!(Trace::parse(&format!("{HEADER}\next DAMAGE lots\n")).is_err());
+    }
+
+    #[test]
+    fn renumbers_extension_events() {
+        let text = format!(
+            "{HEADER}\next RANDR 89\next DAMAGE 91\next SHAPE 64\n0 {}\n0 {}\n0 {}\n0 {}\n0 {}\n",
+            // A core event, damage, a RandR event and one sent with SendEvent, and shape.
+            event(16),
+            event(91),
+            event(90),
+            event(0x80 | 90),
+            event(64),
+        );
+        let mut trace = Trace::parse(&text).unwrap();
+        trace.renumber(&[("DAMAGE".to_owned(), 100), ("RANDR".to_owned(), 70)]);
+
+        let codes: Vec<_> = trace.events.iter().map(|(_, raw)| raw[0]).collect();
+        assert_eq!(codes, [16, 100, 71, 0x80 | 71]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Noticing when the user switches away from the virtual terminal the X server runs on,
+//! so that compositing can stop until they come back.
+//!
+//! The server keeps running meanwhile, but it has given up the display, and presenting
+//! to it in the meantime corrupts the surface or even crashes on some drivers. X has no
+//! event for this, so the kernel's idea of the active VT is polled from sysfs and
+//! compared with the one the server says it's on.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tracing::debug;
+use x11rb::protocol::xproto::{self, AtomEnum};
+use x11rb_async::protocol::xproto::ConnectionExt as _;
+
+use crate::connection::XConn;
+
+/// Where the kernel says which VT is active, as `ttyN`.
+const ACTIVE_VT: &str = "/sys/class/tty/tty0/active";
+
+/// How often to check which VT is active.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches whether the X server's VT is the active one.
+pub struct VtWatcher {
+    vt: u32,
+    active: bool,
+    last_check: Instant,
+}
+
+impl VtWatcher {
+    /// Start watching the VT the server on `conn` runs on, or `None` if it doesn't run on
+    /// one (e.g. Xvfb, Xephyr or a remote display) or the active VT can't be read.
+    pub async fn new(conn: &XConn, root: xproto::Window) -> Result<Option<Self>> {
+        let atom = conn
+            .intern_atom(true, b"XFree86_VT")
+            .await?
+            .reply()
+            .await?
+            .atom;
+        if atom == u32::from(AtomEnum::NONE) {
+            return Ok(None);
+        }
+        let reply = conn
+            .get_property(false, root, atom, AtomEnum::INTEGER, 0, 1)
+            .await?
+            .reply()
+            .await?;
+        let Some(vt) = reply.value32().and_then(|mut value| value.next()) else {
+            return Ok(None);
+        };
+        let Some(active) = active_vt(Path::new(ACTIVE_VT)) else {
+            debug!("Can't read the active VT from {ACTIVE_VT}, not watching for VT switches");
+            return Ok(None);
+        };
+
+        debug!("X server is on VT {vt}, VT {active} is active");
+        Ok(Some(Self {
+            vt,
+            active: active == vt,
+            last_check: Instant::now(),
+        }))
+    }
+
+    /// Check which VT is active, at most every [`POLL_INTERVAL`]. Returns whether the
+    /// server's VT is active if that changed since the last check.
+    pub fn poll(&mut self) -> Option<bool> {
+        if self.last_check.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let active = active_vt(Path::new(ACTIVE_VT))? == self.vt;
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(active)
+    }
+}
+
+/// The number of the active VT, as read from `path`.
+fn active_vt(path: &Path) -> Option<u32> {
+    parse_vt(&std::fs::read_to_string(path).ok()?)
+}
+
+/// Parse the contents of the sysfs file naming the active VT, e.g. `tty2`.
+fn parse_vt(contents: &str) -> Option<u32> {
+    contents.trim().strip_prefix("tty")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_active_vt() {
+        assert_eq!(parse_vt("tty2\n"), Some(2));
+        assert_eq!(parse_vt("tty12"), Some(12));
+        assert_eq!(parse_vt("ttyS0"), None);
+        assert_eq!(parse_vt(""), None);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info};
+
+/// Heartbeat value meaning the loop is deliberately waiting (e.g. idle until the next
+/// event), so not hearing from it isn't a stall.
+const PAUSED: u64 = u64::MAX;
+
+/// What to do when the event loop stalls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StallAction {
+    /// Log an error and keep waiting.
+    #[default]
+    Log,
+    /// Log an error and abort the process, so a supervisor can restart it.
+    Abort,
+}
+
+/// Options for detecting a stalled event loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// How long the loop can go without a heartbeat before it counts as stalled.
+    pub threshold: Duration,
+    pub action: StallAction,
+}
+
+/// Watches for heartbeats from the event loop on a separate thread, which keeps running
+/// even if the loop (or the whole async runtime) is stuck.
+pub struct Watchdog {
+    start: Instant,
+    /// Milliseconds since `start` at the last heartbeat, or [`PAUSED`].
+    heartbeat: Arc<AtomicU64>,
+    stalls: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn spawn(config: WatchdogConfig) -> Self {
+        let watchdog = Self {
+            start: Instant::now(),
+            heartbeat: Arc::new(AtomicU64::new(0)),
+            stalls: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+
+        let start = watchdog.start;
+        let heartbeat = Arc::clone(&watchdog.heartbeat);
+        let stalls = Arc::clone(&watchdog.stalls);
+        let stop = Arc::clone(&watchdog.stop);
+        let interval = (config.threshold / 4).max(Duration::from_millis(1));
+        thread::Builder::new()
+            .name(String::from("recomp-watchdog"))
+            .spawn(move || {
+                // Only report each stall once, rather than on every check while it lasts.
+                let mut stalled_since = None;
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+
+                    let last = heartbeat.load(Ordering::Acquire);
+                    let now = start.elapsed().as_millis() as u64;
+                    let silent = Duration::from_millis(now.saturating_sub(last));
+                    if last == PAUSED || silent < config.threshold {
+                        if stalled_since.take().is_some() {
+                            info!("Event loop recovered");
+                        }
+                        continue;
+                    }
+                    if stalled_since == Some(last) {
+                        continue;
+                    }
+
+                    stalled_since = Some(last);
+                    stalls.fetch_add(1, Ordering::Relaxed);
+                    error!("Event loop has been stalled for {:?}", silent);
+                    if config.action == StallAction::Abort {
+                        error!("Aborting so the compositor can be restarted");
+                        std::process::abort();
+                    }
+                }
+            })
+            .expect("failed to spawn watchdog thread");
+
+        watchdog
+    }
+
+    /// Record that the loop is still making progress.
+    pub fn beat(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.heartbeat.store(now, Ordering::Release);
+    }
+
+    /// Stop expecting heartbeats until the next [`Self::beat`], while the loop waits for
+    /// something to happen.
+    pub fn pause(&self) {
+        self.heartbeat.store(PAUSED, Ordering::Release);
+    }
+
+    /// How many stalls have been detected.
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog() -> Watchdog {
+        Watchdog::spawn(WatchdogConfig {
+            threshold: Duration::from_millis(40),
+            action: StallAction::Log,
+        })
+    }
+
+    #[test]
+    fn detects_stalls_once() {
+        let watchdog = watchdog();
+        watchdog.beat();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(watchdog.stalls(), 1);
+
+        // A new stall after recovering counts again.
+        watchdog.beat();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(watchdog.stalls(), 2);
+    }
+
+    #[test]
+    fn ignores_regular_heartbeats_and_pauses() {
+        let watchdog = watchdog();
+        for _ in 0..20 {
+            watchdog.beat();
+            thread::sleep(Duration::from_millis(5));
+        }
+        watchdog.pause();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(watchdog.stalls(), 0);
+    }
+}
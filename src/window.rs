@@ -0,0 +1,524 @@
+use std::{str::FromStr, time::Instant};
+
+use x11rb::protocol::{damage, xproto};
+
+use crate::texture::WindowTexture;
+
+/// The parts of `_NET_WM_STATE` we care about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WmState {
+    /// The window manager has hidden the window (e.g. minimized it) without unmapping it.
+    pub hidden: bool,
+    pub fullscreen: bool,
+}
+
+/// Set in the flags of `_MOTIF_WM_HINTS` when its decorations field is meaningful.
+const MWM_HINTS_DECORATIONS: u32 = 1 << 1;
+
+/// Whether a window's `_MOTIF_WM_HINTS` ask for decorations, or `None` if they don't say.
+///
+/// The property is five CARD32s: flags, functions, decorations, input mode and status.
+/// Any decoration bit, including `MWM_DECOR_ALL`, counts as asking for them.
+pub fn motif_decorations(hints: &[u32]) -> Option<bool> {
+    let (&flags, &decorations) = (hints.first()?, hints.get(2)?);
+    (flags & MWM_HINTS_DECORATIONS != 0).then_some(decorations != 0)
+}
+
+/// The screen edge a strut reserves space along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// Space along an edge of the screen that a panel or dock has reserved for itself with
+/// `_NET_WM_STRUT_PARTIAL` or `_NET_WM_STRUT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strut {
+    pub edge: Edge,
+    /// The reserved area, relative to the root.
+    pub area: xproto::Rectangle,
+}
+
+impl Strut {
+    /// Parse a strut property on a screen of size `screen`.
+    ///
+    /// Both properties start with the reserved width at the left, right, top and bottom.
+    /// The partial one follows that with the start and end of each along its edge, while
+    /// the plain one always covers the whole edge.
+    pub fn parse(value: &[u32], screen: (u16, u16)) -> Vec<Strut> {
+        let Some(widths) = value.get(..4) else {
+            return Vec::new();
+        };
+        let (screen_w, screen_h) = (screen.0 as u32, screen.1 as u32);
+        let range = |i: usize, full: u32| match value.get(4 + 2 * i..6 + 2 * i) {
+            Some(&[start, end]) if start <= end => (start, end - start + 1),
+            _ => (0, full),
+        };
+
+        let mut struts = Vec::new();
+        for (i, (&width, edge)) in widths
+            .iter()
+            .zip([Edge::Left, Edge::Right, Edge::Top, Edge::Bottom])
+            .enumerate()
+        {
+            if width == 0 {
+                continue;
+            }
+            let clamp = |value: u32| value.min(u16::MAX as u32);
+            let (x, y, w, h) = match edge {
+                Edge::Left | Edge::Right => {
+                    let (start, len) = range(i, screen_h);
+                    let width = width.min(screen_w);
+                    let x = if edge == Edge::Left {
+                        0
+                    } else {
+                        screen_w - width
+                    };
+                    (x, start, width, len)
+                }
+                Edge::Top | Edge::Bottom => {
+                    let (start, len) = range(i, screen_w);
+                    let height = width.min(screen_h);
+                    let y = if edge == Edge::Top {
+                        0
+                    } else {
+                        screen_h - height
+                    };
+                    (start, y, len, height)
+                }
+            };
+            struts.push(Strut {
+                edge,
+                area: xproto::Rectangle {
+                    x: clamp(x) as i16,
+                    y: clamp(y) as i16,
+                    width: clamp(w) as u16,
+                    height: clamp(h) as u16,
+                },
+            });
+        }
+        struts
+    }
+}
+
+/// How the color channels of a window with an alpha channel relate to its alpha.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Color is already multiplied by alpha, which is what X Render expects of ARGB
+    /// visuals and what almost every client does.
+    #[default]
+    Premultiplied,
+    /// Color is independent of alpha, and has to be multiplied by it before blending.
+    Straight,
+}
+
+/// How a window's colors combine with what's below it.
+///
+/// Anything other than `Normal` only makes sense over an opaque backdrop, which the
+/// screen always is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Alpha compositing, drawing the window over what's below it.
+    #[default]
+    Normal,
+    /// Darken what's below by the window's colors, so white leaves it as it was.
+    Multiply,
+    /// Lighten what's below by the window's colors, so black leaves it as it was.
+    Screen,
+    /// Add the window's colors to what's below.
+    Add,
+}
+
+impl FromStr for BlendMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "normal" => BlendMode::Normal,
+            "multiply" => BlendMode::Multiply,
+            "screen" => BlendMode::Screen,
+            "add" => BlendMode::Add,
+            _ => anyhow::bail!("unknown blend mode {s}, expected normal, multiply, screen, or add"),
+        })
+    }
+}
+
+/// Which edges of the screen a window is flush against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScreenEdges {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl ScreenEdges {
+    /// The edges of a screen of size `screen` that `rect` touches or extends past.
+    pub fn touched_by(rect: xproto::Rectangle, screen: (u16, u16)) -> Self {
+        let (x, y) = (rect.x as i32, rect.y as i32);
+        Self {
+            left: x <= 0,
+            right: x + rect.width as i32 >= screen.0 as i32,
+            top: y <= 0,
+            bottom: y + rect.height as i32 >= screen.1 as i32,
+        }
+    }
+
+    /// Whether the window covers the whole screen, as a maximized or fullscreen one does.
+    pub fn all(&self) -> bool {
+        self.left && self.right && self.top && self.bottom
+    }
+
+    pub fn any(&self) -> bool {
+        self.left || self.right || self.top || self.bottom
+    }
+}
+
+/// A snapshot of a tracked window, for debugging and embedders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowInfo {
+    pub id: xproto::Window,
+    /// Position and size relative to the root, excluding the border.
+    pub geometry: xproto::Rectangle,
+    pub border_width: u16,
+    pub mapped: bool,
+    /// The opacity the window is currently drawn with, from 0 to 1.
+    pub opacity: f32,
+    /// Position in the stack, with 0 at the bottom.
+    pub stacking_index: usize,
+    pub wm_state: WmState,
+    /// Whether the client asked for decorations with `_MOTIF_WM_HINTS`, if it said.
+    pub decorations: Option<bool>,
+    /// The corner radius the client asked for with `_RECOMP_CORNER_RADIUS`, if it did.
+    pub corner_radius: Option<u32>,
+}
+
+/// Everything the compositor tracks about a single redirected window.
+pub struct WindowState {
+    /// Position and size of the window relative to the root.
+    ///
+    /// As in X, the position is of the outer corner of the border, while the size
+    /// excludes it.
+    pub geometry: xproto::Rectangle,
+    pub border_width: u16,
+    pub mapped: bool,
+    /// Whether the window uses an ARGB visual, meaning its contents have a meaningful
+    /// alpha channel.
+    pub has_alpha: bool,
+    /// How to interpret the alpha channel, if the window has one.
+    pub alpha_mode: AlphaMode,
+    /// The damage object reporting changes to this window's contents.
+    pub damage: damage::Damage,
+    /// The window's current backing pixmap, named via `composite_name_window_pixmap`.
+    ///
+    /// This is only valid while the window is mapped, and must be re-named whenever
+    /// the window is resized.
+    pub pixmap: Option<xproto::Pixmap>,
+    /// The window contents on the GPU. Kept across frames so damage can be applied
+    /// incrementally.
+    pub texture: Option<WindowTexture>,
+    /// The most recent visibility reported by the server.
+    pub visibility: xproto::Visibility,
+    /// Set when damage was skipped because the window was fully obscured, so the
+    /// texture needs a full upload once it becomes visible again.
+    pub stale: bool,
+    /// Set when the texture holds a placeholder instead of the window's contents, which
+    /// haven't been damaged since it was mapped.
+    pub placeholder: bool,
+    /// Set when the texture was uploaded to since the last frame. Windows without it are
+    /// drawn from the texture as it was, without touching the X server.
+    pub dirty: bool,
+    /// When the texture was last uploaded to, for evicting the least recently used
+    /// textures first.
+    pub last_upload: Instant,
+    /// Set when the texture was dropped to stay under the texture limit, to be made
+    /// again once the window is drawn.
+    pub evicted: bool,
+    /// Whether the window matches one of the configured exclusions, and has been
+    /// unredirected so that the server draws it directly.
+    pub excluded: bool,
+    /// The client window inside this one, if the window manager has reparented it into a
+    /// frame. This is where properties like `_NET_WM_STATE` are set.
+    pub client: Option<xproto::Window>,
+    pub wm_state: WmState,
+    /// Whether the client asked for decorations with `_MOTIF_WM_HINTS`, if it said.
+    /// Clients that draw their own, or want none at all, ask for none.
+    pub decorations: Option<bool>,
+    /// Space reserved by the window, if it's a panel or dock.
+    pub struts: Vec<Strut>,
+    /// The corner radius the client asked for with `_RECOMP_CORNER_RADIUS`, if it did.
+    pub corner_radius: Option<u32>,
+    /// The blend mode of the first [`Config::blend_modes`] rule the window matches.
+    ///
+    /// [`Config::blend_modes`]: crate::config::Config::blend_modes
+    pub matched_blend_mode: BlendMode,
+    /// The blend mode the client asked for with `_RECOMP_BLEND_MODE`, if it did.
+    pub requested_blend_mode: Option<BlendMode>,
+    /// The name of the monitor showing most of the window, if any of it is on screen.
+    pub monitor: Option<String>,
+    /// When the window's geometry last changed, for telling interactive moves apart from
+    /// one-off ones.
+    pub last_configure: Option<Instant>,
+}
+
+impl WindowState {
+    pub fn new(geometry: xproto::Rectangle, border_width: u16, damage: damage::Damage) -> Self {
+        Self {
+            geometry,
+            border_width,
+            mapped: false,
+            has_alpha: false,
+            alpha_mode: AlphaMode::default(),
+            damage,
+            pixmap: None,
+            texture: None,
+            visibility: xproto::Visibility::UNOBSCURED,
+            stale: false,
+            placeholder: false,
+            dirty: false,
+            last_upload: Instant::now(),
+            evicted: false,
+            excluded: false,
+            client: None,
+            wm_state: WmState::default(),
+            decorations: None,
+            struts: Vec::new(),
+            corner_radius: None,
+            matched_blend_mode: BlendMode::default(),
+            requested_blend_mode: None,
+            monitor: None,
+            last_configure: None,
+        }
+    }
+
+    /// The area covered by the window including its border, relative to the root.
+    ///
+    /// This is also the extent of the window's named pixmap.
+    pub fn extent(&self) -> xproto::Rectangle {
+        xproto::Rectangle {
+            x: self.geometry.x,
+            y: self.geometry.y,
+            width: self.geometry.width + 2 * self.border_width,
+            height: self.geometry.height + 2 * self.border_width,
+        }
+    }
+
+    /// Whether to dress the window up with rounded corners and the active border.
+    /// Fullscreen windows, and ones that asked for no decorations, are drawn as they are.
+    pub fn wants_chrome(&self) -> bool {
+        !self.wm_state.fullscreen && self.decorations != Some(false)
+    }
+
+    /// The radius to round the window's corners with, given the configured `default`.
+    ///
+    /// A radius the client asked for wins over the default, even if it asked for no
+    /// decorations, since clients drawing their own are the ones likely to ask. It's
+    /// limited to half the window's shorter side, where the corners meet.
+    pub fn corner_radius(&self, default: f32) -> f32 {
+        if self.wm_state.fullscreen {
+            return 0.0;
+        }
+        let extent = self.extent();
+        let max = extent.width.min(extent.height) as f32 / 2.0;
+        match self.corner_radius {
+            Some(radius) => (radius as f32).min(max),
+            None if self.wants_chrome() => default,
+            None => 0.0,
+        }
+    }
+
+    /// How to blend the window with what's below it. What the client asked for wins over
+    /// the configured rules.
+    pub fn blend_mode(&self) -> BlendMode {
+        self.requested_blend_mode.unwrap_or(self.matched_blend_mode)
+    }
+
+    /// The edges of a screen of size `screen` that the window, including its border, is
+    /// flush against.
+    pub fn screen_edges(&self, screen: (u16, u16)) -> ScreenEdges {
+        ScreenEdges::touched_by(self.extent(), screen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extent_includes_border() {
+        let geometry = xproto::Rectangle {
+            x: 10,
+            y: 20,
+            width: 100,
+            height: 50,
+        };
+        let state = WindowState::new(geometry, 2, 0);
+        assert_eq!(
+            state.extent(),
+            xproto::Rectangle {
+                x: 10,
+                y: 20,
+                width: 104,
+                height: 54,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_motif_decorations() {
+        // No decorations, as set by e.g. borderless games and splash screens.
+        assert_eq!(motif_decorations(&[2, 0, 0, 0, 0]), Some(false));
+        assert_eq!(motif_decorations(&[2, 0, 1, 0, 0]), Some(true));
+        // Only functions are set, so decorations are left to the window manager.
+        assert_eq!(motif_decorations(&[1, 4, 0, 0, 0]), None);
+        assert_eq!(motif_decorations(&[2]), None);
+        assert_eq!(motif_decorations(&[]), None);
+    }
+
+    #[test]
+    fn parses_struts() {
+        let screen = (1920, 1080);
+        // A 30px panel along the top, as _NET_WM_STRUT.
+        assert_eq!(
+            Strut::parse(&[0, 0, 30, 0], screen),
+            [Strut {
+                edge: Edge::Top,
+                area: xproto::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: 1920,
+                    height: 30,
+                },
+            }]
+        );
+
+        // A dock on the right monitor's bottom edge, and a sidebar only part of the way
+        // down the left.
+        let partial = [40, 0, 0, 48, 0, 599, 0, 0, 0, 0, 960, 1919];
+        assert_eq!(
+            Strut::parse(&partial, screen),
+            [
+                Strut {
+                    edge: Edge::Left,
+                    area: xproto::Rectangle {
+                        x: 0,
+                        y: 0,
+                        width: 40,
+                        height: 600,
+                    },
+                },
+                Strut {
+                    edge: Edge::Bottom,
+                    area: xproto::Rectangle {
+                        x: 960,
+                        y: 1032,
+                        width: 960,
+                        height: 48,
+                    },
+                },
+            ]
+        );
+
+        assert!(Strut::parse(&[0, 0, 0, 0], screen).is_empty());
+        assert!(Strut::parse(&[30], screen).is_empty());
+    }
+
+    #[test]
+    fn undecorated_and_fullscreen_windows_get_no_chrome() {
+        let mut state = WindowState::new(xproto::Rectangle::default(), 0, 0);
+        assert!(state.wants_chrome());
+        state.decorations = Some(false);
+        assert!(!state.wants_chrome());
+        state.decorations = Some(true);
+        state.wm_state.fullscreen = true;
+        assert!(!state.wants_chrome());
+    }
+
+    #[test]
+    fn clients_pick_their_own_corner_radius() {
+        let geometry = xproto::Rectangle {
+            x: 0,
+            y: 0,
+            width: 200,
+            height: 40,
+        };
+        let mut state = WindowState::new(geometry, 0, 0);
+        assert_eq!(state.corner_radius(8.0), 8.0);
+        state.decorations = Some(false);
+        assert_eq!(state.corner_radius(8.0), 0.0);
+
+        state.corner_radius = Some(12);
+        assert_eq!(state.corner_radius(8.0), 12.0);
+        // No more than makes the short sides a semicircle.
+        state.corner_radius = Some(1000);
+        assert_eq!(state.corner_radius(8.0), 20.0);
+        state.wm_state.fullscreen = true;
+        assert_eq!(state.corner_radius(8.0), 0.0);
+    }
+
+    #[test]
+    fn clients_pick_their_own_blend_mode() {
+        assert_eq!(
+            "multiply".parse::<BlendMode>().unwrap(),
+            BlendMode::Multiply
+        );
+        assert_eq!("add".parse::<BlendMode>().unwrap(), BlendMode::Add);
+        assert!("overlay".parse::<BlendMode>().is_err());
+
+        let mut state = WindowState::new(xproto::Rectangle::default(), 0, 0);
+        assert_eq!(state.blend_mode(), BlendMode::Normal);
+        state.matched_blend_mode = BlendMode::Screen;
+        assert_eq!(state.blend_mode(), BlendMode::Screen);
+        state.requested_blend_mode = Some(BlendMode::Normal);
+        assert_eq!(state.blend_mode(), BlendMode::Normal);
+    }
+
+    #[test]
+    fn detects_screen_edges() {
+        let rect = |x, y, width, height| xproto::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+        let screen = (1920, 1080);
+
+        let floating = ScreenEdges::touched_by(rect(100, 100, 800, 600), screen);
+        assert_eq!(floating, ScreenEdges::default());
+        assert!(!floating.any());
+
+        let maximized = ScreenEdges::touched_by(rect(0, 0, 1920, 1080), screen);
+        assert!(maximized.all());
+
+        // Tiled into the left half, below a 30px panel.
+        let tiled = ScreenEdges::touched_by(rect(0, 30, 960, 1050), screen);
+        assert_eq!(
+            tiled,
+            ScreenEdges {
+                left: true,
+                right: false,
+                top: false,
+                bottom: true,
+            }
+        );
+
+        // Partly off screen counts as touching.
+        let hanging = ScreenEdges::touched_by(rect(1800, -20, 400, 200), screen);
+        assert!(hanging.right && hanging.top && !hanging.left && !hanging.bottom);
+    }
+
+    #[test]
+    fn screen_edges_include_border() {
+        let geometry = xproto::Rectangle {
+            x: 0,
+            y: 0,
+            width: 1916,
+            height: 1076,
+        };
+        let state = WindowState::new(geometry, 2, 0);
+        assert!(state.screen_edges((1920, 1080)).all());
+    }
+}
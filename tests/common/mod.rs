@@ -0,0 +1,148 @@
+//! Shared helpers for integration tests that need a real X server.
+//!
+//! Those tests are marked `#[ignore = "needs Xvfb"]`, so that machines without it report
+//! them as ignored instead of passing without doing anything. Run them with
+//! `cargo test -- --include-ignored` where `Xvfb` and a GPU adapter (Mesa's llvmpipe will
+//! do) are available; they fail rather than skip if either is missing.
+
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+pub mod reference;
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use recomp::{compositor::Compositor, config::Config, error::Error, events::EventSource};
+use x11rb::protocol::Event;
+
+/// How long to wait for the X server to start accepting connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A headless X server running for the duration of a test.
+///
+/// The server is killed when this is dropped.
+pub struct Xvfb {
+    child: Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// Start `Xvfb` on an unused display number.
+    ///
+    /// Panics if it isn't installed or fails to start.
+    pub fn spawn() -> Self {
+        Self::try_spawn().expect("failed to start Xvfb; is it installed?")
+    }
+
+    /// Start `Xvfb` on an unused display number, or return `None` if it isn't installed
+    /// or fails to start, for benchmarks that are skipped without it.
+    pub fn try_spawn() -> Option<Self> {
+        let number = (90..200).find(|n| {
+            !Path::new(&format!("/tmp/.X11-unix/X{n}")).exists()
+                && !Path::new(&format!("/tmp/.X{n}-lock")).exists()
+        })?;
+        let display = format!(":{number}");
+
+        let child = Command::new("Xvfb")
+            .args([&display, "-screen", "0", "1280x720x24", "-nolisten", "tcp"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let mut server = Self { child, display };
+
+        let socket = format!("/tmp/.X11-unix/X{number}");
+        let start = Instant::now();
+        while !Path::new(&socket).exists() {
+            if start.elapsed() > STARTUP_TIMEOUT || server.child.try_wait().ok()?.is_some() {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Some(server)
+    }
+
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+
+    /// Open a separate client connection to the server, for inspecting state from
+    /// outside the compositor.
+    pub fn connect(&self) -> x11rb::rust_connection::RustConnection {
+        x11rb::connect(Some(&self.display))
+            .expect("failed to connect to Xvfb")
+            .0
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Start a compositor on the given server.
+pub async fn compositor(server: &Xvfb) -> Compositor<'static> {
+    compositor_with(server, Config::default()).await
+}
+
+/// Start a compositor on the given server with non-default options.
+pub async fn compositor_with(server: &Xvfb, options: Config) -> Compositor<'static> {
+    try_compositor(server, options)
+        .await
+        .expect("no GPU adapter available")
+}
+
+/// Start a compositor on the given server, or return `None` if there's no GPU adapter to
+/// run it on, for benchmarks that are skipped without one.
+pub async fn try_compositor(server: &Xvfb, options: Config) -> Option<Compositor<'static>> {
+    match Compositor::new(Some(server.display()), options).await {
+        Ok(compositor) => Some(compositor),
+        Err(e) if matches!(e.downcast_ref(), Some(Error::NoAdapter)) => None,
+        Err(e) => panic!("failed to create compositor: {e:?}"),
+    }
+}
+
+/// Print a message explaining why a test is being skipped.
+pub fn skip(reason: &str) {
+    eprintln!("skipping: {reason}");
+}
+
+/// An event source that replays a fixed list of events, then closes.
+#[derive(Default)]
+pub struct ScriptedEvents {
+    events: VecDeque<Event>,
+}
+
+impl ScriptedEvents {
+    pub fn new(events: impl IntoIterator<Item = Event>) -> Self {
+        Self {
+            events: events.into_iter().collect(),
+        }
+    }
+}
+
+impl EventSource for ScriptedEvents {
+    fn poll_event(&mut self) -> anyhow::Result<Option<Event>> {
+        Ok(self.events.pop_front())
+    }
+
+    async fn wait_event(&mut self) -> anyhow::Result<Event> {
+        self.events
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("no more scripted events"))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.events.is_empty()
+    }
+}
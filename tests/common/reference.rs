@@ -0,0 +1,137 @@
+//! A CPU reference for the window compositing done in `window.wgsl`.
+//!
+//! This is deliberately simple: windows are drawn at integer positions without scaling
+//! or rounded corners, so every output pixel maps onto exactly one texel and the result
+//! can be compared against the GPU within a small tolerance.
+
+use recomp::window::BlendMode;
+use x11rb::protocol::xproto::Rectangle;
+
+/// A window to composite, described the same way the compositor sees it.
+#[derive(Debug, Clone)]
+pub struct RefWindow {
+    pub rect: Rectangle,
+    /// Tightly packed BGRA pixels, as X hands them to us. ARGB windows are premultiplied
+    /// unless `straight_alpha` is set.
+    pub pixels: Vec<u8>,
+    pub opacity: f32,
+    pub dim: f32,
+    pub has_alpha: bool,
+    pub straight_alpha: bool,
+    pub blend_mode: BlendMode,
+}
+
+impl RefWindow {
+    /// An opaque window filled with one sRGB color.
+    pub fn solid(rect: Rectangle, rgb: [u8; 3]) -> Self {
+        let texel = [rgb[2], rgb[1], rgb[0], 0xff];
+        Self {
+            rect,
+            pixels: texel.repeat(rect.width as usize * rect.height as usize),
+            opacity: 1.0,
+            dim: 0.0,
+            has_alpha: false,
+            straight_alpha: false,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+/// A composited frame stored as linear, premultiplied RGBA.
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl Frame {
+    /// Encode the frame the way an `Rgba8UnormSrgb` render target stores it.
+    pub fn to_srgb8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&[r, g, b, a]| {
+                [
+                    encode_srgb(r),
+                    encode_srgb(g),
+                    encode_srgb(b),
+                    (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Composite `windows`, bottom-most first, over a frame cleared to `clear` (linear RGBA).
+pub fn composite(width: u16, height: u16, clear: [f32; 4], windows: &[RefWindow]) -> Frame {
+    let mut pixels = vec![clear; width as usize * height as usize];
+
+    for window in windows {
+        let rect = window.rect;
+        for row in 0..rect.height as i32 {
+            let y = rect.y as i32 + row;
+            if !(0..height as i32).contains(&y) {
+                continue;
+            }
+            for col in 0..rect.width as i32 {
+                let x = rect.x as i32 + col;
+                if !(0..width as i32).contains(&x) {
+                    continue;
+                }
+
+                let texel = (row as usize * rect.width as usize + col as usize) * 4;
+                let [b, g, r, a] = window.pixels[texel..texel + 4] else {
+                    unreachable!()
+                };
+
+                // Matches the sampling of a `Bgra8UnormSrgb` texture followed by
+                // `fs_main`.
+                let alpha = if window.has_alpha {
+                    a as f32 / 255.0
+                } else {
+                    1.0
+                };
+                let shade = 1.0 - window.dim;
+                let premultiply = if window.straight_alpha { alpha } else { 1.0 };
+                let color = |c| decode_srgb(c) * premultiply * shade * window.opacity;
+                let src = [color(r), color(g), color(b), alpha * window.opacity];
+
+                let dst = &mut pixels[y as usize * width as usize + x as usize];
+                for (i, (d, s)) in dst.iter_mut().zip(src).enumerate() {
+                    *d = match window.blend_mode {
+                        // Premultiplied "over", which is also how alpha always adds up.
+                        _ if i == 3 => s + *d * (1.0 - src[3]),
+                        BlendMode::Normal => s + *d * (1.0 - src[3]),
+                        BlendMode::Multiply => s * *d + *d * (1.0 - src[3]),
+                        BlendMode::Screen => s + *d - s * *d,
+                        BlendMode::Add => (s + *d).min(1.0),
+                    };
+                }
+            }
+        }
+    }
+
+    Frame {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn decode_srgb(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn encode_srgb(value: f32) -> u8 {
+    let c = value.clamp(0.0, 1.0);
+    let encoded = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
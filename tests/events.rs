@@ -0,0 +1,529 @@
+mod common;
+
+use std::{future::poll_fn, ops::ControlFlow, pin::Pin, time::Duration};
+
+use common::{ScriptedEvents, Xvfb};
+use futures_core::Stream;
+use recomp::{
+    events::EventSource,
+    notify::{CompositorEvent, EventStream},
+};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        damage,
+        xproto::{self, ConnectionExt, CreateWindowAux, WindowClass},
+        Event,
+    },
+    wrapper::ConnectionExt as _,
+    COPY_DEPTH_FROM_PARENT,
+};
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn tracks_windows_from_scripted_events() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    // The handlers still talk to the server, so the scripted events need to refer to a
+    // real window.
+    let client = server.connect();
+    let root = compositor.root_window();
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            win,
+            root,
+            10,
+            20,
+            100,
+            50,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+    client.map_window(win).unwrap();
+    client.sync().unwrap();
+
+    let mut events = ScriptedEvents::new([Event::CreateNotify(xproto::CreateNotifyEvent {
+        response_type: xproto::CREATE_NOTIFY_EVENT,
+        sequence: 0,
+        parent: root,
+        window: win,
+        x: 10,
+        y: 20,
+        width: 100,
+        height: 50,
+        border_width: 0,
+        override_redirect: false,
+    })]);
+    compositor.run_with(&mut events).await.unwrap();
+
+    let state = compositor
+        .window(win)
+        .expect("created window should be tracked");
+    assert!(!state.mapped);
+
+    let mut events = ScriptedEvents::new([Event::MapNotify(xproto::MapNotifyEvent {
+        response_type: xproto::MAP_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        window: win,
+        override_redirect: false,
+    })]);
+    compositor.run_with(&mut events).await.unwrap();
+
+    let state = compositor.window(win).expect("window should be tracked");
+    assert!(state.mapped);
+    assert_eq!((state.geometry.width, state.geometry.height), (100, 50));
+    assert_eq!(compositor.stacking_order(), [win]);
+
+    let info = compositor.windows();
+    assert_eq!(info.len(), 1);
+    assert_eq!(info[0].id, win);
+    assert!(info[0].mapped);
+    assert_eq!(info[0].stacking_index, 0);
+    assert_eq!(info[0].opacity, 1.0);
+
+    let resources = compositor.resource_stats();
+    assert_eq!(resources.window_textures, 1);
+    assert_eq!(resources.window_texture_bytes, 100 * 50 * 4);
+
+    let damage = state.damage;
+    let mut events = ScriptedEvents::new([
+        Event::DamageNotify(damage::NotifyEvent {
+            response_type: client
+                .extension_information(damage::X11_EXTENSION_NAME)
+                .unwrap()
+                .unwrap()
+                .first_event,
+            level: damage::ReportLevel::NON_EMPTY,
+            sequence: 0,
+            drawable: win,
+            damage,
+            timestamp: 0,
+            area: xproto::Rectangle {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 50,
+            },
+            geometry: xproto::Rectangle {
+                x: 10,
+                y: 20,
+                width: 100,
+                height: 50,
+            },
+        }),
+        Event::UnmapNotify(xproto::UnmapNotifyEvent {
+            response_type: xproto::UNMAP_NOTIFY_EVENT,
+            sequence: 0,
+            event: root,
+            window: win,
+            from_configure: false,
+        }),
+    ]);
+    compositor.run_with(&mut events).await.unwrap();
+
+    let state = compositor
+        .window(win)
+        .expect("window should still be tracked");
+    assert!(!state.mapped);
+    assert!(state.pixmap.is_none());
+
+    client.destroy_window(win).unwrap();
+    client.sync().unwrap();
+
+    let mut events = ScriptedEvents::new([Event::DestroyNotify(xproto::DestroyNotifyEvent {
+        response_type: xproto::DESTROY_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        window: win,
+    })]);
+    compositor.run_with(&mut events).await.unwrap();
+
+    assert!(compositor.window(win).is_none());
+    assert!(compositor.stacking_order().is_empty());
+    assert!(compositor.windows().is_empty());
+    assert_eq!(compositor.resource_stats().window_textures, 0);
+}
+
+async fn next_event(stream: &mut EventStream) -> Option<CompositorEvent> {
+    poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn streams_compositor_events() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+    let mut stream = compositor.events();
+
+    let client = server.connect();
+    let root = compositor.root_window();
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            win,
+            root,
+            10,
+            20,
+            100,
+            50,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+    client.map_window(win).unwrap();
+    client.sync().unwrap();
+
+    let mut events = ScriptedEvents::new([
+        Event::MapNotify(xproto::MapNotifyEvent {
+            response_type: xproto::MAP_NOTIFY_EVENT,
+            sequence: 0,
+            event: root,
+            window: win,
+            override_redirect: false,
+        }),
+        // Moved off every monitor.
+        Event::ConfigureNotify(xproto::ConfigureNotifyEvent {
+            response_type: xproto::CONFIGURE_NOTIFY_EVENT,
+            sequence: 0,
+            event: root,
+            window: win,
+            above_sibling: x11rb::NONE,
+            x: 10_000,
+            y: 20,
+            width: 100,
+            height: 50,
+            border_width: 0,
+            override_redirect: false,
+        }),
+        Event::UnmapNotify(xproto::UnmapNotifyEvent {
+            response_type: xproto::UNMAP_NOTIFY_EVENT,
+            sequence: 0,
+            event: root,
+            window: win,
+            from_configure: false,
+        }),
+    ]);
+    compositor.run_with(&mut events).await.unwrap();
+
+    let monitor = compositor.monitors()[0].name.clone();
+    assert_eq!(
+        next_event(&mut stream).await,
+        Some(CompositorEvent::WindowMapped {
+            window: win,
+            geometry: xproto::Rectangle {
+                x: 10,
+                y: 20,
+                width: 100,
+                height: 50,
+            },
+        })
+    );
+    assert_eq!(
+        next_event(&mut stream).await,
+        Some(CompositorEvent::WindowMonitorChanged {
+            window: win,
+            monitor: Some(monitor),
+        })
+    );
+    assert!(matches!(
+        next_event(&mut stream).await,
+        Some(CompositorEvent::WindowConfigured { window, .. }) if window == win
+    ));
+    assert_eq!(
+        next_event(&mut stream).await,
+        Some(CompositorEvent::WindowMonitorChanged {
+            window: win,
+            monitor: None,
+        })
+    );
+    assert!(compositor.window_monitor(win).is_none());
+    assert_eq!(
+        next_event(&mut stream).await,
+        Some(CompositorEvent::WindowUnmapped { window: win })
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn steps_until_events_close() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let root = compositor.root_window();
+    let mut events = ScriptedEvents::new([Event::MapNotify(xproto::MapNotifyEvent {
+        response_type: xproto::MAP_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        // Not a window we track, so this only wakes the loop up.
+        window: root,
+        override_redirect: false,
+    })]);
+    let frames = compositor.frame_count();
+    assert_eq!(
+        compositor.step_with(&mut events).await.unwrap(),
+        ControlFlow::Break(())
+    );
+    assert_eq!(compositor.frame_count(), frames);
+
+    // With events left over, a step handles them and renders a frame.
+    let client = server.connect();
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            win,
+            root,
+            0,
+            0,
+            64,
+            64,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+    client.sync().unwrap();
+    assert_eq!(compositor.step().await.unwrap(), ControlFlow::Continue(()));
+    assert!(compositor.window(win).is_some());
+    assert_eq!(compositor.frame_count(), frames + 1);
+}
+
+#[tokio::test]
+async fn waits_for_events_with_a_timeout() {
+    let mut events = ScriptedEvents::new([Event::Unknown(vec![0; 32])]);
+    let timeout = Duration::from_millis(10);
+    assert!(events.wait_event_timeout(timeout).await.unwrap().is_some());
+    assert!(events.wait_event_timeout(timeout).await.unwrap().is_none());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn replays_recorded_traces() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let root = compositor.root_window();
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            win,
+            root,
+            10,
+            20,
+            100,
+            50,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+    client.map_window(win).unwrap();
+    client.sync().unwrap();
+
+    let create = <[u8; 32]>::from(&xproto::CreateNotifyEvent {
+        response_type: xproto::CREATE_NOTIFY_EVENT,
+        sequence: 0,
+        parent: root,
+        window: win,
+        x: 10,
+        y: 20,
+        width: 100,
+        height: 50,
+        border_width: 0,
+        override_redirect: false,
+    });
+    let map = <[u8; 32]>::from(&xproto::MapNotifyEvent {
+        response_type: xproto::MAP_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        window: win,
+        override_redirect: false,
+    });
+    let hex = |raw: [u8; 32]| raw.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    let path = std::env::temp_dir().join(format!("recomp-trace-{}", std::process::id()));
+    std::fs::write(
+        &path,
+        format!("recomp-trace 1\n0 {}\n20000 {}\n", hex(create), hex(map)),
+    )
+    .unwrap();
+
+    let replayed = compositor.replay(&path).await;
+    std::fs::remove_file(&path).ok();
+    replayed.unwrap();
+
+    let state = compositor
+        .window(win)
+        .expect("replayed window should be tracked");
+    assert!(state.mapped);
+    assert_eq!(compositor.stacking_order(), [win]);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn defers_events_past_the_budget() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        max_events_per_frame: Some(2),
+        ..Default::default()
+    };
+    let mut compositor = common::compositor_with(&server, options).await;
+
+    let root = compositor.root_window();
+    let wake_up = Event::MapNotify(xproto::MapNotifyEvent {
+        response_type: xproto::MAP_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        window: root,
+        override_redirect: false,
+    });
+    let mut events = ScriptedEvents::new([wake_up.clone(), wake_up.clone(), wake_up]);
+    let frames = compositor.frame_count();
+    // Two events, then a frame before the third.
+    assert_eq!(
+        compositor.step_with(&mut events).await.unwrap(),
+        ControlFlow::Continue(())
+    );
+    assert_eq!(compositor.frame_count(), frames + 1);
+    assert_eq!(
+        compositor.step_with(&mut events).await.unwrap(),
+        ControlFlow::Break(())
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn holds_expose_while_the_key_is_down() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        key_bindings: vec![recomp::keys::KeyBinding {
+            combo: "Mod4+F5".parse().unwrap(),
+            command: recomp::command::Command::HoldExpose,
+        }],
+        ..Default::default()
+    };
+    let mut compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let (min, max) = (client.setup().min_keycode, client.setup().max_keycode);
+    let mapping = client
+        .get_keyboard_mapping(min, max - min + 1)
+        .unwrap()
+        .reply()
+        .unwrap();
+    let f5 = mapping
+        .keysyms
+        .chunks(mapping.keysyms_per_keycode as usize)
+        .position(|keysyms| keysyms.contains(&0xffc2))
+        .expect("no key produces F5") as u8
+        + min;
+
+    let root = compositor.root_window();
+    let key = |response_type, state| xproto::KeyPressEvent {
+        response_type,
+        detail: f5,
+        root,
+        state,
+        ..Default::default()
+    };
+    let press = Event::KeyPress(key(xproto::KEY_PRESS_EVENT, xproto::KeyButMask::MOD4));
+    // Holding the key repeats the press, which mustn't toggle it back off.
+    let mut events = ScriptedEvents::new([press.clone(), press]);
+    compositor.process_events_from(&mut events).await.unwrap();
+    assert!(compositor.expose_active());
+
+    // The modifier may well be let go of first.
+    let release = key(xproto::KEY_RELEASE_EVENT, xproto::KeyButMask::default());
+    let mut events = ScriptedEvents::new([Event::KeyRelease(release)]);
+    compositor.process_events_from(&mut events).await.unwrap();
+    assert!(!compositor.expose_active());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn finds_the_client_while_other_children_are_destroyed() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let root = compositor.root_window();
+    let create = |parent| {
+        let win = client.generate_id().unwrap();
+        client
+            .create_window(
+                COPY_DEPTH_FROM_PARENT,
+                win,
+                parent,
+                0,
+                0,
+                100,
+                50,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+        client.map_window(win).unwrap();
+        win
+    };
+    let frame = create(root);
+    // Lots of other children before the client, so the search is still going through
+    // them while they're destroyed.
+    let decoys: Vec<_> = (0..200).map(|_| create(frame)).collect();
+    let wm_client = create(frame);
+    let wm_state = client
+        .intern_atom(false, b"WM_STATE")
+        .unwrap()
+        .reply()
+        .unwrap()
+        .atom;
+    client
+        .change_property32(
+            xproto::PropMode::REPLACE,
+            wm_client,
+            wm_state,
+            wm_state,
+            &[1, 0],
+        )
+        .unwrap();
+    client.sync().unwrap();
+
+    // Destroy them from the top down while the search goes up from the bottom, so at
+    // some point it asks about one that's already gone.
+    let destroyer = server.connect();
+    let destroying = std::thread::spawn(move || {
+        for &decoy in decoys.iter().rev() {
+            destroyer.destroy_window(decoy).unwrap();
+            destroyer.sync().unwrap();
+        }
+    });
+    let mut events = ScriptedEvents::new([Event::MapNotify(xproto::MapNotifyEvent {
+        response_type: xproto::MAP_NOTIFY_EVENT,
+        sequence: 0,
+        event: root,
+        window: frame,
+        override_redirect: false,
+    })]);
+    compositor.run_with(&mut events).await.unwrap();
+    destroying.join().unwrap();
+
+    let state = compositor
+        .window(frame)
+        .expect("frame should still be tracked");
+    assert!(state.mapped);
+    assert_eq!(state.client, Some(wm_client));
+}
@@ -0,0 +1,960 @@
+//! Compare the GPU compositing path against the CPU reference in `common::reference`.
+
+mod common;
+
+use recomp::{
+    border::{BorderLayer, BorderUniforms},
+    config::ActiveBorder,
+    occlusion::{self, Layer},
+    pipeline::{self, WindowPipeline},
+    scaled::ScaledTarget,
+    texture::{WindowTexture, WindowUniforms},
+    thumbnail,
+    window::BlendMode,
+};
+use x11rb::protocol::xproto::Rectangle;
+
+use common::reference::{self, RefWindow};
+
+const WIDTH: u16 = 64;
+const HEIGHT: u16 = 48;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const CLEAR: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+
+/// Per-channel difference allowed between the GPU and the reference, to absorb
+/// differences in sRGB conversion and rounding between drivers.
+const TOLERANCE: u8 = 2;
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Whether textures can be viewed with a different sRGB-ness than they were created
+    /// with, which the compositor relies on for surfaces without an sRGB format.
+    view_formats: bool,
+    /// The most samples per pixel, up to 4, that [`FORMAT`] can be rendered with.
+    max_samples: u32,
+}
+
+/// Open the default adapter, panicking if there isn't one since the tests that need it
+/// are ignored unless asked for.
+async fn gpu() -> Gpu {
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .expect("no GPU adapter available");
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor::default(), None)
+        .await
+        .expect("failed to open the GPU device");
+    let view_formats = adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::VIEW_FORMATS);
+    let max_samples =
+        pipeline::supported_sample_count(adapter.get_texture_format_features(FORMAT).flags, 4);
+    Gpu {
+        device,
+        queue,
+        view_formats,
+        max_samples,
+    }
+}
+
+/// Draw `windows` with the real window pipeline into an offscreen target and read the
+/// result back as sRGB8 RGBA.
+fn render(gpu: &Gpu, pipeline: &WindowPipeline, windows: &[RefWindow]) -> Vec<u8> {
+    render_with_border(gpu, pipeline, windows, None)
+}
+
+/// Like [`render`], drawing `border` on top of the windows.
+fn render_with_border(
+    gpu: &Gpu,
+    pipeline: &WindowPipeline,
+    windows: &[RefWindow],
+    border: Option<&BorderLayer>,
+) -> Vec<u8> {
+    render_scaled(gpu, pipeline, windows, border, None)
+}
+
+/// Like [`render_with_border`], drawing at `scale` and then scaling up to fill the
+/// target the way the compositor does with a render scale.
+fn render_scaled(
+    gpu: &Gpu,
+    pipeline: &WindowPipeline,
+    windows: &[RefWindow],
+    border: Option<&BorderLayer>,
+    scale: Option<f32>,
+) -> Vec<u8> {
+    let textures = upload(gpu, pipeline, windows);
+    draw(
+        gpu,
+        pipeline,
+        windows,
+        &textures,
+        DrawOptions {
+            border,
+            scale,
+            ..Default::default()
+        },
+    )
+}
+
+/// Upload `windows` to textures the way the compositor does when they're first mapped.
+fn upload(gpu: &Gpu, pipeline: &WindowPipeline, windows: &[RefWindow]) -> Vec<WindowTexture> {
+    let Gpu { device, queue, .. } = gpu;
+    windows
+        .iter()
+        .map(|window| {
+            let texture = WindowTexture::new(
+                device,
+                pipeline.bind_group_layout(),
+                pipeline.sampler(),
+                window.rect.width,
+                window.rect.height,
+            );
+            texture.write(
+                queue,
+                Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: window.rect.width,
+                    height: window.rect.height,
+                },
+                &window.pixels,
+            );
+            texture.write_uniforms(
+                queue,
+                &WindowUniforms {
+                    geometry: window.rect,
+                    screen: (WIDTH as u32, HEIGHT as u32),
+                    dim: window.dim,
+                    opacity: window.opacity,
+                    has_alpha: window.has_alpha,
+                    straight_alpha: window.straight_alpha,
+                    corner_radius: 0.0,
+                    corner_smoothing: 0.0,
+                    corner_aa_width: 1.0,
+                },
+            );
+            texture
+        })
+        .collect()
+}
+
+/// What [`draw`] does besides drawing the windows.
+#[derive(Default, Clone, Copy)]
+struct DrawOptions<'a> {
+    /// Drawn on top of the windows.
+    border: Option<&'a BorderLayer>,
+    /// Draw at this scale, then scale up to fill the target like with a render scale.
+    scale: Option<f32>,
+    /// Draw the first area again over the second, which needs a `scale`.
+    mirror: Option<(Rectangle, Rectangle)>,
+    /// Only draw windows where they show past the opaque ones above them, as with
+    /// `cull_occluded`.
+    cull: bool,
+}
+
+/// Draw the `textures` of `windows` bottom to top, then anything in `options`, into an
+/// offscreen target, and read the result back as sRGB8 RGBA.
+fn draw(
+    gpu: &Gpu,
+    pipeline: &WindowPipeline,
+    windows: &[RefWindow],
+    textures: &[WindowTexture],
+    options: DrawOptions,
+) -> Vec<u8> {
+    let DrawOptions {
+        border,
+        scale,
+        mirror,
+        cull,
+    } = options;
+    let Gpu {
+        device,
+        queue,
+        view_formats,
+        ..
+    } = gpu;
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Golden Target"),
+        size: wgpu::Extent3d {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        // Like a surface without an sRGB format, drawn to through an sRGB view, when the
+        // device supports that.
+        format: if *view_formats {
+            FORMAT.remove_srgb_suffix()
+        } else {
+            FORMAT
+        },
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: if *view_formats { &[FORMAT] } else { &[] },
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor {
+        format: Some(FORMAT),
+        ..Default::default()
+    });
+
+    let padded_row = (WIDTH as u32 * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Golden Readback"),
+        size: (padded_row * HEIGHT as u32) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let screen = (WIDTH as u32, HEIGHT as u32);
+    let scaled = scale.map(|scale| ScaledTarget::new(device, pipeline, FORMAT, screen, scale));
+    let resolved = scaled.as_ref().map_or(&view, |scaled| scaled.view());
+    // Drawn into a multisampled texture and resolved, like the compositor does with MSAA.
+    let msaa = (pipeline.sample_count() > 1).then(|| {
+        let size = scaled.as_ref().map_or(screen, |scaled| scaled.size());
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Golden Multisampled Target"),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: pipeline.sample_count(),
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let [r, g, b, a] = CLEAR.map(f64::from);
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Golden Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: msaa.as_ref().unwrap_or(resolved),
+                resolve_target: msaa.as_ref().map(|_| resolved),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let screen_rect = rect(0, 0, WIDTH, HEIGHT);
+        let layers: Vec<_> = windows
+            .iter()
+            .map(|window| Layer {
+                extent: window.rect,
+                opaque: occlusion::covers(window.has_alpha, window.opacity, 0.0, window.blend_mode)
+                    .then_some(window.rect),
+            })
+            .collect();
+        let regions = if cull {
+            occlusion::visible_regions(&layers, screen_rect)
+        } else {
+            vec![vec![screen_rect]; windows.len()]
+        };
+        let target = scaled.as_ref().map_or(screen, |scaled| scaled.size());
+        for ((window, texture), region) in windows.iter().zip(textures).zip(&regions) {
+            for &rect in region {
+                let (x, y, width, height) = occlusion::scissor(rect, screen, target);
+                pass.set_scissor_rect(x, y, width, height);
+                pass.set_pipeline(pipeline.blended(window.blend_mode));
+                pass.set_bind_group(0, texture.bind_group(), &[]);
+                pass.draw(0..6, 0..1);
+            }
+        }
+        pass.set_scissor_rect(0, 0, target.0, target.1);
+        pass.set_pipeline(pipeline.pipeline());
+        if let Some(border) = border {
+            border.draw(&mut pass);
+        }
+    }
+    if let Some(scaled) = &scaled {
+        scaled.write_uniforms(queue, screen);
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Golden Upscale Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline.single_sampled());
+        scaled.draw(&mut pass);
+        if let Some((source, destination)) = mirror {
+            scaled.write_mirror_uniforms(queue, screen, source, destination);
+            scaled.draw_mirror(&mut pass, screen, destination);
+        }
+    }
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+
+    let data = slice.get_mapped_range();
+    data.chunks_exact(padded_row as usize)
+        .flat_map(|row| &row[..WIDTH as usize * 4])
+        .copied()
+        .collect()
+}
+
+/// Render `windows` on the GPU and check it against the reference.
+async fn check(windows: &[RefWindow]) {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    check_with(&gpu, &pipeline, windows);
+}
+
+fn check_with(gpu: &Gpu, pipeline: &WindowPipeline, windows: &[RefWindow]) {
+    assert_matches_reference(&render(gpu, pipeline, windows), windows);
+}
+
+/// Check a frame rendered on the GPU against the reference rendering of `windows`.
+fn assert_matches_reference(actual: &[u8], windows: &[RefWindow]) {
+    let expected = reference::composite(WIDTH, HEIGHT, CLEAR, windows).to_srgb8();
+
+    for (i, (a, e)) in actual
+        .chunks_exact(4)
+        .zip(expected.chunks_exact(4))
+        .enumerate()
+    {
+        let close = a.iter().zip(e).all(|(a, e)| a.abs_diff(*e) <= TOLERANCE);
+        assert!(
+            close,
+            "pixel ({}, {}) was {a:?}, expected {e:?}",
+            i % WIDTH as usize,
+            i / WIDTH as usize,
+        );
+    }
+}
+
+fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+    Rectangle {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn stacks_opaque_windows_in_order() {
+    check(&[
+        RefWindow::solid(rect(4, 4, 32, 24), [200, 30, 30]),
+        RefWindow::solid(rect(20, 12, 32, 24), [30, 200, 30]),
+        // Partly off screen.
+        RefWindow::solid(rect(48, 36, 32, 24), [30, 30, 200]),
+    ])
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn ignores_alpha_of_opaque_windows() {
+    let mut window = RefWindow::solid(rect(8, 8, 16, 16), [120, 60, 240]);
+    // 24-bit windows leave the alpha byte undefined, so garbage there must not leak.
+    for texel in window.pixels.chunks_exact_mut(4) {
+        texel[3] = 0x10;
+    }
+    check(&[window]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn blends_premultiplied_argb_windows() {
+    let mut overlay = RefWindow::solid(rect(10, 10, 40, 20), [0, 0, 0]);
+    overlay.has_alpha = true;
+    for (i, texel) in overlay.pixels.chunks_exact_mut(4).enumerate() {
+        // A horizontal alpha ramp of premultiplied white.
+        let alpha = ((i % 40) * 255 / 39) as u8;
+        texel.copy_from_slice(&[alpha, alpha, alpha, alpha]);
+    }
+
+    check(&[RefWindow::solid(rect(0, 0, 64, 48), [40, 90, 160]), overlay]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn premultiplies_straight_alpha_windows() {
+    let mut overlay = RefWindow::solid(rect(10, 10, 40, 20), [0, 0, 0]);
+    overlay.has_alpha = true;
+    overlay.straight_alpha = true;
+    for (i, texel) in overlay.pixels.chunks_exact_mut(4).enumerate() {
+        // The same ramp, but with the color left at full white.
+        let alpha = ((i % 40) * 255 / 39) as u8;
+        texel.copy_from_slice(&[0xff, 0xff, 0xff, alpha]);
+    }
+
+    check(&[RefWindow::solid(rect(0, 0, 64, 48), [40, 90, 160]), overlay]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn applies_opacity_and_dim() {
+    let mut faded = RefWindow::solid(rect(16, 8, 32, 32), [250, 200, 20]);
+    faded.opacity = 0.5;
+    let mut dimmed = RefWindow::solid(rect(0, 24, 24, 24), [250, 250, 250]);
+    dimmed.dim = 0.3;
+
+    check(&[
+        RefWindow::solid(rect(8, 0, 40, 40), [20, 20, 120]),
+        faded,
+        dimmed,
+    ])
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn blends_overlaps_in_linear_space() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+
+    let mut white = RefWindow::solid(rect(0, 0, 8, 8), [255, 255, 255]);
+    white.opacity = 0.5;
+    let black = RefWindow::solid(rect(0, 0, 8, 8), [0, 0, 0]);
+
+    let pixels = render(&gpu, &pipeline, &[black, white]);
+    // Half of linear white, encoded as sRGB. Blending the sRGB values directly would
+    // give 128.
+    assert!(
+        pixels[..3].iter().all(|c| c.abs_diff(188) <= TOLERANCE),
+        "{:?}",
+        &pixels[..4]
+    );
+}
+
+/// Fill `area` of `window`, in window coordinates, with a BGRA `texel`, returning the new
+/// contents of the area the way a damage upload reads them back.
+fn paint(window: &mut RefWindow, area: Rectangle, texel: [u8; 4]) -> Vec<u8> {
+    let stride = window.rect.width as usize * 4;
+    for y in area.y as usize..(area.y as usize + area.height as usize) {
+        for x in area.x as usize..(area.x as usize + area.width as usize) {
+            window.pixels[y * stride + x * 4..][..4].copy_from_slice(&texel);
+        }
+    }
+    texel.repeat(area.width as usize * area.height as usize)
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn recomposites_overlapping_damage_in_stacking_order() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+
+    let mut below = RefWindow::solid(rect(4, 4, 32, 24), [200, 30, 30]);
+    let mut above = RefWindow::solid(rect(20, 12, 32, 24), [0, 0, 0]);
+    above.has_alpha = true;
+    paint(&mut above, rect(0, 0, 32, 24), [0, 100, 0, 128]);
+    let mut windows = [below.clone(), above.clone()];
+    let textures = upload(&gpu, &pipeline, &windows);
+    assert_matches_reference(
+        &draw(&gpu, &pipeline, &windows, &textures, DrawOptions::default()),
+        &windows,
+    );
+
+    // Both windows are damaged in the same frame, where they overlap and where they
+    // don't. The window above still has to end up on top of the new contents below it.
+    let below_damage = rect(8, 4, 20, 16);
+    let pixels = paint(&mut below, below_damage, [220, 40, 10, 0xff]);
+    textures[0].write(&gpu.queue, below_damage, &pixels);
+    let above_damage = rect(4, 2, 12, 12);
+    let pixels = paint(&mut above, above_damage, [60, 0, 60, 96]);
+    textures[1].write(&gpu.queue, above_damage, &pixels);
+
+    windows = [below, above];
+    assert_matches_reference(
+        &draw(&gpu, &pipeline, &windows, &textures, DrawOptions::default()),
+        &windows,
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn blends_windows_with_their_blend_modes() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+
+    let backdrop = RefWindow::solid(rect(0, 0, WIDTH, HEIGHT), [180, 90, 40]);
+    for blend_mode in [BlendMode::Multiply, BlendMode::Screen, BlendMode::Add] {
+        let mut opaque = RefWindow::solid(rect(4, 4, 24, 16), [100, 160, 220]);
+        opaque.blend_mode = blend_mode;
+        let mut translucent = RefWindow::solid(rect(20, 20, 32, 20), [0, 0, 0]);
+        translucent.has_alpha = true;
+        translucent.blend_mode = blend_mode;
+        paint(&mut translucent, rect(0, 0, 32, 20), [120, 60, 10, 160]);
+
+        check_with(&gpu, &pipeline, &[backdrop.clone(), opaque, translucent]);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn draws_border_around_window() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let border = BorderLayer::new(&gpu.device, FORMAT);
+    let geometry = rect(16, 12, 32, 24);
+    border.write_uniforms(
+        &gpu.queue,
+        &BorderUniforms {
+            geometry,
+            screen: (WIDTH as u32, HEIGHT as u32),
+            border: ActiveBorder {
+                width: 3.0,
+                color: wgpu::Color {
+                    r: 1.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                },
+                glow_radius: 0.0,
+            },
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            corner_aa_width: 1.0,
+            opacity: 1.0,
+        },
+    );
+
+    let window = RefWindow::solid(geometry, [30, 200, 30]);
+    let pixels = render_with_border(&gpu, &pipeline, &[window], Some(&border));
+    let pixel = |x: usize, y: usize| {
+        let i = (y * WIDTH as usize + x) * 4;
+        &pixels[i..i + 4]
+    };
+
+    // In the middle of the ring, inside the window, and well outside it.
+    assert_eq!(pixel(14, 20), [255, 0, 0, 255]);
+    assert_eq!(pixel(30, 24), [30, 200, 30, 255]);
+    let clear = reference::composite(1, 1, CLEAR, &[]).to_srgb8();
+    assert_eq!(pixel(4, 4), clear.as_slice());
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn keeps_the_glow_out_of_translucent_windows() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let border = BorderLayer::new(&gpu.device, FORMAT);
+    let geometry = rect(16, 12, 32, 24);
+    border.write_uniforms(
+        &gpu.queue,
+        &BorderUniforms {
+            geometry,
+            screen: (WIDTH as u32, HEIGHT as u32),
+            border: ActiveBorder {
+                width: 2.0,
+                color: wgpu::Color {
+                    r: 1.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                },
+                glow_radius: 6.0,
+            },
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            corner_aa_width: 1.0,
+            opacity: 1.0,
+        },
+    );
+
+    let mut window = RefWindow::solid(geometry, [30, 200, 30]);
+    window.opacity = 0.5;
+    let pixels = render_with_border(&gpu, &pipeline, &[window.clone()], Some(&border));
+    let expected = reference::composite(WIDTH, HEIGHT, CLEAR, &[window]).to_srgb8();
+
+    // Whatever shows through the window is what's under it, not the border or glow.
+    for y in geometry.y as usize..(geometry.y as usize + geometry.height as usize) {
+        for x in geometry.x as usize..(geometry.x as usize + geometry.width as usize) {
+            let i = (y * WIDTH as usize + x) * 4;
+            let (a, e) = (&pixels[i..i + 4], &expected[i..i + 4]);
+            let close = a.iter().zip(e).all(|(a, e)| a.abs_diff(*e) <= TOLERANCE);
+            assert!(close, "pixel ({x}, {y}) was {a:?}, expected {e:?}");
+        }
+    }
+    // The glow is still drawn outside it.
+    let i = (20 * WIDTH as usize + 12) * 4;
+    assert!(pixels[i] > expected[i] + 20, "{:?}", &pixels[i..i + 4]);
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn widens_the_falloff_of_rounded_corners() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let windows = [RefWindow::solid(rect(0, 0, WIDTH, HEIGHT), [255, 255, 255])];
+    let textures = upload(&gpu, &pipeline, &windows);
+
+    // Pixels that are neither the background nor the window, i.e. on a corner's edge.
+    let blended = |corner_aa_width| {
+        textures[0].write_uniforms(
+            &gpu.queue,
+            &WindowUniforms {
+                geometry: windows[0].rect,
+                screen: (WIDTH as u32, HEIGHT as u32),
+                dim: 0.0,
+                opacity: 1.0,
+                has_alpha: false,
+                straight_alpha: false,
+                corner_radius: 16.0,
+                corner_smoothing: 0.0,
+                corner_aa_width,
+            },
+        );
+        let frame = draw(&gpu, &pipeline, &windows, &textures, DrawOptions::default());
+        frame
+            .chunks_exact(4)
+            .filter(|texel| (80..=250).contains(&texel[0]))
+            .count()
+    };
+    let sharp = blended(0.0);
+    let default = blended(1.0);
+    let smooth = blended(6.0);
+    assert_eq!(sharp, 0);
+    assert!(default > 0);
+    assert!(
+        smooth > default * 3,
+        "{smooth} edge pixels at 6px, {default} at 1px"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn matches_the_reference_with_msaa() {
+    let gpu = gpu().await;
+    if gpu.max_samples == 1 {
+        return common::skip("no multisampling support");
+    }
+    let pipeline = WindowPipeline::with_samples(&gpu.device, FORMAT, gpu.max_samples);
+    // On whole pixels, where multisampling changes nothing.
+    let mut overlay = RefWindow::solid(rect(20, 12, 32, 24), [30, 200, 30]);
+    overlay.opacity = 0.5;
+    check_with(
+        &gpu,
+        &pipeline,
+        &[RefWindow::solid(rect(4, 4, 32, 24), [200, 30, 30]), overlay],
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn renders_scaled_down_thumbnails() {
+    let gpu = gpu().await;
+    // Red on the left and blue on the right.
+    let (width, height) = (64, 32);
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|i| match i % width < width / 2 {
+            true => [0, 0, 255, 255],
+            false => [255, 0, 0, 255],
+        })
+        .collect();
+
+    for format in [
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        wgpu::TextureFormat::Bgra8UnormSrgb,
+    ] {
+        let pipeline = WindowPipeline::new(&gpu.device, format);
+        let texture = WindowTexture::new(
+            &gpu.device,
+            pipeline.bind_group_layout(),
+            pipeline.sampler(),
+            width,
+            height,
+        );
+        texture.write(&gpu.queue, rect(0, 0, width, height), &pixels);
+        let uniforms = WindowUniforms {
+            geometry: rect(100, 100, width, height),
+            screen: (1920, 1080),
+            dim: 0.0,
+            opacity: 1.0,
+            has_alpha: false,
+            straight_alpha: false,
+            corner_radius: 0.0,
+            corner_smoothing: 0.0,
+            corner_aa_width: 1.0,
+        };
+
+        let size = thumbnail::fit((width, height), (16, 16));
+        let thumb = thumbnail::render(
+            &gpu.device,
+            &gpu.queue,
+            &pipeline,
+            format,
+            &texture,
+            uniforms,
+            size,
+        )
+        .unwrap();
+        assert_eq!((thumb.width, thumb.height), (16, 8));
+        assert_eq!(thumb.pixels.len(), 16 * 8 * 4);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 16 + x) * 4;
+            &thumb.pixels[i..i + 4]
+        };
+        assert_eq!(pixel(2, 4), [255, 0, 0, 255], "{format:?}");
+        assert_eq!(pixel(13, 4), [0, 0, 255, 255], "{format:?}");
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn upscales_from_a_lower_render_scale() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    // On even pixels, so that the edges land on texel boundaries at half scale.
+    let windows = [
+        RefWindow::solid(rect(8, 8, 24, 16), [200, 30, 30]),
+        RefWindow::solid(rect(24, 16, 32, 24), [30, 200, 30]),
+    ];
+    let actual = render_scaled(&gpu, &pipeline, &windows, None, Some(0.5));
+    let expected = reference::composite(WIDTH, HEIGHT, CLEAR, &windows).to_srgb8();
+
+    // Edges get blurred by the filtering, so only compare pixels with the same color
+    // all around them.
+    let at = |pixels: &[u8], x: i32, y: i32| {
+        let i = (y as usize * WIDTH as usize + x as usize) * 4;
+        pixels[i..i + 4].to_vec()
+    };
+    for y in 1..HEIGHT as i32 - 1 {
+        for x in 1..WIDTH as i32 - 1 {
+            let e = at(&expected, x, y);
+            let uniform = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .all(|(dx, dy)| at(&expected, x + dx, y + dy) == e);
+            if !uniform {
+                continue;
+            }
+            let a = at(&actual, x, y);
+            let close = a.iter().zip(&e).all(|(a, e)| a.abs_diff(*e) <= TOLERANCE);
+            assert!(close, "pixel ({x}, {y}) was {a:?}, expected {e:?}");
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn mirrors_one_area_onto_another() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let windows = [
+        RefWindow::solid(rect(4, 4, 16, 12), [200, 30, 30]),
+        // Covered up by the mirror.
+        RefWindow::solid(rect(40, 30, 8, 8), [30, 200, 30]),
+    ];
+    let textures = upload(&gpu, &pipeline, &windows);
+    let (source, destination) = (rect(0, 0, 32, 24), rect(32, 24, 32, 24));
+    let actual = draw(
+        &gpu,
+        &pipeline,
+        &windows,
+        &textures,
+        DrawOptions {
+            scale: Some(1.0),
+            mirror: Some((source, destination)),
+            ..Default::default()
+        },
+    );
+
+    let mut expected = reference::composite(WIDTH, HEIGHT, CLEAR, &windows).to_srgb8();
+    let row = WIDTH as usize * 4;
+    for y in 0..24 {
+        let from = y * row;
+        let to = (y + 24) * row + 32 * 4;
+        expected.copy_within(from..from + 32 * 4, to);
+    }
+    for (i, (a, e)) in actual
+        .chunks_exact(4)
+        .zip(expected.chunks_exact(4))
+        .enumerate()
+    {
+        let close = a.iter().zip(e).all(|(a, e)| a.abs_diff(*e) <= TOLERANCE);
+        assert!(
+            close,
+            "pixel ({}, {}) was {a:?}, expected {e:?}",
+            i % WIDTH as usize,
+            i / WIDTH as usize,
+        );
+    }
+}
+
+#[cfg(feature = "capture-stream")]
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn streams_rendered_frames() {
+    use recomp::stream::{FrameStream, StreamConfig};
+
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let screen = (WIDTH as u32, HEIGHT as u32);
+    let target = ScaledTarget::new(&gpu.device, &pipeline, FORMAT, screen, 1.0);
+    let path = std::env::temp_dir().join(format!("recomp-golden-stream-{}", std::process::id()));
+    let config = StreamConfig {
+        path: path.clone(),
+        fps: 1000,
+    };
+    let mut stream = FrameStream::open(&config, FORMAT).unwrap();
+
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Golden Stream Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target.view(),
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::RED),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    });
+    stream.capture(&gpu.device, &mut encoder, target.texture());
+    gpu.queue.submit([encoder.finish()]);
+    stream.submitted();
+    gpu.device.poll(wgpu::Maintain::Wait);
+    assert_eq!(stream.dropped(), 0);
+    // Closes the stream once the writer is done with the frame.
+    drop(stream);
+
+    let expected = WIDTH as usize * HEIGHT as usize * 4;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let frame = loop {
+        let frame = std::fs::read(&path).unwrap();
+        if frame.len() >= expected || std::time::Instant::now() > deadline {
+            break frame;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(frame.len(), expected);
+    // Red, in BGRA.
+    assert!(frame.chunks_exact(4).all(|pixel| pixel == [0, 0, 255, 255]));
+}
+
+#[test]
+fn reference_blends_premultiplied_over() {
+    let mut window = RefWindow::solid(rect(0, 0, 1, 1), [255, 255, 255]);
+    window.opacity = 0.5;
+
+    let frame = reference::composite(2, 1, [0.0, 0.0, 0.0, 1.0], &[window]);
+    assert_eq!(frame.pixels, [[0.5, 0.5, 0.5, 1.0], [0.0, 0.0, 0.0, 1.0]]);
+    // Half of linear white is much brighter than half of the sRGB value.
+    assert_eq!(&frame.to_srgb8()[..4], [188, 188, 188, 255]);
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn reload_keeps_pipeline_on_error() {
+    let gpu = gpu().await;
+
+    let mut pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    assert!(pipeline
+        .reload(&gpu.device, FORMAT, "this is not wgsl")
+        .await
+        .is_err());
+    pipeline
+        .reload(
+            &gpu.device,
+            FORMAT,
+            include_str!("../src/shaders/window.wgsl"),
+        )
+        .await
+        .unwrap();
+
+    // The reloaded pipeline still draws correctly.
+    check_with(
+        &gpu,
+        &pipeline,
+        &[RefWindow::solid(rect(4, 4, 16, 16), [200, 100, 50])],
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a GPU"]
+async fn culling_occluded_windows_changes_nothing() {
+    let gpu = gpu().await;
+    let pipeline = WindowPipeline::new(&gpu.device, FORMAT);
+    let mut faded = RefWindow::solid(rect(20, 6, 30, 30), [30, 30, 200]);
+    faded.opacity = 0.5;
+    let windows = [
+        // Entirely under the green window.
+        RefWindow::solid(rect(10, 20, 12, 8), [200, 200, 30]),
+        RefWindow::solid(rect(2, 2, 40, 30), [200, 30, 30]),
+        RefWindow::solid(rect(7, 15, 33, 21), [30, 200, 30]),
+        // Hides nothing, so what's under it is still drawn.
+        faded,
+    ];
+    let textures = upload(&gpu, &pipeline, &windows);
+    let culled = |scale| {
+        draw(
+            &gpu,
+            &pipeline,
+            &windows,
+            &textures,
+            DrawOptions {
+                scale,
+                cull: true,
+                ..Default::default()
+            },
+        )
+    };
+    assert_matches_reference(&culled(None), &windows);
+
+    // Odd edges land part way into pixels at a lower render scale, where anything the
+    // scissors cut off would show up as a difference.
+    for scale in [0.5, 0.7] {
+        let whole = draw(
+            &gpu,
+            &pipeline,
+            &windows,
+            &textures,
+            DrawOptions {
+                scale: Some(scale),
+                ..Default::default()
+            },
+        );
+        assert!(culled(Some(scale)) == whole, "differs at scale {scale}");
+    }
+}
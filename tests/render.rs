@@ -0,0 +1,200 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::Xvfb;
+use recomp::command::Command;
+use x11rb::{
+    connection::Connection as _,
+    protocol::xproto::{ConnectionExt as _, CreateWindowAux, ImageFormat, WindowClass},
+};
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn renders_after_resize() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    compositor.render().unwrap();
+
+    // The first frame after reconfiguring can report the surface as outdated, which
+    // should be recovered from rather than returned.
+    compositor.resize(800, 600);
+    compositor.render().unwrap();
+    compositor.render().unwrap();
+
+    let (width, height) = compositor.screen_root_size();
+    compositor.resize(width, height);
+    compositor.render().unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn renders_overlay_rects() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let rect = x11rb::protocol::xproto::Rectangle {
+        x: 10,
+        y: 10,
+        width: 200,
+        height: 100,
+    };
+    let below = compositor.add_overlay_rect(rect, [0.0, 0.0, 0.0, 0.5], -1);
+    let above = compositor.add_overlay_rect(rect, [1.0, 1.0, 1.0, 1.0], 0);
+    compositor.render().unwrap();
+    assert_eq!(compositor.resource_stats().overlay_rects, 2);
+
+    assert!(compositor.remove_overlay_rect(below));
+    assert!(!compositor.remove_overlay_rect(below));
+    compositor.render().unwrap();
+
+    assert!(compositor.remove_overlay_rect(above));
+    compositor.render().unwrap();
+    assert_eq!(compositor.resource_stats().overlay_rects, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn calls_present_hook_for_each_frame() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let presented = Arc::new(Mutex::new(Vec::new()));
+    compositor.on_present({
+        let presented = Arc::clone(&presented);
+        move |info| presented.lock().unwrap().push(*info)
+    });
+    compositor.render().unwrap();
+    compositor.render().unwrap();
+
+    let presented = presented.lock().unwrap();
+    let frames: Vec<_> = presented.iter().map(|info| info.frame).collect();
+    assert_eq!(frames, [0, 1]);
+    assert!(presented[0].presented_at <= presented[1].presented_at);
+    assert_eq!(compositor.frame_count(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn renders_a_single_frame() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    compositor.render_once().await.unwrap();
+    assert_eq!(compositor.frame_count(), 1);
+    compositor.into_connection().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn holds_the_last_frame_while_frozen() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    compositor.render().unwrap();
+    compositor.run_command(Command::Freeze);
+    assert!(compositor.is_frozen());
+    compositor.render().unwrap();
+    assert_eq!(compositor.frame_count(), 1);
+
+    compositor.run_command(Command::ToggleFreeze);
+    assert!(!compositor.is_frozen());
+    compositor.render().unwrap();
+    assert_eq!(compositor.frame_count(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn fades_the_tint_in_and_out() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    compositor.run_command(Command::ToggleTint);
+    assert!(compositor.is_tinted());
+    compositor.render().unwrap();
+
+    // Still drawn while it fades out.
+    compositor.run_command(Command::ToggleTint);
+    assert!(!compositor.is_tinted());
+    compositor.render().unwrap();
+    assert_eq!(compositor.frame_count(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn reuses_textures_of_static_windows() {
+    let server = Xvfb::spawn();
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            root,
+            0,
+            0,
+            64,
+            64,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+
+    let mut compositor = common::compositor(&server).await;
+    compositor.render_once().await.unwrap();
+    let uploads = compositor.upload_count();
+    assert!(uploads > 0);
+
+    // Nothing changed, so the frame is drawn from the texture as it was.
+    compositor.render_once().await.unwrap();
+    assert_eq!(compositor.upload_count(), uploads);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn changes_clear_color_live() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    compositor.render().unwrap();
+    compositor.set_clear_color(wgpu::Color::TRANSPARENT);
+    compositor.render().unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn presents_through_dbe() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        present: recomp::config::PresentBackend::Dbe,
+        clear_color: wgpu::Color::RED,
+        ..Default::default()
+    };
+    let mut compositor = common::compositor_with(&server, options).await;
+
+    compositor.render().unwrap();
+
+    // The swap has put the frame on the overlay itself.
+    let client = server.connect();
+    let image = client
+        .get_image(
+            ImageFormat::Z_PIXMAP,
+            compositor.overlay_window(),
+            0,
+            0,
+            1,
+            1,
+            !0,
+        )
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!(image.data[..3], [0, 0, 0xff]);
+}
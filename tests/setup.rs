@@ -0,0 +1,1016 @@
+mod common;
+
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        composite::{ConnectionExt as _, Redirect},
+        randr::ConnectionExt as _,
+        shape::{self, ConnectionExt as _},
+        xproto::{
+            AtomEnum, ConnectionExt as _, CreateWindowAux, GrabMode, MapState, ModMask, PropMode,
+            Rectangle, WindowClass,
+        },
+    },
+    wrapper::ConnectionExt as _,
+};
+
+use x11rb_async::protocol::xproto::ConnectionExt as AsyncConnectionExt;
+
+use common::Xvfb;
+use recomp::window::{AlphaMode, Edge};
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn acquires_overlay_and_redirects_subwindows() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    // Create a window from another client, which should be redirected automatically.
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            root,
+            0,
+            0,
+            100,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+
+    for _ in 0..3 {
+        compositor.process_events().await.unwrap();
+        compositor.render().unwrap();
+    }
+
+    // The overlay is only mapped while some client holds it.
+    let overlay = client
+        .composite_get_overlay_window(root)
+        .unwrap()
+        .reply()
+        .unwrap()
+        .overlay_win;
+    let attrs = client
+        .get_window_attributes(overlay)
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!(attrs.map_state, MapState::VIEWABLE);
+
+    // Naming a window's pixmap fails with BadMatch unless the window is redirected.
+    let pixmap = client.generate_id().unwrap();
+    client
+        .composite_name_window_pixmap(win, pixmap)
+        .unwrap()
+        .check()
+        .expect("window was not redirected");
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn sets_overlay_input_region() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let overlay = compositor.overlay_window();
+    let input_rects = || {
+        client
+            .shape_get_rectangles(overlay, shape::SK::INPUT)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .rectangles
+    };
+
+    // Input passes through the overlay entirely by default.
+    assert!(input_rects().is_empty());
+
+    let rect = Rectangle {
+        x: 10,
+        y: 20,
+        width: 30,
+        height: 40,
+    };
+    compositor.set_overlay_input_region(&[rect]).await.unwrap();
+    assert_eq!(input_rects(), [rect]);
+
+    compositor.set_overlay_input_region(&[]).await.unwrap();
+    assert!(input_rects().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn into_connection_releases_overlay() {
+    let server = Xvfb::spawn();
+    let compositor = common::compositor(&server).await;
+
+    let overlay = compositor.overlay_window();
+    let conn = compositor.into_connection().await.unwrap();
+
+    // The connection is still usable after the compositor is gone.
+    AsyncConnectionExt::get_input_focus(&*conn)
+        .await
+        .unwrap()
+        .reply()
+        .await
+        .unwrap();
+
+    // Nobody holds the overlay any more, so it's unmapped.
+    let client = server.connect();
+    let attrs = client
+        .get_window_attributes(overlay)
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!(attrs.map_state, MapState::UNMAPPED);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn redirects_manually_when_configured() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        manual_redirect: true,
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+
+    // Only one client can redirect a window manually, so another attempt must fail.
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let result = client
+        .composite_redirect_subwindows(root, Redirect::MANUAL)
+        .unwrap()
+        .check();
+    assert!(result.is_err(), "subwindows were not redirected manually");
+
+    drop(compositor);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn drop_unredirects_subwindows() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        manual_redirect: true,
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let redirect = || {
+        client
+            .composite_redirect_subwindows(root, Redirect::MANUAL)
+            .unwrap()
+            .check()
+    };
+    assert!(
+        redirect().is_err(),
+        "subwindows were not redirected manually"
+    );
+
+    // Dropping cleans up on a spawned task, so give it a chance to run.
+    drop(compositor);
+    let mut result = redirect();
+    for _ in 0..40 {
+        if result.is_ok() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        result = redirect();
+    }
+    result.expect("dropping the compositor didn't unredirect the root's subwindows");
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn suspends_and_resumes_compositing() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        manual_redirect: true,
+        ..Default::default()
+    };
+    let mut compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let redirect = || {
+        client
+            .composite_redirect_subwindows(root, Redirect::MANUAL)
+            .unwrap()
+            .check()
+    };
+
+    compositor.suspend().await.unwrap();
+    assert!(compositor.is_suspended());
+    redirect().expect("suspending didn't unredirect the root's subwindows");
+    client
+        .composite_unredirect_subwindows(root, Redirect::MANUAL)
+        .unwrap()
+        .check()
+        .unwrap();
+    // Doesn't draw anything, but isn't an error either.
+    compositor.render().unwrap();
+
+    compositor.resume().await.unwrap();
+    assert!(!compositor.is_suspended());
+    assert!(redirect().is_err(), "resuming didn't redirect subwindows");
+    let overlay = client
+        .get_geometry(compositor.overlay_window())
+        .unwrap()
+        .reply();
+    assert!(overlay.is_ok(), "no overlay after resuming");
+    compositor.render().unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn resumes_past_windows_destroyed_while_suspended() {
+    let server = Xvfb::spawn();
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            root,
+            0,
+            0,
+            100,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+
+    let mut compositor = common::compositor(&server).await;
+    assert!(compositor.window(win).is_some());
+
+    compositor.suspend().await.unwrap();
+    // Its DestroyNotify hasn't been handled by the time we resume.
+    client.destroy_window(win).unwrap().check().unwrap();
+    compositor.resume().await.unwrap();
+    assert!(!compositor.is_suspended());
+    assert!(compositor.window(win).is_none());
+    compositor.render().unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn grabs_and_releases_bound_keys() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        key_bindings: vec![recomp::keys::KeyBinding {
+            combo: "Mod4+F5".parse().unwrap(),
+            command: recomp::command::Command::ToggleExpose,
+        }],
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let (min, max) = (client.setup().min_keycode, client.setup().max_keycode);
+    let mapping = client
+        .get_keyboard_mapping(min, max - min + 1)
+        .unwrap()
+        .reply()
+        .unwrap();
+    let f5 = mapping
+        .keysyms
+        .chunks(mapping.keysyms_per_keycode as usize)
+        .position(|keysyms| keysyms.contains(&0xffc2))
+        .expect("no key produces F5") as u8
+        + min;
+
+    // Grabbing a combination someone else holds fails with BadAccess.
+    let grab = || {
+        client
+            .grab_key(
+                false,
+                root,
+                ModMask::M4,
+                f5,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+            )
+            .unwrap()
+            .check()
+    };
+    assert!(grab().is_err(), "compositor didn't grab the key");
+
+    compositor.into_connection().await.unwrap();
+    grab().expect("compositor didn't release the key");
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn grabs_keys_again_when_the_mapping_changes() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        key_bindings: vec![recomp::keys::KeyBinding {
+            combo: "Mod4+F5".parse().unwrap(),
+            command: recomp::command::Command::ToggleExpose,
+        }],
+        ..Default::default()
+    };
+    let mut compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let (min, max) = (client.setup().min_keycode, client.setup().max_keycode);
+    let mapping = client
+        .get_keyboard_mapping(min, max - min + 1)
+        .unwrap()
+        .reply()
+        .unwrap();
+    let per_keycode = mapping.keysyms_per_keycode;
+    let keycode_where = |found: &dyn Fn(&[u32]) -> bool| {
+        mapping
+            .keysyms
+            .chunks(per_keycode as usize)
+            .position(found)
+            .map(|i| i as u8 + min)
+    };
+    let old = keycode_where(&|keysyms| keysyms.contains(&0xffc2)).expect("no key produces F5");
+    let Some(new) = keycode_where(&|keysyms| keysyms.iter().all(|&keysym| keysym == 0)) else {
+        return common::skip("no unused key code to move F5 to");
+    };
+
+    // Move F5 to another key, like switching layouts can.
+    let mut keysyms = vec![0; per_keycode as usize];
+    client
+        .change_keyboard_mapping(1, old, per_keycode, &keysyms)
+        .unwrap()
+        .check()
+        .unwrap();
+    keysyms[0] = 0xffc2;
+    client
+        .change_keyboard_mapping(1, new, per_keycode, &keysyms)
+        .unwrap()
+        .check()
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    compositor.process_events().await.unwrap();
+
+    let grab = |keycode| {
+        client
+            .grab_key(
+                false,
+                root,
+                ModMask::M4,
+                keycode,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+            )
+            .unwrap()
+            .check()
+    };
+    assert!(grab(new).is_err(), "compositor didn't grab the new key");
+    grab(old).expect("compositor kept the old key");
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn adopts_windows_that_existed_before_startup() {
+    let server = Xvfb::spawn();
+
+    // Open some windows before the compositor starts, one of them unmapped.
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let mut existing = Vec::new();
+    for (i, map) in [true, false, true].into_iter().enumerate() {
+        let win = client.generate_id().unwrap();
+        client
+            .create_window(
+                0,
+                win,
+                root,
+                i as i16 * 50,
+                0,
+                100,
+                100,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                0,
+                &CreateWindowAux::default(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+        if map {
+            client.map_window(win).unwrap().check().unwrap();
+        }
+        existing.push((win, map));
+    }
+
+    let compositor = common::compositor(&server).await;
+
+    // Every window is tracked in stacking order, without any events having arrived.
+    let tracked: Vec<_> = compositor
+        .windows()
+        .into_iter()
+        .map(|info| (info.id, info.mapped))
+        .collect();
+    assert_eq!(tracked, existing);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn picks_alpha_mode_by_class() {
+    let server = Xvfb::spawn();
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let mut windows = Vec::new();
+    for class in ["term\0Alacritty\0", "navigator\0Firefox\0"] {
+        let win = client.generate_id().unwrap();
+        client
+            .create_window(
+                0,
+                win,
+                root,
+                0,
+                0,
+                100,
+                100,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                0,
+                &CreateWindowAux::default(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+        client
+            .change_property8(
+                PropMode::REPLACE,
+                win,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                class.as_bytes(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+        client.map_window(win).unwrap().check().unwrap();
+        windows.push(win);
+    }
+
+    let options = recomp::config::Config {
+        straight_alpha: vec![recomp::config::WindowMatch::Class("Alacritty".into())],
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+
+    let alpha_mode = |win| compositor.window(win).unwrap().alpha_mode;
+    assert_eq!(alpha_mode(windows[0]), AlphaMode::Straight);
+    assert_eq!(alpha_mode(windows[1]), AlphaMode::Premultiplied);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn pointer_passes_through_overlay() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            root,
+            100,
+            100,
+            200,
+            200,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+    compositor.process_events().await.unwrap();
+    compositor.render().unwrap();
+
+    // With an empty input region the overlay is invisible to the pointer, so the window
+    // underneath it is what gets clicked.
+    client
+        .warp_pointer(x11rb::NONE, root, 0, 0, 0, 0, 150, 150)
+        .unwrap()
+        .check()
+        .unwrap();
+    let pointer = client.query_pointer(root).unwrap().reply().unwrap();
+    assert_eq!(pointer.child, win);
+    assert_ne!(pointer.child, compositor.overlay_window());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn composites_inside_nested_window() {
+    let server = Xvfb::spawn();
+    let compositor = recomp::compositor::Compositor::new_nested(
+        Some(server.display()),
+        None,
+        (320, 240),
+        Default::default(),
+    )
+    .await
+    .expect("failed to create a nested compositor");
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let container = compositor.root_window();
+    let output = compositor.overlay_window();
+    assert_ne!(container, root);
+    assert_eq!(compositor.screen_root_size(), (320, 240));
+
+    // The real overlay stays unused, so it's unmapped.
+    let overlay = client
+        .composite_get_overlay_window(root)
+        .unwrap()
+        .reply()
+        .unwrap()
+        .overlay_win;
+    assert_ne!(output, overlay);
+    let attrs = client
+        .get_window_attributes(overlay)
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!(attrs.map_state, MapState::UNMAPPED);
+    client.composite_release_overlay_window(overlay).unwrap();
+
+    // The container and output share one top-level frame, with the output on top.
+    let container_tree = client.query_tree(container).unwrap().reply().unwrap();
+    let frame_tree = client
+        .query_tree(container_tree.parent)
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!(frame_tree.parent, root);
+    assert_eq!(frame_tree.children, [container, output]);
+
+    // Only the container's children are redirected.
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            container,
+            0,
+            0,
+            100,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+    let pixmap = client.generate_id().unwrap();
+    client
+        .composite_name_window_pixmap(win, pixmap)
+        .unwrap()
+        .check()
+        .expect("window in the container was not redirected");
+
+    compositor.into_connection().await.unwrap();
+    assert!(client
+        .get_window_attributes(container_tree.parent)
+        .unwrap()
+        .reply()
+        .is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn finds_window_manager() {
+    let server = Xvfb::spawn();
+    let compositor = common::compositor(&server).await;
+    assert_eq!(compositor.window_manager(), None);
+    compositor.into_connection().await.unwrap();
+
+    // Advertise the way an EWMH window manager does.
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let atom = |name: &[u8]| {
+        client
+            .intern_atom(false, name)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .atom
+    };
+    let (check_atom, name_atom, utf8) = (
+        atom(b"_NET_SUPPORTING_WM_CHECK"),
+        atom(b"_NET_WM_NAME"),
+        atom(b"UTF8_STRING"),
+    );
+    let check = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            check,
+            root,
+            -10,
+            -10,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    for win in [root, check] {
+        client
+            .change_property32(
+                PropMode::REPLACE,
+                win,
+                check_atom,
+                AtomEnum::WINDOW,
+                &[check],
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+    }
+    client
+        .change_property8(PropMode::REPLACE, check, name_atom, utf8, b"testwm")
+        .unwrap()
+        .check()
+        .unwrap();
+
+    let compositor = common::compositor(&server).await;
+    assert_eq!(compositor.window_manager(), Some("testwm"));
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn waits_for_the_window_manager() {
+    let server = Xvfb::spawn();
+
+    // A window manager that starts a little after the compositor.
+    let client = server.connect();
+    let wm = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        let root = client.setup().roots[0].root;
+        let check_atom = client
+            .intern_atom(false, b"_NET_SUPPORTING_WM_CHECK")
+            .unwrap()
+            .reply()
+            .unwrap()
+            .atom;
+        let check = client.generate_id().unwrap();
+        client
+            .create_window(
+                0,
+                check,
+                root,
+                -10,
+                -10,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                0,
+                &CreateWindowAux::default(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+        for win in [check, root] {
+            client
+                .change_property32(
+                    PropMode::REPLACE,
+                    win,
+                    check_atom,
+                    AtomEnum::WINDOW,
+                    &[check],
+                )
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+        // The check window goes away with the connection.
+        client
+    });
+
+    let options = recomp::config::Config {
+        wait_for_wm: Some(std::time::Duration::from_secs(5)),
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+    assert!(compositor.window_manager().is_some());
+    drop(wm.join().unwrap());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn stops_when_another_compositor_takes_over() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+    assert!(compositor
+        .cursor()
+        .is_some_and(|cursor| cursor.size != (0, 0)));
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let selection = client
+        .intern_atom(false, b"_NET_WM_CM_S0")
+        .unwrap()
+        .reply()
+        .unwrap()
+        .atom;
+    let owner = client
+        .get_selection_owner(selection)
+        .unwrap()
+        .reply()
+        .unwrap()
+        .owner;
+    assert_ne!(owner, x11rb::NONE);
+
+    let usurper = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            usurper,
+            root,
+            -1,
+            -1,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client
+        .set_selection_owner(usurper, selection, x11rb::CURRENT_TIME)
+        .unwrap()
+        .check()
+        .unwrap();
+
+    tokio::time::timeout(std::time::Duration::from_secs(5), compositor.run())
+        .await
+        .expect("compositor should stop once replaced")
+        .unwrap();
+    assert!(compositor.replaced());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn interns_prefetched_atoms() {
+    let server = Xvfb::spawn();
+    let options = recomp::config::Config {
+        prefetch_atoms: vec!["_RECOMP_TEST".to_owned()],
+        ..Default::default()
+    };
+    let compositor = common::compositor_with(&server, options).await;
+
+    let client = server.connect();
+    let atom = |name: &[u8]| {
+        client
+            .intern_atom(false, name)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .atom
+    };
+    let atoms = compositor.atoms();
+    assert_eq!(atoms.get("_RECOMP_TEST"), Some(atom(b"_RECOMP_TEST")));
+    assert_eq!(
+        atoms.get("_NET_WM_WINDOW_OPACITY"),
+        Some(atoms.net_wm_window_opacity)
+    );
+    assert_eq!(atoms.net_wm_cm_s, atom(b"_NET_WM_CM_S0"));
+    assert_eq!(atoms.get("_NOT_PREFETCHED"), None);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn resizes_overlay_with_the_screen() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let range = client
+        .randr_get_screen_size_range(root)
+        .unwrap()
+        .reply()
+        .unwrap();
+    // Growing, since the screen can't shrink to smaller than its CRTCs.
+    let size = (1600, 900);
+    if size.0 > range.max_width || size.1 > range.max_height {
+        return common::skip("the server can't grow the screen");
+    }
+    client
+        .randr_set_screen_size(root, size.0, size.1, 423, 238)
+        .unwrap()
+        .check()
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    compositor.process_events().await.unwrap();
+
+    assert_eq!(compositor.screen_root_size(), size);
+    let overlay = client
+        .get_geometry(compositor.overlay_window())
+        .unwrap()
+        .reply()
+        .unwrap();
+    assert_eq!((overlay.width, overlay.height), size);
+    compositor.render().unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn pointer_passes_through_overlay_after_resizing_the_screen() {
+    let server = Xvfb::spawn();
+    let mut compositor = common::compositor(&server).await;
+
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let range = client
+        .randr_get_screen_size_range(root)
+        .unwrap()
+        .reply()
+        .unwrap();
+    let size = (1600, 900);
+    if size.0 > range.max_width || size.1 > range.max_height {
+        return common::skip("the server can't grow the screen");
+    }
+    client
+        .randr_set_screen_size(root, size.0, size.1, 423, 238)
+        .unwrap()
+        .check()
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // A window in the part of the screen that's only there since the resize.
+    let win = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            win,
+            root,
+            1300,
+            750,
+            200,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(win).unwrap().check().unwrap();
+    compositor.process_events().await.unwrap();
+    compositor.render().unwrap();
+
+    let input_rects = client
+        .shape_get_rectangles(compositor.overlay_window(), shape::SK::INPUT)
+        .unwrap()
+        .reply()
+        .unwrap()
+        .rectangles;
+    assert!(input_rects.is_empty());
+    client
+        .warp_pointer(x11rb::NONE, root, 0, 0, 0, 0, 1400, 800)
+        .unwrap()
+        .check()
+        .unwrap();
+    let pointer = client.query_pointer(root).unwrap().reply().unwrap();
+    assert_eq!(pointer.child, win);
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn reserves_space_for_panels() {
+    let server = Xvfb::spawn();
+
+    let client = server.connect();
+    let screen = &client.setup().roots[0];
+    let (root, width) = (screen.root, screen.width_in_pixels);
+    let panel = client.generate_id().unwrap();
+    client
+        .create_window(
+            0,
+            panel,
+            root,
+            0,
+            0,
+            width,
+            30,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::default(),
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    let strut = client
+        .intern_atom(false, b"_NET_WM_STRUT")
+        .unwrap()
+        .reply()
+        .unwrap()
+        .atom;
+    client
+        .change_property32(
+            PropMode::REPLACE,
+            panel,
+            strut,
+            AtomEnum::CARDINAL,
+            &[0, 0, 30, 0],
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    client.map_window(panel).unwrap().check().unwrap();
+
+    let mut compositor = common::compositor(&server).await;
+    let reserved = compositor.reserved();
+    assert_eq!(reserved.len(), 1);
+    assert_eq!(reserved[0].edge, Edge::Top);
+    assert_eq!(
+        (reserved[0].area.width, reserved[0].area.height),
+        (width, 30)
+    );
+
+    // Panels that get taller, or go away, give the space back.
+    client
+        .change_property32(
+            PropMode::REPLACE,
+            panel,
+            strut,
+            AtomEnum::CARDINAL,
+            &[0, 0, 40, 0],
+        )
+        .unwrap()
+        .check()
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    compositor.process_events().await.unwrap();
+    assert_eq!(compositor.reserved()[0].area.height, 40);
+
+    client.unmap_window(panel).unwrap().check().unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    compositor.process_events().await.unwrap();
+    assert!(compositor.reserved().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs Xvfb"]
+async fn describes_adapters_alongside_a_running_compositor() {
+    let server = Xvfb::spawn();
+    let compositor = common::compositor(&server).await;
+    let client = server.connect();
+    let root = client.setup().roots[0].root;
+    let children = || client.query_tree(root).unwrap().reply().unwrap().children;
+    let before = children();
+
+    let description = recomp::compositor::describe_adapters(Some(server.display()))
+        .await
+        .unwrap();
+    assert!(description.starts_with("Adapter 0: "), "{description}");
+    assert!(description.contains("Surface formats: "), "{description}");
+
+    // The window it presented to is gone again.
+    assert_eq!(children(), before);
+    drop(compositor);
+}